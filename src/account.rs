@@ -11,3 +11,55 @@ pub mod address;
 pub mod contacts;
 pub mod pools;
 pub mod txs;
+
+use anyhow::Result;
+use rusqlite::Connection;
+use zcash_primitives::consensus::Network;
+
+use crate::{db::account::get_account_info, keys::export_sk_bip38};
+
+/// Secret key of the transparent address of the account in WIF,
+/// the format read back by the sweep functions. The shielded keys
+/// are not exported
+pub fn export_transparent_key(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+) -> Result<String> {
+    let ai = get_account_info(network, connection, account)?;
+    let ti = ai
+        .transparent
+        .as_ref()
+        .ok_or(anyhow::anyhow!("Account {account} has no transparent key"))?;
+    Ok(export_sk_bip38(&ti.sk))
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::encoding::AddressCodec as _;
+    use zcash_primitives::consensus::Network;
+
+    use super::export_transparent_key;
+    use crate::{
+        db::testing::{test_account, test_db},
+        keys::{import_sk_bip38, transparent_from_sk},
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    #[test]
+    fn exported_wif_gives_the_same_address() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let wif = export_transparent_key(&NETWORK, &connection, account).unwrap();
+        // compressed key on mainnet
+        assert!(wif.starts_with('K') || wif.starts_with('L'), "{wif}");
+        let sk = import_sk_bip38(&wif).unwrap();
+        let ti = transparent_from_sk(sk, true);
+        assert_eq!(
+            ti.addr.encode(&NETWORK),
+            "t1XVXWCvpMgBvUaed4XDqWtgQgJSu1Ghz7F"
+        );
+        assert!(export_transparent_key(&NETWORK, &connection, account + 1).is_err());
+    }
+}
@@ -2,42 +2,245 @@ use anyhow::Result;
 use bech32::{Bech32m, Hrp};
 use orchard::keys::Scope;
 use rusqlite::Connection;
+use serde::Serialize;
 use zcash_client_backend::encoding::AddressCodec;
-use zcash_primitives::{consensus::Network, legacy::TransparentAddress};
+use zcash_primitives::{consensus::{Network, NetworkConstants as _}, legacy::TransparentAddress};
 
-use crate::{db::account::get_account_info, types::PoolMask};
+use crate::{
+    db::{
+        account::get_account_info,
+        account_manager::{get_address_rotation, parse_seed_phrase, set_diversifier_index},
+    },
+    keys::Bip32KeyIterator,
+    types::{AccountInfo, PoolMask},
+    utils::ua::ua_of_orchard,
+};
 
+#[derive(Clone, Serialize, Debug)]
+pub struct DerivedAddress {
+    /// transparent, sapling, orchard or unified
+    pub kind: String,
+    /// Derivation path, None for imported keys
+    pub path: Option<String>,
+    /// Address or diversifier index
+    pub index: Option<u64>,
+    pub address: String,
+}
+
+/// Addresses of the account with their derivation:
+/// - the transparent address of the account, then `transparent_gap`
+/// addresses of the BIP-44 external chain of the account index,
+/// - the default unified address,
+/// - the first `shielded_count` diversified sapling and orchard addresses.
+/// The transparent chain is only available for accounts with a seed
+pub fn derivation_report(
+    network: &Network,
+    ai: &AccountInfo,
+    transparent_gap: u32,
+    shielded_count: u32,
+) -> Result<Vec<DerivedAddress>> {
+    let coin = network.coin_type();
+    let seed = ai.seed.as_deref().map(parse_seed_phrase).transpose()?;
+    let mut addresses = vec![];
+
+    if let Some(ti) = ai.transparent.as_ref() {
+        addresses.push(DerivedAddress {
+            kind: "transparent".to_string(),
+            // Same path as ZecWallet Lite, see create_new_account
+            path: seed.as_ref().map(|_| format!("m/44'/{coin}'/0'/0/{}", ai.aindex)),
+            index: seed.as_ref().map(|_| ai.aindex as u64),
+            address: ti.addr.encode(network),
+        });
+    }
+    if let Some(seed) = seed.as_ref() {
+        let keys = Bip32KeyIterator::new(network, seed, ai.aindex, 0, true);
+        for (i, ti) in keys.take(transparent_gap as usize).enumerate() {
+            addresses.push(DerivedAddress {
+                kind: "transparent".to_string(),
+                path: Some(format!("m/44'/{coin}'/{}'/0/{i}", ai.aindex)),
+                index: Some(i as u64),
+                address: ti.addr.encode(network),
+            });
+        }
+    }
+
+    let shielded_path = seed.as_ref().map(|_| format!("m/32'/{coin}'/{}'", ai.aindex));
+    if let Some(ua) = ai.to_address(network, PoolMask(7)) {
+        addresses.push(DerivedAddress {
+            kind: "unified".to_string(),
+            path: shielded_path.clone(),
+            index: None,
+            address: ua,
+        });
+    }
+
+    let mut di = 0u64;
+    for _ in 0..shielded_count {
+        let Some((index, address)) = ai.sapling.vk.find_address(to_diversifier_index(di)) else {
+            break;
+        };
+        let index = from_diversifier_index(&index);
+        addresses.push(DerivedAddress {
+            kind: "sapling".to_string(),
+            path: shielded_path.clone(),
+            index: Some(index),
+            address: address.encode(network),
+        });
+        di = index + 1;
+    }
+
+    if let Some(oi) = ai.orchard.as_ref() {
+        for i in 0..shielded_count as u64 {
+            let address = oi.vk.address_at(i, Scope::External);
+            addresses.push(DerivedAddress {
+                kind: "orchard".to_string(),
+                path: shielded_path.clone(),
+                index: Some(i),
+                address: ua_of_orchard(&address.to_raw_address_bytes()).encode(network),
+            });
+        }
+    }
+    Ok(addresses)
+}
+
+fn to_diversifier_index(index: u64) -> zcash_primitives::zip32::DiversifierIndex {
+    let mut bytes = [0u8; 11];
+    bytes[0..8].copy_from_slice(&index.to_le_bytes());
+    zcash_primitives::zip32::DiversifierIndex::from(bytes)
+}
+
+fn from_diversifier_index(index: &zcash_primitives::zip32::DiversifierIndex) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&index.as_bytes()[0..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Address with the shielded receivers at the first valid sapling
+/// diversifier index from `index`, and that index
+fn address_at_index(
+    network: &Network,
+    ai: &AccountInfo,
+    index: u64,
+    pools: PoolMask,
+) -> Result<(u64, String, [u8; 43], Option<[u8; 43]>)> {
+    let (di, saddr) = ai
+        .sapling
+        .vk
+        .find_address(to_diversifier_index(index))
+        .ok_or(anyhow::anyhow!("No diversifier address found"))?;
+    let index = from_diversifier_index(&di);
+    let oaddr = ai
+        .orchard
+        .as_ref()
+        .map(|oi| oi.vk.address_at(index, Scope::External));
+    let taddr = ai.transparent.as_ref().map(|ti| ti.addr);
+    let t = taddr.filter(|_| pools.0 & 1 != 0);
+    let s = Some(saddr).filter(|_| pools.0 & 2 != 0);
+    let o = oaddr.filter(|_| pools.0 & 4 != 0);
+    let address = match (t, s, o) {
+        (None, None, None) => anyhow::bail!("Invalid pools"),
+        (Some(t), None, None) => t.encode(network),
+        (None, Some(s), None) => s.encode(network),
+        (t, s, o) => zcash_client_backend::address::UnifiedAddress::from_receivers(o, s, t)
+            .ok_or(anyhow::anyhow!("Cannot build UA"))?
+            .encode(network),
+    };
+    Ok((
+        index,
+        address,
+        saddr.to_bytes(),
+        oaddr.map(|a| a.to_raw_address_bytes()),
+    ))
+}
+
+/// Receiving address of the account. With the rotation policy it is
+/// the diversified address at the issued index, which moves forward
+/// when funds arrive on it. Otherwise it is the default address.
+/// A mask of 0 includes every pool of the account
+pub fn get_receiving_address(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    pools: PoolMask,
+) -> Result<String> {
+    let ai = get_account_info(network, connection, account)?;
+    let (rotate, index) = get_address_rotation(connection, account)?;
+    if !rotate {
+        if pools.0 == 0 {
+            return Ok(ai.default_unified_address(network));
+        }
+        return ai
+            .to_address(network, pools)
+            .ok_or(anyhow::anyhow!("Invalid mask"));
+    }
+    let pools = if pools.0 == 0 { PoolMask(7) } else { pools };
+    let (_, address, _, _) = address_at_index(network, &ai, index, pools)?;
+    Ok(address)
+}
+
+/// Advance the issued index of the accounts that rotate their
+/// address when one of the notes `(account, receiver)` was received
+/// on their current address. Called by the sync
+pub fn rotate_received_addresses(
+    network: &Network,
+    connection: &Connection,
+    received: &[(u32, [u8; 43])],
+) -> Result<()> {
+    let mut accounts = received.iter().map(|(a, _)| *a).collect::<Vec<_>>();
+    accounts.sort();
+    accounts.dedup();
+    for account in accounts {
+        let (rotate, index) = get_address_rotation(connection, account)?;
+        if !rotate {
+            continue;
+        }
+        let ai = get_account_info(network, connection, account)?;
+        let (index, _, saddr, oaddr) = address_at_index(network, &ai, index, PoolMask(6))?;
+        let hit = received
+            .iter()
+            .any(|(a, r)| *a == account && (*r == saddr || Some(*r) == oaddr));
+        if hit {
+            tracing::info!("Account {account}: address {index} received funds, rotating");
+            set_diversifier_index(connection, account, index + 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// Diversified address derived from `time`, and its diversifier index.
+/// Not every index gives a sapling address: with a sapling receiver,
+/// the first valid index from the requested one is used, and the
+/// orchard receiver is taken at that same index
 pub fn get_diversified_address(
     network: &Network,
     connection: &Connection,
     account: u32,
     time: u32,
     pools: PoolMask,
-) -> Result<String> {
+) -> Result<(u64, String)> {
     let ai = get_account_info(network, connection, account)?;
     let ai = ai.select_pools(pools);
+    let mut index = (time as u64) << 32;
     let saddr = ai
         .sapling
         .as_ref()
         .map(|si| {
-            let mut di = [0u8; 11];
-            di[4..8].copy_from_slice(&time.to_le_bytes());
-            let di = zcash_primitives::zip32::DiversifierIndex::from(di);
-            let (_, saddr) = si
+            let (di, saddr) = si
                 .vk
-                .find_address(di)
+                .find_address(to_diversifier_index(index))
                 .ok_or(anyhow::anyhow!("No diversifier address found"))?;
+            index = from_diversifier_index(&di);
             Ok::<_, anyhow::Error>(saddr)
         })
         .transpose()?;
-    let oaddr = ai.orchard.as_ref().map(|oi| {
-        let di = orchard::keys::DiversifierIndex::from(time);
-        oi.vk.address_at(di, Scope::External)
-    });
+    let oaddr = ai
+        .orchard
+        .as_ref()
+        .map(|oi| oi.vk.address_at(index, Scope::External));
     let ua = zcash_client_backend::address::UnifiedAddress::from_receivers(oaddr, saddr, None)
         .ok_or(anyhow::anyhow!("Cannot build UA"))?;
     let address = ua.encode(network);
-    Ok(address)
+    Ok((index, address))
 }
 
 const TEX_HRP: Hrp = Hrp::parse_unchecked("tex");
@@ -65,3 +268,131 @@ pub fn convert_tex_address(network: &Network, address: &str, to_tex: bool) -> Re
         Ok(address)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use zcash_primitives::consensus::Network;
+
+    use super::{
+        address_at_index, derivation_report, get_diversified_address, get_receiving_address,
+        rotate_received_addresses, to_diversifier_index,
+    };
+    use crate::{
+        db::{
+            account::get_account_info,
+            account_manager::{get_address_rotation, set_rotate_on_receive},
+            testing::{test_account, test_db},
+        },
+        types::PoolMask,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    #[test]
+    fn transparent_addresses_of_the_test_seed() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let report = derivation_report(&NETWORK, &ai, 3, 2).unwrap();
+
+        let transparent = report
+            .iter()
+            .filter(|a| a.kind == "transparent")
+            .map(|a| (a.path.as_deref().unwrap(), a.address.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transparent,
+            [
+                ("m/44'/133'/0'/0/0", "t1XVXWCvpMgBvUaed4XDqWtgQgJSu1Ghz7F"),
+                ("m/44'/133'/0'/0/0", "t1XVXWCvpMgBvUaed4XDqWtgQgJSu1Ghz7F"),
+                ("m/44'/133'/0'/0/1", "t1aQ2b1XszNVo15BguYLbQGqETBL9QZA8Jq"),
+                ("m/44'/133'/0'/0/2", "t1gLHX58QpcZr2SfaMhc5XbxabE455KZhoA"),
+            ]
+        );
+        let count = |kind: &str| report.iter().filter(|a| a.kind == kind).count();
+        assert_eq!(count("unified"), 1);
+        assert_eq!(count("sapling"), 2);
+        assert_eq!(count("orchard"), 2);
+    }
+
+    #[test]
+    fn receiving_on_the_current_address_rotates_it() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let pools = PoolMask(6);
+        let address = || get_receiving_address(&NETWORK, &connection, account, pools).unwrap();
+        let (index, _, saddr, _) = address_at_index(&NETWORK, &ai, 0, pools).unwrap();
+
+        // without the policy, the address never changes
+        let default = address();
+        rotate_received_addresses(&NETWORK, &connection, &[(account, saddr)]).unwrap();
+        assert_eq!(address(), default);
+        assert_eq!(get_address_rotation(&connection, account).unwrap().1, 0);
+
+        set_rotate_on_receive(&connection, account, true).unwrap();
+        let current = address();
+        // funds on another address
+        rotate_received_addresses(&NETWORK, &connection, &[(account, [0u8; 43])]).unwrap();
+        assert_eq!(address(), current);
+        rotate_received_addresses(&NETWORK, &connection, &[(account, saddr)]).unwrap();
+        assert_eq!(
+            get_address_rotation(&connection, account).unwrap(),
+            (true, index + 1)
+        );
+        assert_ne!(address(), current);
+    }
+
+    #[test]
+    fn default_address_has_every_receiver() {
+        use zcash_keys::address::Address;
+
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let mut ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let receivers = |address: &str| match Address::decode(&NETWORK, address) {
+            Some(Address::Unified(ua)) => (
+                ua.transparent().is_some(),
+                ua.sapling().is_some(),
+                ua.orchard().is_some(),
+            ),
+            _ => panic!("{address} is not a unified address"),
+        };
+        let address = get_receiving_address(&NETWORK, &connection, account, PoolMask(0)).unwrap();
+        assert_eq!(address, ai.default_unified_address(&NETWORK));
+        assert_eq!(receivers(&address), (true, true, true));
+
+        // a sapling and orchard account
+        ai.transparent = None;
+        let address = ai.default_unified_address(&NETWORK);
+        assert_eq!(receivers(&address), (false, true, true));
+    }
+
+    #[test]
+    fn invalid_sapling_diversifier_is_skipped() {
+        use zcash_keys::address::Address;
+
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let vk = &ai.sapling.vk;
+        // about half of the diversifiers give no sapling address
+        let time = (0..100u32)
+            .find(|&t| vk.address(to_diversifier_index((t as u64) << 32)).is_none())
+            .unwrap();
+        let requested = (time as u64) << 32;
+
+        let (index, address) =
+            get_diversified_address(&NETWORK, &connection, account, time, PoolMask(6)).unwrap();
+        assert!(index > requested);
+        assert!((requested + 1..index).all(|i| vk.address(to_diversifier_index(i)).is_none()));
+        let Some(Address::Unified(ua)) = Address::decode(&NETWORK, &address) else {
+            panic!("{address} is not a unified address");
+        };
+        assert_eq!(
+            ua.sapling(),
+            vk.address(to_diversifier_index(index)).as_ref()
+        );
+        assert!(ua.orchard().is_some());
+    }
+}
@@ -19,7 +19,7 @@ use crate::{
         account::get_account_info,
         contacts::{get_unsaved_contacts, store_contact},
     },
-    pay::{make_payment, Payment, PaymentItem, UnsignedTransaction},
+    pay::{make_payment, Payment, PaymentItem, PaymentOptions, UnsignedTransaction},
     types::{CheckpointHeight, PoolMask},
     warp::legacy::CommitmentTreeFrontier,
 };
@@ -103,6 +103,12 @@ pub fn commit_unsaved_contacts(
         true,
         s,
         o,
+        // the memos go to our own address, and may repeat
+        &PaymentOptions {
+            force: true,
+            allow_self: true,
+            ..PaymentOptions::default()
+        },
     )?;
     Ok(utx)
 }
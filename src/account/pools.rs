@@ -4,9 +4,13 @@ use rusqlite::Connection;
 use zcash_primitives::{consensus::Network, memo::MemoBytes};
 
 use crate::{
-    db::{account::get_account_info, notes::snap_to_checkpoint},
+    db::{
+        account::get_account_info,
+        notes::{list_utxos, snap_to_checkpoint},
+    },
     keys::TSKStore,
-    pay::{Payment, PaymentBuilder, PaymentItem},
+    pay::{Payment, PaymentBuilder, PaymentItem, SignedTransaction, UnsignedTransaction},
+    types::{CheckpointHeight, PoolMask},
     warp::legacy::CommitmentTreeFrontier, EXPIRATION_HEIGHT_DELTA,
 };
 
@@ -24,9 +28,50 @@ pub fn transfer_pools<R: RngCore + CryptoRng>(
     s: &CommitmentTreeFrontier,
     o: &CommitmentTreeFrontier,
     rng: R,
-) -> Result<Vec<u8>> {
+) -> Result<SignedTransaction> {
+    let confirmation_height =
+        snap_to_checkpoint(connection, (height + 1).saturating_sub(confirmations))?;
+    let utx = prepare_transfer_pools(
+        network,
+        connection,
+        account,
+        confirmation_height,
+        from_pool,
+        to_pool,
+        amount,
+        memo,
+        split_amount,
+        s,
+        o,
+    )?;
+    let tx = utx.build(network, connection, height + EXPIRATION_HEIGHT_DELTA, &mut TSKStore::default(), rng)?;
+    Ok(tx)
+}
+
+/// Unsigned transaction that moves `amount` from the pool `from_pool`
+/// to the address of the account in `to_pool`, in outputs of at most
+/// `split_amount` (0 for a single output). Pools are 0, 1 or 2 for
+/// transparent, sapling and orchard
+pub fn prepare_transfer_pools(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    cp_height: CheckpointHeight,
+    from_pool: u8,
+    to_pool: u8,
+    mut amount: u64,
+    memo: Option<MemoBytes>,
+    split_amount: u64,
+    s: &CommitmentTreeFrontier,
+    o: &CommitmentTreeFrontier,
+) -> Result<UnsignedTransaction> {
+    if from_pool > 2 || to_pool > 2 {
+        anyhow::bail!("Invalid pool, must be 0, 1 or 2");
+    }
     let ai = get_account_info(network, connection, account)?;
-    let to_address = ai.to_address(network, Some(to_pool).into()).unwrap();
+    let to_address = ai
+        .to_address(network, Some(to_pool).into())
+        .ok_or(anyhow::anyhow!("Account {account} has no address in pool {to_pool}"))?;
     let split_amount = if split_amount == 0 {
         amount
     } else {
@@ -44,12 +89,11 @@ pub fn transfer_pools<R: RngCore + CryptoRng>(
         amount -= a;
     }
     let payment = Payment { recipients };
-    let confirmation_height = snap_to_checkpoint(connection, height - confirmations + 1)?;
     let mut builder = PaymentBuilder::new(
         network,
         connection,
         account,
-        confirmation_height,
+        cp_height,
         payment,
         Some(from_pool).into(),
         &s,
@@ -59,6 +103,176 @@ pub fn transfer_pools<R: RngCore + CryptoRng>(
     builder.set_use_change(true)?;
     let utx = builder.prepare()?;
     let utx = builder.finalize(utx)?;
-    let tx = utx.build(network, connection, height + EXPIRATION_HEIGHT_DELTA, &mut TSKStore::default(), rng)?;
-    Ok(tx)
+    Ok(utx)
+}
+
+/// Unsigned transaction that shields the transparent funds of the
+/// account into `to_pool` (1: sapling, 2: orchard), except for at least
+/// `keep_transparent` that stay in the transparent pool.
+/// UTXOs are not split, so the balance left behind is the target rounded
+/// up to whole UTXOs: while it is short of the target, the smallest UTXO
+/// that covers the rest is kept, or the largest one when none does.
+/// The fee is taken from the shielded amount
+pub fn prepare_shield(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    cp_height: CheckpointHeight,
+    to_pool: u8,
+    keep_transparent: u64,
+    s: &CommitmentTreeFrontier,
+    o: &CommitmentTreeFrontier,
+) -> Result<UnsignedTransaction> {
+    if to_pool != 1 && to_pool != 2 {
+        anyhow::bail!("Invalid pool, must be 1 or 2");
+    }
+    let ai = get_account_info(network, connection, account)?;
+    let to_address = ai
+        .to_address(network, Some(to_pool).into())
+        .ok_or(anyhow::anyhow!("Account {account} has no address in pool {to_pool}"))?;
+    let mut utxos = list_utxos(connection, cp_height)?
+        .into_iter()
+        .filter(|u| u.account == account)
+        .collect::<Vec<_>>();
+    utxos.sort_by_key(|u| u.value);
+    let mut kept = 0;
+    while kept < keep_transparent && !utxos.is_empty() {
+        let rest = keep_transparent - kept;
+        let i = utxos
+            .iter()
+            .position(|u| u.value >= rest)
+            .unwrap_or(utxos.len() - 1);
+        kept += utxos.remove(i).value;
+    }
+    let amount = utxos.iter().map(|u| u.value).sum::<u64>();
+    if amount == 0 {
+        anyhow::bail!("No transparent funds to shield above {keep_transparent}");
+    }
+    tracing::info!("Shielding {amount}, keeping {kept} transparent");
+
+    let payment = Payment {
+        recipients: vec![PaymentItem {
+            address: to_address,
+            amount,
+            memo: None,
+        }],
+    };
+    let mut builder = PaymentBuilder::new(
+        network,
+        connection,
+        account,
+        cp_height,
+        payment,
+        PoolMask(1),
+        s,
+        o,
+    )?;
+    builder.add_utxos(&utxos)?;
+    builder.set_use_change(false)?;
+    let mut utx = builder.prepare()?;
+    let change = utx.change;
+    utx.add_to_change(-change)?;
+    let utx = builder.finalize(utx)?;
+    Ok(utx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prepare_shield, prepare_transfer_pools};
+    use crate::{
+        db::testing::{add_note, add_utxo, test_account, test_db, TEST_BIRTH},
+        pay::{InputNote, OutputNote},
+        types::CheckpointHeight,
+        warp::legacy::CommitmentTreeFrontier,
+    };
+    use zcash_primitives::consensus::Network;
+
+    const NETWORK: Network = Network::MainNetwork;
+    const CP_HEIGHT: CheckpointHeight = CheckpointHeight(TEST_BIRTH + 100);
+
+    #[test]
+    fn sapling_to_orchard_transfer() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_note(&connection, account, CP_HEIGHT.0, 10_000_000, false);
+        let tree = CommitmentTreeFrontier::default();
+        let transfer = |to_pool: u8| {
+            prepare_transfer_pools(
+                &NETWORK,
+                &connection,
+                account,
+                CP_HEIGHT,
+                1,
+                to_pool,
+                3_000_000,
+                None,
+                2_000_000,
+                &tree,
+                &tree,
+            )
+        };
+        let utx = transfer(2).unwrap();
+        assert!(utx
+            .tx_notes
+            .iter()
+            .all(|n| matches!(n.note, InputNote::Sapling { .. })));
+        let outputs = utx
+            .tx_outputs
+            .iter()
+            .filter(|o| !o.change)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outputs.iter().map(|o| o.amount).collect::<Vec<_>>(),
+            vec![2_000_000, 1_000_000]
+        );
+        assert!(outputs
+            .iter()
+            .all(|o| matches!(o.note, OutputNote::Orchard { .. })));
+
+        assert!(transfer(3).is_err());
+    }
+
+    #[test]
+    fn shield_keeping_a_transparent_balance() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        for value in [1_000_000, 2_000_000, 5_000_000] {
+            add_utxo(&connection, account, TEST_BIRTH + 10, value);
+        }
+        let tree = CommitmentTreeFrontier::default();
+        let shield = |keep_transparent: u64| {
+            prepare_shield(
+                &NETWORK,
+                &connection,
+                account,
+                CP_HEIGHT,
+                2,
+                keep_transparent,
+                &tree,
+                &tree,
+            )
+        };
+        let shielded = |keep_transparent: u64| {
+            let utx = shield(keep_transparent).unwrap();
+            assert!(utx
+                .tx_notes
+                .iter()
+                .all(|n| matches!(n.note, InputNote::Transparent { .. })));
+            assert_eq!(utx.tx_outputs.len(), 1);
+            let output = &utx.tx_outputs[0];
+            assert!(matches!(output.note, OutputNote::Orchard { .. }));
+            let amount = utx.tx_notes.iter().map(|n| n.amount).sum::<u64>();
+            // the fee comes out of the shielded amount
+            assert!(output.amount < amount);
+            amount
+        };
+        // 2 ZEC is the smallest utxo that covers 1.5 ZEC
+        assert_eq!(shielded(1_500_000), 6_000_000);
+        // no utxo is 2.5 ZEC, 5 ZEC stay transparent
+        assert_eq!(shielded(2_500_000), 3_000_000);
+        assert_eq!(shielded(3_000_000), 3_000_000);
+        assert_eq!(shielded(0), 8_000_000);
+        // everything is kept
+        assert!(shield(8_000_000).is_err());
+    }
 }
@@ -1,8 +1,15 @@
 use crate::{
     data::fb::TransactionInfoT,
-    db::{contacts::list_contacts, tx::list_txs},
+    db::{
+        contacts::list_contacts,
+        tx::{for_each_tx, list_txs, list_txs_filtered, list_txs_paged, TxFilter},
+    },
+    types::Contact,
+    warp::sync::ExtendedReceivedTx,
     utils::to_txid_str,
 };
+use std::{io::Write, str::FromStr};
+
 use anyhow::Result;
 use rusqlite::Connection;
 use zcash_primitives::consensus::Network;
@@ -10,6 +17,27 @@ use zcash_keys::address::Address as RecipientAddress;
 
 use super::contacts::recipient_contains;
 
+/// Confirmations after which a transaction is considered final
+pub const FINAL_CONFIRMATIONS: u32 = 10;
+
+/// Number of blocks from the block that mined the transaction
+/// to the tip, both included. 0 if the transaction is not mined
+pub fn confirmations(bc_height: u32, height: u32) -> u32 {
+    if height == 0 || height > bc_height {
+        0
+    } else {
+        bc_height - height + 1
+    }
+}
+
+pub fn tx_status(confirmations: u32) -> &'static str {
+    match confirmations {
+        0 => "pending",
+        c if c < FINAL_CONFIRMATIONS => "confirmed",
+        _ => "final",
+    }
+}
+
 pub fn get_txs(
     network: &Network,
     connection: &Connection,
@@ -17,31 +45,195 @@ pub fn get_txs(
     bc_height: u32,
 ) -> Result<Vec<TransactionInfoT>> {
     let txs = list_txs(connection, account)?;
+    to_transaction_infos(network, connection, txs, bc_height)
+}
+
+/// Same as `get_txs` but only returns `limit` transactions
+/// starting at `offset`, with the total number of transactions
+pub fn get_txs_paged(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    bc_height: u32,
+    offset: u32,
+    limit: u32,
+) -> Result<(Vec<TransactionInfoT>, usize)> {
+    let (txs, total) = list_txs_paged(connection, account, offset, limit)?;
+    let tis = to_transaction_infos(network, connection, txs, bc_height)?;
+    Ok((tis, total))
+}
+
+pub fn get_txs_filtered(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    bc_height: u32,
+    filter: &TxFilter,
+    offset: u32,
+    limit: u32,
+) -> Result<(Vec<TransactionInfoT>, usize)> {
+    let (txs, total) = list_txs_filtered(connection, account, filter, offset, limit)?;
+    let tis = to_transaction_infos(network, connection, txs, bc_height)?;
+    Ok((tis, total))
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => anyhow::bail!("Invalid format {s} (csv or json)"),
+        }
+    }
+}
+
+/// Write the transaction history of the account to `w`, latest first.
+/// Transactions are streamed from the database and written one by one.
+/// Returns the number of transactions
+pub fn export_txs<W: Write>(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    bc_height: u32,
+    format: ExportFormat,
+    w: &mut W,
+) -> Result<u32> {
     let contacts = list_contacts(network, connection)?;
-    let mut tis = vec![];
-    for ertx in txs {
-        let rtx = &ertx.rtx;
-        let mut contact = None;
-        if let Some(tx_address) = &ertx.address {
-            let tx_address = RecipientAddress::decode(network, tx_address).unwrap();
-            for c in contacts.iter() {
-                if recipient_contains(&c.address, &tx_address)? {
-                    contact = c.card.name.clone();
-                }
+    if format == ExportFormat::Csv {
+        writeln!(
+            w,
+            "txid,height,timestamp,amount,value_in,value_out,address,contact,memo,status"
+        )?;
+    }
+    let mut count = 0;
+    for_each_tx(connection, account, bc_height, |ertx| {
+        let ti = to_transaction_info(network, &contacts, ertx, bc_height)?;
+        match format {
+            ExportFormat::Csv => writeln!(
+                w,
+                "{},{},{},{},{},{},{},{},{},{}",
+                ti.txid.unwrap_or_default(),
+                ti.height,
+                ti.timestamp,
+                ti.amount,
+                ti.value_in,
+                ti.value_out,
+                csv_field(ti.address.as_deref()),
+                csv_field(ti.contact.as_deref()),
+                csv_field(ti.memo.as_deref()),
+                ti.status.unwrap_or_default(),
+            )?,
+            ExportFormat::Json => {
+                serde_json::to_writer(&mut *w, &ti)?;
+                writeln!(w)?;
             }
         }
-        let ti = TransactionInfoT {
-            id: rtx.id,
-            txid: Some(to_txid_str(&rtx.txid)),
-            height: rtx.height,
-            confirmations: bc_height - rtx.height + 1,
-            timestamp: rtx.timestamp,
-            amount: rtx.value,
-            address: ertx.address,
-            contact,
-            memo: ertx.memo,
-        };
-        tis.push(ti);
+        count += 1;
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// Quote a CSV field, doubling the quotes it contains
+fn csv_field(s: Option<&str>) -> String {
+    format!("\"{}\"", s.unwrap_or_default().replace('"', "\"\""))
+}
+
+fn to_transaction_infos(
+    network: &Network,
+    connection: &Connection,
+    txs: Vec<ExtendedReceivedTx>,
+    bc_height: u32,
+) -> Result<Vec<TransactionInfoT>> {
+    let contacts = list_contacts(network, connection)?;
+    txs.into_iter()
+        .map(|ertx| to_transaction_info(network, &contacts, ertx, bc_height))
+        .collect()
+}
+
+fn to_transaction_info(
+    network: &Network,
+    contacts: &[Contact],
+    ertx: ExtendedReceivedTx,
+    bc_height: u32,
+) -> Result<TransactionInfoT> {
+    let rtx = &ertx.rtx;
+    let mut contact = None;
+    if let Some(tx_address) = &ertx.address {
+        let tx_address = RecipientAddress::decode(network, tx_address).unwrap();
+        for c in contacts.iter() {
+            if recipient_contains(&c.address, &tx_address)? {
+                contact = c.card.name.clone();
+            }
+        }
+    }
+    let confirmations = confirmations(bc_height, rtx.height);
+    // the value is net, what we spent is what we received minus it
+    let value_in = ertx.value_in;
+    let value_out = (value_in as i64 - rtx.value).max(0) as u64;
+    let ti = TransactionInfoT {
+        id: rtx.id,
+        txid: Some(to_txid_str(&rtx.txid)),
+        height: rtx.height,
+        confirmations,
+        timestamp: rtx.timestamp,
+        amount: rtx.value,
+        address: ertx.address,
+        contact,
+        memo: ertx.memo,
+        status: Some(tx_status(confirmations).to_string()),
+        self_transfer: ertx.self_transfer,
+        value_in,
+        value_out,
+    };
+    Ok(ti)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{confirmations, get_txs, tx_status};
+    use crate::db::testing::test_db;
+    use rusqlite::params;
+    use zcash_primitives::consensus::Network;
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    #[test]
+    fn confirmations_from_the_tip() {
+        let connection = test_db();
+        for height in [0, 2_000_100, 2_000_080] {
+            connection
+                .execute(
+                    "INSERT INTO txs(account, txid, height, timestamp, value)
+                    VALUES (1, randomblob(32), ?1, 0, 1000)",
+                    params![height],
+                )
+                .unwrap();
+        }
+        let txs = get_txs(&NETWORK, &connection, 1, 2_000_100).unwrap();
+        let status = txs
+            .iter()
+            .map(|tx| (tx.height, tx.confirmations, tx.status.clone().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            status,
+            vec![
+                (2_000_100, 1, "confirmed".to_string()),
+                (2_000_080, 21, "final".to_string()),
+                (0, 0, "pending".to_string()),
+            ]
+        );
+        assert_eq!(confirmations(2_000_100, 2_000_101), 0);
+        assert_eq!(tx_status(9), "confirmed");
+        assert_eq!(tx_status(10), "final");
     }
-    Ok(tis)
 }
@@ -1,12 +1,18 @@
 use std::{
+    io::{BufRead as _, IsTerminal as _, Write as _},
+    path::PathBuf,
     str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use clap_repl::{
-    reedline::{DefaultPrompt, DefaultPromptSegment, FileBackedHistory},
+    reedline::{
+        DefaultPrompt, DefaultPromptSegment, FileBackedHistory, History, HistoryItem,
+        HistoryItemId, HistorySessionId, SearchQuery,
+    },
     ClapEditor,
 };
 use console::style;
@@ -18,48 +24,83 @@ use flatbuffers::{FlatBufferBuilder, WIPOffset};
 use parking_lot::Mutex;
 use rand::rngs::OsRng;
 use rusqlite::{Connection, DropBehavior};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing_subscriber::{fmt, layer::SubscriberExt as _, util::SubscriberInitExt as _, EnvFilter};
+use zcash_client_backend::encoding::AddressCodec as _;
 use zcash_keys::address::Address as RecipientAddress;
-use zcash_protocol::consensus::{Network, NetworkUpgrade, Parameters};
+use zcash_primitives::{memo::MemoBytes, transaction::Transaction};
+use zcash_protocol::{
+    consensus::{BranchId, Network, NetworkUpgrade, Parameters},
+    memo::Memo,
+};
 
 use crate::{
     account::{
-        address::get_diversified_address,
+        address::{derivation_report, get_diversified_address, get_receiving_address},
         contacts::{add_contact, commit_unsaved_contacts},
-        txs::get_txs,
+        pools::{prepare_shield, prepare_transfer_pools},
+        export_transparent_key,
+        txs::{export_txs, get_txs, get_txs_filtered, ExportFormat},
     },
     coin::CoinDef,
-    data::fb::{PaymentRequestT, ShieldedNote, TransactionInfo},
+    data::fb::{BackupT, PaymentRequestT, ShieldedNote, TransactionInfo},
     db::{
-        account::{get_account_info, get_balance, list_accounts},
+        account::{
+            account_exists, address_totals, detect_address_reuse, get_account_info, get_balance,
+            get_balance_at, has_accounts, list_accounts_sorted, AccountSort,
+        },
         account_manager::{
-            create_new_account, delete_account, detect_key, edit_account_birth, edit_account_name,
-            get_min_birth,
+            create_account_from_backup, create_new_account, delete_account, detect_key, edit_account_birth, edit_account_name,
+            freeze_account, get_min_birth, set_default_memo, set_rotate_on_receive,
         },
         contacts::{delete_contact, edit_contact_address, edit_contact_name, list_contacts},
         notes::{
-            get_sync_height, get_txid, get_unspent_notes, snap_to_checkpoint, store_block, store_tx_details, truncate_scan
+            checkpoint_anchor, get_scanned_range, get_sync_block_hash, get_sync_height, get_txid,
+            get_unspent_notes, has_activity_before, list_checkpoints, list_unspent_nullifiers, snap_to_checkpoint, store_block, store_tx_details, truncate_scan,
+            verify_block_continuity,
+        },
+        pending::{
+            delete_broadcast, delete_pending_tx, get_latest_pending_tx, get_pending_tx,
+            list_pending_txs, list_unmined_broadcasts, store_broadcast, store_pending_tx, PendingTx,
         },
         reset_tables,
-        tx::{get_tx_details, list_messages},
+        tx::{
+            get_raw_tx, get_tx_details, list_messages, list_pending, store_raw_tx, TxDirection,
+            TxFilter,
+        },
     },
     fb_vec_to_bytes,
     keys::{generate_random_mnemonic_phrase, TSKStore},
-    lwd::{broadcast, get_compact_block, get_last_height, get_transaction, get_tree_state},
+    lwd::{
+        broadcast, get_compact_block, get_last_height, get_server_bounds, get_transaction,
+        BroadcastResult,
+    },
     pay::{
-        make_payment,
-        sweep::{prepare_sweep, scan_utxo_by_seed},
-        Payment, PaymentItem, UnsignedTransaction,
+        self, estimate_fee, expiry_height, make_payment,
+        sweep::{prepare_sweep, scan_utxo_by_seed, sweep_seed},
+        FeeStrategy, Payment, PaymentItem, PaymentOptions, UnsignedTransaction, MIN_PADDING,
+    },
+    txdetails::{
+        analyze_raw_transaction, check_payment_proof, decode_raw_transaction, decode_tx_details,
+        export_payment_proof, retrieve_tx_details, PaymentProof,
     },
-    txdetails::{analyze_raw_transaction, decode_tx_details, retrieve_tx_details},
     types::{CheckpointHeight, PoolMask},
     utils::{
         db::encrypt_db,
+        to_txid_str,
         ua::decode_ua,
-        uri::{make_payment_uri, parse_payment_uri},
+        uri::{make_payment_uri, parse_payment_uri, parse_payment_uri_details, UriPayment},
+    },
+    warp::{
+        hasher::{OrchardHasher, SaplingHasher},
+        import::{import_notes, ImportCheckpoint, ImportedNote},
+        snapshot::{export_state, import_state},
+        sync::{simulate_sync, warp_sync, SyncError, SyncStats},
+        verify::audit_notes,
+        BlockHeader,
     },
-    warp::{sync::warp_sync, BlockHeader},
-    EXPIRATION_HEIGHT_DELTA,
+    Hash,
 };
 
 #[derive(Deserialize)]
@@ -70,6 +111,131 @@ pub struct Config {
     pub warp_end_height: u32,
     pub seed: String,
     pub confirmations: u32,
+    #[serde(default)]
+    pub json: bool,
+    pub history_path: Option<String>,
+    pub history_size: Option<usize>,
+    pub log_level: Option<String>,
+    pub log_file: Option<String>,
+    pub checkpoint_interval: Option<u32>,
+    /// Concurrent block requests of the sync, 1 fetches the blocks
+    /// with a single request
+    pub block_fetch_concurrency: Option<usize>,
+    /// Ignore received shielded notes below this value (zats)
+    pub min_note_value: Option<u64>,
+    /// Accept 0 confirmations, for regtest where blocks are mined
+    /// on demand. Notes of the last block become spendable right away
+    /// and are lost if that block is reorged: never set it on mainnet
+    #[serde(default)]
+    pub allow_unconfirmed: bool,
+}
+
+/// End of the next sync step. A checkpoint is created at every multiple
+/// of the checkpoint interval and at the final height (tip minus confirmations)
+fn next_checkpoint(start_height: u32, end_height: u32, interval: u32) -> u32 {
+    let interval = interval.max(1);
+    ((start_height / interval + 1) * interval).min(end_height)
+}
+
+/// Commands that carry secrets and never go into the history file
+const SENSITIVE_COMMANDS: &[&str] = &[
+    "set-db-password",
+    "encrypt-db",
+    "account create",
+    "backup",
+    "generate-seed",
+    "sweep-seed",
+];
+
+fn is_sensitive(line: &str) -> bool {
+    let words = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    SENSITIVE_COMMANDS
+        .iter()
+        .any(|c| words == *c || words.starts_with(&format!("{c} ")))
+}
+
+/// A file backed history that drops sensitive commands
+struct FilteredHistory(FileBackedHistory);
+
+impl History for FilteredHistory {
+    fn save(&mut self, h: HistoryItem) -> clap_repl::reedline::Result<HistoryItem> {
+        if is_sensitive(&h.command_line) {
+            return Ok(h);
+        }
+        self.0.save(h)
+    }
+
+    fn load(&self, id: HistoryItemId) -> clap_repl::reedline::Result<HistoryItem> {
+        self.0.load(id)
+    }
+
+    fn count(&self, query: SearchQuery) -> clap_repl::reedline::Result<i64> {
+        self.0.count(query)
+    }
+
+    fn search(&self, query: SearchQuery) -> clap_repl::reedline::Result<Vec<HistoryItem>> {
+        self.0.search(query)
+    }
+
+    fn update(
+        &mut self,
+        id: HistoryItemId,
+        updater: &dyn Fn(HistoryItem) -> HistoryItem,
+    ) -> clap_repl::reedline::Result<()> {
+        self.0.update(id, updater)
+    }
+
+    fn clear(&mut self) -> clap_repl::reedline::Result<()> {
+        self.0.clear()
+    }
+
+    fn delete(&mut self, h: HistoryItemId) -> clap_repl::reedline::Result<()> {
+        self.0.delete(h)
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.0.sync()
+    }
+
+    fn session(&self) -> Option<HistorySessionId> {
+        self.0.session()
+    }
+}
+
+/// Location of the REPL history: the configured path or
+/// a file in the per-user XDG data directory
+fn history_path(configured: Option<&str>) -> Result<PathBuf> {
+    if let Some(path) = configured {
+        return Ok(PathBuf::from(path));
+    }
+    let data_dir = match std::env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = std::env::var("HOME")
+                .map_err(|_| anyhow::anyhow!("Cannot locate the home directory"))?;
+            PathBuf::from(home).join(".local").join("share")
+        }
+    };
+    Ok(data_dir.join("zcash-warp").join("history"))
+}
+
+/// Create the history file, readable by the user only
+fn create_history_file(path: PathBuf) -> Result<PathBuf> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt as _, PermissionsExt as _};
+        options.mode(0o600);
+        options.open(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    options.open(&path)?;
+    Ok(path)
 }
 
 #[derive(Parser, Clone, Debug)]
@@ -80,11 +246,20 @@ pub struct Account {
 
 #[derive(Subcommand, Clone, Debug)]
 pub enum AccountCommand {
-    List,
+    List {
+        /// id, name, birth or balance
+        #[arg(long)]
+        sort: Option<AccountSort>,
+        #[arg(long)]
+        desc: bool,
+    },
     Create {
         key: Option<String>,
         name: Option<String>,
         birth: Option<u32>,
+        /// Combine with the account that already has this key
+        #[arg(long)]
+        merge: bool,
     },
     EditName {
         account: u32,
@@ -94,6 +269,28 @@ pub enum AccountCommand {
         account: u32,
         birth: u32,
     },
+    /// Restore an account from the JSON output of Backup
+    Restore {
+        backup: String,
+    },
+    /// Memo used for shielded outputs without one; omit it to clear
+    SetDefaultMemo {
+        account: u32,
+        memo: Option<String>,
+    },
+    /// Give a new receiving address after each payment received
+    /// on the current one (1) or always the default address (0)
+    SetAddressRotation {
+        account: u32,
+        rotate: u8,
+    },
+    /// Refuse to build transactions from the account
+    Freeze {
+        account: u32,
+    },
+    Unfreeze {
+        account: u32,
+    },
     Delete {
         account: u32,
     },
@@ -148,50 +345,283 @@ pub enum Command {
     },
     LastHeight,
     SyncHeight,
+    /// Hash of the block at the sync height, to compare with an explorer
+    SyncHash,
+    /// Server reachability, sync lag, database integrity,
+    /// proving parameters and number of accounts
+    HealthCheck,
+    /// Check the stored checkpoints and compare their hashes with the server
+    VerifyChain,
+    /// Save the sync state to a file
+    ExportSyncState {
+        path: String,
+    },
+    /// Replace the sync state with a file saved by ExportSyncState
+    ImportSyncState {
+        path: String,
+    },
+    /// Import the unspent notes exported by another wallet instead of
+    /// scanning. The file has the checkpoint height and the notes with
+    /// their witnesses at that height
+    ImportNotes {
+        account: u32,
+        path: String,
+    },
     Reset {
         height: Option<u32>,
     },
     Sync {
         confirmations: Option<u32>,
     },
+    /// Sync up to the given height instead of the tip
+    SyncTo {
+        height: u32,
+        #[arg(long)]
+        confirmations: Option<u32>,
+    },
+    /// Retrieve the details of the transactions that have none,
+    /// for instance after failures during Sync
+    RetryTxDetails,
+    /// Scan a range without saving anything and list what it finds.
+    /// `from` is rounded down to a checkpoint
+    SimulateSync {
+        from: u32,
+        to: u32,
+    },
     Address {
         account: u32,
+        /// Pools of the receivers, 0 for every pool of the account
         mask: u8,
     },
     GetTx {
         account: u32,
         id: u32,
     },
+    /// Proof that the transaction paid its shielded recipients
+    ProvePayment {
+        id: u32,
+    },
+    /// Check a proof made by ProvePayment, given as JSON
+    CheckPaymentProof {
+        proof: String,
+    },
     Balance {
         account: u32,
     },
+    /// Balance of all pools at a past height
+    BalanceAt {
+        account: u32,
+        height: u32,
+    },
+    AddressTotals {
+        account: u32,
+    },
     GenDiversifiedAddress {
         account: u32,
         pools: u8,
     },
+    /// List the derived addresses of the account and their paths
+    DerivationReport {
+        account: u32,
+        #[arg(long, default_value_t = 20)]
+        transparent_gap: u32,
+        #[arg(long, default_value_t = 5)]
+        shielded_count: u32,
+    },
     Pay {
         account: u32,
         address: String,
+        /// In zats, `max` sends everything the account can spend
+        #[arg(value_parser = parse_amount)]
+        amount: u64,
+        /// Source pools, 0 selects them automatically
+        pools: u8,
+        fee_paid_by_sender: u8,
+        /// Split the amount into this many outputs
+        #[arg(long, default_value_t = 1)]
+        split: u32,
+        /// Identifies the payment, an identical payment built shortly
+        /// before with the same key is refused
+        #[arg(long)]
+        key: Option<String>,
+        /// Build the payment even if it looks like a duplicate
+        #[arg(long)]
+        force: bool,
+        /// Highest acceptable fee, defaults to a tenth of the amount
+        #[arg(long)]
+        max_fee: Option<u64>,
+        /// Anchor the payment at the checkpoint at this height
+        #[arg(long)]
+        anchor_height: Option<u32>,
+        /// Pay an address of one of our accounts
+        #[arg(long)]
+        allow_self: bool,
+        /// Height at which the transaction expires, 0 for never
+        #[arg(long)]
+        expiry_height: Option<u32>,
+        /// Text memo
+        #[arg(long, conflicts_with = "memo_hex")]
+        memo: Option<String>,
+        /// Memo given as up to 512 raw bytes in hex
+        #[arg(long)]
+        memo_hex: Option<String>,
+        /// Pools that pay, in order: 0 for T, 1 for S, 2 for O. Pools
+        /// not listed are not used
+        #[arg(long, value_delimiter = ',')]
+        source_priority: Option<Vec<u8>>,
+        /// zip317 (default), minimum or a fixed fee in zats
+        #[arg(long)]
+        fee_strategy: Option<FeeStrategy>,
+        /// Send the change to this address instead of the account
+        #[arg(long, requires = "change_pool")]
+        change_address: Option<String>,
+        /// Pool of the change address: 0 for T, 1 for S, 2 for O
+        #[arg(long, requires = "change_address")]
+        change_pool: Option<u8>,
+        /// Pad the Sapling outputs to this number, at least 2
+        #[arg(long)]
+        min_sapling_outputs: Option<u32>,
+        /// Pad the Orchard actions to this number, at least 2
+        #[arg(long)]
+        min_orchard_actions: Option<u32>,
+    },
+    EstimateFee {
+        account: u32,
+        address: String,
+        /// In zats or `max`, like `Pay`
+        #[arg(value_parser = parse_amount)]
         amount: u64,
         pools: u8,
+        /// 0 when the recipient pays the fee, like `Pay`
+        #[arg(long, default_value_t = 1)]
         fee_paid_by_sender: u8,
+        /// Like `Pay`
+        #[arg(long, value_delimiter = ',')]
+        source_priority: Option<Vec<u8>>,
+        #[arg(long)]
+        fee_strategy: Option<FeeStrategy>,
+        #[arg(long, requires = "change_pool")]
+        change_address: Option<String>,
+        #[arg(long, requires = "change_address")]
+        change_pool: Option<u8>,
+        #[arg(long)]
+        min_sapling_outputs: Option<u32>,
+        #[arg(long)]
+        min_orchard_actions: Option<u32>,
     },
     Sweep {
         account: u32,
         destination_address: String,
+        /// Overrides the confirmations of the configuration
+        #[arg(long)]
+        confirmations: Option<u32>,
+    },
+    /// Shield the transparent funds of the account
+    Shield {
+        account: u32,
+        /// 1 (sapling) or 2 (orchard)
+        #[arg(long, default_value_t = 2)]
+        to_pool: u8,
+        /// Transparent balance to keep, rounded up to whole UTXOs
+        #[arg(long, default_value_t = 0)]
+        keep_transparent: u64,
+        /// Overrides the confirmations of the configuration
+        #[arg(long)]
+        confirmations: Option<u32>,
+    },
+    /// Move funds between the pools of the account.
+    /// Pools are 0 (transparent), 1 (sapling) or 2 (orchard)
+    TransferPools {
+        account: u32,
+        from_pool: u8,
+        to_pool: u8,
+        amount: u64,
+        /// Largest output, 0 for a single output
+        #[arg(long, default_value_t = 0)]
+        split_amount: u64,
+        /// Overrides the confirmations of the configuration
+        #[arg(long)]
+        confirmations: Option<u32>,
+    },
+    /// Sweep the transparent funds of several accounts of a seed.
+    /// The transaction is made by the wallet account `account`
+    SweepSeed {
+        account: u32,
+        seed: String,
+        destination_address: String,
+        /// Number of account indices to scan, from 0
+        #[arg(long, default_value_t = 5)]
+        accounts: u32,
+        #[arg(long, default_value_t = 20)]
+        gap_limit: usize,
     },
     GetTxDetails {
         id: u32,
     },
+    GetRawTx {
+        id: u32,
+    },
+    AnalyzeTx {
+        account: u32,
+        hex: String,
+        /// Height used to select the consensus branch. Defaults to the sync height
+        #[arg(long)]
+        height: Option<u32>,
+    },
     DecodeAddress {
         address: String,
     },
+    /// Check a signed transaction, given in hex or as the id of
+    /// a pending transaction, without broadcasting it
+    ValidateTx {
+        #[arg(long, conflicts_with = "id")]
+        hex: Option<String>,
+        #[arg(long)]
+        id: Option<u32>,
+    },
     ListTxs {
         account: u32,
+        #[arg(long)]
+        offset: Option<u32>,
+        #[arg(long)]
+        limit: Option<u32>,
+        #[arg(long)]
+        from_height: Option<u32>,
+        #[arg(long)]
+        to_height: Option<u32>,
+        #[arg(long)]
+        from_time: Option<u32>,
+        #[arg(long)]
+        to_time: Option<u32>,
+        /// sent, received or self
+        #[arg(long)]
+        direction: Option<TxDirection>,
+    },
+    /// Transactions broadcast by the wallet that are not mined yet
+    ListPendingTxs {
+        account: u32,
+    },
+    /// Send again the transactions of ListPendingTxs, in case they were
+    /// dropped from the mempool. Expired transactions are removed
+    RebroadcastPending {
+        account: u32,
+    },
+    /// Write the transaction history to a file
+    ExportTxs {
+        account: u32,
+        path: String,
+        /// csv or json (one transaction per line)
+        #[arg(long, default_value = "csv")]
+        format: ExportFormat,
     },
     ListNotes {
         account: u32,
     },
+    /// Nullifiers of the unspent notes, to monitor their spends.
+    /// Anyone who has them can link the spends to the wallet
+    ListNullifiers {
+        account: u32,
+    },
     ListMessages {
         account: u32,
     },
@@ -201,15 +631,262 @@ pub enum Command {
     MakePaymentURI {
         recipients: Vec<PaymentRequestT>,
     },
+    /// Show the recipients of a payment URI without paying it
+    DecodePaymentURI {
+        uri: String,
+    },
     PayPaymentUri {
         account: u32,
         uri: String,
+        /// Build the payment even if it looks like a duplicate
+        #[arg(long)]
+        force: bool,
+        /// Highest acceptable fee, defaults to a tenth of the amount
+        #[arg(long)]
+        max_fee: Option<u64>,
+        /// Anchor the payment at the checkpoint at this height
+        #[arg(long)]
+        anchor_height: Option<u32>,
+        /// Pay an address of one of our accounts
+        #[arg(long)]
+        allow_self: bool,
     },
     BroadcastLatest {
         clear: Option<u8>,
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Show the secret key of the transparent address in WIF
+    ExportTKey {
+        account: u32,
+        /// Do not ask for a confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    ShowPending,
+    DataUsage,
+    AuditNotes {
+        account: u32,
+    },
+    ListPending,
+    BroadcastPending {
+        id: u32,
+        #[arg(long)]
+        yes: bool,
+    },
+    DropPending {
+        id: u32,
     },
 }
 
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+/// Fields of the JSON document of the running command
+static DOCUMENT: Mutex<Option<serde_json::Map<String, Value>>> = Mutex::new(None);
+
+fn json_mode() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Add a value to a JSON document: the fields of an object
+/// are merged in, any other value goes under `result`
+fn merge_output(document: &mut serde_json::Map<String, Value>, value: Value) {
+    match value {
+        Value::Object(o) => document.extend(o),
+        value => {
+            document.insert("result".to_string(), value);
+        }
+    }
+}
+
+/// Print the result of a command: in json mode, the value is added
+/// to the document printed when the command ends,
+/// otherwise the human readable text is printed
+fn output(value: Value, text: impl FnOnce() -> String) {
+    if json_mode() {
+        merge_output(DOCUMENT.lock().get_or_insert_with(Default::default), value);
+    } else {
+        println!("{}", text());
+    }
+}
+
+/// Print a serializable value, pretty in text mode
+/// and under `key` in the document in json mode
+fn output_json<T: Serialize + ?Sized>(key: &str, value: &T) -> Result<()> {
+    if json_mode() {
+        let value = serde_json::to_value(value)?;
+        DOCUMENT
+            .lock()
+            .get_or_insert_with(Default::default)
+            .insert(key.to_string(), value);
+    } else {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    }
+    Ok(())
+}
+
+/// Report the progress of a long running command, on stderr in json mode
+/// so that stdout only has the document of the command
+fn output_progress(value: Value, text: impl FnOnce() -> String) {
+    if json_mode() {
+        eprintln!("{}", value);
+    } else {
+        println!("{}", text());
+    }
+}
+
+/// Print the JSON document of a command that succeeded,
+/// `{"ok": true}` if it had no output
+fn flush_output() {
+    let document = DOCUMENT.lock().take();
+    if json_mode() {
+        let document = document.unwrap_or_else(|| {
+            let mut o = serde_json::Map::new();
+            o.insert("ok".to_string(), Value::Bool(true));
+            o
+        });
+        println!("{}", Value::Object(document));
+    }
+}
+
+/// Drop the output of a command that failed, and print the error
+fn output_error(e: &anyhow::Error) {
+    DOCUMENT.lock().take();
+    if json_mode() {
+        eprintln!("{}", json!({"error": e.to_string()}));
+    } else {
+        println!("{} {}", style("Error:").red().bold(), e);
+    }
+}
+
+/// Process exit code for the category of an error
+/// returned in batch mode
+fn exit_code(e: &anyhow::Error) -> i32 {
+    if e.downcast_ref::<clap::Error>().is_some() {
+        2
+    } else if e.downcast_ref::<pay::Error>().is_some() {
+        3
+    } else if e.downcast_ref::<SyncError>().is_some() {
+        4
+    } else if e.downcast_ref::<tonic::Status>().is_some()
+        || e.downcast_ref::<tonic::transport::Error>().is_some()
+    {
+        5
+    } else if e.downcast_ref::<rusqlite::Error>().is_some()
+        || e.downcast_ref::<r2d2::Error>().is_some()
+    {
+        6
+    } else {
+        1
+    }
+}
+
+/// Split a command line into arguments, honoring single and double quotes
+fn split_args(line: &str) -> Result<Vec<String>> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => arg.push(c),
+            None => match c {
+                '\'' | '"' => {
+                    quote = Some(c);
+                    in_arg = true;
+                }
+                c if c.is_whitespace() => {
+                    if in_arg {
+                        args.push(std::mem::take(&mut arg));
+                        in_arg = false;
+                    }
+                }
+                c => {
+                    arg.push(c);
+                    in_arg = true;
+                }
+            },
+        }
+    }
+    if quote.is_some() {
+        anyhow::bail!("Unterminated quote");
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// Run the commands read from stdin, one per line, and
+/// stop at the first error with an exit code that reflects its category
+fn run_batch(zec: &mut CoinDef, txbytes: &mut Vec<u8>) -> i32 {
+    for line in std::io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                let e = anyhow::Error::from(e);
+                output_error(&e);
+                return 1;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let r = split_args(line).and_then(|args| {
+            let command =
+                Command::try_parse_from(std::iter::once("zcash-warp".to_string()).chain(args))?;
+            process_command(command, zec, txbytes)
+        });
+        match r {
+            Ok(_) => flush_output(),
+            Err(e) => {
+                output_error(&e);
+                return exit_code(&e);
+            }
+        }
+    }
+    0
+}
+
+/// Run the command given on the process command line and exit.
+/// A transaction built by the command is either broadcast (with `--broadcast`)
+/// or printed in hex
+fn run_once(args: Vec<String>, broadcast: bool, zec: &mut CoinDef, txbytes: &mut Vec<u8>) -> i32 {
+    let r = Command::try_parse_from(std::iter::once("zcash-warp".to_string()).chain(args))
+        .map_err(anyhow::Error::from)
+        .and_then(|command| process_command(command, zec, txbytes))
+        .and_then(|_| {
+            if !txbytes.is_empty() {
+                if broadcast {
+                    process_command(
+                        Command::BroadcastLatest {
+                            clear: Some(1),
+                            yes: true,
+                        },
+                        zec,
+                        txbytes,
+                    )?;
+                } else {
+                    output(json!({"tx": hex::encode(&txbytes)}), || {
+                        hex::encode(&txbytes)
+                    });
+                }
+            }
+            Ok(())
+        });
+    match r {
+        Ok(_) => {
+            flush_output();
+            0
+        }
+        Err(e) => {
+            output_error(&e);
+            exit_code(&e)
+        }
+    }
+}
+
 impl FromStr for PaymentRequestT {
     type Err = serde_json::Error;
 
@@ -224,18 +901,421 @@ fn display_tx(
     cp_height: CheckpointHeight,
     unsigned_tx: UnsignedTransaction,
     tsk_store: &mut TSKStore,
+) -> Result<Vec<u8>> {
+    display_tx_with_expiry(network, connection, cp_height, unsigned_tx, tsk_store, None)
+}
+
+/// `display_tx` with a custom expiry height, see `pay::expiry_height`
+fn display_tx_with_expiry(
+    network: &Network,
+    connection: &Connection,
+    cp_height: CheckpointHeight,
+    unsigned_tx: UnsignedTransaction,
+    tsk_store: &mut TSKStore,
+    expiry: Option<u32>,
 ) -> Result<Vec<u8>> {
     let mut summary = unsigned_tx.to_summary()?;
     summary.detach();
-    println!("{}", serde_json::to_string_pretty(&summary).unwrap());
-    let txb = unsigned_tx.build(
+    let account = unsigned_tx.account;
+    let expiration_height = expiry_height(cp_height.0, expiry)?;
+    let tx = unsigned_tx.build(
         network,
         &connection,
-        cp_height.0 + EXPIRATION_HEIGHT_DELTA,
+        expiration_height,
         tsk_store,
         OsRng,
     )?;
-    Ok(txb)
+    summary.size = tx.data.len() as u32;
+    output_json("summary", &summary)?;
+    store_pending_tx(
+        connection,
+        account,
+        cp_height.0,
+        expiration_height,
+        &tx.txid,
+        &tx.data,
+        &serde_json::to_string(&summary)?,
+    )?;
+    Ok(tx.data)
+}
+
+/// Ask the user for a yes/no confirmation on the terminal.
+/// The prompt goes to stderr to keep stdout for the command output
+fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("{prompt} [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+/// Decode the raw bytes of a pending transaction back into a `Transaction`
+/// and summarize what it actually does
+fn decode_pending(network: &Network, pending: &PendingTx) -> Result<(Hash, Value)> {
+    let tx = Transaction::read(
+        &*pending.data,
+        BranchId::for_height(network, pending.height.into()),
+    )?;
+    let txid: Hash = *tx.txid().as_ref();
+    if txid != pending.txid {
+        anyhow::bail!("Pending transaction #{} does not match its txid", pending.id);
+    }
+    let expiry_height: u32 = tx.expiry_height().into();
+    if expiry_height != pending.expiration_height {
+        anyhow::bail!(
+            "Transaction expiry {} does not match the pending entry {}",
+            expiry_height,
+            pending.expiration_height
+        );
+    }
+    let transparent_outputs = tx
+        .transparent_bundle()
+        .map(|b| {
+            b.vout
+                .iter()
+                .map(|o| {
+                    json!({
+                        "address": o.recipient_address().map(|a| a.encode(network)),
+                        "value": u64::from(o.value),
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let sapling_outputs = tx
+        .sapling_bundle()
+        .map(|b| b.shielded_outputs().len())
+        .unwrap_or_default();
+    let orchard_actions = tx
+        .orchard_bundle()
+        .map(|b| b.actions().len())
+        .unwrap_or_default();
+    let summary: Value = serde_json::from_str(&pending.summary)?;
+    let decoded = json!({
+        "id": pending.id,
+        "txid": to_txid_str(&txid),
+        "expiry_height": expiry_height,
+        "transparent_outputs": transparent_outputs,
+        "sapling_outputs": sapling_outputs,
+        "orchard_actions": orchard_actions,
+        "recipients": summary["recipients"],
+        "fee": summary["fee"],
+    });
+    Ok((txid, decoded))
+}
+
+/// Blocks left before expiry under which `warn_blocks_left` warns
+const LOW_BLOCKS_LEFT: u32 = 10;
+
+/// Warn when a pending transaction is about to expire
+fn warn_blocks_left(pending: &PendingTx, bc_height: u32) {
+    if let Some(blocks_left) = pending.blocks_left(bc_height) {
+        if blocks_left <= LOW_BLOCKS_LEFT {
+            tracing::warn!(
+                "Pending transaction #{} anchored at {} expires in {blocks_left} blocks",
+                pending.id,
+                pending.height
+            );
+        }
+    }
+}
+
+/// Broadcast a pending transaction, unless it has expired,
+/// and move it to the broadcast history if the server accepts it.
+/// The decoded transaction is shown first, and in interactive mode
+/// the user must confirm unless `yes` is set
+async fn broadcast_pending(
+    zec: &CoinDef,
+    connection: &Connection,
+    pending: &PendingTx,
+    yes: bool,
+) -> Result<BroadcastResult> {
+    let mut client = zec.connect_lwd().await?;
+    let bc_height = get_last_height(&mut client).await?;
+    if pending.is_expired(bc_height) {
+        anyhow::bail!(
+            "Pending transaction expired at height {}, prepare it again",
+            pending.expiration_height
+        );
+    }
+    warn_blocks_left(pending, bc_height);
+    let (txid, decoded) = decode_pending(&zec.network, pending)?;
+    output_json("broadcast", &decoded)?;
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!("Confirmation required, use --yes");
+        }
+        if json_mode() {
+            // the document is only printed after the broadcast
+            eprintln!("{}", decoded);
+        }
+        if !confirm("Broadcast this transaction?")? {
+            anyhow::bail!("Broadcast cancelled");
+        }
+    }
+    let r = broadcast(&mut client, bc_height, &pending.data).await?;
+    if r.accepted {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        store_broadcast(connection, pending, &txid, bc_height, timestamp)?;
+    }
+    Ok(r)
+}
+
+/// Print the txid of an accepted transaction, fail with
+/// the server message otherwise
+fn output_broadcast(id: u32, r: &BroadcastResult) -> Result<()> {
+    if !r.accepted {
+        anyhow::bail!(
+            "Transaction #{id} rejected: {}",
+            r.error.as_deref().unwrap_or_default()
+        );
+    }
+    let txid = r.txid.as_ref().map(to_txid_str);
+    output(json!({"id": id, "txid": &txid}), || {
+        txid.clone().unwrap_or_default()
+    });
+    Ok(())
+}
+
+/// Fail with a clear message when the wallet has no account
+/// or not this one
+fn check_account(connection: &Connection, account: Option<u32>) -> Result<()> {
+    if !has_accounts(connection)? {
+        anyhow::bail!("No accounts; create one first");
+    }
+    if let Some(account) = account {
+        if !account_exists(connection, account)? {
+            anyhow::bail!("Account {account} does not exist");
+        }
+    }
+    Ok(())
+}
+
+/// Warn about the addresses of the account that received funds
+/// more than once, since payments to them can be linked
+fn warn_address_reuse(network: &Network, connection: &Connection, account: u32) -> Result<()> {
+    for (address, count) in detect_address_reuse(network, connection, account)? {
+        tracing::warn!("Address {address} received funds {count} times");
+    }
+    Ok(())
+}
+
+/// Amount in zats, or `max` / `rest` for the funds left after
+/// the other recipients and the fee (`pay::REMAINDER`)
+fn parse_amount(s: &str) -> std::result::Result<u64, String> {
+    match s {
+        "max" | "rest" => Ok(pay::REMAINDER),
+        _ => s
+            .parse::<u64>()
+            .map_err(|e| format!("{e}, use an amount in zats or max"))
+            .and_then(|a| {
+                if a == pay::REMAINDER {
+                    Err("Amount too large".to_string())
+                } else {
+                    Ok(a)
+                }
+            }),
+    }
+}
+
+/// `PaymentOptions` of the `Pay` and `EstimateFee` flags. Setting one
+/// of the padding minimums keeps the default for the other
+fn payment_options(
+    source_priority: Option<Vec<u8>>,
+    fee_strategy: Option<FeeStrategy>,
+    change_address: Option<String>,
+    change_pool: Option<u8>,
+    min_sapling_outputs: Option<u32>,
+    min_orchard_actions: Option<u32>,
+) -> PaymentOptions {
+    let padding = (min_sapling_outputs.is_some() || min_orchard_actions.is_some()).then(|| {
+        (
+            min_sapling_outputs.unwrap_or(MIN_PADDING),
+            min_orchard_actions.unwrap_or(MIN_PADDING),
+        )
+    });
+    PaymentOptions {
+        source_priority,
+        fee_strategy,
+        change_address: change_address.zip(change_pool),
+        padding,
+        ..PaymentOptions::default()
+    }
+}
+
+/// Confirmations counted from the tip, which has one. 0 is only
+/// accepted with `allow_unconfirmed` and is then the same as 1, since
+/// mempool transactions have no witness and cannot be spent
+fn checked_confirmations(confirmations: u32, allow_unconfirmed: bool) -> Result<u32> {
+    if confirmations == 0 {
+        if !allow_unconfirmed {
+            anyhow::bail!("# Confirmations must be > 0 unless allow_unconfirmed is set");
+        }
+        return Ok(1);
+    }
+    Ok(confirmations)
+}
+
+/// Checkpoint used as the anchor of payments, at least
+/// `CONFIG.confirmations` below the tip
+fn spend_anchor(connection: &Connection, bc_height: u32) -> Result<CheckpointHeight> {
+    spend_anchor_with(connection, bc_height, CONFIG.confirmations)
+}
+
+/// `spend_anchor` with the given number of confirmations
+fn spend_anchor_with(
+    connection: &Connection,
+    bc_height: u32,
+    confirmations: u32,
+) -> Result<CheckpointHeight> {
+    let confirmations = checked_confirmations(confirmations, CONFIG.allow_unconfirmed)?;
+    snap_to_checkpoint(connection, (bc_height + 1).saturating_sub(confirmations))
+}
+
+/// `spend_anchor` unless the checkpoint is given explicitly
+fn payment_anchor(
+    connection: &Connection,
+    account: u32,
+    bc_height: u32,
+    anchor_height: Option<u32>,
+) -> Result<CheckpointHeight> {
+    match anchor_height {
+        None => spend_anchor(connection, bc_height),
+        Some(height) => {
+            if height > bc_height {
+                anyhow::bail!("Anchor height {height} is above the tip {bc_height}");
+            }
+            checkpoint_anchor(connection, account, height)
+        }
+    }
+}
+
+/// Recipients of a payment URI, with the amounts in ZEC and the
+/// text memos decoded. Every address must be of the network
+fn decode_payment_uri(network: &Network, uri: &str) -> Result<Vec<Value>> {
+    let recipients = parse_payment_uri_details(network, uri)?;
+    for r in recipients.iter() {
+        if RecipientAddress::decode(network, &r.item.address).is_none() {
+            anyhow::bail!("Invalid Address {}", r.item.address);
+        }
+    }
+    let recipients = recipients
+        .iter()
+        .map(|r| {
+            let memo = r
+                .item
+                .memo
+                .as_ref()
+                .map(|m| match Memo::try_from(m.clone()) {
+                    Ok(Memo::Text(text)) => text.to_string(),
+                    _ => hex::encode(m.as_slice()),
+                });
+            let zec = format!(
+                "{}.{:08}",
+                r.item.amount / 100_000_000,
+                r.item.amount % 100_000_000
+            );
+            json!({
+                "address": &r.item.address,
+                "amount": r.item.amount,
+                "zec": zec,
+                "memo": memo,
+                "label": &r.label,
+                "message": &r.message,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(recipients)
+}
+
+/// Status of the server and of the wallet. Failed checks are reported
+/// in the result, the server being unreachable is not an error
+async fn health_check(zec: &CoinDef) -> Result<Value> {
+    let server_height = match zec.connect_lwd().await {
+        Ok(mut client) => get_last_height(&mut client).await,
+        Err(e) => Err(e),
+    };
+    let (server, server_height) = match server_height {
+        Ok(height) => (json!({"reachable": true}), Some(height)),
+        Err(e) => (json!({"reachable": false, "error": e.to_string()}), None),
+    };
+    let connection = zec.connection()?;
+    let sync_height = get_sync_height(&connection)?;
+    let lag = match (server_height, sync_height) {
+        (Some(server_height), Some(sync_height)) => {
+            Some(server_height.saturating_sub(sync_height))
+        }
+        _ => None,
+    };
+    let integrity =
+        connection.query_row("PRAGMA integrity_check", [], |r| r.get::<_, String>(0))?;
+    let accounts =
+        connection.query_row("SELECT COUNT(*) FROM accounts", [], |r| r.get::<_, u32>(0))?;
+    let parameters = pay::provers().is_ok();
+    let healthy = server_height.is_some() && integrity == "ok" && parameters;
+    Ok(json!({
+        "healthy": healthy,
+        "server": server,
+        "server_height": server_height,
+        "sync_height": sync_height,
+        "lag": lag,
+        "integrity": integrity,
+        "parameters": parameters,
+        "accounts": accounts,
+    }))
+}
+
+/// Sync by steps of one checkpoint interval, up to `target` or
+/// the tip minus the confirmations
+async fn sync_loop(zec: &CoinDef, confirmations: u32, target: Option<u32>) -> Result<SyncStats> {
+    let confirmations = checked_confirmations(confirmations, CONFIG.allow_unconfirmed)?;
+    check_account(&zec.connection()?, None)?;
+    if let Some(target) = target {
+        let sync_height = get_sync_height(&zec.connection()?)?.unwrap_or_default();
+        if target <= sync_height {
+            anyhow::bail!("Target height {target} is not above the sync height {sync_height}");
+        }
+    }
+    let mut stats = SyncStats::default();
+    loop {
+        let mut client = zec.connect_lwd().await?;
+        let bc_height = get_last_height(&mut client).await?;
+        let connection = zec.connection()?;
+        let max_height = (bc_height + 1).saturating_sub(confirmations);
+        let end_height = match target {
+            Some(target) if target > max_height => {
+                anyhow::bail!("Target height {target} is above {max_height} (tip - confirmations)")
+            }
+            Some(target) => target,
+            None => max_height,
+        };
+        let start_height = get_sync_height(&connection)?
+            .ok_or(anyhow::anyhow!("no sync data. Have you run reset?"))?;
+        if start_height >= end_height {
+            break;
+        }
+        let interval = CONFIG.checkpoint_interval.unwrap_or(100_000);
+        let end_height = next_checkpoint(start_height, end_height, interval);
+        let s = warp_sync(zec, CheckpointHeight(start_height), end_height).await?;
+        stats.add(&s);
+        let connection = Mutex::new(zec.connection()?);
+        let details =
+            retrieve_tx_details(&zec.network, connection, zec.url.clone(), &zec.data_usage)
+                .await?;
+        if !details.failed.is_empty() {
+            tracing::warn!(
+                "{} transaction details retrieved, {} failed and will be retried",
+                details.succeeded,
+                details.failed.len()
+            );
+        }
+        output_progress(
+            json!({"sync_height": end_height, "target_height": bc_height}),
+            || format!("Synced to {end_height}/{bc_height}"),
+        );
+    }
+    Ok(stats)
 }
 
 #[tokio::main]
@@ -259,18 +1339,25 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
         Command::Account(account_cmd) => {
             let connection = zec.connection()?;
             match account_cmd.command {
-                AccountCommand::List => {
-                    let accounts = list_accounts(&connection)?;
-                    println!("{}", serde_json::to_string_pretty(&accounts)?);
+                AccountCommand::List { sort, desc } => {
+                    let accounts =
+                        list_accounts_sorted(&connection, sort.unwrap_or(AccountSort::Id), desc)?;
+                    output_json("accounts", &accounts)?;
                 }
-                AccountCommand::Create { key, name, birth } => {
+                AccountCommand::Create {
+                    key,
+                    name,
+                    birth,
+                    merge,
+                } => {
                     let mut client = zec.connect_lwd().await?;
                     let bc_height = get_last_height(&mut client).await?;
                     let key = key.unwrap_or(CONFIG.seed.clone());
                     let name = name.unwrap_or("<unnamed>".to_string());
                     let kt = detect_key(network, &key, 0, 0)?;
                     let birth = birth.unwrap_or(bc_height);
-                    create_new_account(network, &connection, &name, kt, birth)?;
+                    let account = create_new_account(network, &connection, &name, kt, birth, merge)?;
+                    output(json!({"account": account}), || format!("Account #{account}"));
                 }
                 AccountCommand::EditName { account, name } => {
                     edit_account_name(&connection, account, &name)?;
@@ -278,8 +1365,28 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
                 AccountCommand::EditBirthHeight { account, birth } => {
                     edit_account_birth(&connection, account, birth)?;
                 }
-                AccountCommand::Delete { account } => {
-                    delete_account(&connection, account)?;
+                AccountCommand::Restore { backup } => {
+                    let backup: BackupT = serde_json::from_str(&backup)?;
+                    let account = create_account_from_backup(network, &connection, &backup)?;
+                    output(json!({"account": account}), || format!("Account #{account}"));
+                }
+                AccountCommand::SetDefaultMemo { account, memo } => {
+                    set_default_memo(&connection, account, memo.as_deref())?;
+                }
+                AccountCommand::SetAddressRotation { account, rotate } => {
+                    check_account(&connection, Some(account))?;
+                    set_rotate_on_receive(&connection, account, rotate != 0)?;
+                }
+                AccountCommand::Freeze { account } => {
+                    check_account(&connection, Some(account))?;
+                    freeze_account(&connection, account, true)?;
+                }
+                AccountCommand::Unfreeze { account } => {
+                    check_account(&connection, Some(account))?;
+                    freeze_account(&connection, account, false)?;
+                }
+                AccountCommand::Delete { account } => {
+                    delete_account(&connection, account)?;
                 }
             }
         }
@@ -289,7 +1396,7 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
                 ContactCommand::List => {
                     let contacts = list_contacts(network, &connection)?;
                     let cards = contacts.iter().map(|c| c.card.clone()).collect::<Vec<_>>();
-                    println!("{}", serde_json::to_string_pretty(&cards).unwrap());
+                    output_json("contacts", &cards)?;
                 }
                 ContactCommand::Create {
                     account,
@@ -310,8 +1417,8 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
                 ContactCommand::Save { account } => {
                     let mut client = zec.connect_lwd().await?;
                     let bc_height = get_last_height(&mut client).await?;
-                    let cp_height = snap_to_checkpoint(&connection, bc_height - CONFIG.confirmations + 1)?;
-                    let (s_tree, o_tree) = get_tree_state(&mut client, cp_height).await?;
+                    let cp_height = spend_anchor(&connection, bc_height)?;
+                    let (s_tree, o_tree) = zec.get_tree_state(&mut client, cp_height).await?;
                     let unsigned_tx = commit_unsaved_contacts(
                         network,
                         &connection,
@@ -327,71 +1434,194 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
         }
         Command::GenerateSeed => {
             let seed = generate_random_mnemonic_phrase(&mut OsRng);
-            println!("{seed}");
+            output(json!({"seed": &seed}), || seed.clone());
         }
         Command::Backup { account } => {
             let connection = zec.connection()?;
             let ai = get_account_info(network, &connection, account)?;
             let backup = ai.to_backup(network);
-            println!("{}", serde_json::to_string_pretty(&backup).unwrap());
+            output_json("backup", &backup)?;
         }
         Command::LastHeight => {
             let mut client = zec.connect_lwd().await?;
             let bc_height = get_last_height(&mut client).await?;
-            println!("{bc_height}");
+            output(json!({"height": bc_height}), || bc_height.to_string());
         }
         Command::SyncHeight => {
             let connection = zec.connection()?;
             let height = get_sync_height(&connection)?;
-            println!("{height:?}");
+            output(json!({"height": height}), || format!("{height:?}"));
+        }
+        Command::VerifyChain => {
+            let connection = zec.connection()?;
+            verify_block_continuity(&connection)?;
+            let mut client = zec.connect_lwd().await?;
+            let bounds = get_server_bounds(&mut client).await?;
+            for (height, hash) in list_checkpoints(&connection)? {
+                let block = get_compact_block(&mut client, &zec.data_usage, &bounds, height).await?;
+                if BlockHeader::from(&block).hash != hash {
+                    anyhow::bail!("Checkpoint {height} is not on the server chain, reset to an earlier height");
+                }
+            }
+            output(json!({"ok": true}), || "Chain OK".to_string());
+        }
+        Command::ExportSyncState { path } => {
+            let connection = zec.connection()?;
+            let data = export_state(&connection)?;
+            std::fs::write(&path, &data)?;
+            output(json!({"bytes": data.len()}), || format!("{} bytes written", data.len()));
+        }
+        Command::ImportSyncState { path } => {
+            let data = std::fs::read(&path)?;
+            let mut connection = zec.connection()?;
+            import_state(&mut connection, &data)?;
+        }
+        Command::ImportNotes { account, path } => {
+            #[derive(Deserialize)]
+            struct NotesFile {
+                height: u32,
+                notes: Vec<ImportedNote>,
+            }
+            let file: NotesFile = serde_json::from_slice(&std::fs::read(&path)?)?;
+            let mut connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let mut client = zec.connect_lwd().await?;
+            let bounds = get_server_bounds(&mut client).await?;
+            let block = get_compact_block(&mut client, &zec.data_usage, &bounds, file.height).await?;
+            let (s, o) = zec
+                .get_tree_state(&mut client, CheckpointHeight(file.height))
+                .await?;
+            let checkpoint = ImportCheckpoint {
+                block: BlockHeader::from(&block),
+                sapling: s.to_edge(&SaplingHasher::default()),
+                orchard: o.to_edge(&OrchardHasher::default()),
+            };
+            let count = file.notes.len();
+            import_notes(network, &mut connection, account, &checkpoint, file.notes)?;
+            output(json!({"imported": count, "height": file.height}), || {
+                format!("{count} notes imported at {}", file.height)
+            });
+        }
+        Command::SyncHash => {
+            let connection = zec.connection()?;
+            let height = get_sync_height(&connection)?;
+            let hash = get_sync_block_hash(&connection)?.map(|h| to_txid_str(&h));
+            output(json!({"height": height, "hash": hash}), || match (height, &hash) {
+                (Some(height), Some(hash)) => format!("{height} {hash}"),
+                _ => "Not synced".to_string(),
+            });
+        }
+        Command::HealthCheck => {
+            let health = health_check(zec).await?;
+            output(health.clone(), || serde_json::to_string_pretty(&health).unwrap());
         }
         Command::Reset { height } => {
             let connection = zec.connection()?;
-            truncate_scan(&connection)?;
             let activation: u32 = network
                 .activation_height(NetworkUpgrade::Sapling)
                 .unwrap()
                 .into();
-            let min_birth_height = get_min_birth(&connection)?.unwrap_or(activation);
-            let height = height.unwrap_or(min_birth_height).max(activation + 1);
+            let nu5: u32 = network
+                .activation_height(NetworkUpgrade::Nu5)
+                .unwrap()
+                .into();
+            // Skip the Sapling era when a previous scan went through it
+            // and found no Sapling note, utxo or transaction before NU5
+            let scanned_sapling_era = get_scanned_range(&connection)?
+                .map(|(start, end)| start <= nu5 && end >= nu5)
+                .unwrap_or_default();
+            let floor = if scanned_sapling_era && !has_activity_before(&connection, nu5)? {
+                nu5
+            } else {
+                activation
+            };
+            truncate_scan(&connection)?;
+            let min_birth_height = get_min_birth(&connection)?.unwrap_or(floor);
+            let height = match height {
+                Some(height) => height.max(activation + 1),
+                None => min_birth_height.max(floor + 1),
+            };
             let mut client = zec.connect_lwd().await?;
-            let block = get_compact_block(&mut client, height).await?;
+            let bounds = get_server_bounds(&mut client).await?;
+            let block = get_compact_block(&mut client, &zec.data_usage, &bounds, height).await?;
             let mut connection = zec.connection()?;
             let mut transaction = connection.transaction()?;
             transaction.set_drop_behavior(DropBehavior::Commit);
             store_block(&transaction, &BlockHeader::from(&block))?;
         }
-        Command::Sync { confirmations } => loop {
-            let mut client = zec.connect_lwd().await?;
-            let bc_height = get_last_height(&mut client).await?;
-            let confirmations = confirmations.unwrap_or(1);
-            if confirmations == 0 {
-                anyhow::bail!("# Confirmations must be > 0");
-            }
+        Command::Sync { confirmations } => {
+            zec.reset_data_usage();
+            let stats = sync_loop(zec, confirmations.unwrap_or(1), None).await?;
+            output_json("stats", &stats)?;
+        }
+        Command::SyncTo {
+            height,
+            confirmations,
+        } => {
+            zec.reset_data_usage();
+            let stats = sync_loop(zec, confirmations.unwrap_or(1), Some(height)).await?;
+            output_json("stats", &stats)?;
+        }
+        Command::RetryTxDetails => {
+            let connection = Mutex::new(zec.connection()?);
+            let report =
+                retrieve_tx_details(network, connection, zec.url.clone(), &zec.data_usage).await?;
+            output_json("details", &report)?;
+        }
+        Command::SimulateSync { from, to } => {
             let connection = zec.connection()?;
-            let end_height = bc_height - confirmations + 1;
-            let start_height = get_sync_height(&connection)?.ok_or(anyhow::anyhow!("no sync data. Have you run reset?"))?;
-            if start_height >= end_height {
-                break;
+            check_account(&connection, None)?;
+            let start = snap_to_checkpoint(&connection, from)?;
+            if to <= start.0 {
+                anyhow::bail!("End height {to} is not above the checkpoint {}", start.0);
             }
-            let end_height = (start_height + 100_000).min(end_height);
-            warp_sync(&zec, CheckpointHeight(start_height), end_height).await?;
-            let connection = Mutex::new(zec.connection()?);
-            retrieve_tx_details(network, connection, zec.url.clone()).await?;
-        },
+            let report = simulate_sync(zec, start, to).await?;
+            output_json("report", &report)?;
+        }
         Command::Address { account, mask } => {
             let connection = zec.connection()?;
-            let ai = get_account_info(network, &connection, account)?;
-            let address = ai
-                .to_address(network, PoolMask(mask))
-                .ok_or(anyhow::anyhow!("Invalid mask"))?;
-            println!("Address: {}", address);
+            let address = get_receiving_address(network, &connection, account, PoolMask(mask))?;
+            output(json!({"address": &address}), || format!("Address: {}", address));
         }
         Command::Balance { account } => {
             let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
             let height = get_sync_height(&connection)?.unwrap_or_default();
             let balance = get_balance(&connection, account, height)?;
-            println!("Balance: {:?}", balance);
+            output(serde_json::to_value(&balance)?, || {
+                format!(
+                    "Transparent: {} ({} utxos)\nSapling: {} ({} notes)\nOrchard: {} ({} notes)",
+                    balance.transparent,
+                    balance.transparent_notes,
+                    balance.sapling,
+                    balance.sapling_notes,
+                    balance.orchard,
+                    balance.orchard_notes
+                )
+            });
+        }
+        Command::BalanceAt { account, height } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let sync_height = get_sync_height(&connection)?.unwrap_or_default();
+            if height > sync_height {
+                anyhow::bail!("Height {height} is above the sync height {sync_height}");
+            }
+            let balance = get_balance_at(&connection, account, height)?;
+            output(json!({"height": height, "balance": balance}), || {
+                format!("Balance at {height}: {balance}")
+            });
+        }
+        Command::AddressTotals { account } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let totals = address_totals(network, &connection, account)?;
+            warn_address_reuse(network, &connection, account)?;
+            let totals = totals
+                .into_iter()
+                .map(|(address, value)| json!({"address": address, "value": value}))
+                .collect::<Vec<_>>();
+            output_json("totals", &totals)?;
         }
         Command::Pay {
             account,
@@ -399,18 +1629,53 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
             amount,
             pools,
             fee_paid_by_sender,
+            split,
+            key,
+            force,
+            max_fee,
+            anchor_height,
+            allow_self,
+            expiry_height,
+            memo,
+            memo_hex,
+            source_priority,
+            fee_strategy,
+            change_address,
+            change_pool,
+            min_sapling_outputs,
+            min_orchard_actions,
         } => {
+            let options = PaymentOptions {
+                idempotency_key: key,
+                force,
+                max_fee,
+                allow_self,
+                ..payment_options(
+                    source_priority,
+                    fee_strategy,
+                    change_address,
+                    change_pool,
+                    min_sapling_outputs,
+                    min_orchard_actions,
+                )
+            };
+            let memo = match (memo, memo_hex) {
+                (_, Some(memo_hex)) => Some(PaymentItem::memo_from_hex(&memo_hex)?),
+                (Some(memo), None) => Some(MemoBytes::from(&Memo::from_str(&memo)?)),
+                (None, None) => None,
+            };
             let mut client = zec.connect_lwd().await?;
             let bc_height = get_last_height(&mut client).await?;
             let connection = zec.connection()?;
-            let cp_height = snap_to_checkpoint(&connection, bc_height - CONFIG.confirmations + 1)?;
-            let (s_tree, o_tree) = get_tree_state(&mut client, cp_height).await?;
+            let cp_height = payment_anchor(&connection, account, bc_height, anchor_height)?;
+            let (s_tree, o_tree) = zec.get_tree_state(&mut client, cp_height).await?;
+            let item = PaymentItem {
+                address,
+                amount,
+                memo,
+            };
             let p = Payment {
-                recipients: vec![PaymentItem {
-                    address,
-                    amount,
-                    memo: None,
-                }],
+                recipients: item.split(split, &mut OsRng)?,
             };
             let connection = zec.connection()?;
             let unsigned_tx = make_payment(
@@ -423,50 +1688,124 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
                 fee_paid_by_sender != 0,
                 &s_tree,
                 &o_tree,
+                &options,
             )?;
-            *txbytes = display_tx(
+            *txbytes = display_tx_with_expiry(
                 network,
                 &connection,
                 cp_height,
                 unsigned_tx,
                 &mut TSKStore::default(),
+                expiry_height,
             )?;
         }
+        Command::EstimateFee {
+            account,
+            address,
+            amount,
+            pools,
+            fee_paid_by_sender,
+            source_priority,
+            fee_strategy,
+            change_address,
+            change_pool,
+            min_sapling_outputs,
+            min_orchard_actions,
+        } => {
+            let options = payment_options(
+                source_priority,
+                fee_strategy,
+                change_address,
+                change_pool,
+                min_sapling_outputs,
+                min_orchard_actions,
+            );
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let connection = zec.connection()?;
+            let cp_height = spend_anchor(&connection, bc_height)?;
+            let p = Payment {
+                recipients: vec![PaymentItem {
+                    address,
+                    amount,
+                    memo: None,
+                }],
+            };
+            let fee = estimate_fee(
+                network,
+                &connection,
+                account,
+                cp_height,
+                p,
+                PoolMask(pools),
+                fee_paid_by_sender != 0,
+                &options,
+            )?;
+            output(json!({"fee": fee}), || format!("Fee: {}", fee));
+        }
+        Command::ProvePayment { id } => {
+            let connection = zec.connection()?;
+            let proof = export_payment_proof(network, &connection, id)?;
+            output_json("proof", &proof)?;
+        }
+        Command::CheckPaymentProof { proof } => {
+            let proof: PaymentProof = serde_json::from_str(&proof)?;
+            let mut client = zec.connect_lwd().await?;
+            check_payment_proof(network, &mut client, &zec.data_usage, &proof).await?;
+            output(json!({"ok": true}), || format!("Payment proof of {} OK", proof.txid));
+        }
         Command::GetTx { account, id } => {
             let connection = zec.connection()?;
             let (txid, timestamp) = get_txid(&connection, id)?;
             let mut client = zec.connect_lwd().await?;
-            let (height, tx) = get_transaction(network, &mut client, &txid).await?;
+            let (height, tx) = get_transaction(network, &mut client, &zec.data_usage, &txid).await?;
+            let mut raw = vec![];
+            tx.write(&mut raw)?;
             let tx = analyze_raw_transaction(
                 network,
                 &connection,
                 zec.url.clone(),
+                &zec.data_usage,
                 height,
                 timestamp,
                 account,
                 tx,
             )?;
             let txb = serde_cbor::to_vec(&tx)?;
-            println!("{}", hex::encode(&txb));
+            output(json!({"tx": hex::encode(&txb)}), || hex::encode(&txb));
             store_tx_details(&connection, id, &tx.txid, &txb)?;
+            store_raw_tx(&connection, id, &raw)?;
         }
         Command::GenDiversifiedAddress { account, pools } => {
             let connection = zec.connection()?;
             let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
-            let address =
+            let (index, address) =
                 get_diversified_address(network, &connection, account, time, PoolMask(pools))?;
-            println!("{}", address);
+            output(json!({"address": &address, "index": index}), || address.clone());
+        }
+        Command::DerivationReport {
+            account,
+            transparent_gap,
+            shielded_count,
+        } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let ai = get_account_info(network, &connection, account)?;
+            let report = derivation_report(network, &ai, transparent_gap, shielded_count)?;
+            output_json("addresses", &report)?;
         }
         Command::Sweep {
             account,
             destination_address,
+            confirmations,
         } => {
             let connection = zec.connection()?;
             let ai = get_account_info(network, &connection, account)?;
             let mut client = zec.connect_lwd().await?;
             let bc_height = get_last_height(&mut client).await?;
-            let cp_height = snap_to_checkpoint(&connection, bc_height - CONFIG.confirmations + 1)?;
-            let (s, o) = get_tree_state(&mut client, cp_height).await?;
+            let confirmations = confirmations.unwrap_or(CONFIG.confirmations);
+            let cp_height = spend_anchor_with(&connection, bc_height, confirmations)?;
+            let (s, o) = zec.get_tree_state(&mut client, cp_height).await?;
             let (utxos, mut tsk_store) =
                 scan_utxo_by_seed(network, &zec.url, ai, bc_height, 0, true, 40).await?;
             let connection = zec.connection()?;
@@ -482,63 +1821,340 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
             )?;
             *txbytes = display_tx(network, &connection, cp_height, unsigned_tx, &mut tsk_store)?;
         }
+        Command::Shield {
+            account,
+            to_pool,
+            keep_transparent,
+            confirmations,
+        } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let confirmations = confirmations.unwrap_or(CONFIG.confirmations);
+            let cp_height = spend_anchor_with(&connection, bc_height, confirmations)?;
+            let (s, o) = zec.get_tree_state(&mut client, cp_height).await?;
+            let unsigned_tx = prepare_shield(
+                network,
+                &connection,
+                account,
+                cp_height,
+                to_pool,
+                keep_transparent,
+                &s,
+                &o,
+            )?;
+            *txbytes = display_tx(
+                network,
+                &connection,
+                cp_height,
+                unsigned_tx,
+                &mut TSKStore::default(),
+            )?;
+        }
+        Command::TransferPools {
+            account,
+            from_pool,
+            to_pool,
+            amount,
+            split_amount,
+            confirmations,
+        } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let confirmations = confirmations.unwrap_or(CONFIG.confirmations);
+            let cp_height = spend_anchor_with(&connection, bc_height, confirmations)?;
+            let (s, o) = zec.get_tree_state(&mut client, cp_height).await?;
+            let unsigned_tx = prepare_transfer_pools(
+                network,
+                &connection,
+                account,
+                cp_height,
+                from_pool,
+                to_pool,
+                amount,
+                None,
+                split_amount,
+                &s,
+                &o,
+            )?;
+            *txbytes = display_tx(
+                network,
+                &connection,
+                cp_height,
+                unsigned_tx,
+                &mut TSKStore::default(),
+            )?;
+        }
+        Command::SweepSeed {
+            account,
+            seed,
+            destination_address,
+            accounts,
+            gap_limit,
+        } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let cp_height = spend_anchor(&connection, bc_height)?;
+            let (s, o) = zec.get_tree_state(&mut client, cp_height).await?;
+            let (utxos, mut tsk_store) = sweep_seed(
+                network,
+                &zec.url,
+                &seed,
+                account,
+                0..accounts,
+                bc_height,
+                gap_limit,
+            )
+            .await?;
+            if utxos.is_empty() {
+                anyhow::bail!("No transparent funds found");
+            }
+            let connection = zec.connection()?;
+            let unsigned_tx = prepare_sweep(
+                network,
+                &connection,
+                account,
+                bc_height,
+                &utxos,
+                destination_address,
+                &s,
+                &o,
+            )?;
+            *txbytes = display_tx(network, &connection, cp_height, unsigned_tx, &mut tsk_store)?;
+        }
+        Command::GetRawTx { id } => {
+            let connection = zec.connection()?;
+            let raw = get_raw_tx(&connection, id)?;
+            output(json!({"raw": hex::encode(&raw)}), || hex::encode(&raw));
+        }
+        Command::ValidateTx { hex, id } => {
+            let data = match (hex, id) {
+                (Some(hex), _) => hex::decode(hex.trim())
+                    .map_err(|_| anyhow::anyhow!("Invalid transaction hex"))?,
+                (None, Some(id)) => {
+                    let connection = zec.connection()?;
+                    get_pending_tx(&connection, id)?
+                        .ok_or(anyhow::anyhow!("No pending transaction #{id}"))?
+                        .data
+                }
+                (None, None) => anyhow::bail!("Give the transaction with --hex or --id"),
+            };
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let connection = zec.connection()?;
+            pay::validate_tx(network, &connection, &data, bc_height)?;
+            output(json!({"valid": true}), || "Transaction is valid".to_string());
+        }
+        Command::AnalyzeTx {
+            account,
+            hex,
+            height,
+        } => {
+            let connection = zec.connection()?;
+            let height = match height {
+                Some(height) => height,
+                None => get_sync_height(&connection)?.unwrap_or_default(),
+            };
+            let tx = decode_raw_transaction(network, &hex, height)?;
+            let tx = analyze_raw_transaction(
+                network,
+                &connection,
+                zec.url.clone(),
+                &zec.data_usage,
+                height,
+                0,
+                account,
+                tx,
+            )?;
+            let sync_height = get_sync_height(&connection)?.unwrap_or_default();
+            let etx = tx.to_transaction_info_ext(network, sync_height);
+            output_json("tx", &etx)?;
+        }
         Command::GetTxDetails { id } => {
             let connection = zec.connection()?;
             let (account, tx) = get_tx_details(&connection, id)?;
             decode_tx_details(network, &connection, account, id, &tx)?;
-            let etx = tx.to_transaction_info_ext(network);
-            println!("{}", serde_json::to_string_pretty(&etx).unwrap());
+            let sync_height = get_sync_height(&connection)?.unwrap_or_default();
+            let etx = tx.to_transaction_info_ext(network, sync_height);
+            output_json("tx", &etx)?;
         }
         Command::DecodeAddress { address } => {
             let ra = RecipientAddress::decode(network, &address)
                 .ok_or(anyhow::anyhow!("Invalid Address"))?;
-            println!("{:?}", ra);
+            output(json!({"address": format!("{:?}", ra)}), || format!("{:?}", ra));
         }
-        Command::ListTxs { account } => {
+        Command::ListTxs {
+            account,
+            offset,
+            limit,
+            from_height,
+            to_height,
+            from_time,
+            to_time,
+            direction,
+        } => {
+            check_account(&zec.connection()?, Some(account))?;
             let mut client = zec.connect_lwd().await?;
             let bc_height = get_last_height(&mut client).await?;
             let connection = zec.connection()?;
-            let txs = get_txs(network, &connection, account, bc_height)?;
+            let filter = TxFilter {
+                from_height,
+                to_height,
+                from_time,
+                to_time,
+                direction,
+            };
+            let filtered = from_height.is_some()
+                || to_height.is_some()
+                || from_time.is_some()
+                || to_time.is_some()
+                || direction.is_some();
+            let txs = match (offset, limit, filtered) {
+                (None, None, false) => get_txs(network, &connection, account, bc_height)?,
+                (offset, limit, _) => {
+                    let (txs, total) = get_txs_filtered(
+                        network,
+                        &connection,
+                        account,
+                        bc_height,
+                        &filter,
+                        offset.unwrap_or_default(),
+                        limit.unwrap_or(u32::MAX),
+                    )?;
+                    output(json!({"total": total}), || format!("{} transactions", total));
+                    txs
+                }
+            };
 
-            for tx in txs.iter() {
-                println!("{}", serde_json::to_string_pretty(tx).unwrap());
-            }
+            output_json("txs", &txs)?;
             let _data = fb_vec_to_bytes!(txs, TransactionInfo)?;
             // println!("{}", hex::encode(data));
         }
+        Command::ListPendingTxs { account } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let txs = list_pending(&connection, account)?;
+            output_json("txs", &txs)?;
+        }
+        Command::RebroadcastPending { account } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let mut results = vec![];
+            for b in list_unmined_broadcasts(&connection, account)? {
+                let txid = to_txid_str(&b.txid);
+                if b.is_expired(bc_height) {
+                    tracing::info!("Transaction {txid} expired at {}", b.expiration_height);
+                    delete_broadcast(&connection, b.id)?;
+                    results.push(json!({"txid": txid, "expired": true}));
+                    continue;
+                }
+                let r = match broadcast(&mut client, bc_height, &b.data).await {
+                    Ok(r) => r,
+                    Err(e) => {
+                        // keep going with the other transactions
+                        tracing::warn!("Transaction {txid} could not be sent: {e}");
+                        results.push(json!({"txid": txid, "error": e.to_string()}));
+                        continue;
+                    }
+                };
+                if !r.accepted {
+                    tracing::warn!(
+                        "Transaction {txid} rejected: {}",
+                        r.error.as_deref().unwrap_or_default()
+                    );
+                }
+                results.push(json!({
+                    "txid": txid,
+                    "expired": false,
+                    "accepted": r.accepted,
+                    "error": r.error,
+                }));
+            }
+            output_json("broadcasts", &results)?;
+        }
+        Command::ExportTxs {
+            account,
+            path,
+            format,
+        } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let mut w = std::io::BufWriter::new(std::fs::File::create(&path)?);
+            let count = export_txs(network, &connection, account, bc_height, format, &mut w)?;
+            w.flush()?;
+            output(json!({"count": count}), || format!("{count} transactions written"));
+        }
         Command::ListNotes { account } => {
+            check_account(&zec.connection()?, Some(account))?;
             let mut client = zec.connect_lwd().await?;
             let bc_height = get_last_height(&mut client).await?;
             let connection = zec.connection()?;
             let notes = get_unspent_notes(&connection, account, bc_height)?;
+            warn_address_reuse(network, &connection, account)?;
 
-            println!("{}", serde_json::to_string_pretty(&notes).unwrap());
+            output_json("notes", &notes)?;
             let _data = fb_vec_to_bytes!(notes, ShieldedNote)?;
         }
+        Command::ListNullifiers { account } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let nfs = list_unspent_nullifiers(&connection, account)?
+                .iter()
+                .map(hex::encode)
+                .collect::<Vec<_>>();
+            output_json("nullifiers", &nfs)?;
+        }
         Command::ListMessages { account } => {
             let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
             let msgs = list_messages(&connection, account)?;
-            println!("{}", serde_json::to_string_pretty(&msgs).unwrap());
+            output_json("messages", &msgs)?;
         }
         Command::DecodeUA { ua } => {
             let ua = decode_ua(network, &ua)?;
-            println!("{}", serde_json::to_string_pretty(&ua).unwrap());
+            output_json("ua", &ua)?;
         }
         Command::MakePaymentURI { recipients } => {
             let recipients = recipients
                 .iter()
-                .map(|r| PaymentItem::try_from(r))
+                .map(|r| {
+                    Ok::<_, pay::Error>(UriPayment {
+                        item: PaymentItem::try_from(r)?,
+                        label: r.label.clone(),
+                        message: r.message.clone(),
+                    })
+                })
                 .collect::<Result<Vec<_>, _>>()?;
-            let payment_uri = make_payment_uri(&recipients)?;
-            println!("{}", payment_uri);
+            let payment_uri = make_payment_uri(network, &recipients)?;
+            output(json!({"uri": &payment_uri}), || payment_uri.clone());
         }
-        Command::PayPaymentUri { account, uri } => {
-            let recipients = parse_payment_uri(&uri)?;
+        Command::DecodePaymentURI { uri } => {
+            let recipients = decode_payment_uri(network, &uri)?;
+            output_json("recipients", &recipients)?;
+        }
+        Command::PayPaymentUri {
+            account,
+            uri,
+            force,
+            max_fee,
+            anchor_height,
+            allow_self,
+        } => {
+            let recipients = parse_payment_uri(network, &uri)?;
             let mut client = zec.connect_lwd().await?;
             let bc_height = get_last_height(&mut client).await?;
             let connection = zec.connection()?;
-            let cp_height = snap_to_checkpoint(&connection, bc_height - CONFIG.confirmations + 1)?;
-            let (s, o) = get_tree_state(&mut client, cp_height).await?;
+            let cp_height = payment_anchor(&connection, account, bc_height, anchor_height)?;
+            let (s, o) = zec.get_tree_state(&mut client, cp_height).await?;
             let unsigned_tx = make_payment(
                 network,
                 &connection,
@@ -549,6 +2165,12 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
                 true,
                 &s,
                 &o,
+                &PaymentOptions {
+                    force,
+                    max_fee,
+                    allow_self,
+                    ..PaymentOptions::default()
+                },
             )?;
             *txbytes = display_tx(
                 network,
@@ -558,43 +2180,202 @@ async fn process_command(command: Command, zec: &mut CoinDef, txbytes: &mut Vec<
                 &mut TSKStore::default(),
             )?;
         }
-        Command::BroadcastLatest { clear } => {
+        Command::ExportTKey { account, yes } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            if !yes {
+                if !std::io::stdin().is_terminal() {
+                    anyhow::bail!("Confirmation required, use --yes");
+                }
+                if !confirm("Anyone with this key can spend the transparent funds. Show it?")? {
+                    anyhow::bail!("Export cancelled");
+                }
+            }
+            let wif = export_transparent_key(network, &connection, account)?;
+            output(json!({"wif": &wif}), || wif.clone());
+        }
+        Command::BroadcastLatest { clear, yes } => {
             let clear = clear.unwrap_or(1);
             if clear != 0 {
-                if !txbytes.is_empty() {
-                    let mut client = zec.connect_lwd().await?;
-                    let bc_height = get_last_height(&mut client).await?;
-                    let r = broadcast(&mut client, bc_height, &txbytes).await?;
-                    println!("{}", r);
+                // The prepared transaction is persisted and survives a restart
+                let connection = zec.connection()?;
+                if let Some(pending) = get_latest_pending_tx(&connection)? {
+                    let r = broadcast_pending(zec, &connection, &pending, yes).await?;
+                    if r.accepted {
+                        txbytes.clear();
+                    }
+                    output_broadcast(pending.id, &r)?;
                 }
             }
         }
+        Command::DataUsage => {
+            let bytes = zec.data_usage();
+            output(json!({"bytes": bytes}), || {
+                format!("{:.3} MB received since the last sync", bytes as f64 / 1_000_000.0)
+            });
+        }
+        Command::AuditNotes { account } => {
+            let connection = zec.connection()?;
+            check_account(&connection, Some(account))?;
+            let audits = audit_notes(network, &connection, account)?;
+            for a in audits.iter() {
+                tracing::warn!("Note {} at position {}: {}", a.id_note, a.position, a.reason);
+            }
+            output_json("audits", &audits)?;
+        }
+        Command::ShowPending => {
+            let connection = zec.connection()?;
+            let pending = get_latest_pending_tx(&connection)?
+                .ok_or(anyhow::anyhow!("No pending transaction"))?;
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let summary: Value = serde_json::from_str(&pending.summary)?;
+            let expired = pending.is_expired(bc_height);
+            if expired {
+                tracing::warn!("Pending transaction expired at {}", pending.expiration_height);
+            }
+            output_json(
+                "pending",
+                &json!({
+                    "id": pending.id,
+                    "account": pending.account,
+                    "height": pending.height,
+                    "expiration_height": pending.expiration_height,
+                    "expired": expired,
+                    "size": pending.data.len(),
+                    "summary": summary,
+                }),
+            )?;
+        }
+        Command::ListPending => {
+            let connection = zec.connection()?;
+            let mut client = zec.connect_lwd().await?;
+            let bc_height = get_last_height(&mut client).await?;
+            let pending = list_pending_txs(&connection)?;
+            let pending = pending
+                .iter()
+                .map(|p| {
+                    let summary: Value = serde_json::from_str(&p.summary)?;
+                    warn_blocks_left(p, bc_height);
+                    Ok(json!({
+                        "id": p.id,
+                        "account": p.account,
+                        "txid": to_txid_str(&p.txid),
+                        "height": p.height,
+                        "expiration_height": p.expiration_height,
+                        "blocks_left": p.blocks_left(bc_height),
+                        "summary": summary,
+                    }))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            output_json("pending", &pending)?;
+        }
+        Command::BroadcastPending { id, yes } => {
+            let connection = zec.connection()?;
+            let pending = get_pending_tx(&connection, id)?
+                .ok_or(anyhow::anyhow!("No pending transaction #{id}"))?;
+            let r = broadcast_pending(zec, &connection, &pending, yes).await?;
+            output_broadcast(id, &r)?;
+        }
+        Command::DropPending { id } => {
+            let connection = zec.connection()?;
+            delete_pending_tx(&connection, id)?;
+        }
     }
     Ok(())
 }
 
+/// Log to stderr, and optionally to a file. RUST_LOG takes precedence
+/// over the configured level
+pub fn init_logging(level: Option<&str>, log_file: Option<&str>) -> Result<()> {
+    let directives = std::env::var(EnvFilter::DEFAULT_ENV).ok();
+    let filter = log_filter(level, directives.as_deref())?;
+    log_subscriber(filter, log_file)?.try_init()?;
+    Ok(())
+}
+
+/// Filter from the RUST_LOG directives when they are set, from the
+/// configured level otherwise
+fn log_filter(level: Option<&str>, directives: Option<&str>) -> Result<EnvFilter> {
+    let filter = match directives {
+        Some(directives) if !directives.is_empty() => EnvFilter::try_new(directives)?,
+        _ => EnvFilter::try_new(level.unwrap_or("info"))?,
+    };
+    Ok(filter)
+}
+
+fn log_subscriber(
+    filter: EnvFilter,
+    log_file: Option<&str>,
+) -> Result<impl tracing::Subscriber + Send + Sync> {
+    let file_layer = log_file
+        .map(|path| {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            Ok::<_, anyhow::Error>(
+                fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(std::sync::Mutex::new(file)),
+            )
+        })
+        .transpose()?;
+    let subscriber = tracing_subscriber::registry()
+        .with(fmt::layer().with_ansi(false).compact().with_writer(std::io::stderr))
+        .with(file_layer)
+        .with(filter);
+    Ok(subscriber)
+}
+
 pub fn cli_main() -> Result<()> {
+    init_logging(CONFIG.log_level.as_deref(), CONFIG.log_file.as_deref())?;
     let mut zec = CoinDef::from_network(zcash_primitives::consensus::Network::MainNetwork);
     zec.set_db_path(&CONFIG.db_path).unwrap();
     zec.set_url(&CONFIG.lwd_url);
     zec.set_warp(&CONFIG.warp_url);
+    zec.set_min_note_value(CONFIG.min_note_value.unwrap_or_default());
+    let mut json = CONFIG.json;
+    let mut broadcast = false;
+    let mut args = vec![];
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--broadcast" => broadcast = true,
+            _ => args.push(arg),
+        }
+    }
+    JSON_OUTPUT.store(json, Ordering::Relaxed);
+
+    let mut txbytes = vec![];
+    if !args.is_empty() {
+        let code = run_once(args, broadcast, &mut zec, &mut txbytes);
+        std::process::exit(code);
+    }
+    if !std::io::stdin().is_terminal() {
+        let code = run_batch(&mut zec, &mut txbytes);
+        std::process::exit(code);
+    }
+
     let prompt = DefaultPrompt {
         left_prompt: DefaultPromptSegment::Basic("zcash-warp".to_owned()),
         ..DefaultPrompt::default()
     };
+    let history_path = create_history_file(history_path(CONFIG.history_path.as_deref())?)?;
+    let history_size = CONFIG.history_size.unwrap_or(10000);
     let rl = ClapEditor::<Command>::builder()
         .with_prompt(Box::new(prompt))
-        .with_editor_hook(|reed| {
-            reed.with_history(Box::new(
-                FileBackedHistory::with_file(10000, "/tmp/zcash-warp-history".into()).unwrap(),
-            ))
+        .with_editor_hook(move |reed| {
+            reed.with_history(Box::new(FilteredHistory(
+                FileBackedHistory::with_file(history_size, history_path.clone()).unwrap(),
+            )))
         })
         .build();
 
-    let mut txbytes = vec![];
     rl.repl(|command| {
-        if let Err(e) = process_command(command, &mut zec, &mut txbytes) {
-            println!("{} {}", style("Error:").red().bold(), e);
+        match process_command(command, &mut zec, &mut txbytes) {
+            Ok(_) => flush_output(),
+            Err(e) => output_error(&e),
         }
     });
 
@@ -613,3 +2394,531 @@ pub fn init_config() -> Config {
 lazy_static::lazy_static! {
     pub static ref CONFIG: Config = init_config();
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use zcash_primitives::consensus::Network;
+
+    use clap::Parser as _;
+
+    use super::{
+        checked_confirmations, create_history_file, decode_payment_uri, decode_pending, display_tx,
+        exit_code, health_check, history_path, is_sensitive, log_filter, log_subscriber,
+        merge_output, next_checkpoint, parse_amount, process_command, run_once, split_args,
+        sync_loop, warn_blocks_left, Command, LOW_BLOCKS_LEFT,
+    };
+    use crate::{
+        coin::CoinDef,
+        db::{
+            account::get_balance,
+            notes::get_sync_height,
+            pending::{get_pending_tx, list_unmined_broadcasts, store_broadcast, store_pending_tx},
+            reset_tables,
+            testing::{add_utxo, external_address, test_account, test_db, TEST_BIRTH},
+        },
+        lwd::{
+            mock::{
+                testing::{chain, wallet},
+                MockChain, MockLwd,
+            },
+            rpc::{CompactBlock, TreeState},
+        },
+        pay::{self, REMAINDER},
+        warp::sync::SyncError,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    #[test]
+    fn amounts_are_parsed() {
+        assert_eq!(parse_amount("100000"), Ok(100_000));
+        assert_eq!(parse_amount("max"), Ok(REMAINDER));
+        assert_eq!(parse_amount("rest"), Ok(REMAINDER));
+        assert!(parse_amount(&u64::MAX.to_string()).is_err());
+        assert!(parse_amount("all").is_err());
+    }
+
+    #[test]
+    fn outputs_make_one_document() {
+        let mut document = serde_json::Map::new();
+        merge_output(&mut document, json!({"summary": {"fee": 10000}}));
+        merge_output(&mut document, json!({"tx": "00"}));
+        merge_output(&mut document, json!(42));
+        assert_eq!(
+            serde_json::Value::Object(document),
+            json!({"summary": {"fee": 10000}, "tx": "00", "result": 42})
+        );
+    }
+
+    #[test]
+    fn balance_is_a_json_object() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 50_000);
+        let balance = get_balance(&connection, account, TEST_BIRTH + 100).unwrap();
+        let mut document = serde_json::Map::new();
+        merge_output(&mut document, serde_json::to_value(&balance).unwrap());
+        let text = serde_json::Value::Object(document).to_string();
+        let document: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert!(document.is_object());
+        assert_eq!(document["transparent"].as_u64(), Some(50_000));
+        assert_eq!(document["transparent_notes"].as_u64(), Some(1));
+    }
+
+    #[test]
+    fn errors_map_to_exit_codes() {
+        let e = Command::try_parse_from(["zcash-warp", "no-such-command"]).unwrap_err();
+        assert_eq!(exit_code(&e.into()), 2);
+        assert_eq!(exit_code(&pay::Error::NotEnoughFunds(1).into()), 3);
+        assert_eq!(exit_code(&SyncError::Reorg(1).into()), 4);
+        assert_eq!(exit_code(&rusqlite::Error::QueryReturnedNoRows.into()), 6);
+        assert_eq!(exit_code(&anyhow::anyhow!("other")), 1);
+    }
+
+    #[test]
+    fn batch_lines_are_split() {
+        assert_eq!(
+            split_args("pay 1 'a b' \"c\"").unwrap(),
+            vec!["pay", "1", "a b", "c"]
+        );
+        assert_eq!(split_args("  ").unwrap(), Vec::<String>::new());
+        assert!(split_args("pay 'a").is_err());
+    }
+
+    #[test]
+    fn single_command_exits() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut chain = MockChain::default();
+        chain.add_block(CompactBlock {
+            height: 100,
+            ..CompactBlock::default()
+        });
+        let url = runtime.block_on(MockLwd::new(chain).start()).unwrap();
+        let mut zec = CoinDef::from_network(NETWORK);
+        zec.set_url(&url);
+        let mut txbytes = vec![];
+        let args = vec!["last-height".to_string()];
+        assert_eq!(run_once(args, false, &mut zec, &mut txbytes), 0);
+        assert!(txbytes.is_empty());
+        let args = vec!["no-such-command".to_string()];
+        assert_eq!(run_once(args, false, &mut zec, &mut txbytes), 2);
+    }
+
+    #[test]
+    fn configured_history_file() {
+        let dir = std::env::temp_dir().join(format!("warp-history-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let configured = dir.join("history");
+        let path = history_path(configured.to_str()).unwrap();
+        assert_eq!(path, configured);
+        let path = create_history_file(path).unwrap();
+        assert!(path.is_file());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sensitive_commands_are_not_saved() {
+        assert!(is_sensitive("generate-seed"));
+        assert!(is_sensitive("account  create 'seed words'"));
+        assert!(!is_sensitive("account list"));
+        assert!(!is_sensitive("backup-all"));
+    }
+
+    #[test]
+    fn logging_respects_the_level() {
+        use tracing_subscriber::filter::LevelFilter;
+
+        let path = std::env::temp_dir().join(format!("warp-log-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let filter = log_filter(Some("warn"), None).unwrap();
+        let subscriber = log_subscriber(filter, path.to_str()).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("filtered out by the level");
+            tracing::warn!("above the level");
+        });
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.contains("above the level"));
+        assert!(!log.contains("filtered out by the level"));
+        let _ = std::fs::remove_file(&path);
+
+        // RUST_LOG wins over the level, unless it is empty
+        let filter = log_filter(Some("warn"), Some("debug")).unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::DEBUG));
+        let filter = log_filter(Some("warn"), Some("")).unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::WARN));
+    }
+
+    #[test]
+    fn checkpoints_at_every_interval() {
+        let mut heights = vec![];
+        let mut height = 1_234;
+        while height < 350_123 {
+            height = next_checkpoint(height, 350_123, 100_000);
+            heights.push(height);
+        }
+        assert_eq!(heights, vec![100_000, 200_000, 300_000, 350_123]);
+        assert_eq!(next_checkpoint(10, 20, 0), 11);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sync_stops_at_the_target() {
+        let mut chain = chain(10);
+        chain.set_tree_state(TreeState {
+            height: (TEST_BIRTH + 4) as u64,
+            ..TreeState::default()
+        });
+        let lwd = MockLwd::new(chain);
+        let coin = wallet("sync-to", &lwd).await;
+        sync_loop(&coin, 1, Some(TEST_BIRTH + 4)).await.unwrap();
+        let sync_height = get_sync_height(&coin.connection().unwrap()).unwrap();
+        assert_eq!(sync_height, Some(TEST_BIRTH + 4));
+        assert!(sync_loop(&coin, 1, Some(TEST_BIRTH + 2)).await.is_err());
+        assert!(sync_loop(&coin, 1, Some(TEST_BIRTH + 11)).await.is_err());
+    }
+
+    #[test]
+    fn read_commands_need_an_account() {
+        let path = std::env::temp_dir().join(format!("warp-empty-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut zec = CoinDef::from_network(NETWORK);
+        zec.set_db_path(&path).unwrap();
+        reset_tables(&zec.connection().unwrap()).unwrap();
+        let mut txbytes = vec![];
+        for command in [
+            "balance 1",
+            "address-totals 1",
+            "list-txs 1",
+            "list-notes 1",
+            "list-messages 1",
+            "sync",
+            "sync-to 2000100",
+        ] {
+            let args = std::iter::once("zcash-warp")
+                .chain(command.split(' '))
+                .collect::<Vec<_>>();
+            let command = Command::try_parse_from(args).unwrap();
+            let e = process_command(command, &mut zec, &mut txbytes).unwrap_err();
+            assert_eq!(e.to_string(), "No accounts; create one first");
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn freshly_mined_funds_with_zero_confirmations() {
+        use crate::{
+            db::notes::snap_to_checkpoint,
+            pay::{make_payment, Payment, PaymentItem, PaymentOptions},
+            types::PoolMask,
+            warp::legacy::CommitmentTreeFrontier,
+        };
+
+        assert!(checked_confirmations(0, false).is_err());
+        assert_eq!(checked_confirmations(0, true).unwrap(), 1);
+        assert_eq!(checked_confirmations(3, false).unwrap(), 3);
+
+        // regtest: the funds are in the block just mined, which has a checkpoint
+        let tip = TEST_BIRTH + 10;
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        for height in [TEST_BIRTH, tip] {
+            connection
+                .execute(
+                    "INSERT INTO blcks(height, hash, prev_hash, timestamp)
+                    VALUES (?1, randomblob(32), randomblob(32), 0)",
+                    [height],
+                )
+                .unwrap();
+        }
+        add_utxo(&connection, account, tip, 10_000_000);
+        let tree = CommitmentTreeFrontier::default();
+        let pay = |confirmations: u32| {
+            let confirmations = checked_confirmations(confirmations, true).unwrap();
+            let cp_height = snap_to_checkpoint(&connection, tip + 1 - confirmations).unwrap();
+            let p = Payment {
+                recipients: vec![PaymentItem {
+                    address: crate::db::testing::external_address(&NETWORK),
+                    amount: 1_000_000,
+                    memo: None,
+                }],
+            };
+            make_payment(
+                &NETWORK,
+                &connection,
+                account,
+                cp_height,
+                p,
+                PoolMask(1),
+                true,
+                &tree,
+                &tree,
+                &PaymentOptions::default(),
+            )
+        };
+        assert!(pay(0).is_ok());
+        // with the default safe confirmations, the funds are not spendable yet
+        assert!(pay(3).is_err());
+    }
+
+    #[test]
+    fn read_commands_do_not_load_the_provers() {
+        let params_missing = zcash_proofs::prover::LocalTxProver::with_default_location().is_none();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let url = runtime.block_on(MockLwd::new(chain(1)).start()).unwrap();
+        let path = std::env::temp_dir().join(format!("warp-read-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut zec = CoinDef::from_network(NETWORK);
+        zec.set_db_path(&path).unwrap();
+        zec.set_url(&url);
+        let connection = zec.connection().unwrap();
+        reset_tables(&connection).unwrap();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH, 1000);
+        let mut txbytes = vec![];
+        for command in ["balance 1", "list-txs 1", "list-notes 1"] {
+            let args = std::iter::once("zcash-warp")
+                .chain(command.split(' '))
+                .collect::<Vec<_>>();
+            let command = Command::try_parse_from(args).unwrap();
+            process_command(command, &mut zec, &mut txbytes).unwrap();
+        }
+        if params_missing {
+            assert!(matches!(
+                pay::provers(),
+                Err(pay::Error::NoSaplingParameters)
+            ));
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn health_with_an_unreachable_server() {
+        let path = std::env::temp_dir().join(format!("warp-health-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut zec = CoinDef::from_network(NETWORK);
+        zec.set_db_path(&path).unwrap();
+        // nothing listens on this port
+        zec.set_url("http://127.0.0.1:1");
+        let connection = zec.connection().unwrap();
+        reset_tables(&connection).unwrap();
+        test_account(&NETWORK, &connection);
+
+        let health = health_check(&zec).await.unwrap();
+        for key in [
+            "healthy",
+            "server",
+            "server_height",
+            "sync_height",
+            "lag",
+            "integrity",
+            "parameters",
+            "accounts",
+        ] {
+            assert!(health.get(key).is_some(), "{key} is missing");
+        }
+        assert_eq!(health["healthy"], json!(false));
+        assert_eq!(health["server"]["reachable"], json!(false));
+        assert!(health["server_height"].is_null() && health["lag"].is_null());
+        assert_eq!(health["integrity"], json!("ok"));
+        assert_eq!(health["accounts"], json!(1));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unexpired_broadcasts_are_sent_again() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let lwd = MockLwd::new(chain(10));
+        let url = runtime.block_on(lwd.start()).unwrap();
+        let path = std::env::temp_dir().join(format!("warp-rebroadcast-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut zec = CoinDef::from_network(NETWORK);
+        zec.set_db_path(&path).unwrap();
+        zec.set_url(&url);
+        let connection = zec.connection().unwrap();
+        reset_tables(&connection).unwrap();
+        let account = test_account(&NETWORK, &connection);
+        // the tip is at TEST_BIRTH + 10
+        for (i, expiration_height) in [(1u8, TEST_BIRTH + 40), (2, TEST_BIRTH + 5)] {
+            let id = store_pending_tx(
+                &connection,
+                account,
+                TEST_BIRTH,
+                expiration_height,
+                &[i; 32],
+                &[i; 10],
+                "{}",
+            )
+            .unwrap();
+            let pending = get_pending_tx(&connection, id).unwrap().unwrap();
+            store_broadcast(&connection, &pending, &pending.txid, TEST_BIRTH, 0).unwrap();
+        }
+
+        let command = Command::try_parse_from(["zcash-warp", "rebroadcast-pending", "1"]).unwrap();
+        process_command(command, &mut zec, &mut vec![]).unwrap();
+        assert_eq!(lwd.chain.lock().sent, vec![vec![1u8; 10]]);
+        let broadcasts = list_unmined_broadcasts(&connection, account).unwrap();
+        assert_eq!(broadcasts.len(), 1);
+        assert_eq!(broadcasts[0].txid, [1u8; 32]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn multi_recipient_uri_is_decoded() {
+        use crate::{
+            pay::PaymentItem,
+            utils::uri::{make_payment_uri, UriPayment},
+        };
+        use zcash_primitives::memo::{Memo, MemoBytes};
+
+        let shielded = external_address(&NETWORK);
+        let transparent = "t1XVXWCvpMgBvUaed4XDqWtgQgJSu1Ghz7F".to_string();
+        let recipients = [
+            UriPayment {
+                item: PaymentItem {
+                    address: shielded.clone(),
+                    amount: 150_000_000,
+                    memo: Some(MemoBytes::from(&"Thanks".parse::<Memo>().unwrap())),
+                },
+                label: Some("Coffee".to_string()),
+                message: None,
+            },
+            UriPayment {
+                item: PaymentItem {
+                    address: transparent.clone(),
+                    amount: 2_500_000,
+                    memo: None,
+                },
+                label: None,
+                message: Some("Change".to_string()),
+            },
+        ];
+        let uri = make_payment_uri(&NETWORK, &recipients).unwrap();
+        assert_eq!(
+            decode_payment_uri(&NETWORK, &uri).unwrap(),
+            vec![
+                json!({
+                    "address": shielded,
+                    "amount": 150_000_000,
+                    "zec": "1.50000000",
+                    "memo": "Thanks",
+                    "label": "Coffee",
+                    "message": null,
+                }),
+                json!({
+                    "address": transparent,
+                    "amount": 2_500_000,
+                    "zec": "0.02500000",
+                    "memo": null,
+                    "label": null,
+                    "message": "Change",
+                }),
+            ]
+        );
+        // the addresses are checked against the network, with or without memo
+        assert!(decode_payment_uri(&Network::TestNetwork, &uri).is_err());
+        let uri = make_payment_uri(&NETWORK, &recipients[1..]).unwrap();
+        assert!(decode_payment_uri(&Network::TestNetwork, &uri).is_err());
+    }
+
+    #[test]
+    fn decoded_pending_matches_the_payment() {
+        use crate::{
+            db::{account::get_account_info, pending::get_latest_pending_tx},
+            keys::TSKStore,
+            pay::{
+                testing::{pay_from, CP_HEIGHT},
+                Payment, PaymentItem, PaymentOptions,
+            },
+            types::PoolMask,
+        };
+        use zcash_client_backend::encoding::AddressCodec as _;
+
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let taddr = ai.transparent.as_ref().unwrap().addr.encode(&NETWORK);
+        // m/44'/133'/0'/0/1 of the test seed, not an address of the account
+        let recipient = "t1aQ2b1XszNVo15BguYLbQGqETBL9QZA8Jq".to_string();
+        let p = Payment {
+            recipients: vec![PaymentItem {
+                address: recipient.clone(),
+                amount: 1_000_000,
+                memo: None,
+            }],
+        };
+        let options = PaymentOptions {
+            change_address: Some((taddr.clone(), 1)),
+            ..PaymentOptions::default()
+        };
+        let utx = pay_from(&connection, account, p, PoolMask(1), &options).unwrap();
+        let fee = utx.to_summary().unwrap().fee;
+        display_tx(
+            &NETWORK,
+            &connection,
+            CP_HEIGHT,
+            utx,
+            &mut TSKStore::default(),
+        )
+        .unwrap();
+
+        let pending = get_latest_pending_tx(&connection).unwrap().unwrap();
+        let (txid, decoded) = decode_pending(&NETWORK, &pending).unwrap();
+        assert_eq!(txid, pending.txid);
+        assert_eq!(decoded["expiry_height"], json!(pending.expiration_height));
+        assert_eq!(decoded["fee"], json!(fee));
+        assert_eq!(
+            decoded["recipients"],
+            json!([{"address": &recipient, "amount": 1_000_000}])
+        );
+        // the outputs are shuffled
+        let mut outputs = decoded["transparent_outputs"].as_array().unwrap().clone();
+        outputs.sort_by_key(|o| o["value"].as_u64());
+        assert_eq!(
+            json!(outputs),
+            json!([
+                {"address": &recipient, "value": 1_000_000},
+                {"address": &taddr, "value": 10_000_000 - 1_000_000 - fee},
+            ])
+        );
+        assert_eq!(decoded["sapling_outputs"], json!(0));
+        assert_eq!(decoded["orchard_actions"], json!(0));
+    }
+
+    #[test]
+    fn old_anchor_is_warned_about() {
+        use crate::{
+            db::pending::PendingTx, utils::testing::capture_logs, EXPIRATION_HEIGHT_DELTA,
+        };
+
+        let anchor = TEST_BIRTH;
+        let pending = |expiration_height: u32| PendingTx {
+            id: 1,
+            account: 1,
+            height: anchor,
+            expiration_height,
+            txid: [0u8; 32],
+            data: vec![],
+            summary: "{}".to_string(),
+        };
+        let expiring = pending(anchor + EXPIRATION_HEIGHT_DELTA);
+        let warned = |pending: &PendingTx, bc_height: u32| {
+            capture_logs(|| warn_blocks_left(pending, bc_height))
+                .lines()
+                .any(|l| l.contains("WARN") && l.contains("expires in"))
+        };
+        // the tip moved on since the anchor
+        let bc_height = anchor + EXPIRATION_HEIGHT_DELTA - LOW_BLOCKS_LEFT;
+        assert_eq!(expiring.blocks_left(bc_height), Some(LOW_BLOCKS_LEFT));
+        assert!(warned(&expiring, bc_height));
+        assert!(!warned(&expiring, anchor + 1));
+        // no expiry, nothing to warn about
+        assert!(!warned(&pending(0), bc_height));
+    }
+}
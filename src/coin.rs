@@ -4,12 +4,17 @@ use parking_lot::Mutex;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::OptionalExtension;
-use std::{path::Path, time::Duration};
+use std::{collections::VecDeque, path::Path, time::Duration};
 use tonic::transport::{Certificate, ClientTlsConfig};
 
 use zcash_primitives::consensus::Network;
 
-use crate::{lwd::rpc::compact_tx_streamer_client::CompactTxStreamerClient, Client};
+use crate::{
+    lwd::{rpc::compact_tx_streamer_client::CompactTxStreamerClient, DataUsage},
+    types::CheckpointHeight,
+    warp::legacy::CommitmentTreeFrontier,
+    Client,
+};
 
 type Connection = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
 
@@ -20,6 +25,44 @@ pub struct CoinDef {
     pub warp: String,
     pub pool: Option<Pool<SqliteConnectionManager>>,
     pub db_password: Option<String>,
+    /// Shielded notes received below this value are ignored by the sync
+    pub min_note_value: u64,
+    pub tree_states: Mutex<TreeStateCache>,
+    pub data_usage: DataUsage,
+}
+
+/// Number of tree states kept by `CoinDef::get_tree_state`
+const TREE_STATE_CACHE_SIZE: usize = 16;
+
+/// Sapling and orchard tree states by checkpoint height. When it is
+/// full, the least recently used one is dropped
+#[derive(Default, Debug)]
+pub struct TreeStateCache {
+    /// Most recently used last
+    states: VecDeque<(u32, CommitmentTreeFrontier, CommitmentTreeFrontier)>,
+}
+
+impl TreeStateCache {
+    fn get(&mut self, height: u32) -> Option<(CommitmentTreeFrontier, CommitmentTreeFrontier)> {
+        let i = self.states.iter().position(|(h, _, _)| *h == height)?;
+        let state = self.states.remove(i).unwrap();
+        let (_, s, o) = &state;
+        let r = (s.clone(), o.clone());
+        self.states.push_back(state);
+        Some(r)
+    }
+
+    fn insert(&mut self, height: u32, s: CommitmentTreeFrontier, o: CommitmentTreeFrontier) {
+        self.states.retain(|(h, _, _)| *h != height);
+        if self.states.len() >= TREE_STATE_CACHE_SIZE {
+            self.states.pop_front();
+        }
+        self.states.push_back((height, s, o));
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
 }
 
 impl CoinDef {
@@ -30,6 +73,9 @@ impl CoinDef {
             warp: "".to_string(),
             pool: None,
             db_password: None,
+            min_note_value: 0,
+            tree_states: Mutex::new(TreeStateCache::default()),
+            data_usage: DataUsage::default(),
         }
     }
 
@@ -52,6 +98,10 @@ impl CoinDef {
         self.warp = warp.to_string();
     }
 
+    pub fn set_min_note_value(&mut self, min_note_value: u64) {
+        self.min_note_value = min_note_value;
+    }
+
     pub fn connection(&self) -> Result<Connection> {
         let pool = self.pool.as_ref().expect("No db path set");
         let connection = pool.get().unwrap();
@@ -69,12 +119,39 @@ impl CoinDef {
         if c.is_none() {
             anyhow::bail!("Could not open db (invalid password?)")
         }
+        crate::db::migrate_tables(&connection)?;
         Ok(connection)
     }
 
     pub async fn connect_lwd(&self) -> Result<Client> {
         connect_lwd(&self.url).await
     }
+
+    /// `lwd::get_tree_state` with the recent checkpoints cached, so that
+    /// the payments of a session at the same anchor fetch it once.
+    /// The cache is cleared when the sync detects a reorg
+    pub async fn get_tree_state(
+        &self,
+        client: &mut Client,
+        height: CheckpointHeight,
+    ) -> Result<(CommitmentTreeFrontier, CommitmentTreeFrontier)> {
+        let h: u32 = height.into();
+        if let Some(states) = self.tree_states.lock().get(h) {
+            return Ok(states);
+        }
+        let (s, o) = crate::lwd::get_tree_state(client, &self.data_usage, height).await?;
+        self.tree_states.lock().insert(h, s.clone(), o.clone());
+        Ok((s, o))
+    }
+
+    /// Bytes received from lightwalletd since the last reset
+    pub fn data_usage(&self) -> u64 {
+        self.data_usage.get()
+    }
+
+    pub fn reset_data_usage(&self) {
+        self.data_usage.reset()
+    }
 }
 
 pub async fn connect_lwd(url: &str) -> Result<Client> {
@@ -95,3 +172,43 @@ lazy_static! {
         // Mutex::new(CoinDef::from_network(Network::YCashMainNetwork)),
     ];
 }
+
+#[cfg(test)]
+mod tests {
+    use zcash_primitives::consensus::Network;
+
+    use super::CoinDef;
+    use crate::{
+        db::testing::TEST_BIRTH,
+        lwd::mock::{testing::chain, MockLwd},
+        types::CheckpointHeight,
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tree_states_are_cached() {
+        let lwd = MockLwd::new(chain(2));
+        let url = lwd.start().await.unwrap();
+        let mut coin = CoinDef::from_network(Network::MainNetwork);
+        coin.set_url(&url);
+        let mut client = coin.connect_lwd().await.unwrap();
+        let requests = || lwd.chain.lock().tree_state_requests;
+
+        for _ in 0..2 {
+            coin.get_tree_state(&mut client, CheckpointHeight(TEST_BIRTH + 2))
+                .await
+                .unwrap();
+        }
+        assert_eq!(requests(), 1);
+        coin.get_tree_state(&mut client, CheckpointHeight(TEST_BIRTH))
+            .await
+            .unwrap();
+        assert_eq!(requests(), 2);
+
+        // after a reorg
+        coin.tree_states.lock().clear();
+        coin.get_tree_state(&mut client, CheckpointHeight(TEST_BIRTH + 2))
+            .await
+            .unwrap();
+        assert_eq!(requests(), 3);
+    }
+}
@@ -5,9 +5,14 @@ pub(crate) mod account;
 pub(crate) mod account_manager;
 pub(crate) mod contacts;
 pub(crate) mod notes;
+pub(crate) mod pending;
 pub(crate) mod tx;
 pub(crate) mod witnesses;
 
+/// Version of the schema created by `reset_tables`, stored in
+/// `PRAGMA user_version`
+const SCHEMA_VERSION: u32 = 1;
+
 pub fn reset_tables(connection: &Connection) -> Result<()> {
     connection.execute("DROP TABLE IF EXISTS txs", [])?;
     connection.execute("DROP TABLE IF EXISTS notes", [])?;
@@ -17,6 +22,9 @@ pub fn reset_tables(connection: &Connection) -> Result<()> {
     connection.execute("DROP TABLE IF EXISTS txdetails", [])?;
     connection.execute("DROP TABLE IF EXISTS msgs", [])?;
     connection.execute("DROP TABLE IF EXISTS contacts", [])?;
+    connection.execute("DROP TABLE IF EXISTS pending_txs", [])?;
+    connection.execute("DROP TABLE IF EXISTS broadcasts", [])?;
+    connection.execute("DROP TABLE IF EXISTS recent_payments", [])?;
 
     connection.execute(
         "CREATE TABLE IF NOT EXISTS accounts(
@@ -28,7 +36,11 @@ pub fn reset_tables(connection: &Connection) -> Result<()> {
         vk TEXT NOT NULL,
         address TEXT NOT NULL UNIQUE,
         birth INTEGER NOT NULL,
-        saved BOOL NOT NULL)",
+        saved BOOL NOT NULL,
+        default_memo TEXT,
+        frozen BOOL NOT NULL DEFAULT FALSE,
+        rotate_on_receive BOOL NOT NULL DEFAULT FALSE,
+        diversifier_index INTEGER NOT NULL DEFAULT 0)",
         [],
     )?;
 
@@ -66,6 +78,7 @@ pub fn reset_tables(connection: &Connection) -> Result<()> {
         value INTEGER NOT NULL,
         address TEXT,
         memo TEXT,
+        self_transfer BOOL NOT NULL DEFAULT FALSE,
         UNIQUE (account, txid))",
         [],
     )?;
@@ -112,7 +125,8 @@ pub fn reset_tables(connection: &Connection) -> Result<()> {
         "CREATE TABLE IF NOT EXISTS txdetails(
         id_tx INTEGER PRIMARY KEY,
         txid BLOB NOT NULL UNIQUE,
-        data BLOB NOT NULL)",
+        data BLOB NOT NULL,
+        raw BLOB)",
         [],
     )?;
     connection.execute(
@@ -142,6 +156,252 @@ pub fn reset_tables(connection: &Connection) -> Result<()> {
         UNIQUE (account, name))",
         [],
     )?;
+    create_pending_tables(connection)?;
+    connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+    Ok(())
+}
+
+fn create_pending_tables(connection: &Connection) -> Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS pending_txs(
+        id_pending INTEGER PRIMARY KEY,
+        account INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        expiration_height INTEGER NOT NULL,
+        txid BLOB NOT NULL,
+        data BLOB NOT NULL,
+        summary TEXT NOT NULL)",
+        [],
+    )?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS broadcasts(
+        id_broadcast INTEGER PRIMARY KEY,
+        account INTEGER NOT NULL,
+        txid BLOB NOT NULL,
+        height INTEGER NOT NULL,
+        expiration_height INTEGER NOT NULL,
+        data BLOB NOT NULL,
+        summary TEXT NOT NULL,
+        timestamp INTEGER NOT NULL)",
+        [],
+    )?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS recent_payments(
+        id_payment INTEGER PRIMARY KEY,
+        account INTEGER NOT NULL,
+        fingerprint BLOB NOT NULL,
+        timestamp INTEGER NOT NULL)",
+        [],
+    )?;
+
+    Ok(())
+}
 
+/// Columns added since the first release, as (table, column, definition)
+const COLUMNS_V1: &[(&str, &str, &str)] = &[
+    ("accounts", "default_memo", "TEXT"),
+    ("accounts", "frozen", "BOOL NOT NULL DEFAULT FALSE"),
+    (
+        "accounts",
+        "rotate_on_receive",
+        "BOOL NOT NULL DEFAULT FALSE",
+    ),
+    (
+        "accounts",
+        "diversifier_index",
+        "INTEGER NOT NULL DEFAULT 0",
+    ),
+    ("txs", "self_transfer", "BOOL NOT NULL DEFAULT FALSE"),
+    ("txdetails", "raw", "BLOB"),
+];
+
+/// Bring a database created by an earlier version up to `SCHEMA_VERSION`.
+/// Runs every time a connection is opened and does nothing once the
+/// database is current
+pub fn migrate_tables(connection: &Connection) -> Result<()> {
+    let version: u32 = connection.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+    if version < 1 {
+        for (table, column, definition) in COLUMNS_V1 {
+            add_column(connection, table, column, definition)?;
+        }
+        create_pending_tables(connection)?;
+    }
+    connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
     Ok(())
 }
+
+/// Add a column unless the table already has it. Tables that do not
+/// exist yet are left for `reset_tables`
+fn add_column(connection: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let columns = table_columns(connection, table)?;
+    if !columns.is_empty() && !columns.iter().any(|c| c == column) {
+        connection.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Names of the columns of a table, empty if it does not exist
+pub(crate) fn table_columns(connection: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut s = connection.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    let columns = s
+        .query_map([table], |r| r.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(columns)
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use rusqlite::{params, Connection};
+    use sapling_crypto::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::encoding::encode_payment_address;
+    use zcash_primitives::consensus::{Network, NetworkConstants as _};
+
+    use super::{
+        account_manager::{create_new_account, detect_key},
+        reset_tables,
+    };
+
+    /// Seed phrase of the BIP-39 test vectors
+    pub const TEST_SEED: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    pub const TEST_BIRTH: u32 = 2_000_000;
+
+    /// In memory database with the tables of a new wallet
+    pub fn test_db() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        reset_tables(&connection).unwrap();
+        connection
+    }
+
+    /// Account created from `TEST_SEED`, with all the pools
+    pub fn test_account(network: &Network, connection: &Connection) -> u32 {
+        let key = detect_key(network, TEST_SEED, 0, 0).unwrap();
+        create_new_account(network, connection, "test", key, TEST_BIRTH, false).unwrap()
+    }
+
+    /// Unspent transparent output of `value` received by the account
+    pub fn add_utxo(connection: &Connection, account: u32, height: u32, value: u64) {
+        connection
+            .execute(
+                "INSERT INTO utxos(account, height, txid, vout, value)
+                VALUES (?1, ?2, randomblob(32), 0, ?3)",
+                params![account, height, value],
+            )
+            .unwrap();
+    }
+
+    /// Unspent shielded note of `value` received by the account in a
+    /// transaction of its own, with a witness at `height`. Returns the
+    /// id of the transaction
+    pub fn add_note(
+        connection: &Connection,
+        account: u32,
+        height: u32,
+        value: u64,
+        orchard: bool,
+    ) -> u32 {
+        connection
+            .execute(
+                "INSERT INTO txs(account, txid, height, timestamp, value)
+                VALUES (?1, randomblob(32), ?2, 0, ?3)",
+                params![account, height, value],
+            )
+            .unwrap();
+        let id_tx = connection.last_insert_rowid();
+        connection
+            .execute(
+                "INSERT INTO notes(account, position, height, tx, output_index, address,
+                value, rcm, nf, rho, orchard)
+                VALUES (?1, (SELECT COUNT(*) FROM notes WHERE orchard = ?5), ?2, ?3, 0,
+                zeroblob(43), ?4, zeroblob(32), randomblob(32),
+                CASE WHEN ?5 THEN zeroblob(32) END, ?5)",
+                params![account, height, id_tx, value, orchard],
+            )
+            .unwrap();
+        let witness = bincode::serialize(&crate::warp::Witness::default()).unwrap();
+        connection
+            .execute(
+                "INSERT INTO witnesses(account, note, height, witness) VALUES (?1, ?2, ?3, ?4)",
+                params![account, connection.last_insert_rowid(), height, witness],
+            )
+            .unwrap();
+        id_tx as u32
+    }
+
+    /// Sapling address that belongs to no test account
+    pub fn external_address(network: &Network) -> String {
+        let (_, address) = ExtendedSpendingKey::master(&[1u8; 32]).default_address();
+        encode_payment_address(network.hrp_sapling_payment_address(), &address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{migrate_tables, reset_tables, table_columns, COLUMNS_V1, SCHEMA_VERSION};
+    use rusqlite::Connection;
+
+    fn user_version(connection: &Connection) -> u32 {
+        connection
+            .pragma_query_value(None, "user_version", |r| r.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn new_database_is_current() {
+        let connection = Connection::open_in_memory().unwrap();
+        reset_tables(&connection).unwrap();
+        assert_eq!(user_version(&connection), SCHEMA_VERSION);
+        migrate_tables(&connection).unwrap();
+    }
+
+    #[test]
+    fn old_database_is_migrated() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE accounts(id_account INTEGER PRIMARY KEY, name TEXT NOT NULL);
+                INSERT INTO accounts(name) VALUES ('old');
+                CREATE TABLE txs(id_tx INTEGER PRIMARY KEY, value INTEGER NOT NULL);
+                CREATE TABLE txdetails(id_tx INTEGER PRIMARY KEY, data BLOB NOT NULL);",
+            )
+            .unwrap();
+        migrate_tables(&connection).unwrap();
+        assert_eq!(user_version(&connection), SCHEMA_VERSION);
+
+        for (table, column, _) in COLUMNS_V1 {
+            let columns = table_columns(&connection, table).unwrap();
+            assert!(
+                columns.iter().any(|c| c == column),
+                "missing {table}.{column}"
+            );
+        }
+        for t in ["pending_txs", "broadcasts", "recent_payments"] {
+            assert!(
+                !table_columns(&connection, t).unwrap().is_empty(),
+                "missing {t}"
+            );
+        }
+        let frozen: bool = connection
+            .query_row("SELECT frozen FROM accounts WHERE name = 'old'", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert!(!frozen);
+
+        // Running it again is a no-op
+        migrate_tables(&connection).unwrap();
+    }
+
+    #[test]
+    fn missing_tables_are_skipped() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate_tables(&connection).unwrap();
+        assert!(table_columns(&connection, "accounts").unwrap().is_empty());
+    }
+}
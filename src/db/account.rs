@@ -1,44 +1,99 @@
+use std::str::FromStr;
+
 use anyhow::Result;
 use orchard::keys::{FullViewingKey, Scope, SpendingKey};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension as _};
 use zcash_client_backend::encoding::{
     decode_extended_full_viewing_key, decode_extended_spending_key, decode_payment_address,
     AddressCodec as _,
 };
+use zcash_keys::address::Address as RecipientAddress;
 use zcash_primitives::consensus::{Network, NetworkConstants as _};
 use zcash_primitives::legacy::TransparentAddress;
+use sapling_crypto::PaymentAddress;
 
 use crate::data::fb::AccountNameT;
+use crate::utils::ua::ua_of_orchard;
 use crate::keys::import_sk_bip38;
 use crate::types::{
     AccountInfo, Balance, OrchardAccountInfo, SaplingAccountInfo, TransparentAccountInfo,
 };
 
+/// Accounts with their unspent balance, in creation order
 pub fn list_accounts(connection: &Connection) -> Result<Vec<AccountNameT>> {
-    let mut s =
-        connection.prepare("SELECT id_account, name, address, birth FROM accounts ORDER BY id_account")?;
+    let mut s = connection.prepare(
+        "SELECT a.id_account, a.name, a.address, a.birth,
+        (SELECT COALESCE(SUM(value), 0) FROM utxos WHERE account = a.id_account AND spent IS NULL) +
+        (SELECT COALESCE(SUM(value), 0) FROM notes WHERE account = a.id_account AND spent IS NULL)
+        FROM accounts a ORDER BY a.id_account",
+    )?;
     let rows = s.query_map([], |r| {
         Ok((
             r.get::<_, u32>(0)?,
             r.get::<_, String>(1)?,
             r.get::<_, String>(2)?,
             r.get::<_, u32>(3)?,
+            r.get::<_, u64>(4)?,
         ))
     })?;
     let mut accounts = vec![];
     for r in rows {
-        let (id, name, address, birth) = r?;
+        let (id, name, address, birth, balance) = r?;
         accounts.push(AccountNameT {
             id,
             name: Some(name),
             sapling_address: Some(address),
             birth,
+            balance,
         });
     }
 
     Ok(accounts)
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AccountSort {
+    Id,
+    Name,
+    Birth,
+    Balance,
+}
+
+impl FromStr for AccountSort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "id" => Ok(AccountSort::Id),
+            "name" => Ok(AccountSort::Name),
+            "birth" => Ok(AccountSort::Birth),
+            "balance" => Ok(AccountSort::Balance),
+            _ => anyhow::bail!("Invalid sort {s} (id, name, birth or balance)"),
+        }
+    }
+}
+
+/// `list_accounts` sorted by the given key. Ties are broken
+/// by account id so the order is stable
+pub fn list_accounts_sorted(
+    connection: &Connection,
+    sort_by: AccountSort,
+    descending: bool,
+) -> Result<Vec<AccountNameT>> {
+    let mut accounts = list_accounts(connection)?;
+    accounts.sort_by(|a, b| {
+        let o = match sort_by {
+            AccountSort::Id => a.id.cmp(&b.id),
+            AccountSort::Name => a.name.cmp(&b.name),
+            AccountSort::Birth => a.birth.cmp(&b.birth),
+            AccountSort::Balance => a.balance.cmp(&b.balance),
+        };
+        let o = if descending { o.reverse() } else { o };
+        o.then(a.id.cmp(&b.id))
+    });
+    Ok(accounts)
+}
+
 pub fn get_account_info(
     network: &Network,
     connection: &Connection,
@@ -116,41 +171,359 @@ pub fn get_account_info(
             };
             Ok(ai)
         },
-    )?;
+    )
+    .optional()?
+    .ok_or(anyhow::anyhow!("Account {account} does not exist"))?;
     Ok(ai)
 }
 
+pub fn account_exists(connection: &Connection, account: u32) -> Result<bool> {
+    let c = connection.query_row(
+        "SELECT COUNT(*) FROM accounts WHERE id_account = ?1",
+        [account],
+        |r| r.get::<_, u32>(0),
+    )?;
+    Ok(c > 0)
+}
+
+pub fn has_accounts(connection: &Connection) -> Result<bool> {
+    let c = connection.query_row("SELECT COUNT(*) FROM accounts", [], |r| r.get::<_, u32>(0))?;
+    Ok(c > 0)
+}
+
+/// Value received by each address of the account, spent or not.
+/// Notes are attributed to the diversified address they were sent to.
+/// Change and funds moved between our pools are not counted
+pub fn address_totals(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+) -> Result<Vec<(String, u64)>> {
+    let mut totals = vec![];
+    let mut s = connection.prepare(
+        "SELECT t.address, SUM(u.value) FROM utxos u
+        JOIN t_accounts t ON u.account = t.account
+        JOIN txs x ON x.account = u.account AND x.txid = u.txid
+        WHERE u.account = ?1 AND x.value > 0 GROUP BY t.address",
+    )?;
+    let rows = s.query_map([account], |r| {
+        Ok((r.get::<_, String>(0)?, r.get::<_, u64>(1)?))
+    })?;
+    for r in rows {
+        totals.push(r?);
+    }
+
+    let mut s = connection.prepare(
+        "SELECT n.address, n.orchard, SUM(n.value) FROM notes n
+        JOIN txs x ON n.tx = x.id_tx
+        WHERE n.account = ?1 AND x.value > 0 GROUP BY n.address, n.orchard",
+    )?;
+    let rows = s.query_map([account], |r| {
+        Ok((
+            r.get::<_, [u8; 43]>(0)?,
+            r.get::<_, bool>(1)?,
+            r.get::<_, u64>(2)?,
+        ))
+    })?;
+    for r in rows {
+        let (address, orchard, value) = r?;
+        let address = encode_note_address(network, &address, orchard)?;
+        totals.push((address, value));
+    }
+    Ok(totals)
+}
+
+/// Addresses of the account that received funds in more than one
+/// transaction, with the number of transactions. Like `address_totals`,
+/// change and funds moved between our pools are not counted
+pub fn detect_address_reuse(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+) -> Result<Vec<(String, u32)>> {
+    let mut reused = vec![];
+    let mut s = connection.prepare(
+        "SELECT t.address, COUNT(DISTINCT u.txid) FROM utxos u
+        JOIN t_accounts t ON u.account = t.account
+        JOIN txs x ON x.account = u.account AND x.txid = u.txid
+        WHERE u.account = ?1 AND x.value > 0 GROUP BY t.address
+        HAVING COUNT(DISTINCT u.txid) > 1",
+    )?;
+    let rows = s.query_map([account], |r| {
+        Ok((r.get::<_, String>(0)?, r.get::<_, u32>(1)?))
+    })?;
+    for r in rows {
+        reused.push(r?);
+    }
+
+    let mut s = connection.prepare(
+        "SELECT n.address, n.orchard, COUNT(DISTINCT n.tx) FROM notes n
+        JOIN txs x ON n.tx = x.id_tx
+        WHERE n.account = ?1 AND x.value > 0 GROUP BY n.address, n.orchard
+        HAVING COUNT(DISTINCT n.tx) > 1",
+    )?;
+    let rows = s.query_map([account], |r| {
+        Ok((
+            r.get::<_, [u8; 43]>(0)?,
+            r.get::<_, bool>(1)?,
+            r.get::<_, u32>(2)?,
+        ))
+    })?;
+    for r in rows {
+        let (address, orchard, count) = r?;
+        let address = encode_note_address(network, &address, orchard)?;
+        reused.push((address, count));
+    }
+    Ok(reused)
+}
+
+/// Account of the wallet that receives on `address`, if any
+pub fn find_address_account(
+    network: &Network,
+    connection: &Connection,
+    address: &str,
+) -> Result<Option<u32>> {
+    let address = RecipientAddress::decode(network, address)
+        .ok_or(anyhow::anyhow!("Invalid address {address}"))?;
+    for a in list_accounts(connection)? {
+        let ai = get_account_info(network, connection, a.id)?;
+        if ai.owns_address(&address) {
+            return Ok(Some(a.id));
+        }
+    }
+    Ok(None)
+}
+
+fn encode_note_address(network: &Network, address: &[u8; 43], orchard: bool) -> Result<String> {
+    let address = if orchard {
+        ua_of_orchard(address).encode(network)
+    } else {
+        PaymentAddress::from_bytes(address)
+            .ok_or(anyhow::anyhow!("Invalid sapling address"))?
+            .encode(network)
+    };
+    Ok(address)
+}
+
+/// Balance of the account at `height`, all pools together: the notes
+/// and UTXOs received at or before that height and not spent by then.
+/// A spent note is still counted at the heights before its spend
+pub fn get_balance_at(connection: &Connection, account: u32, height: u32) -> Result<u64> {
+    let balance = connection.query_row(
+        "SELECT
+        (SELECT COALESCE(SUM(value), 0) FROM utxos
+        WHERE account = ?1 AND height <= ?2 AND (spent IS NULL OR spent > ?2)) +
+        (SELECT COALESCE(SUM(value), 0) FROM notes
+        WHERE account = ?1 AND height <= ?2 AND (spent IS NULL OR spent > ?2))",
+        params![account, height],
+        |r| r.get::<_, u64>(0),
+    )?;
+    Ok(balance)
+}
+
+fn to_value_count(r: &rusqlite::Row) -> rusqlite::Result<(u64, u32)> {
+    Ok((
+        r.get::<_, Option<u64>>(0)?.unwrap_or_default(),
+        r.get::<_, u32>(1)?,
+    ))
+}
+
 pub fn get_balance(connection: &Connection, account: u32, height: u32) -> Result<Balance> {
-    let transparent = connection
-        .query_row(
-            "SELECT SUM(value) FROM utxos
+    let (transparent, transparent_notes) = connection.query_row(
+        "SELECT SUM(value), COUNT(*) FROM utxos
         WHERE account = ?1 AND height <= ?2 AND spent IS NULL",
-            params![account, height],
-            |r| r.get::<_, Option<u64>>(0),
-        )?
-        .unwrap_or_default();
-    let sapling = connection
-        .query_row(
-            "SELECT SUM(value) FROM notes
+        params![account, height],
+        to_value_count,
+    )?;
+    let (sapling, sapling_notes) = connection.query_row(
+        "SELECT SUM(value), COUNT(*) FROM notes
         WHERE account = ?1 AND height <= ?2 AND orchard = 0
         AND spent IS NULL",
-            params![account, height],
-            |r| r.get::<_, Option<u64>>(0),
-        )?
-        .unwrap_or_default();
-    let orchard = connection
-        .query_row(
-            "SELECT SUM(value) FROM notes
+        params![account, height],
+        to_value_count,
+    )?;
+    let (orchard, orchard_notes) = connection.query_row(
+        "SELECT SUM(value), COUNT(*) FROM notes
         WHERE account = ?1 AND height <= ?2 AND orchard = 1
         AND spent IS NULL",
-            params![account, height],
-            |r| r.get::<_, Option<u64>>(0),
-        )?
-        .unwrap_or_default();
+        params![account, height],
+        to_value_count,
+    )?;
     let b = Balance {
         transparent,
         sapling,
         orchard,
+        transparent_notes,
+        sapling_notes,
+        orchard_notes,
     };
     Ok(b)
 }
+
+#[cfg(test)]
+mod tests {
+    use orchard::keys::Scope;
+    use rusqlite::params;
+    use zcash_client_backend::encoding::AddressCodec as _;
+    use zcash_primitives::consensus::Network;
+
+    use super::{
+        address_totals, detect_address_reuse, get_account_info, get_balance, get_balance_at,
+        list_accounts_sorted, AccountSort,
+    };
+    use crate::{
+        db::{
+            account_manager::{create_new_account, detect_key},
+            testing::{add_note, add_utxo, test_account, test_db, TEST_BIRTH, TEST_SEED},
+        },
+        utils::ua::ua_of_orchard,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    #[test]
+    fn totals_per_receiving_address() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let oi = ai.orchard.as_ref().unwrap();
+        let address = |i: u64| oi.vk.address_at(i, Scope::External).to_raw_address_bytes();
+        let (a0, a1) = (address(0), address(1));
+        let txid = [1u8; 32];
+        connection
+            .execute(
+                "INSERT INTO txs(id_tx, account, txid, height, timestamp, value)
+                VALUES (1, ?1, ?2, ?3, 0, 420)",
+                params![account, txid, TEST_BIRTH],
+            )
+            .unwrap();
+        for (position, address, value) in [(0u32, a0, 100u64), (1, a1, 70), (2, a0, 50)] {
+            connection
+                .execute(
+                    "INSERT INTO notes(account, position, height, tx, output_index, address,
+                    value, rcm, nf, rho, orchard)
+                    VALUES (?1, ?2, ?3, 1, ?2, ?4, ?5, zeroblob(32), randomblob(32),
+                    zeroblob(32), TRUE)",
+                    params![account, position, TEST_BIRTH, &address[..], value],
+                )
+                .unwrap();
+        }
+        connection
+            .execute(
+                "INSERT INTO utxos(account, height, txid, vout, value)
+                VALUES (?1, ?2, ?3, 0, 200)",
+                params![account, TEST_BIRTH, txid],
+            )
+            .unwrap();
+
+        let mut totals = address_totals(&NETWORK, &connection, account).unwrap();
+        totals.sort();
+        let mut expected = vec![
+            (ai.transparent.as_ref().unwrap().addr.encode(&NETWORK), 200),
+            (ua_of_orchard(&a0).encode(&NETWORK), 150),
+            (ua_of_orchard(&a1).encode(&NETWORK), 70),
+        ];
+        expected.sort();
+        assert_eq!(totals, expected);
+    }
+
+    #[test]
+    fn accounts_sorted_by_balance() {
+        let connection = test_db();
+        for (aindex, name, value) in [(0, "a", 300), (1, "b", 500), (2, "c", 300)] {
+            let key = detect_key(&NETWORK, TEST_SEED, aindex, 0).unwrap();
+            let account =
+                create_new_account(&NETWORK, &connection, name, key, TEST_BIRTH, false).unwrap();
+            add_utxo(&connection, account, TEST_BIRTH, value);
+        }
+        let accounts = list_accounts_sorted(&connection, AccountSort::Balance, true).unwrap();
+        let order = accounts
+            .iter()
+            .map(|a| (a.name.clone().unwrap(), a.balance))
+            .collect::<Vec<_>>();
+        // ties keep the creation order
+        assert_eq!(
+            order,
+            vec![
+                ("b".to_string(), 500),
+                ("a".to_string(), 300),
+                ("c".to_string(), 300)
+            ]
+        );
+    }
+
+    #[test]
+    fn note_counts_per_pool() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH, 1000);
+        for value in [100, 200, 300] {
+            add_note(&connection, account, TEST_BIRTH, value, false);
+        }
+        add_note(&connection, account, TEST_BIRTH, 5000, true);
+        add_note(&connection, account, TEST_BIRTH, 7000, true);
+        // not confirmed at the height of the balance
+        add_note(&connection, account, TEST_BIRTH + 10, 9000, true);
+
+        let balance = get_balance(&connection, account, TEST_BIRTH).unwrap();
+        assert_eq!((balance.transparent, balance.transparent_notes), (1000, 1));
+        assert_eq!((balance.sapling, balance.sapling_notes), (600, 3));
+        assert_eq!((balance.orchard, balance.orchard_notes), (12_000, 2));
+    }
+
+    #[test]
+    fn balances_at_past_heights() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let h = TEST_BIRTH;
+        add_utxo(&connection, account, h + 10, 1000);
+        add_note(&connection, account, h + 20, 20_000, false);
+        add_note(&connection, account, h + 30, 300_000, true);
+        // the utxo is spent at +25, the sapling note at +40
+        connection
+            .execute("UPDATE utxos SET spent = ?1", [h + 25])
+            .unwrap();
+        connection
+            .execute("UPDATE notes SET spent = ?1 WHERE orchard = 0", [h + 40])
+            .unwrap();
+
+        let balance = |height: u32| get_balance_at(&connection, account, height).unwrap();
+        assert_eq!(balance(h + 5), 0);
+        assert_eq!(balance(h + 20), 21_000);
+        assert_eq!(balance(h + 30), 320_000);
+        assert_eq!(balance(h + 40), 300_000);
+    }
+
+    #[test]
+    fn address_receiving_twice_is_reused() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let oi = ai.orchard.as_ref().unwrap();
+        let address = |i: u64| oi.vk.address_at(i, Scope::External).to_raw_address_bytes();
+        let (a0, a1) = (address(0), address(1));
+        // the last transaction is a payment of ours, its note is change
+        let notes = [(1u32, 100i64, a0), (2, 200, a0), (3, 300, a1), (4, -50, a1)];
+        for (id_tx, value, address) in notes {
+            connection
+                .execute(
+                    "INSERT INTO txs(id_tx, account, txid, height, timestamp, value)
+                    VALUES (?1, ?2, randomblob(32), ?3, 0, ?4)",
+                    params![id_tx, account, TEST_BIRTH, value],
+                )
+                .unwrap();
+            connection
+                .execute(
+                    "INSERT INTO notes(account, position, height, tx, output_index, address,
+                    value, rcm, nf, rho, orchard)
+                    VALUES (?1, ?2, ?3, ?2, 0, ?4, 10, zeroblob(32), randomblob(32),
+                    zeroblob(32), TRUE)",
+                    params![account, id_tx, TEST_BIRTH, &address[..]],
+                )
+                .unwrap();
+        }
+
+        let reused = detect_address_reuse(&NETWORK, &connection, account).unwrap();
+        assert_eq!(reused, [(ua_of_orchard(&a0).encode(&NETWORK), 2)]);
+    }
+}
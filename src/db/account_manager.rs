@@ -1,6 +1,8 @@
+use std::str::FromStr as _;
+
 use anyhow::Result;
 use bip39::{Mnemonic, Seed};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension as _};
 use zcash_client_backend::{
     encoding::{
         decode_extended_full_viewing_key, decode_extended_spending_key,
@@ -9,11 +11,18 @@ use zcash_client_backend::{
     },
     keys::UnifiedFullViewingKey,
 };
-use zcash_primitives::consensus::{Network, NetworkConstants as _};
+use zcash_primitives::{
+    consensus::{Network, NetworkConstants as _},
+    memo::Memo,
+};
 use sapling_crypto::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
 
 use crate::{
-    keys::{derive_bip32, derive_orchard_zip32, derive_zip32, export_sk_bip38, import_sk_bip38},
+    data::fb::BackupT,
+    keys::{
+        derive_bip32, derive_orchard_zip32, derive_zip32, export_sk_bip38, import_sk_bip38,
+        transparent_from_sk,
+    },
     types::{OrchardAccountInfo, SaplingAccountInfo, TransparentAccountInfo},
 };
 
@@ -75,10 +84,31 @@ pub fn create_new_account(
     name: &str,
     key: KeyType,
     birth: u32,
+    merge: bool,
 ) -> Result<u32> {
+    let si = match &key {
+        KeyType::Seed(_, seed, acc_index, _) => derive_zip32(network, seed, *acc_index),
+        KeyType::SaplingSK(sk) => SaplingAccountInfo::from_sk(sk),
+        KeyType::SaplingVK(vk) => SaplingAccountInfo::from_vk(vk),
+        KeyType::UnifiedVK(uvk) => {
+            let svk = uvk.sapling().ok_or(anyhow::anyhow!("Missing sapling receiver"))?;
+            SaplingAccountInfo::from_dvk(svk)
+        }
+        KeyType::Transparent => {
+            anyhow::bail!("Transparent Private Keys are not supported. Use Sweep instead.")
+        }
+    };
+    if let Some(existing) = find_account_by_vk(network, connection, &si)? {
+        if !merge {
+            anyhow::bail!("This key is already used by account #{existing}, use --merge to combine them");
+        }
+        merge_account(connection, existing, name, birth)?;
+        upgrade_account_keys(network, connection, existing, &key, &si)?;
+        return Ok(existing);
+    }
+
     let account = match key {
         KeyType::Seed(seed_str, seed, acc_index, _addr_index) => {
-            let si = derive_zip32(network, &seed, acc_index);
             let account =
                 create_sapling_account(network, connection, name, Some(&seed_str), acc_index, birth, &si)?;
             // This should have been acc_index / addr_index but ZecWallet Lite derives
@@ -89,21 +119,17 @@ pub fn create_new_account(
             create_orchard_account(network, connection, account, &oi)?;
             account
         }
-        KeyType::SaplingSK(sk) => {
-            let si = SaplingAccountInfo::from_sk(&sk);
+        KeyType::SaplingSK(_) => {
             let account =
                 create_sapling_account(network, connection, name, None, 0, birth, &si)?;
             account
         },
-        KeyType::SaplingVK(vk) => {
-            let si = SaplingAccountInfo::from_vk(&vk);
+        KeyType::SaplingVK(_) => {
             let account =
                 create_sapling_account(network, connection, name, None, 0, birth, &si)?;
             account
         },
         KeyType::UnifiedVK(uvk) => {
-            let svk = uvk.sapling().ok_or(anyhow::anyhow!("Missing sapling receiver"))?;
-            let si = SaplingAccountInfo::from_dvk(&svk);
             let account =
                 create_sapling_account(network, connection, name, None, 0, birth, &si)?;
             uvk.orchard().map(|ovk| {
@@ -120,6 +146,112 @@ pub fn create_new_account(
     Ok(account)
 }
 
+/// Account that has the same sapling viewing key
+pub fn find_account_by_vk(
+    network: &Network,
+    connection: &Connection,
+    si: &SaplingAccountInfo,
+) -> Result<Option<u32>> {
+    let vk =
+        encode_extended_full_viewing_key(network.hrp_sapling_extended_full_viewing_key(), &si.vk);
+    let account = connection
+        .query_row("SELECT id_account FROM accounts WHERE vk = ?1", [vk], |r| {
+            r.get::<_, u32>(0)
+        })
+        .optional()?;
+    Ok(account)
+}
+
+/// Fold a duplicate import into an existing account: keep the
+/// earliest birth height and append the new name if it differs
+pub fn merge_account(connection: &Connection, account: u32, name: &str, birth: u32) -> Result<()> {
+    connection.execute(
+        "UPDATE accounts SET birth = MIN(birth, ?2),
+        name = CASE WHEN name = ?3 THEN name ELSE name || ' / ' || ?3 END
+        WHERE id_account = ?1",
+        params![account, birth, name],
+    )?;
+    Ok(())
+}
+
+/// Store the keys of a merged import that the existing account lacks,
+/// so that importing the spending key or the seed of a view-only
+/// account makes it spendable. Keys already present are kept
+fn upgrade_account_keys(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    key: &KeyType,
+    si: &SaplingAccountInfo,
+) -> Result<()> {
+    if let Some(sk) = si.sk.as_ref() {
+        let sk = encode_extended_spending_key(network.hrp_sapling_extended_spending_key(), sk);
+        connection.execute(
+            "UPDATE accounts SET sk = ?2 WHERE id_account = ?1 AND sk IS NULL",
+            params![account, sk],
+        )?;
+    }
+    if let KeyType::Seed(seed_str, seed, acc_index, _) = key {
+        connection.execute(
+            "UPDATE accounts SET seed = ?2, aindex = ?3 WHERE id_account = ?1 AND seed IS NULL",
+            params![account, seed_str, acc_index],
+        )?;
+        let ti = derive_bip32(network, seed, 0, *acc_index, true);
+        connection.execute(
+            "INSERT INTO t_accounts(account, sk, address) VALUES (?1, ?2, ?3)
+            ON CONFLICT (account) DO NOTHING",
+            params![account, export_sk_bip38(&ti.sk), ti.addr.encode(network)],
+        )?;
+        let oi = derive_orchard_zip32(network, seed, *acc_index);
+        connection.execute(
+            "INSERT INTO o_accounts(account, sk, vk) VALUES (?1, ?2, ?3)
+            ON CONFLICT (account) DO UPDATE SET sk = excluded.sk
+            WHERE o_accounts.sk IS NULL AND o_accounts.vk = excluded.vk",
+            params![account, oi.sk.as_ref().map(|sk| sk.to_bytes()), oi.vk.to_bytes()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Restore an account from a `Backup`, with its name, birth height
+/// and backup status. The key is taken from the seed phrase if any,
+/// then the sapling secret key, the unified viewing key and
+/// the sapling viewing key. A transparent key is imported too
+/// when the account has no seed.
+/// The number of confirmations is a wallet setting and is not
+/// part of the backup
+pub fn create_account_from_backup(
+    network: &Network,
+    connection: &Connection,
+    backup: &BackupT,
+) -> Result<u32> {
+    let key = backup
+        .seed
+        .as_ref()
+        .or(backup.sk.as_ref())
+        .or(backup.uvk.as_ref())
+        .or(backup.fvk.as_ref())
+        .ok_or(anyhow::anyhow!("Backup has no key"))?;
+    let kt = detect_key(network, key, backup.index, 0)?;
+    let is_seed = matches!(kt, KeyType::Seed(..));
+    let name = backup.name.as_deref().unwrap_or("<unnamed>");
+    let account = create_new_account(network, connection, name, kt, backup.birth, false)?;
+    if !is_seed {
+        if let Some(tsk) = backup.tsk.as_ref() {
+            let sk = import_sk_bip38(tsk)?;
+            let ti = transparent_from_sk(sk, true);
+            create_transparent_account(network, connection, account, &ti)?;
+        }
+    }
+    if backup.saved {
+        connection.execute(
+            "UPDATE accounts SET saved = TRUE WHERE id_account = ?1",
+            [account],
+        )?;
+    }
+    Ok(account)
+}
+
 pub fn create_sapling_account(
     network: &Network,
     connection: &Connection,
@@ -195,6 +327,59 @@ pub fn edit_account_birth(connection: &Connection, account: u32, birth: u32) ->
     Ok(())    
 }
 
+/// Memo of the shielded outputs that do not have one.
+/// None removes it
+pub fn set_default_memo(connection: &Connection, account: u32, memo: Option<&str>) -> Result<()> {
+    if let Some(memo) = memo {
+        Memo::from_str(memo)?;
+    }
+    connection.execute("UPDATE accounts SET default_memo = ?2 where id_account = ?1",
+        params![account, memo])?;
+    Ok(())
+}
+
+pub fn get_default_memo(connection: &Connection, account: u32) -> Result<Option<String>> {
+    let memo = connection.query_row("SELECT default_memo FROM accounts WHERE id_account = ?1",
+        [account], |r| r.get::<_, Option<String>>(0))?;
+    Ok(memo)
+}
+
+/// A frozen account keeps syncing but cannot spend
+pub fn freeze_account(connection: &Connection, account: u32, frozen: bool) -> Result<()> {
+    connection.execute("UPDATE accounts SET frozen = ?2 where id_account = ?1",
+        params![account, frozen])?;
+    Ok(())
+}
+
+pub fn is_account_frozen(connection: &Connection, account: u32) -> Result<bool> {
+    let frozen = connection.query_row("SELECT frozen FROM accounts WHERE id_account = ?1",
+        [account], |r| r.get::<_, bool>(0))?;
+    Ok(frozen)
+}
+
+/// Policy and index of the receiving address, see
+/// `account::address::rotate_received_addresses`
+pub fn get_address_rotation(connection: &Connection, account: u32) -> Result<(bool, u64)> {
+    let r = connection.query_row(
+        "SELECT rotate_on_receive, diversifier_index FROM accounts WHERE id_account = ?1",
+        [account],
+        |r| Ok((r.get::<_, bool>(0)?, r.get::<_, u64>(1)?)),
+    )?;
+    Ok(r)
+}
+
+pub fn set_rotate_on_receive(connection: &Connection, account: u32, rotate: bool) -> Result<()> {
+    connection.execute("UPDATE accounts SET rotate_on_receive = ?2 where id_account = ?1",
+        params![account, rotate])?;
+    Ok(())
+}
+
+pub fn set_diversifier_index(connection: &Connection, account: u32, index: u64) -> Result<()> {
+    connection.execute("UPDATE accounts SET diversifier_index = ?2 where id_account = ?1",
+        params![account, index])?;
+    Ok(())
+}
+
 pub fn delete_account(connection: &Connection, account: u32) -> Result<()> {
     connection.execute("DELETE FROM notes WHERE account = ?1", params![account])?;
     connection.execute("DELETE FROM txs WHERE account = ?1", params![account])?;
@@ -218,3 +403,104 @@ pub fn get_min_birth(connection: &Connection) -> Result<Option<u32>> {
     let birth = connection.query_row("SELECT MIN(birth) FROM accounts", [], |r| r.get::<_, Option<u32>>(0))?;
     Ok(birth)
 }
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::encoding::encode_extended_full_viewing_key;
+    use zcash_primitives::consensus::{Network, NetworkConstants as _};
+
+    use super::{
+        create_account_from_backup, create_new_account, detect_key, edit_account_birth,
+        edit_account_name, parse_seed_phrase, KeyType,
+    };
+    use crate::{
+        db::{
+            account::get_account_info,
+            testing::{test_account, test_db, TEST_BIRTH, TEST_SEED},
+        },
+        keys::derive_zip32,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    fn viewing_key() -> KeyType {
+        let seed = parse_seed_phrase(TEST_SEED).unwrap();
+        let si = derive_zip32(&NETWORK, &seed, 0);
+        let vk = encode_extended_full_viewing_key(
+            NETWORK.hrp_sapling_extended_full_viewing_key(),
+            &si.vk,
+        );
+        detect_key(&NETWORK, &vk, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn merged_seed_makes_view_only_account_spendable() {
+        let connection = test_db();
+        let account =
+            create_new_account(&NETWORK, &connection, "view", viewing_key(), TEST_BIRTH, false)
+                .unwrap();
+        assert!(get_account_info(&NETWORK, &connection, account)
+            .unwrap()
+            .sapling
+            .sk
+            .is_none());
+
+        let seed = detect_key(&NETWORK, TEST_SEED, 0, 0).unwrap();
+        let merged =
+            create_new_account(&NETWORK, &connection, "seed", seed, TEST_BIRTH, true).unwrap();
+        assert_eq!(merged, account);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        assert!(ai.sapling.sk.is_some());
+        assert!(ai.seed.is_some());
+        assert!(ai.transparent.is_some());
+        assert!(ai.orchard.and_then(|oi| oi.sk).is_some());
+    }
+
+    #[test]
+    fn duplicate_needs_merge() {
+        let connection = test_db();
+        create_new_account(&NETWORK, &connection, "view", viewing_key(), TEST_BIRTH, false)
+            .unwrap();
+        let seed = detect_key(&NETWORK, TEST_SEED, 0, 0).unwrap();
+        assert!(
+            create_new_account(&NETWORK, &connection, "seed", seed, TEST_BIRTH, false).is_err()
+        );
+    }
+
+    #[test]
+    fn transparent_key_is_refused() {
+        let connection = test_db();
+        assert!(create_new_account(
+            &NETWORK,
+            &connection,
+            "t",
+            KeyType::Transparent,
+            TEST_BIRTH,
+            true
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn backup_round_trips_name_and_birth() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        edit_account_name(&connection, account, "savings").unwrap();
+        edit_account_birth(&connection, account, TEST_BIRTH + 1_000).unwrap();
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let backup = ai.to_backup(&NETWORK);
+        let mut view_only = ai.to_backup(&NETWORK);
+        view_only.seed = None;
+        view_only.sk = None;
+
+        for (backup, spendable) in [(backup, true), (view_only, false)] {
+            let connection = test_db();
+            let restored = create_account_from_backup(&NETWORK, &connection, &backup).unwrap();
+            let ai2 = get_account_info(&NETWORK, &connection, restored).unwrap();
+            assert_eq!(ai2.name, "savings");
+            assert_eq!(ai2.birth, TEST_BIRTH + 1_000);
+            assert_eq!(ai2.sapling.addr, ai.sapling.addr);
+            assert_eq!(ai2.sapling.sk.is_some(), spendable);
+        }
+    }
+}
@@ -46,6 +46,37 @@ pub fn snap_to_checkpoint(connection: &Connection,
     Ok(CheckpointHeight(height))
 }
 
+/// Checkpoint at exactly `height`, to anchor a payment of the account
+/// instead of the one derived from the confirmations. It is too old if
+/// funds of the account were spent after it, since they would still
+/// be selected as inputs
+pub fn checkpoint_anchor(
+    connection: &Connection,
+    account: u32,
+    height: u32,
+) -> Result<CheckpointHeight> {
+    let exists = connection
+        .query_row("SELECT 1 FROM blcks WHERE height = ?1", [height], |_| Ok(()))
+        .optional()?
+        .is_some();
+    if !exists {
+        anyhow::bail!("No checkpoint at {height}");
+    }
+    let last_spent = connection.query_row(
+        "SELECT MAX(spent) FROM (
+        SELECT spent FROM notes WHERE account = ?1
+        UNION ALL SELECT spent FROM utxos WHERE account = ?1)",
+        [account],
+        |r| r.get::<_, Option<u32>>(0),
+    )?;
+    if let Some(last_spent) = last_spent {
+        if last_spent > height {
+            anyhow::bail!("Checkpoint {height} is too old, funds were spent at {last_spent}");
+        }
+    }
+    Ok(CheckpointHeight(height))
+}
+
 pub fn list_received_notes(
     connection: &Connection,
     height: CheckpointHeight,
@@ -275,6 +306,18 @@ pub fn list_utxos(connection: &Connection, height: CheckpointHeight) -> Result<V
     Ok(utxos)
 }
 
+/// Value of the utxo `txid:vout`, if the wallet has it
+pub fn get_utxo_value(connection: &Connection, txid: &[u8], vout: u32) -> Result<Option<u64>> {
+    let value = connection
+        .query_row(
+            "SELECT value FROM utxos WHERE txid = ?1 AND vout = ?2",
+            params![txid, vout],
+            |r| r.get::<_, u64>(0),
+        )
+        .optional()?;
+    Ok(value)
+}
+
 pub fn store_utxo(
     connection: &Transaction,
     utxo: &UTXO,
@@ -349,6 +392,24 @@ pub fn get_unspent_notes(connection: &Connection, account: u32, bc_height: u32)
     Ok(notes)
 }
 
+/// Nullifiers of the unspent shielded notes of the account, for a
+/// service that watches the chain for their spends.
+/// A nullifier is public only once the note is spent: whoever
+/// receives them before can recognize the spend and link it to
+/// the wallet
+pub fn list_unspent_nullifiers(connection: &Connection, account: u32) -> Result<Vec<Hash>> {
+    let mut s = connection.prepare(
+        "SELECT nf FROM notes WHERE account = ?1 AND spent IS NULL
+        ORDER BY id_note",
+    )?;
+    let rows = s.query_map([account], |r| r.get::<_, Hash>(0))?;
+    let mut nfs = vec![];
+    for r in rows {
+        nfs.push(r?);
+    }
+    Ok(nfs)
+}
+
 pub fn get_sync_height(connection: &Connection) -> Result<Option<u32>> {
     let height = connection.query_row("SELECT MAX(height) FROM blcks", [], |r| {
         r.get::<_, Option<u32>>(0)
@@ -356,6 +417,93 @@ pub fn get_sync_height(connection: &Connection) -> Result<Option<u32>> {
     Ok(height)
 }
 
+/// Hash of the block at the sync height, in internal byte order
+pub fn get_sync_block_hash(connection: &Connection) -> Result<Option<Hash>> {
+    let hash = connection
+        .query_row(
+            "SELECT hash FROM blcks WHERE height = (SELECT MAX(height) FROM blcks)",
+            [],
+            |r| r.get::<_, Hash>(0),
+        )
+        .optional()?;
+    Ok(hash)
+}
+
+/// Check the stored blocks. They are the checkpoints of the sync,
+/// so their heights are not contiguous, but:
+/// - a block at the height following another one must link to its hash,
+/// - every note not spent yet at a checkpoint must have a witness there,
+/// otherwise a sync step did not complete.
+/// Fails on the first problem found
+pub fn verify_block_continuity(connection: &Connection) -> Result<()> {
+    let mut s = connection.prepare("SELECT height, hash, prev_hash FROM blcks ORDER BY height")?;
+    let rows = s.query_map([], |r| {
+        Ok((
+            r.get::<_, u32>(0)?,
+            r.get::<_, Hash>(1)?,
+            r.get::<_, Hash>(2)?,
+        ))
+    })?;
+    let mut prev: Option<(u32, Hash)> = None;
+    for r in rows {
+        let (height, hash, prev_hash) = r?;
+        if let Some((prev_height, prev_block_hash)) = prev {
+            if prev_height + 1 == height && prev_block_hash != prev_hash {
+                anyhow::bail!("Block {height} does not link to block {prev_height}");
+            }
+        }
+        prev = Some((height, hash));
+    }
+
+    let missing = connection
+        .query_row(
+            "SELECT b.height, n.id_note FROM blcks b
+            JOIN notes n ON n.height <= b.height AND (n.spent IS NULL OR n.spent > b.height)
+            LEFT JOIN witnesses w ON w.note = n.id_note AND w.height = b.height
+            WHERE w.note IS NULL ORDER BY b.height LIMIT 1",
+            [],
+            |r| Ok((r.get::<_, u32>(0)?, r.get::<_, u32>(1)?)),
+        )
+        .optional()?;
+    if let Some((height, id_note)) = missing {
+        anyhow::bail!("Note {id_note} has no witness at checkpoint {height}, the sync has a gap");
+    }
+    Ok(())
+}
+
+/// Stored checkpoints (height, hash), oldest first
+pub fn list_checkpoints(connection: &Connection) -> Result<Vec<(u32, Hash)>> {
+    let mut s = connection.prepare("SELECT height, hash FROM blcks ORDER BY height")?;
+    let rows = s.query_map([], |r| Ok((r.get::<_, u32>(0)?, r.get::<_, Hash>(1)?)))?;
+    let mut checkpoints = vec![];
+    for r in rows {
+        checkpoints.push(r?);
+    }
+    Ok(checkpoints)
+}
+
+/// True if a scan starting at `height` could miss funds: any account
+/// ever received a sapling note, or has a utxo or a transaction
+/// below `height`
+pub fn has_activity_before(connection: &Connection, height: u32) -> Result<bool> {
+    let c = connection.query_row(
+        "SELECT (SELECT COUNT(*) FROM notes WHERE orchard = 0)
+        + (SELECT COUNT(*) FROM utxos WHERE height < ?1)
+        + (SELECT COUNT(*) FROM txs WHERE height < ?1)",
+        [height],
+        |r| r.get::<_, u32>(0),
+    )?;
+    Ok(c > 0)
+}
+
+/// Range of heights covered by the stored checkpoints
+pub fn get_scanned_range(connection: &Connection) -> Result<Option<(u32, u32)>> {
+    let range = connection.query_row("SELECT MIN(height), MAX(height) FROM blcks", [], |r| {
+        Ok((r.get::<_, Option<u32>>(0)?, r.get::<_, Option<u32>>(1)?))
+    })?;
+    Ok(range.0.zip(range.1))
+}
+
 pub fn truncate_scan(connection: &Connection) -> Result<()> {
     connection.execute("DELETE FROM blcks", [])?;
     connection.execute("DELETE FROM txs", [])?;
@@ -421,3 +569,129 @@ pub fn store_tx_details(connection: &Connection, id: u32, txid: &Hash, data: &[u
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::{
+        get_sync_block_hash, has_activity_before, list_unspent_nullifiers, store_block,
+        verify_block_continuity,
+    };
+    use crate::{
+        db::testing::{add_note, add_utxo, test_db},
+        warp::BlockHeader,
+        Hash,
+    };
+
+    const NU5: u32 = 1_687_104;
+
+    #[test]
+    fn empty_wallet_has_no_activity() {
+        let connection = test_db();
+        assert!(!has_activity_before(&connection, NU5).unwrap());
+    }
+
+    #[test]
+    fn utxo_before_nu5_is_activity() {
+        let connection = test_db();
+        add_utxo(&connection, 1, NU5 + 10, 1000);
+        assert!(!has_activity_before(&connection, NU5).unwrap());
+        add_utxo(&connection, 1, NU5 - 10, 1000);
+        assert!(has_activity_before(&connection, NU5).unwrap());
+    }
+
+    #[test]
+    fn transaction_before_nu5_is_activity() {
+        let connection = test_db();
+        connection
+            .execute(
+                "INSERT INTO txs(account, txid, height, timestamp, value)
+                VALUES (1, x'aa', ?1, 0, -1000)",
+                [NU5 - 10],
+            )
+            .unwrap();
+        assert!(has_activity_before(&connection, NU5).unwrap());
+    }
+
+    #[test]
+    fn sapling_note_is_activity() {
+        let connection = test_db();
+        connection
+            .execute(
+                "INSERT INTO notes(account, position, height, output_index, address,
+                value, rcm, nf, orchard) VALUES (1, 0, ?1, 0, x'', 1000, x'', x'00', FALSE)",
+                [NU5 + 10],
+            )
+            .unwrap();
+        assert!(has_activity_before(&connection, NU5).unwrap());
+    }
+
+    /// Store the block at `height` with the hash `[hash; 32]`,
+    /// linked to `[prev; 32]`
+    fn store(connection: &mut Connection, height: u32, hash: u8, prev: u8) {
+        let db_tx = connection.transaction().unwrap();
+        let bh = BlockHeader {
+            height,
+            hash: [hash; 32],
+            prev_hash: [prev; 32],
+            timestamp: 0,
+        };
+        store_block(&db_tx, &bh).unwrap();
+        db_tx.commit().unwrap();
+    }
+
+    #[test]
+    fn hash_of_the_last_stored_block() {
+        let mut connection = test_db();
+        assert_eq!(get_sync_block_hash(&connection).unwrap(), None);
+        store(&mut connection, NU5, 1, 0);
+        assert_eq!(get_sync_block_hash(&connection).unwrap(), Some([1u8; 32]));
+        store(&mut connection, NU5 + 1, 2, 1);
+        assert_eq!(get_sync_block_hash(&connection).unwrap(), Some([2u8; 32]));
+    }
+
+    #[test]
+    fn fork_between_stored_blocks_is_detected() {
+        let mut connection = test_db();
+        store(&mut connection, NU5, 1, 0);
+        store(&mut connection, NU5 + 1, 2, 1);
+        // checkpoints are not contiguous
+        store(&mut connection, NU5 + 10, 3, 9);
+        verify_block_continuity(&connection).unwrap();
+        store(&mut connection, NU5 + 11, 4, 5);
+        assert!(verify_block_continuity(&connection).is_err());
+    }
+
+    #[test]
+    fn missing_witness_is_detected() {
+        let mut connection = test_db();
+        store(&mut connection, NU5, 1, 0);
+        add_note(&connection, 1, NU5, 1000, false);
+        verify_block_continuity(&connection).unwrap();
+        // the sync step at the next checkpoint did not store the witness
+        store(&mut connection, NU5 + 10, 2, 1);
+        assert!(verify_block_continuity(&connection).is_err());
+    }
+
+    #[test]
+    fn nullifiers_of_the_unspent_notes() {
+        let connection = test_db();
+        for (account, orchard) in [(1, false), (1, true), (1, false), (2, false)] {
+            add_note(&connection, account, 2_000_000, 1000, orchard);
+        }
+        connection
+            .execute("UPDATE notes SET spent = 2000010 WHERE id_note = 3", [])
+            .unwrap();
+        let mut s = connection
+            .prepare("SELECT nf FROM notes WHERE id_note IN (1, 2) ORDER BY id_note")
+            .unwrap();
+        let stored = s
+            .query_map([], |r| r.get::<_, Hash>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(list_unspent_nullifiers(&connection, 1).unwrap(), stored);
+        assert_eq!(list_unspent_nullifiers(&connection, 2).unwrap().len(), 1);
+    }
+}
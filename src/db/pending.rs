@@ -0,0 +1,301 @@
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension as _};
+
+use crate::{Hash, DUPLICATE_PAYMENT_WINDOW};
+
+#[derive(Clone, Debug)]
+pub struct PendingTx {
+    pub id: u32,
+    pub account: u32,
+    pub height: u32,
+    pub expiration_height: u32,
+    pub txid: Hash,
+    pub data: Vec<u8>,
+    pub summary: String,
+}
+
+impl PendingTx {
+    /// A transaction cannot be mined after its expiration height
+    pub fn is_expired(&self, bc_height: u32) -> bool {
+        self.expiration_height != 0 && self.expiration_height <= bc_height
+    }
+
+    /// Blocks left before the expiration height, 0 once expired.
+    /// Consensus does not limit how old the anchor may be, but the
+    /// expiration height is set relative to the anchor, so this is
+    /// how long the transaction built on it stays valid
+    pub fn blocks_left(&self, bc_height: u32) -> Option<u32> {
+        if self.expiration_height == 0 {
+            return None;
+        }
+        Some(self.expiration_height.saturating_sub(bc_height))
+    }
+}
+
+/// Queue a prepared transaction, ready to be broadcast
+pub fn store_pending_tx(
+    connection: &Connection,
+    account: u32,
+    height: u32,
+    expiration_height: u32,
+    txid: &Hash,
+    data: &[u8],
+    summary: &str,
+) -> Result<u32> {
+    let id = connection.query_row(
+        "INSERT INTO pending_txs(account, height, expiration_height, txid, data, summary)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6) RETURNING id_pending",
+        params![account, height, expiration_height, txid, data, summary],
+        |r| r.get::<_, u32>(0),
+    )?;
+    Ok(id)
+}
+
+fn to_pending_tx(r: &rusqlite::Row) -> rusqlite::Result<PendingTx> {
+    Ok(PendingTx {
+        id: r.get(0)?,
+        account: r.get(1)?,
+        height: r.get(2)?,
+        expiration_height: r.get(3)?,
+        txid: r.get(4)?,
+        data: r.get(5)?,
+        summary: r.get(6)?,
+    })
+}
+
+pub fn list_pending_txs(connection: &Connection) -> Result<Vec<PendingTx>> {
+    let mut s = connection.prepare(
+        "SELECT id_pending, account, height, expiration_height, txid, data, summary
+        FROM pending_txs ORDER BY id_pending",
+    )?;
+    let rows = s.query_map([], to_pending_tx)?;
+    let mut txs = vec![];
+    for r in rows {
+        txs.push(r?);
+    }
+    Ok(txs)
+}
+
+pub fn get_pending_tx(connection: &Connection, id: u32) -> Result<Option<PendingTx>> {
+    let tx = connection
+        .query_row(
+            "SELECT id_pending, account, height, expiration_height, txid, data, summary
+            FROM pending_txs WHERE id_pending = ?1",
+            [id],
+            to_pending_tx,
+        )
+        .optional()?;
+    Ok(tx)
+}
+
+pub fn get_latest_pending_tx(connection: &Connection) -> Result<Option<PendingTx>> {
+    let tx = connection
+        .query_row(
+            "SELECT id_pending, account, height, expiration_height, txid, data, summary
+            FROM pending_txs ORDER BY id_pending DESC LIMIT 1",
+            [],
+            to_pending_tx,
+        )
+        .optional()?;
+    Ok(tx)
+}
+
+pub fn delete_pending_tx(connection: &Connection, id: u32) -> Result<()> {
+    connection.execute("DELETE FROM pending_txs WHERE id_pending = ?1", [id])?;
+    Ok(())
+}
+
+/// Move a pending transaction to the broadcast history
+pub fn store_broadcast(
+    connection: &Connection,
+    pending: &PendingTx,
+    txid: &Hash,
+    height: u32,
+    timestamp: u32,
+) -> Result<()> {
+    connection.execute(
+        "INSERT INTO broadcasts(account, txid, height, expiration_height, data, summary,
+        timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            pending.account,
+            txid,
+            height,
+            pending.expiration_height,
+            pending.data,
+            pending.summary,
+            timestamp
+        ],
+    )?;
+    delete_pending_tx(connection, pending.id)?;
+    Ok(())
+}
+
+/// A transaction of the broadcast history
+#[derive(Clone, Debug)]
+pub struct Broadcast {
+    pub id: u32,
+    pub account: u32,
+    pub txid: Hash,
+    pub expiration_height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Broadcast {
+    pub fn is_expired(&self, bc_height: u32) -> bool {
+        self.expiration_height != 0 && self.expiration_height <= bc_height
+    }
+}
+
+/// Broadcast transactions of the account that are not mined yet
+pub fn list_unmined_broadcasts(connection: &Connection, account: u32) -> Result<Vec<Broadcast>> {
+    let mut s = connection.prepare(
+        "SELECT b.id_broadcast, b.account, b.txid, b.expiration_height, b.data
+        FROM broadcasts b WHERE b.account = ?1 AND NOT EXISTS
+        (SELECT 1 FROM txs t WHERE t.txid = b.txid AND t.height > 0)
+        ORDER BY b.id_broadcast",
+    )?;
+    let rows = s.query_map([account], |r| {
+        Ok(Broadcast {
+            id: r.get(0)?,
+            account: r.get(1)?,
+            txid: r.get(2)?,
+            expiration_height: r.get(3)?,
+            data: r.get(4)?,
+        })
+    })?;
+    let mut txs = vec![];
+    for r in rows {
+        txs.push(r?);
+    }
+    Ok(txs)
+}
+
+pub fn delete_broadcast(connection: &Connection, id: u32) -> Result<()> {
+    connection.execute("DELETE FROM broadcasts WHERE id_broadcast = ?1", [id])?;
+    Ok(())
+}
+
+/// Remember a payment that was built, see `Payment::fingerprint`.
+/// The entries older than `DUPLICATE_PAYMENT_WINDOW` are pruned
+pub fn store_recent_payment(
+    connection: &Connection,
+    account: u32,
+    fingerprint: &Hash,
+    timestamp: u32,
+) -> Result<()> {
+    connection.execute(
+        "DELETE FROM recent_payments WHERE timestamp < ?1",
+        [timestamp.saturating_sub(DUPLICATE_PAYMENT_WINDOW)],
+    )?;
+    connection.execute(
+        "INSERT INTO recent_payments(account, fingerprint, timestamp)
+        VALUES (?1, ?2, ?3)",
+        params![account, fingerprint, timestamp],
+    )?;
+    Ok(())
+}
+
+/// Timestamp of the latest payment with the same fingerprint built
+/// after `since`
+pub fn find_recent_payment(
+    connection: &Connection,
+    account: u32,
+    fingerprint: &Hash,
+    since: u32,
+) -> Result<Option<u32>> {
+    let timestamp = connection.query_row(
+        "SELECT MAX(timestamp) FROM recent_payments
+        WHERE account = ?1 AND fingerprint = ?2 AND timestamp >= ?3",
+        params![account, fingerprint, since],
+        |r| r.get::<_, Option<u32>>(0),
+    )?;
+    Ok(timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::{
+        find_recent_payment, get_latest_pending_tx, get_pending_tx, list_pending_txs,
+        list_unmined_broadcasts, store_broadcast, store_pending_tx, store_recent_payment,
+    };
+    use crate::{
+        db::{reset_tables, testing::test_db},
+        DUPLICATE_PAYMENT_WINDOW,
+    };
+
+    #[test]
+    fn pending_tx_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("warp-pending-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = Connection::open(&path).unwrap();
+        reset_tables(&connection).unwrap();
+        let id = store_pending_tx(&connection, 1, 100, 140, &[1u8; 32], &[2u8; 10], "{}").unwrap();
+        drop(connection);
+
+        let connection = Connection::open(&path).unwrap();
+        let pending = get_latest_pending_tx(&connection).unwrap().unwrap();
+        assert_eq!(pending.id, id);
+        assert_eq!(pending.txid, [1u8; 32]);
+        assert_eq!(pending.data, vec![2u8; 10]);
+        assert_eq!(pending.expiration_height, 140);
+        assert_eq!(pending.summary, "{}");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn broadcast_the_second_payment_only() {
+        let connection = test_db();
+        let first =
+            store_pending_tx(&connection, 1, 100, 140, &[1u8; 32], &[1u8; 10], "{}").unwrap();
+        let second =
+            store_pending_tx(&connection, 1, 100, 140, &[2u8; 32], &[2u8; 10], "{}").unwrap();
+        assert_eq!(list_pending_txs(&connection).unwrap().len(), 2);
+
+        let pending = get_pending_tx(&connection, second).unwrap().unwrap();
+        store_broadcast(&connection, &pending, &pending.txid, 101, 0).unwrap();
+        let pending = list_pending_txs(&connection).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, first);
+        let broadcasts = list_unmined_broadcasts(&connection, 1).unwrap();
+        assert_eq!(broadcasts.len(), 1);
+        assert_eq!(broadcasts[0].txid, [2u8; 32]);
+        assert_eq!(broadcasts[0].data, vec![2u8; 10]);
+    }
+
+    #[test]
+    fn recent_payments_are_pruned_on_insert() {
+        let connection = test_db();
+        let count = || {
+            connection
+                .query_row("SELECT COUNT(*) FROM recent_payments", [], |r| {
+                    r.get::<_, u32>(0)
+                })
+                .unwrap()
+        };
+        store_recent_payment(&connection, 1, &[1u8; 32], 1000).unwrap();
+        let later = 1000 + DUPLICATE_PAYMENT_WINDOW + 1;
+        // looking up does not change the table
+        assert_eq!(
+            find_recent_payment(&connection, 1, &[1u8; 32], 1000).unwrap(),
+            Some(1000)
+        );
+        assert_eq!(
+            find_recent_payment(&connection, 1, &[1u8; 32], 1001).unwrap(),
+            None
+        );
+        assert_eq!(count(), 1);
+
+        store_recent_payment(&connection, 1, &[2u8; 32], later).unwrap();
+        assert_eq!(count(), 1);
+        assert_eq!(
+            find_recent_payment(&connection, 1, &[1u8; 32], 0).unwrap(),
+            None
+        );
+        assert_eq!(
+            find_recent_payment(&connection, 1, &[2u8; 32], 0).unwrap(),
+            Some(later)
+        );
+    }
+}
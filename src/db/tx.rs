@@ -1,11 +1,27 @@
 use crate::{
-    data::fb::ShieldedMessageT,
+    data::fb::{ShieldedMessageT, TransactionInfoT, TransactionSummaryT},
+    utils::to_txid_str,
     txdetails::TransactionDetails,
     warp::sync::{ExtendedReceivedTx, ReceivedTx, TxValueUpdate},
     Hash,
 };
+use std::str::FromStr;
+
 use anyhow::Result;
-use rusqlite::{params, Connection, Transaction};
+use rusqlite::{params, Connection, OptionalExtension as _, Transaction};
+
+/// Columns read by `to_extended_received_tx`, from `TX_FROM`
+const TX_COLUMNS: &str = "id_tx, account, txid, height, timestamp, value, address, memo,
+    self_transfer, COALESCE(n_value, 0) + COALESCE(u_value, 0)";
+
+/// Transactions with the value received by their notes and UTXOs,
+/// summed in one pass instead of a query per transaction
+const TX_FROM: &str = "txs
+    LEFT JOIN (SELECT tx AS n_tx, SUM(value) AS n_value FROM notes GROUP BY tx)
+    ON n_tx = id_tx
+    LEFT JOIN (SELECT account AS u_account, txid AS u_txid, SUM(value) AS u_value
+    FROM utxos GROUP BY account, txid)
+    ON u_txid = txid AND u_account = account";
 
 pub fn list_new_txids(connection: &Connection) -> Result<Vec<(u32, u32, u32, Hash)>> {
     let mut s = connection.prepare(
@@ -30,39 +46,203 @@ pub fn list_new_txids(connection: &Connection) -> Result<Vec<(u32, u32, u32, Has
 }
 
 pub fn list_txs(connection: &Connection, account: u32) -> Result<Vec<ExtendedReceivedTx>> {
+    let mut s = connection.prepare(&format!(
+        "SELECT {TX_COLUMNS} FROM {TX_FROM}
+        WHERE account = ?1 ORDER BY height DESC, id_tx DESC"
+    ))?;
+    let rows = s.query_map([account], to_extended_received_tx)?;
+    let mut txs = vec![];
+    for r in rows {
+        txs.push(r?);
+    }
+    Ok(txs)
+}
+
+/// Call `f` with each transaction of the account mined at or before
+/// `height`, latest first. Rows are read from the cursor one at a
+/// time, so the history is never held in memory
+pub fn for_each_tx<F: FnMut(ExtendedReceivedTx) -> Result<()>>(
+    connection: &Connection,
+    account: u32,
+    height: u32,
+    mut f: F,
+) -> Result<()> {
+    let mut s = connection.prepare(&format!(
+        "SELECT {TX_COLUMNS} FROM {TX_FROM}
+        WHERE account = ?1 AND height <= ?2 ORDER BY height DESC, id_tx DESC"
+    ))?;
+    let rows = s.query_map([account, height], to_extended_received_tx)?;
+    for r in rows {
+        f(r?)?;
+    }
+    Ok(())
+}
+
+/// Transactions of the account that were broadcast but are not mined
+/// yet, latest first. The amount and the recipient come from the
+/// summary made when the transaction was built
+pub fn list_pending(connection: &Connection, account: u32) -> Result<Vec<TransactionInfoT>> {
     let mut s = connection.prepare(
-        "SELECT id_tx, txid, height, timestamp, value, address, memo FROM txs
-        WHERE account = ?1",
+        "SELECT b.id_broadcast, b.txid, b.timestamp, b.summary FROM broadcasts b
+        WHERE b.account = ?1 AND NOT EXISTS
+        (SELECT 1 FROM txs t WHERE t.txid = b.txid AND t.height > 0)
+        ORDER BY b.id_broadcast DESC",
     )?;
     let rows = s.query_map([account], |r| {
         Ok((
             r.get::<_, u32>(0)?,
-            r.get::<_, Vec<u8>>(1)?,
+            r.get::<_, Hash>(1)?,
             r.get::<_, u32>(2)?,
-            r.get::<_, u32>(3)?,
-            r.get::<_, i64>(4)?,
-            r.get::<_, Option<String>>(5)?,
-            r.get::<_, Option<String>>(6)?,
+            r.get::<_, String>(3)?,
         ))
     })?;
     let mut txs = vec![];
     for r in rows {
-        let (id_tx, txid, height, timestamp, value, address, memo) = r?;
-        let rtx = ReceivedTx {
-            id: id_tx,
-            account,
-            height,
-            txid: txid.try_into().unwrap(),
+        let (id, txid, timestamp, summary) = r?;
+        let summary: TransactionSummaryT = serde_json::from_str(&summary)?;
+        let recipients = summary.recipients.unwrap_or_default();
+        let value_out = recipients.iter().map(|r| r.amount).sum::<u64>() + summary.fee;
+        let address = recipients.first().and_then(|r| r.address.clone());
+        txs.push(TransactionInfoT {
+            id,
+            txid: Some(to_txid_str(&txid)),
+            height: 0,
+            confirmations: 0,
             timestamp,
-            value,
-            ivtx: 0,
-        };
-        let ertx = ExtendedReceivedTx { rtx, address, memo };
-        txs.push(ertx);
+            amount: -(value_out as i64),
+            address,
+            contact: None,
+            memo: None,
+            status: Some("pending".to_string()),
+            self_transfer: false,
+            value_in: 0,
+            value_out,
+        });
     }
     Ok(txs)
 }
 
+/// A page of the transactions of the account, latest first,
+/// and the total number of transactions
+pub fn list_txs_paged(
+    connection: &Connection,
+    account: u32,
+    offset: u32,
+    limit: u32,
+) -> Result<(Vec<ExtendedReceivedTx>, usize)> {
+    list_txs_filtered(connection, account, &TxFilter::default(), offset, limit)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TxDirection {
+    Sent,
+    Received,
+    /// Funds moved between the pools of the account,
+    /// there is no external recipient
+    SelfTransfer,
+}
+
+impl FromStr for TxDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sent" => Ok(TxDirection::Sent),
+            "received" => Ok(TxDirection::Received),
+            "self" => Ok(TxDirection::SelfTransfer),
+            _ => anyhow::bail!("Invalid direction {s} (sent, received or self)"),
+        }
+    }
+}
+
+/// Criteria on the transaction history. Bounds are inclusive
+/// and None does not filter
+#[derive(Clone, Default, Debug)]
+pub struct TxFilter {
+    pub from_height: Option<u32>,
+    pub to_height: Option<u32>,
+    pub from_time: Option<u32>,
+    pub to_time: Option<u32>,
+    pub direction: Option<TxDirection>,
+}
+
+/// Same as `list_txs_paged` with a filter. The total is the number of
+/// transactions that pass the filter
+pub fn list_txs_filtered(
+    connection: &Connection,
+    account: u32,
+    filter: &TxFilter,
+    offset: u32,
+    limit: u32,
+) -> Result<(Vec<ExtendedReceivedTx>, usize)> {
+    const CONDITION: &str = "account = ?1
+        AND (?2 IS NULL OR height >= ?2) AND (?3 IS NULL OR height <= ?3)
+        AND (?4 IS NULL OR timestamp >= ?4) AND (?5 IS NULL OR timestamp <= ?5)
+        AND (?6 IS NULL
+            OR (?6 = 0 AND value < 0 AND NOT self_transfer)
+            OR (?6 = 1 AND value > 0)
+            OR (?6 = 2 AND self_transfer))";
+    let direction = filter.direction.map(|d| match d {
+        TxDirection::Sent => 0,
+        TxDirection::Received => 1,
+        TxDirection::SelfTransfer => 2,
+    });
+    let ps = params![
+        account,
+        filter.from_height,
+        filter.to_height,
+        filter.from_time,
+        filter.to_time,
+        direction,
+    ];
+    let total = connection.query_row(
+        &format!("SELECT COUNT(*) FROM txs WHERE {CONDITION}"),
+        ps,
+        |r| r.get::<_, usize>(0),
+    )?;
+    let mut s = connection.prepare(&format!(
+        "SELECT {TX_COLUMNS} FROM {TX_FROM}
+        WHERE {CONDITION} ORDER BY height DESC, id_tx DESC LIMIT ?7 OFFSET ?8"
+    ))?;
+    let rows = s.query_map(
+        params![
+            account,
+            filter.from_height,
+            filter.to_height,
+            filter.from_time,
+            filter.to_time,
+            direction,
+            limit,
+            offset,
+        ],
+        to_extended_received_tx,
+    )?;
+    let mut txs = vec![];
+    for r in rows {
+        txs.push(r?);
+    }
+    Ok((txs, total))
+}
+
+fn to_extended_received_tx(r: &rusqlite::Row) -> rusqlite::Result<ExtendedReceivedTx> {
+    let rtx = ReceivedTx {
+        id: r.get(0)?,
+        account: r.get(1)?,
+        height: r.get(3)?,
+        txid: r.get(2)?,
+        timestamp: r.get(4)?,
+        value: r.get(5)?,
+        ivtx: 0,
+    };
+    Ok(ExtendedReceivedTx {
+        rtx,
+        address: r.get(6)?,
+        memo: r.get(7)?,
+        self_transfer: r.get(8)?,
+        value_in: r.get(9)?,
+    })
+}
+
 pub fn get_tx(connection: &Connection, id_tx: u32) -> Result<ReceivedTx> {
     let (account, txid, height, timestamp, value) = connection.query_row(
         "SELECT account, txid, height, timestamp, value
@@ -102,6 +282,28 @@ pub fn get_tx_details(connection: &Connection, id_tx: u32) -> Result<(u32, Trans
     Ok((account, tx))
 }
 
+/// Keep the raw bytes of a transaction next to its details
+pub fn store_raw_tx(connection: &Connection, id_tx: u32, raw: &[u8]) -> Result<()> {
+    connection.execute(
+        "UPDATE txdetails SET raw = ?2 WHERE id_tx = ?1 AND raw IS NULL",
+        params![id_tx, raw],
+    )?;
+    Ok(())
+}
+
+pub fn get_raw_tx(connection: &Connection, id_tx: u32) -> Result<Vec<u8>> {
+    let raw = connection
+        .query_row(
+            "SELECT raw FROM txdetails WHERE id_tx = ?1",
+            [id_tx],
+            |r| r.get::<_, Option<Vec<u8>>>(0),
+        )
+        .optional()?
+        .flatten()
+        .ok_or(anyhow::anyhow!("No raw transaction stored for {id_tx}"))?;
+    Ok(raw)
+}
+
 pub fn store_tx(connection: &Transaction, tx: &ReceivedTx) -> Result<()> {
     let mut s_tx = connection.prepare_cached(
         "INSERT INTO txs
@@ -196,6 +398,14 @@ pub fn store_message(
     Ok(())
 }
 
+pub fn update_tx_self_transfer(connection: &Connection, id_tx: u32, self_transfer: bool) -> Result<()> {
+    connection.execute(
+        "UPDATE txs SET self_transfer = ?2 WHERE id_tx = ?1",
+        params![id_tx, self_transfer],
+    )?;
+    Ok(())
+}
+
 pub fn update_tx_primary_address_memo(
     connection: &Connection,
     id_tx: u32,
@@ -208,3 +418,231 @@ pub fn update_tx_primary_address_memo(
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        for_each_tx, get_raw_tx, list_pending, list_txs, list_txs_filtered, list_txs_paged,
+        store_raw_tx, TxDirection, TxFilter,
+    };
+    use crate::{
+        data::fb::{TransactionRecipientT, TransactionSummaryT},
+        db::{
+            notes::store_tx_details,
+            pending::{get_pending_tx, list_unmined_broadcasts, store_broadcast, store_pending_tx},
+            testing::{add_note, add_utxo, test_db},
+        },
+        utils::to_txid_str,
+        warp::sync::ExtendedReceivedTx,
+    };
+    use rusqlite::{params, Connection};
+
+    fn add_tx(connection: &Connection, id_tx: u32, txid: &[u8], value: i64) {
+        connection
+            .execute(
+                "INSERT INTO txs(id_tx, account, txid, height, timestamp, value)
+                VALUES (?1, 1, ?2, ?1, 0, ?3)",
+                params![id_tx, txid, value],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn value_in_sums_notes_and_utxos() {
+        let connection = test_db();
+        let id1 = add_note(&connection, 1, 100, 30, false);
+        let id2 = add_note(&connection, 1, 200, 70, false);
+        add_utxo(&connection, 1, 100, 50);
+        add_utxo(&connection, 1, 100, 5);
+        // another account received an output of the same transaction
+        add_utxo(&connection, 2, 100, 999);
+        // all the utxos are outputs of the first transaction
+        connection
+            .execute(
+                "UPDATE utxos SET vout = id_utxo,
+                txid = (SELECT txid FROM txs WHERE id_tx = ?1)",
+                [id1],
+            )
+            .unwrap();
+
+        let txs = list_txs(&connection, 1).unwrap();
+        let value_in = txs
+            .iter()
+            .map(|t| (t.rtx.id, t.value_in))
+            .collect::<Vec<_>>();
+        assert_eq!(value_in, vec![(id2, 70), (id1, 85)]);
+
+        let (page, total) = list_txs_paged(&connection, 1, 1, 1).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(page[0].rtx.id, id1);
+        assert_eq!(page[0].value_in, 85);
+    }
+
+    #[test]
+    fn value_in_without_outputs() {
+        let connection = test_db();
+        add_tx(&connection, 1, &[1u8; 32], -100);
+        let txs = list_txs(&connection, 1).unwrap();
+        assert_eq!(txs[0].value_in, 0);
+    }
+
+    #[test]
+    fn raw_tx_round_trip() {
+        let connection = test_db();
+        let raw = (0..=255u8).collect::<Vec<_>>();
+        assert!(get_raw_tx(&connection, 1).is_err());
+        store_tx_details(&connection, 1, &[1u8; 32], &[0u8; 4]).unwrap();
+        assert!(get_raw_tx(&connection, 1).is_err());
+        store_raw_tx(&connection, 1, &raw).unwrap();
+        assert_eq!(get_raw_tx(&connection, 1).unwrap(), raw);
+        // the first raw transaction stored is kept
+        store_raw_tx(&connection, 1, &[1, 2, 3]).unwrap();
+        assert_eq!(get_raw_tx(&connection, 1).unwrap(), raw);
+    }
+
+    #[test]
+    fn pages_cover_the_history() {
+        let connection = test_db();
+        for id_tx in 1..=5 {
+            add_tx(&connection, id_tx, &[id_tx as u8; 32], 10);
+        }
+        // same height as the transaction 5, ordered by id after it
+        connection
+            .execute(
+                "INSERT INTO txs(id_tx, account, txid, height, timestamp, value)
+                VALUES (6, 1, randomblob(32), 5, 0, 10)",
+                [],
+            )
+            .unwrap();
+        let ids = |txs: Vec<ExtendedReceivedTx>| txs.iter().map(|t| t.rtx.id).collect::<Vec<_>>();
+        let all = ids(list_txs(&connection, 1).unwrap());
+        assert_eq!(all, vec![6, 5, 4, 3, 2, 1]);
+        let (page1, total) = list_txs_paged(&connection, 1, 0, 4).unwrap();
+        assert_eq!(total, 6);
+        let (page2, _) = list_txs_paged(&connection, 1, 4, 4).unwrap();
+        assert_eq!([ids(page1), ids(page2)].concat(), all);
+    }
+
+    /// Ids of the transactions that pass the filter
+    fn filtered(connection: &Connection, filter: TxFilter) -> Vec<u32> {
+        let (txs, total) = list_txs_filtered(connection, 1, &filter, 0, 100).unwrap();
+        assert_eq!(total, txs.len());
+        txs.iter().map(|t| t.rtx.id).collect()
+    }
+
+    #[test]
+    fn history_filters() {
+        let connection = test_db();
+        // id, height, timestamp, value, self transfer
+        for (id_tx, height, timestamp, value, self_transfer) in [
+            (1, 100, 1_000, 500, false),
+            (2, 200, 2_000, -300, false),
+            (3, 300, 3_000, -10, true),
+            (4, 400, 4_000, 700, false),
+        ] {
+            connection
+                .execute(
+                    "INSERT INTO txs(id_tx, account, txid, height, timestamp, value, self_transfer)
+                    VALUES (?1, 1, randomblob(32), ?2, ?3, ?4, ?5)",
+                    params![id_tx, height, timestamp, value, self_transfer],
+                )
+                .unwrap();
+        }
+        let heights = TxFilter {
+            from_height: Some(200),
+            to_height: Some(300),
+            ..TxFilter::default()
+        };
+        assert_eq!(filtered(&connection, heights), vec![3, 2]);
+        let times = TxFilter {
+            from_time: Some(3_000),
+            ..TxFilter::default()
+        };
+        assert_eq!(filtered(&connection, times), vec![4, 3]);
+        let direction = |d| TxFilter {
+            direction: Some(d),
+            ..TxFilter::default()
+        };
+        assert_eq!(filtered(&connection, direction(TxDirection::Sent)), vec![2]);
+        assert_eq!(
+            filtered(&connection, direction(TxDirection::Received)),
+            vec![4, 1]
+        );
+        assert_eq!(
+            filtered(&connection, direction(TxDirection::SelfTransfer)),
+            vec![3]
+        );
+        assert_eq!(filtered(&connection, TxFilter::default()), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn callback_once_per_transaction() {
+        let connection = test_db();
+        for id_tx in 1..=5 {
+            add_tx(&connection, id_tx, &[id_tx as u8; 32], 10);
+        }
+        let mut count = 0;
+        let mut last = None;
+        // the transaction 5 is above the height
+        for_each_tx(&connection, 1, 4, |tx| {
+            count += 1;
+            if let Some(id) = last {
+                assert!(tx.rtx.id < id);
+            }
+            last = Some(tx.rtx.id);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!((count, last), (4, Some(1)));
+
+        // an error of the callback stops the iteration
+        let mut count = 0;
+        let r = for_each_tx(&connection, 1, 5, |_| {
+            count += 1;
+            anyhow::bail!("stop")
+        });
+        assert!(r.is_err());
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn broadcast_but_unmined_is_pending() {
+        let connection = test_db();
+        let summary = TransactionSummaryT {
+            recipients: Some(vec![TransactionRecipientT {
+                address: Some("t1XVXWCvpMgBvUaed4XDqWtgQgJSu1Ghz7F".to_string()),
+                amount: 100_000,
+            }]),
+            fee: 10_000,
+            ..TransactionSummaryT::default()
+        };
+        let summary = serde_json::to_string(&summary).unwrap();
+        let id =
+            store_pending_tx(&connection, 1, 100, 140, &[3u8; 32], &[3u8; 10], &summary).unwrap();
+        let pending = get_pending_tx(&connection, id).unwrap().unwrap();
+        // not broadcast yet
+        assert!(list_pending(&connection, 1).unwrap().is_empty());
+
+        store_broadcast(&connection, &pending, &pending.txid, 101, 1000).unwrap();
+        let txs = list_pending(&connection, 1).unwrap();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].txid, Some(to_txid_str(&[3u8; 32])));
+        assert_eq!(txs[0].amount, -110_000);
+        assert_eq!(txs[0].timestamp, 1000);
+        assert_eq!(txs[0].status.as_deref(), Some("pending"));
+        assert!(list_pending(&connection, 2).unwrap().is_empty());
+
+        assert_eq!(list_unmined_broadcasts(&connection, 1).unwrap().len(), 1);
+
+        // the sync finds it mined, the broadcast is kept in the history
+        add_tx(&connection, 7, &[3u8; 32], -110_000);
+        assert!(list_pending(&connection, 1).unwrap().is_empty());
+        assert!(list_unmined_broadcasts(&connection, 1).unwrap().is_empty());
+        let count = connection
+            .query_row("SELECT COUNT(*) FROM broadcasts", [], |r| {
+                r.get::<_, u32>(0)
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
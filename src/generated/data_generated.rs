@@ -379,6 +379,10 @@ impl<'a> TransactionInfo<'a> {
   pub const VT_ADDRESS: flatbuffers::VOffsetT = 16;
   pub const VT_CONTACT: flatbuffers::VOffsetT = 18;
   pub const VT_MEMO: flatbuffers::VOffsetT = 20;
+  pub const VT_STATUS: flatbuffers::VOffsetT = 22;
+  pub const VT_SELF_TRANSFER: flatbuffers::VOffsetT = 24;
+  pub const VT_VALUE_IN: flatbuffers::VOffsetT = 26;
+  pub const VT_VALUE_OUT: flatbuffers::VOffsetT = 28;
 
   #[inline]
   pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -390,7 +394,10 @@ impl<'a> TransactionInfo<'a> {
     args: &'args TransactionInfoArgs<'args>
   ) -> flatbuffers::WIPOffset<TransactionInfo<'bldr>> {
     let mut builder = TransactionInfoBuilder::new(_fbb);
+    builder.add_value_out(args.value_out);
+    builder.add_value_in(args.value_in);
     builder.add_amount(args.amount);
+    if let Some(x) = args.status { builder.add_status(x); }
     if let Some(x) = args.memo { builder.add_memo(x); }
     if let Some(x) = args.contact { builder.add_contact(x); }
     if let Some(x) = args.address { builder.add_address(x); }
@@ -399,6 +406,7 @@ impl<'a> TransactionInfo<'a> {
     builder.add_height(args.height);
     if let Some(x) = args.txid { builder.add_txid(x); }
     builder.add_id(args.id);
+    builder.add_self_transfer(args.self_transfer);
     builder.finish()
   }
 
@@ -420,6 +428,12 @@ impl<'a> TransactionInfo<'a> {
     let memo = self.memo().map(|x| {
       x.to_string()
     });
+    let status = self.status().map(|x| {
+      x.to_string()
+    });
+    let self_transfer = self.self_transfer();
+    let value_in = self.value_in();
+    let value_out = self.value_out();
     TransactionInfoT {
       id,
       txid,
@@ -430,6 +444,10 @@ impl<'a> TransactionInfo<'a> {
       address,
       contact,
       memo,
+      status,
+      self_transfer,
+      value_in,
+      value_out,
     }
   }
 
@@ -496,6 +514,34 @@ impl<'a> TransactionInfo<'a> {
     // which contains a valid value in this slot
     unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TransactionInfo::VT_MEMO, None)}
   }
+  #[inline]
+  pub fn status(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TransactionInfo::VT_STATUS, None)}
+  }
+  #[inline]
+  pub fn self_transfer(&self) -> bool {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<bool>(TransactionInfo::VT_SELF_TRANSFER, Some(false)).unwrap()}
+  }
+  #[inline]
+  pub fn value_in(&self) -> u64 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TransactionInfo::VT_VALUE_IN, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn value_out(&self) -> u64 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TransactionInfo::VT_VALUE_OUT, Some(0)).unwrap()}
+  }
 }
 
 impl flatbuffers::Verifiable for TransactionInfo<'_> {
@@ -514,6 +560,10 @@ impl flatbuffers::Verifiable for TransactionInfo<'_> {
      .visit_field::<flatbuffers::ForwardsUOffset<&str>>("address", Self::VT_ADDRESS, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<&str>>("contact", Self::VT_CONTACT, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<&str>>("memo", Self::VT_MEMO, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("status", Self::VT_STATUS, false)?
+     .visit_field::<bool>("self_transfer", Self::VT_SELF_TRANSFER, false)?
+     .visit_field::<u64>("value_in", Self::VT_VALUE_IN, false)?
+     .visit_field::<u64>("value_out", Self::VT_VALUE_OUT, false)?
      .finish();
     Ok(())
   }
@@ -528,6 +578,10 @@ pub struct TransactionInfoArgs<'a> {
     pub address: Option<flatbuffers::WIPOffset<&'a str>>,
     pub contact: Option<flatbuffers::WIPOffset<&'a str>>,
     pub memo: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub status: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub self_transfer: bool,
+    pub value_in: u64,
+    pub value_out: u64,
 }
 impl<'a> Default for TransactionInfoArgs<'a> {
   #[inline]
@@ -542,6 +596,10 @@ impl<'a> Default for TransactionInfoArgs<'a> {
       address: None,
       contact: None,
       memo: None,
+      status: None,
+      self_transfer: false,
+      value_in: 0,
+      value_out: 0,
     }
   }
 }
@@ -588,6 +646,22 @@ impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> TransactionInfoBuilder<'a, 'b,
     self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TransactionInfo::VT_MEMO, memo);
   }
   #[inline]
+  pub fn add_status(&mut self, status: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TransactionInfo::VT_STATUS, status);
+  }
+  #[inline]
+  pub fn add_self_transfer(&mut self, self_transfer: bool) {
+    self.fbb_.push_slot::<bool>(TransactionInfo::VT_SELF_TRANSFER, self_transfer, false);
+  }
+  #[inline]
+  pub fn add_value_in(&mut self, value_in: u64) {
+    self.fbb_.push_slot::<u64>(TransactionInfo::VT_VALUE_IN, value_in, 0);
+  }
+  #[inline]
+  pub fn add_value_out(&mut self, value_out: u64) {
+    self.fbb_.push_slot::<u64>(TransactionInfo::VT_VALUE_OUT, value_out, 0);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>) -> TransactionInfoBuilder<'a, 'b, A> {
     let start = _fbb.start_table();
     TransactionInfoBuilder {
@@ -614,6 +688,10 @@ impl core::fmt::Debug for TransactionInfo<'_> {
       ds.field("address", &self.address());
       ds.field("contact", &self.contact());
       ds.field("memo", &self.memo());
+      ds.field("status", &self.status());
+      ds.field("self_transfer", &self.self_transfer());
+      ds.field("value_in", &self.value_in());
+      ds.field("value_out", &self.value_out());
       ds.finish()
   }
 }
@@ -629,6 +707,10 @@ pub struct TransactionInfoT {
   pub address: Option<String>,
   pub contact: Option<String>,
   pub memo: Option<String>,
+  pub status: Option<String>,
+  pub self_transfer: bool,
+  pub value_in: u64,
+  pub value_out: u64,
 }
 impl Default for TransactionInfoT {
   fn default() -> Self {
@@ -642,6 +724,10 @@ impl Default for TransactionInfoT {
       address: None,
       contact: None,
       memo: None,
+      status: None,
+      self_transfer: false,
+      value_in: 0,
+      value_out: 0,
     }
   }
 }
@@ -667,6 +753,12 @@ impl TransactionInfoT {
     let memo = self.memo.as_ref().map(|x|{
       _fbb.create_string(x)
     });
+    let status = self.status.as_ref().map(|x|{
+      _fbb.create_string(x)
+    });
+    let self_transfer = self.self_transfer;
+    let value_in = self.value_in;
+    let value_out = self.value_out;
     TransactionInfo::create(_fbb, &TransactionInfoArgs{
       id,
       txid,
@@ -677,6 +769,10 @@ impl TransactionInfoT {
       address,
       contact,
       memo,
+      status,
+      self_transfer,
+      value_in,
+      value_out,
     })
   }
 }
@@ -705,6 +801,8 @@ impl<'a> TransactionInfoExtended<'a> {
   pub const VT_SOUTS: flatbuffers::VOffsetT = 16;
   pub const VT_OINS: flatbuffers::VOffsetT = 18;
   pub const VT_OOUTS: flatbuffers::VOffsetT = 20;
+  pub const VT_CONFIRMATIONS: flatbuffers::VOffsetT = 22;
+  pub const VT_STATUS: flatbuffers::VOffsetT = 24;
 
   #[inline]
   pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -716,6 +814,8 @@ impl<'a> TransactionInfoExtended<'a> {
     args: &'args TransactionInfoExtendedArgs<'args>
   ) -> flatbuffers::WIPOffset<TransactionInfoExtended<'bldr>> {
     let mut builder = TransactionInfoExtendedBuilder::new(_fbb);
+    if let Some(x) = args.status { builder.add_status(x); }
+    builder.add_confirmations(args.confirmations);
     if let Some(x) = args.oouts { builder.add_oouts(x); }
     if let Some(x) = args.oins { builder.add_oins(x); }
     if let Some(x) = args.souts { builder.add_souts(x); }
@@ -752,6 +852,10 @@ impl<'a> TransactionInfoExtended<'a> {
     let oouts = self.oouts().map(|x| {
       x.iter().map(|t| t.unpack()).collect()
     });
+    let confirmations = self.confirmations();
+    let status = self.status().map(|x| {
+      x.to_string()
+    });
     TransactionInfoExtendedT {
       height,
       timestamp,
@@ -762,6 +866,8 @@ impl<'a> TransactionInfoExtended<'a> {
       souts,
       oins,
       oouts,
+      confirmations,
+      status,
     }
   }
 
@@ -828,6 +934,20 @@ impl<'a> TransactionInfoExtended<'a> {
     // which contains a valid value in this slot
     unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<OutputShielded>>>>(TransactionInfoExtended::VT_OOUTS, None)}
   }
+  #[inline]
+  pub fn confirmations(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(TransactionInfoExtended::VT_CONFIRMATIONS, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn status(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TransactionInfoExtended::VT_STATUS, None)}
+  }
 }
 
 impl flatbuffers::Verifiable for TransactionInfoExtended<'_> {
@@ -846,6 +966,8 @@ impl flatbuffers::Verifiable for TransactionInfoExtended<'_> {
      .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<OutputShielded>>>>("souts", Self::VT_SOUTS, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<InputShielded>>>>("oins", Self::VT_OINS, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<OutputShielded>>>>("oouts", Self::VT_OOUTS, false)?
+     .visit_field::<u32>("confirmations", Self::VT_CONFIRMATIONS, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("status", Self::VT_STATUS, false)?
      .finish();
     Ok(())
   }
@@ -860,6 +982,8 @@ pub struct TransactionInfoExtendedArgs<'a> {
     pub souts: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<OutputShielded<'a>>>>>,
     pub oins: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<InputShielded<'a>>>>>,
     pub oouts: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<OutputShielded<'a>>>>>,
+    pub confirmations: u32,
+    pub status: Option<flatbuffers::WIPOffset<&'a str>>,
 }
 impl<'a> Default for TransactionInfoExtendedArgs<'a> {
   #[inline]
@@ -874,6 +998,8 @@ impl<'a> Default for TransactionInfoExtendedArgs<'a> {
       souts: None,
       oins: None,
       oouts: None,
+      confirmations: 0,
+      status: None,
     }
   }
 }
@@ -920,6 +1046,14 @@ impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> TransactionInfoExtendedBuilder<
     self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TransactionInfoExtended::VT_OOUTS, oouts);
   }
   #[inline]
+  pub fn add_confirmations(&mut self, confirmations: u32) {
+    self.fbb_.push_slot::<u32>(TransactionInfoExtended::VT_CONFIRMATIONS, confirmations, 0);
+  }
+  #[inline]
+  pub fn add_status(&mut self, status: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TransactionInfoExtended::VT_STATUS, status);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>) -> TransactionInfoExtendedBuilder<'a, 'b, A> {
     let start = _fbb.start_table();
     TransactionInfoExtendedBuilder {
@@ -946,6 +1080,8 @@ impl core::fmt::Debug for TransactionInfoExtended<'_> {
       ds.field("souts", &self.souts());
       ds.field("oins", &self.oins());
       ds.field("oouts", &self.oouts());
+      ds.field("confirmations", &self.confirmations());
+      ds.field("status", &self.status());
       ds.finish()
   }
 }
@@ -961,6 +1097,8 @@ pub struct TransactionInfoExtendedT {
   pub souts: Option<Vec<OutputShieldedT>>,
   pub oins: Option<Vec<InputShieldedT>>,
   pub oouts: Option<Vec<OutputShieldedT>>,
+  pub confirmations: u32,
+  pub status: Option<String>,
 }
 impl Default for TransactionInfoExtendedT {
   fn default() -> Self {
@@ -974,6 +1112,8 @@ impl Default for TransactionInfoExtendedT {
       souts: None,
       oins: None,
       oouts: None,
+      confirmations: 0,
+      status: None,
     }
   }
 }
@@ -1005,6 +1145,10 @@ impl TransactionInfoExtendedT {
     let oouts = self.oouts.as_ref().map(|x|{
       let w: Vec<_> = x.iter().map(|t| t.pack(_fbb)).collect();_fbb.create_vector(&w)
     });
+    let confirmations = self.confirmations;
+    let status = self.status.as_ref().map(|x|{
+      _fbb.create_string(x)
+    });
     TransactionInfoExtended::create(_fbb, &TransactionInfoExtendedArgs{
       height,
       timestamp,
@@ -1015,6 +1159,8 @@ impl TransactionInfoExtendedT {
       souts,
       oins,
       oouts,
+      confirmations,
+      status,
     })
   }
 }
@@ -2713,6 +2859,8 @@ impl<'a> PaymentRequest<'a> {
   pub const VT_AMOUNT: flatbuffers::VOffsetT = 6;
   pub const VT_MEMO_STRING: flatbuffers::VOffsetT = 8;
   pub const VT_MEMO_BYTES: flatbuffers::VOffsetT = 10;
+  pub const VT_LABEL: flatbuffers::VOffsetT = 12;
+  pub const VT_MESSAGE: flatbuffers::VOffsetT = 14;
 
   #[inline]
   pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -2725,6 +2873,8 @@ impl<'a> PaymentRequest<'a> {
   ) -> flatbuffers::WIPOffset<PaymentRequest<'bldr>> {
     let mut builder = PaymentRequestBuilder::new(_fbb);
     builder.add_amount(args.amount);
+    if let Some(x) = args.message { builder.add_message(x); }
+    if let Some(x) = args.label { builder.add_label(x); }
     if let Some(x) = args.memo_bytes { builder.add_memo_bytes(x); }
     if let Some(x) = args.memo_string { builder.add_memo_string(x); }
     if let Some(x) = args.address { builder.add_address(x); }
@@ -2742,11 +2892,19 @@ impl<'a> PaymentRequest<'a> {
     let memo_bytes = self.memo_bytes().map(|x| {
       x.into_iter().collect()
     });
+    let label = self.label().map(|x| {
+      x.to_string()
+    });
+    let message = self.message().map(|x| {
+      x.to_string()
+    });
     PaymentRequestT {
       address,
       amount,
       memo_string,
       memo_bytes,
+      label,
+      message,
     }
   }
 
@@ -2778,6 +2936,20 @@ impl<'a> PaymentRequest<'a> {
     // which contains a valid value in this slot
     unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, u8>>>(PaymentRequest::VT_MEMO_BYTES, None)}
   }
+  #[inline]
+  pub fn label(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(PaymentRequest::VT_LABEL, None)}
+  }
+  #[inline]
+  pub fn message(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(PaymentRequest::VT_MESSAGE, None)}
+  }
 }
 
 impl flatbuffers::Verifiable for PaymentRequest<'_> {
@@ -2791,6 +2963,8 @@ impl flatbuffers::Verifiable for PaymentRequest<'_> {
      .visit_field::<u64>("amount", Self::VT_AMOUNT, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<&str>>("memo_string", Self::VT_MEMO_STRING, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, u8>>>("memo_bytes", Self::VT_MEMO_BYTES, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("label", Self::VT_LABEL, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("message", Self::VT_MESSAGE, false)?
      .finish();
     Ok(())
   }
@@ -2800,6 +2974,8 @@ pub struct PaymentRequestArgs<'a> {
     pub amount: u64,
     pub memo_string: Option<flatbuffers::WIPOffset<&'a str>>,
     pub memo_bytes: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, u8>>>,
+    pub label: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub message: Option<flatbuffers::WIPOffset<&'a str>>,
 }
 impl<'a> Default for PaymentRequestArgs<'a> {
   #[inline]
@@ -2809,6 +2985,8 @@ impl<'a> Default for PaymentRequestArgs<'a> {
       amount: 0,
       memo_string: None,
       memo_bytes: None,
+      label: None,
+      message: None,
     }
   }
 }
@@ -2835,6 +3013,14 @@ impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> PaymentRequestBuilder<'a, 'b, A
     self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(PaymentRequest::VT_MEMO_BYTES, memo_bytes);
   }
   #[inline]
+  pub fn add_label(&mut self, label: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(PaymentRequest::VT_LABEL, label);
+  }
+  #[inline]
+  pub fn add_message(&mut self, message: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(PaymentRequest::VT_MESSAGE, message);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>) -> PaymentRequestBuilder<'a, 'b, A> {
     let start = _fbb.start_table();
     PaymentRequestBuilder {
@@ -2856,6 +3042,8 @@ impl core::fmt::Debug for PaymentRequest<'_> {
       ds.field("amount", &self.amount());
       ds.field("memo_string", &self.memo_string());
       ds.field("memo_bytes", &self.memo_bytes());
+      ds.field("label", &self.label());
+      ds.field("message", &self.message());
       ds.finish()
   }
 }
@@ -2866,6 +3054,8 @@ pub struct PaymentRequestT {
   pub amount: u64,
   pub memo_string: Option<String>,
   pub memo_bytes: Option<Vec<u8>>,
+  pub label: Option<String>,
+  pub message: Option<String>,
 }
 impl Default for PaymentRequestT {
   fn default() -> Self {
@@ -2874,6 +3064,8 @@ impl Default for PaymentRequestT {
       amount: 0,
       memo_string: None,
       memo_bytes: None,
+      label: None,
+      message: None,
     }
   }
 }
@@ -2892,11 +3084,19 @@ impl PaymentRequestT {
     let memo_bytes = self.memo_bytes.as_ref().map(|x|{
       _fbb.create_vector(x)
     });
+    let label = self.label.as_ref().map(|x|{
+      _fbb.create_string(x)
+    });
+    let message = self.message.as_ref().map(|x|{
+      _fbb.create_string(x)
+    });
     PaymentRequest::create(_fbb, &PaymentRequestArgs{
       address,
       amount,
       memo_string,
       memo_bytes,
+      label,
+      message,
     })
   }
 }
@@ -2920,6 +3120,7 @@ impl<'a> AccountName<'a> {
   pub const VT_NAME: flatbuffers::VOffsetT = 6;
   pub const VT_SAPLING_ADDRESS: flatbuffers::VOffsetT = 8;
   pub const VT_BIRTH: flatbuffers::VOffsetT = 10;
+  pub const VT_BALANCE: flatbuffers::VOffsetT = 12;
 
   #[inline]
   pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -2931,6 +3132,7 @@ impl<'a> AccountName<'a> {
     args: &'args AccountNameArgs<'args>
   ) -> flatbuffers::WIPOffset<AccountName<'bldr>> {
     let mut builder = AccountNameBuilder::new(_fbb);
+    builder.add_balance(args.balance);
     builder.add_birth(args.birth);
     if let Some(x) = args.sapling_address { builder.add_sapling_address(x); }
     if let Some(x) = args.name { builder.add_name(x); }
@@ -2947,11 +3149,13 @@ impl<'a> AccountName<'a> {
       x.to_string()
     });
     let birth = self.birth();
+    let balance = self.balance();
     AccountNameT {
       id,
       name,
       sapling_address,
       birth,
+      balance,
     }
   }
 
@@ -2983,6 +3187,13 @@ impl<'a> AccountName<'a> {
     // which contains a valid value in this slot
     unsafe { self._tab.get::<u32>(AccountName::VT_BIRTH, Some(0)).unwrap()}
   }
+  #[inline]
+  pub fn balance(&self) -> u64 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(AccountName::VT_BALANCE, Some(0)).unwrap()}
+  }
 }
 
 impl flatbuffers::Verifiable for AccountName<'_> {
@@ -2996,6 +3207,7 @@ impl flatbuffers::Verifiable for AccountName<'_> {
      .visit_field::<flatbuffers::ForwardsUOffset<&str>>("name", Self::VT_NAME, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<&str>>("sapling_address", Self::VT_SAPLING_ADDRESS, false)?
      .visit_field::<u32>("birth", Self::VT_BIRTH, false)?
+     .visit_field::<u64>("balance", Self::VT_BALANCE, false)?
      .finish();
     Ok(())
   }
@@ -3005,6 +3217,7 @@ pub struct AccountNameArgs<'a> {
     pub name: Option<flatbuffers::WIPOffset<&'a str>>,
     pub sapling_address: Option<flatbuffers::WIPOffset<&'a str>>,
     pub birth: u32,
+    pub balance: u64,
 }
 impl<'a> Default for AccountNameArgs<'a> {
   #[inline]
@@ -3014,6 +3227,7 @@ impl<'a> Default for AccountNameArgs<'a> {
       name: None,
       sapling_address: None,
       birth: 0,
+      balance: 0,
     }
   }
 }
@@ -3040,6 +3254,10 @@ impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> AccountNameBuilder<'a, 'b, A> {
     self.fbb_.push_slot::<u32>(AccountName::VT_BIRTH, birth, 0);
   }
   #[inline]
+  pub fn add_balance(&mut self, balance: u64) {
+    self.fbb_.push_slot::<u64>(AccountName::VT_BALANCE, balance, 0);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>) -> AccountNameBuilder<'a, 'b, A> {
     let start = _fbb.start_table();
     AccountNameBuilder {
@@ -3061,6 +3279,7 @@ impl core::fmt::Debug for AccountName<'_> {
       ds.field("name", &self.name());
       ds.field("sapling_address", &self.sapling_address());
       ds.field("birth", &self.birth());
+      ds.field("balance", &self.balance());
       ds.finish()
   }
 }
@@ -3071,6 +3290,7 @@ pub struct AccountNameT {
   pub name: Option<String>,
   pub sapling_address: Option<String>,
   pub birth: u32,
+  pub balance: u64,
 }
 impl Default for AccountNameT {
   fn default() -> Self {
@@ -3079,6 +3299,7 @@ impl Default for AccountNameT {
       name: None,
       sapling_address: None,
       birth: 0,
+      balance: 0,
     }
   }
 }
@@ -3095,11 +3316,13 @@ impl AccountNameT {
       _fbb.create_string(x)
     });
     let birth = self.birth;
+    let balance = self.balance;
     AccountName::create(_fbb, &AccountNameArgs{
       id,
       name,
       sapling_address,
       birth,
+      balance,
     })
   }
 }
@@ -3482,6 +3705,182 @@ impl TransactionRecipientT {
     })
   }
 }
+pub enum TransactionChangeOffset {}
+#[derive(Copy, Clone, PartialEq)]
+
+pub struct TransactionChange<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for TransactionChange<'a> {
+  type Inner = TransactionChange<'a>;
+  #[inline]
+  unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+    Self { _tab: flatbuffers::Table::new(buf, loc) }
+  }
+}
+
+impl<'a> TransactionChange<'a> {
+  pub const VT_ADDRESS: flatbuffers::VOffsetT = 4;
+  pub const VT_AMOUNT: flatbuffers::VOffsetT = 6;
+  pub const VT_POOL: flatbuffers::VOffsetT = 8;
+
+  #[inline]
+  pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+    TransactionChange { _tab: table }
+  }
+  #[allow(unused_mut)]
+  pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr, A: flatbuffers::Allocator + 'bldr>(
+    _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr, A>,
+    args: &'args TransactionChangeArgs<'args>
+  ) -> flatbuffers::WIPOffset<TransactionChange<'bldr>> {
+    let mut builder = TransactionChangeBuilder::new(_fbb);
+    builder.add_amount(args.amount);
+    if let Some(x) = args.address { builder.add_address(x); }
+    builder.add_pool(args.pool);
+    builder.finish()
+  }
+
+  pub fn unpack(&self) -> TransactionChangeT {
+    let address = self.address().map(|x| {
+      x.to_string()
+    });
+    let amount = self.amount();
+    let pool = self.pool();
+    TransactionChangeT {
+      address,
+      amount,
+      pool,
+    }
+  }
+
+  #[inline]
+  pub fn address(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(TransactionChange::VT_ADDRESS, None)}
+  }
+  #[inline]
+  pub fn amount(&self) -> u64 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u64>(TransactionChange::VT_AMOUNT, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn pool(&self) -> u8 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u8>(TransactionChange::VT_POOL, Some(0)).unwrap()}
+  }
+}
+
+impl flatbuffers::Verifiable for TransactionChange<'_> {
+  #[inline]
+  fn run_verifier(
+    v: &mut flatbuffers::Verifier, pos: usize
+  ) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+    use self::flatbuffers::Verifiable;
+    v.visit_table(pos)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("address", Self::VT_ADDRESS, false)?
+     .visit_field::<u64>("amount", Self::VT_AMOUNT, false)?
+     .visit_field::<u8>("pool", Self::VT_POOL, false)?
+     .finish();
+    Ok(())
+  }
+}
+pub struct TransactionChangeArgs<'a> {
+    pub address: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub amount: u64,
+    pub pool: u8,
+}
+impl<'a> Default for TransactionChangeArgs<'a> {
+  #[inline]
+  fn default() -> Self {
+    TransactionChangeArgs {
+      address: None,
+      amount: 0,
+      pool: 0,
+    }
+  }
+}
+
+pub struct TransactionChangeBuilder<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a, A>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> TransactionChangeBuilder<'a, 'b, A> {
+  #[inline]
+  pub fn add_address(&mut self, address: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TransactionChange::VT_ADDRESS, address);
+  }
+  #[inline]
+  pub fn add_amount(&mut self, amount: u64) {
+    self.fbb_.push_slot::<u64>(TransactionChange::VT_AMOUNT, amount, 0);
+  }
+  #[inline]
+  pub fn add_pool(&mut self, pool: u8) {
+    self.fbb_.push_slot::<u8>(TransactionChange::VT_POOL, pool, 0);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>) -> TransactionChangeBuilder<'a, 'b, A> {
+    let start = _fbb.start_table();
+    TransactionChangeBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<TransactionChange<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
+impl core::fmt::Debug for TransactionChange<'_> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let mut ds = f.debug_struct("TransactionChange");
+      ds.field("address", &self.address());
+      ds.field("amount", &self.amount());
+      ds.field("pool", &self.pool());
+      ds.finish()
+  }
+}
+#[non_exhaustive]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransactionChangeT {
+  pub address: Option<String>,
+  pub amount: u64,
+  pub pool: u8,
+}
+impl Default for TransactionChangeT {
+  fn default() -> Self {
+    Self {
+      address: None,
+      amount: 0,
+      pool: 0,
+    }
+  }
+}
+impl TransactionChangeT {
+  pub fn pack<'b, A: flatbuffers::Allocator + 'b>(
+    &self,
+    _fbb: &mut flatbuffers::FlatBufferBuilder<'b, A>
+  ) -> flatbuffers::WIPOffset<TransactionChange<'b>> {
+    let address = self.address.as_ref().map(|x|{
+      _fbb.create_string(x)
+    });
+    let amount = self.amount;
+    let pool = self.pool;
+    TransactionChange::create(_fbb, &TransactionChangeArgs{
+      address,
+      amount,
+      pool,
+    })
+  }
+}
 pub enum TransactionSummaryOffset {}
 #[derive(Copy, Clone, PartialEq)]
 
@@ -3504,6 +3903,9 @@ impl<'a> TransactionSummary<'a> {
   pub const VT_ORCHARD_NET: flatbuffers::VOffsetT = 10;
   pub const VT_FEE: flatbuffers::VOffsetT = 12;
   pub const VT_DATA: flatbuffers::VOffsetT = 14;
+  pub const VT_ANCHOR_HEIGHT: flatbuffers::VOffsetT = 16;
+  pub const VT_CHANGE: flatbuffers::VOffsetT = 18;
+  pub const VT_SIZE: flatbuffers::VOffsetT = 20;
 
   #[inline]
   pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -3519,6 +3921,9 @@ impl<'a> TransactionSummary<'a> {
     builder.add_orchard_net(args.orchard_net);
     builder.add_sapling_net(args.sapling_net);
     builder.add_transparent_ins(args.transparent_ins);
+    builder.add_size(args.size);
+    if let Some(x) = args.change { builder.add_change(x); }
+    builder.add_anchor_height(args.anchor_height);
     if let Some(x) = args.data { builder.add_data(x); }
     if let Some(x) = args.recipients { builder.add_recipients(x); }
     builder.finish()
@@ -3535,6 +3940,11 @@ impl<'a> TransactionSummary<'a> {
     let data = self.data().map(|x| {
       x.into_iter().collect()
     });
+    let anchor_height = self.anchor_height();
+    let change = self.change().map(|x| {
+      Box::new(x.unpack())
+    });
+    let size = self.size();
     TransactionSummaryT {
       recipients,
       transparent_ins,
@@ -3542,6 +3952,9 @@ impl<'a> TransactionSummary<'a> {
       orchard_net,
       fee,
       data,
+      anchor_height,
+      change,
+      size,
     }
   }
 
@@ -3587,6 +4000,27 @@ impl<'a> TransactionSummary<'a> {
     // which contains a valid value in this slot
     unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, u8>>>(TransactionSummary::VT_DATA, None)}
   }
+  #[inline]
+  pub fn anchor_height(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(TransactionSummary::VT_ANCHOR_HEIGHT, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn change(&self) -> Option<TransactionChange<'a>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<TransactionChange>>(TransactionSummary::VT_CHANGE, None)}
+  }
+  #[inline]
+  pub fn size(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(TransactionSummary::VT_SIZE, Some(0)).unwrap()}
+  }
 }
 
 impl flatbuffers::Verifiable for TransactionSummary<'_> {
@@ -3602,6 +4036,9 @@ impl flatbuffers::Verifiable for TransactionSummary<'_> {
      .visit_field::<i64>("orchard_net", Self::VT_ORCHARD_NET, false)?
      .visit_field::<u64>("fee", Self::VT_FEE, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, u8>>>("data", Self::VT_DATA, false)?
+     .visit_field::<u32>("anchor_height", Self::VT_ANCHOR_HEIGHT, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<TransactionChange>>("change", Self::VT_CHANGE, false)?
+     .visit_field::<u32>("size", Self::VT_SIZE, false)?
      .finish();
     Ok(())
   }
@@ -3613,6 +4050,9 @@ pub struct TransactionSummaryArgs<'a> {
     pub orchard_net: i64,
     pub fee: u64,
     pub data: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, u8>>>,
+    pub anchor_height: u32,
+    pub change: Option<flatbuffers::WIPOffset<TransactionChange<'a>>>,
+    pub size: u32,
 }
 impl<'a> Default for TransactionSummaryArgs<'a> {
   #[inline]
@@ -3624,6 +4064,9 @@ impl<'a> Default for TransactionSummaryArgs<'a> {
       orchard_net: 0,
       fee: 0,
       data: None,
+      anchor_height: 0,
+      change: None,
+      size: 0,
     }
   }
 }
@@ -3658,6 +4101,18 @@ impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> TransactionSummaryBuilder<'a, '
     self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(TransactionSummary::VT_DATA, data);
   }
   #[inline]
+  pub fn add_anchor_height(&mut self, anchor_height: u32) {
+    self.fbb_.push_slot::<u32>(TransactionSummary::VT_ANCHOR_HEIGHT, anchor_height, 0);
+  }
+  #[inline]
+  pub fn add_change(&mut self, change: flatbuffers::WIPOffset<TransactionChange<'b >>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<TransactionChange>>(TransactionSummary::VT_CHANGE, change);
+  }
+  #[inline]
+  pub fn add_size(&mut self, size: u32) {
+    self.fbb_.push_slot::<u32>(TransactionSummary::VT_SIZE, size, 0);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>) -> TransactionSummaryBuilder<'a, 'b, A> {
     let start = _fbb.start_table();
     TransactionSummaryBuilder {
@@ -3681,6 +4136,9 @@ impl core::fmt::Debug for TransactionSummary<'_> {
       ds.field("orchard_net", &self.orchard_net());
       ds.field("fee", &self.fee());
       ds.field("data", &self.data());
+      ds.field("anchor_height", &self.anchor_height());
+      ds.field("change", &self.change());
+      ds.field("size", &self.size());
       ds.finish()
   }
 }
@@ -3693,6 +4151,9 @@ pub struct TransactionSummaryT {
   pub orchard_net: i64,
   pub fee: u64,
   pub data: Option<Vec<u8>>,
+  pub anchor_height: u32,
+  pub change: Option<Box<TransactionChangeT>>,
+  pub size: u32,
 }
 impl Default for TransactionSummaryT {
   fn default() -> Self {
@@ -3703,6 +4164,9 @@ impl Default for TransactionSummaryT {
       orchard_net: 0,
       fee: 0,
       data: None,
+      anchor_height: 0,
+      change: None,
+      size: 0,
     }
   }
 }
@@ -3721,6 +4185,11 @@ impl TransactionSummaryT {
     let data = self.data.as_ref().map(|x|{
       _fbb.create_vector(x)
     });
+    let anchor_height = self.anchor_height;
+    let change = self.change.as_ref().map(|x|{
+      x.pack(_fbb)
+    });
+    let size = self.size;
     TransactionSummary::create(_fbb, &TransactionSummaryArgs{
       recipients,
       transparent_ins,
@@ -3728,6 +4197,9 @@ impl TransactionSummaryT {
       orchard_net,
       fee,
       data,
+      anchor_height,
+      change,
+      size,
     })
   }
 }
@@ -70,6 +70,11 @@ pub fn derive_bip32(
     );
     let ext = ExtendedPrivKey::derive(seed.as_bytes(), &*bip44_path).unwrap();
     let sk = SecretKey::from_slice(&ext.secret()).unwrap();
+    transparent_from_sk(sk, compressed)
+}
+
+/// Transparent account of a secret key, with its P2PKH address
+pub fn transparent_from_sk(sk: SecretKey, compressed: bool) -> TransparentAccountInfo {
     let secp = Secp256k1::<All>::new();
     let pub_key = PublicKey::from_secret_key(&secp, &sk);
     let pub_key = if compressed {
@@ -24,6 +24,15 @@ pub type PooledSQLConnection = PooledConnection<SqliteConnectionManager>;
 pub type Hash = [u8; 32];
 
 pub const EXPIRATION_HEIGHT_DELTA: u32 = 50;
+/// Seconds during which building the same payment again is refused
+pub const DUPLICATE_PAYMENT_WINDOW: u32 = 600;
+/// Number of blocks between the anchor of the witnesses and the
+/// expiration height of a transaction above which a warning is logged.
+/// Consensus accepts any anchor, but an old one means that the wallet
+/// is far behind the chain
+pub const ANCHOR_WARN_DEPTH: u32 = 200;
+/// Consensus limit on the serialized size of a transaction
+pub const MAX_TX_SIZE: usize = 2_000_000;
 
 // pub use coin::{CoinDef, COINS};
 // pub use keys::{generate_random_mnemonic_phrase, TSKStore};
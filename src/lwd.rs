@@ -1,9 +1,19 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
 use anyhow::Result;
+use prost::Message as _;
+use serde::Serialize;
+use thiserror::Error;
 use rpc::{
-    BlockId, BlockRange, CompactBlock, Empty, RawTransaction, TransparentAddressBlockFilter,
-    TreeState, TxFilter,
+    BlockId, BlockRange, CompactBlock, Empty, GetSubtreeRootsArg, RawTransaction,
+    ShieldedProtocol, TransparentAddressBlockFilter, TreeState, TxFilter,
 };
-use tokio::runtime::Handle;
+use tokio::{runtime::Handle, sync::mpsc::Receiver};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
 use tonic::{Request, Streaming};
 use zcash_client_backend::encoding::AddressCodec as _;
 use zcash_primitives::{
@@ -13,12 +23,15 @@ use zcash_primitives::{
 };
 
 use crate::{
-    coin::connect_lwd, types::CheckpointHeight, warp::{legacy::CommitmentTreeFrontier, OutPoint, TransparentTx, TxOut2}, Client
+    coin::connect_lwd, types::CheckpointHeight, warp::{legacy::CommitmentTreeFrontier, OutPoint, TransparentTx, TxOut2}, Client, Hash
 };
 
 #[path = "./generated/cash.z.wallet.sdk.rpc.rs"]
 pub mod rpc;
 
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+
 pub async fn get_last_height(client: &mut Client) -> Result<u32> {
     let r = client
         .get_lightd_info(Request::new(Empty {}))
@@ -27,8 +40,63 @@ pub async fn get_last_height(client: &mut Client) -> Result<u32> {
     Ok(r.block_height as u32)
 }
 
+/// Bytes of blocks and transactions received from lightwalletd,
+/// kept by the `CoinDef` the requests are made for
+#[derive(Default, Debug)]
+pub struct DataUsage(AtomicU64);
+
+impl DataUsage {
+    pub fn record(&self, bytes: usize) {
+        self.0.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Roots of the complete subtrees of the note commitment tree of a
+/// shielded pool (1: sapling, 2: orchard), starting at `start_index`.
+/// `limit` = 0 returns all of them
+pub async fn get_subtree_roots(
+    client: &mut Client,
+    usage: &DataUsage,
+    pool: u8,
+    start_index: u32,
+    limit: u32,
+) -> Result<Vec<Hash>> {
+    let shielded_protocol = match pool {
+        1 => ShieldedProtocol::Sapling,
+        2 => ShieldedProtocol::Orchard,
+        _ => anyhow::bail!("Invalid shielded pool {pool}"),
+    };
+    let mut roots = client
+        .get_subtree_roots(Request::new(GetSubtreeRootsArg {
+            start_index,
+            shielded_protocol: shielded_protocol as i32,
+            max_entries: limit,
+        }))
+        .await?
+        .into_inner();
+    let mut hashes = vec![];
+    while let Some(root) = roots.message().await? {
+        usage.record(root.encoded_len());
+        let hash: Hash = root
+            .root_hash
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid subtree root"))?;
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
+
 pub async fn get_tree_state(
     client: &mut Client,
+    usage: &DataUsage,
     height: CheckpointHeight,
 ) -> Result<(CommitmentTreeFrontier, CommitmentTreeFrontier)> {
     let height: u32 = height.into();
@@ -39,6 +107,7 @@ pub async fn get_tree_state(
         }))
         .await?
         .into_inner();
+    usage.record(tree_state.encoded_len());
 
     let TreeState {
         sapling_tree,
@@ -77,7 +146,59 @@ pub async fn get_tree_state(
     Ok((sapling, orchard))
 }
 
-pub async fn get_compact_block(client: &mut Client, height: u32) -> Result<CompactBlock> {
+#[derive(Error, Debug)]
+pub enum BlockError {
+    #[error("Block {0} is above the tip {1} of the server")]
+    AboveTip(u32, u32),
+    #[error("Block {0} is below the first block {1} of the server")]
+    BelowStart(u32, u32),
+    #[error("No block found at {0}")]
+    NotFound(u32),
+}
+
+/// Heights of the blocks a server can serve. Servers start
+/// at the Sapling activation
+#[derive(Clone, Copy, Debug)]
+pub struct ServerBounds {
+    pub start: u32,
+    pub tip: u32,
+}
+
+impl ServerBounds {
+    pub fn check(&self, height: u32) -> Result<(), BlockError> {
+        if height > self.tip {
+            return Err(BlockError::AboveTip(height, self.tip));
+        }
+        if height < self.start {
+            return Err(BlockError::BelowStart(height, self.start));
+        }
+        Ok(())
+    }
+}
+
+/// Bounds of the server, to fetch once per command and pass
+/// to `get_compact_block`
+pub async fn get_server_bounds(client: &mut Client) -> Result<ServerBounds> {
+    let info = client
+        .get_lightd_info(Request::new(Empty {}))
+        .await?
+        .into_inner();
+    Ok(ServerBounds {
+        start: info.sapling_activation_height as u32,
+        tip: info.block_height as u32,
+    })
+}
+
+/// Block at `height`. The bounds of the server are checked first
+/// so that a height it cannot serve fails with a `BlockError`
+/// that tells why
+pub async fn get_compact_block(
+    client: &mut Client,
+    usage: &DataUsage,
+    bounds: &ServerBounds,
+    height: u32,
+) -> Result<CompactBlock> {
+    bounds.check(height)?;
     let mut blocks = client
         .get_block_range(Request::new(BlockRange {
             start: Some(BlockId {
@@ -93,9 +214,10 @@ pub async fn get_compact_block(client: &mut Client, height: u32) -> Result<Compa
         .await?
         .into_inner();
     while let Some(block) = blocks.message().await? {
+        usage.record(block.encoded_len());
         return Ok(block);
     }
-    Err(anyhow::anyhow!("No block found"))
+    Err(BlockError::NotFound(height).into())
 }
 
 pub async fn get_compact_block_range(
@@ -120,9 +242,153 @@ pub async fn get_compact_block_range(
     Ok(blocks)
 }
 
+/// Number of blocks of each request made by `fetch_blocks`
+const FETCH_CHUNK: u32 = 1_000;
+
+/// Blocks read from a single stream or from the concurrent
+/// requests of `fetch_blocks`
+pub enum BlockSource {
+    Stream(Streaming<CompactBlock>),
+    Channel(Receiver<Result<CompactBlock>>),
+}
+
+impl BlockSource {
+    pub async fn next(&mut self) -> Result<Option<CompactBlock>> {
+        match self {
+            BlockSource::Stream(blocks) => Ok(blocks.message().await?),
+            BlockSource::Channel(blocks) => blocks.recv().await.transpose(),
+        }
+    }
+}
+
+/// Blocks from `start` to `end`, in chunks of `FETCH_CHUNK` blocks
+/// with up to `concurrency` requests in flight. The chunks are
+/// delivered in order, the blocks come in sequence as with a single
+/// request. A concurrency of 1 streams the whole range
+pub async fn fetch_blocks(
+    client: &Client,
+    start: u32,
+    end: u32,
+    concurrency: usize,
+) -> Result<BlockSource> {
+    let mut client = client.clone();
+    if concurrency <= 1 {
+        let blocks = get_compact_block_range(&mut client, start, end).await?;
+        return Ok(BlockSource::Stream(blocks));
+    }
+    let (tx, rx) = tokio::sync::mpsc::channel(FETCH_CHUNK as usize);
+    tokio::spawn(async move {
+        let mut chunks = (start..=end)
+            .step_by(FETCH_CHUNK as usize)
+            .map(|s| (s, s.saturating_add(FETCH_CHUNK - 1).min(end)));
+        let mut in_flight = VecDeque::new();
+        loop {
+            while in_flight.len() < concurrency {
+                let Some((s, e)) = chunks.next() else {
+                    break;
+                };
+                let mut client = client.clone();
+                in_flight.push_back(tokio::spawn(async move {
+                    fetch_chunk(&mut client, s, e).await
+                }));
+            }
+            let Some(chunk) = in_flight.pop_front() else {
+                break;
+            };
+            let blocks = chunk.await.map_err(anyhow::Error::new).and_then(|r| r);
+            match blocks {
+                Ok(blocks) => {
+                    for block in blocks {
+                        if tx.send(Ok(block)).await.is_err() {
+                            // the sync stopped reading
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            }
+        }
+    });
+    Ok(BlockSource::Channel(rx))
+}
+
+/// Attempts made by `compact_block_stream` for each chunk
+const FETCH_RETRIES: u32 = 3;
+/// Time allowed to fetch a chunk before it is retried
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Blocks from `start` to `end` as a stream, for tools that process
+/// compact blocks without running the sync. The range is fetched in
+/// chunks of `FETCH_CHUNK` blocks and a chunk that fails or takes
+/// longer than `FETCH_TIMEOUT` is requested again, up to `FETCH_RETRIES`
+/// times. After that the stream ends with the error
+pub fn compact_block_stream(
+    client: &Client,
+    start: u32,
+    end: u32,
+) -> impl Stream<Item = Result<CompactBlock>> {
+    let mut client = client.clone();
+    let (tx, rx) = tokio::sync::mpsc::channel(FETCH_CHUNK as usize);
+    tokio::spawn(async move {
+        for s in (start..=end).step_by(FETCH_CHUNK as usize) {
+            let e = s.saturating_add(FETCH_CHUNK - 1).min(end);
+            match fetch_chunk_with_retries(&mut client, s, e).await {
+                Ok(blocks) => {
+                    for block in blocks {
+                        if tx.send(Ok(block)).await.is_err() {
+                            // the stream was dropped
+                            return;
+                        }
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(Err(err)).await;
+                    return;
+                }
+            }
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+async fn fetch_chunk_with_retries(
+    client: &mut Client,
+    start: u32,
+    end: u32,
+) -> Result<Vec<CompactBlock>> {
+    let mut attempt = 1;
+    loop {
+        let blocks = tokio::time::timeout(FETCH_TIMEOUT, fetch_chunk(client, start, end))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout fetching blocks {start}-{end}"))
+            .and_then(|r| r);
+        match blocks {
+            Ok(blocks) => return Ok(blocks),
+            Err(err) if attempt < FETCH_RETRIES => {
+                tracing::warn!("Fetching blocks {start}-{end} failed ({err}), retrying");
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+async fn fetch_chunk(client: &mut Client, start: u32, end: u32) -> Result<Vec<CompactBlock>> {
+    let mut blocks = get_compact_block_range(client, start, end).await?;
+    let mut chunk = vec![];
+    while let Some(block) = blocks.message().await? {
+        chunk.push(block);
+    }
+    Ok(chunk)
+}
+
 pub async fn get_transparent(
     network: &Network,
     client: &mut Client,
+    usage: &DataUsage,
     account: u32,
     taddr: TransparentAddress,
     start: u32,
@@ -147,6 +413,7 @@ pub async fn get_transparent(
         .into_inner();
     let mut ttxs = vec![];
     while let Some(raw_tx) = txs.message().await? {
+        usage.record(raw_tx.encoded_len());
         let height = raw_tx.height as u32;
         let raw_tx = raw_tx.data;
         let branch_id = BranchId::for_height(network, BlockHeight::from_u32(height));
@@ -187,7 +454,38 @@ pub async fn get_transparent(
     Ok(ttxs)
 }
 
-pub async fn broadcast(client: &mut Client, height: u32, tx: &[u8]) -> Result<String> {
+#[derive(Clone, Serialize, Debug)]
+pub struct BroadcastResult {
+    /// Computed from the submitted bytes, None if they do not parse
+    pub txid: Option<Hash>,
+    pub accepted: bool,
+    /// Message of the server when the transaction was rejected
+    pub error: Option<String>,
+}
+
+impl BroadcastResult {
+    /// lightwalletd reports the node error with a non zero code.
+    /// A transaction the node already has is not an error for us
+    pub fn from_response(txid: Option<Hash>, error_code: i32, error_message: &str) -> Self {
+        let already_known = error_message.contains("txn-already-known")
+            || error_message.contains("txn-already-in-mempool");
+        let accepted = error_code == 0 || already_known;
+        Self {
+            txid,
+            accepted,
+            error: if accepted {
+                None
+            } else {
+                Some(error_message.to_string())
+            },
+        }
+    }
+}
+
+pub async fn broadcast(client: &mut Client, height: u32, tx: &[u8]) -> Result<BroadcastResult> {
+    let txid = Transaction::read(tx, BranchId::Nu5)
+        .ok()
+        .map(|tx| *tx.txid().as_ref());
     let res = client
         .send_transaction(Request::new(RawTransaction {
             data: tx.to_vec(),
@@ -195,10 +493,19 @@ pub async fn broadcast(client: &mut Client, height: u32, tx: &[u8]) -> Result<St
         }))
         .await?
         .into_inner();
-    Ok(res.error_message)
+    Ok(BroadcastResult::from_response(
+        txid,
+        res.error_code,
+        &res.error_message,
+    ))
 }
 
-pub fn get_txin_coins(network: Network, url: String, ops: Vec<OutPoint>) -> Result<Vec<TxOut2>> {
+pub fn get_txin_coins(
+    network: Network,
+    url: String,
+    usage: &DataUsage,
+    ops: Vec<OutPoint>,
+) -> Result<Vec<TxOut2>> {
     tokio::task::block_in_place(move || {
         Handle::current().block_on(async move {
             let mut client = connect_lwd(&url).await?;
@@ -212,6 +519,7 @@ pub fn get_txin_coins(network: Network, url: String, ops: Vec<OutPoint>) -> Resu
                     }))
                     .await?
                     .into_inner();
+                usage.record(tx.encoded_len());
                 let data = &*tx.data;
                 let tx = Transaction::read(data, BranchId::Nu5)?;
                 let tx_data = tx.into_data();
@@ -234,6 +542,7 @@ pub fn get_txin_coins(network: Network, url: String, ops: Vec<OutPoint>) -> Resu
 pub async fn get_transaction(
     network: &Network,
     client: &mut Client,
+    usage: &DataUsage,
     txid: &[u8],
 ) -> Result<(u32, Transaction)> {
     let tx = client
@@ -244,6 +553,7 @@ pub async fn get_transaction(
         }))
         .await?
         .into_inner();
+    usage.record(tx.encoded_len());
     let height = tx.height as u32;
     let tx = Transaction::read(
         &*tx.data,
@@ -251,3 +561,143 @@ pub async fn get_transaction(
     )?;
     Ok((height, tx))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        broadcast, compact_block_stream, fetch_blocks, get_compact_block, get_server_bounds,
+        mock::{MockChain, MockLwd},
+        rpc::{CompactBlock, CompactSaplingOutput, CompactTx},
+        BlockError, BroadcastResult, DataUsage, ServerBounds, FETCH_CHUNK,
+    };
+    use crate::coin::connect_lwd;
+    use tokio_stream::StreamExt as _;
+
+    #[test]
+    fn heights_outside_the_server_are_rejected() {
+        let bounds = ServerBounds {
+            start: 419_200,
+            tip: 2_000_000,
+        };
+        assert!(bounds.check(419_200).is_ok());
+        assert!(bounds.check(2_000_000).is_ok());
+        assert!(matches!(
+            bounds.check(2_000_001),
+            Err(BlockError::AboveTip(2_000_001, 2_000_000))
+        ));
+        assert!(matches!(
+            bounds.check(1),
+            Err(BlockError::BelowStart(1, 419_200))
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn block_within_the_bounds() {
+        let mut chain = MockChain::default();
+        for height in [10, 12] {
+            chain.add_block(CompactBlock {
+                height,
+                ..CompactBlock::default()
+            });
+        }
+        let url = MockLwd::new(chain).start().await.unwrap();
+        let mut client = connect_lwd(&url).await.unwrap();
+        let bounds = get_server_bounds(&mut client).await.unwrap();
+        assert_eq!(bounds.tip, 12);
+        let usage = DataUsage::default();
+        let block = get_compact_block(&mut client, &usage, &bounds, 10)
+            .await
+            .unwrap();
+        assert_eq!(block.height, 10);
+        assert!(usage.get() > 0);
+        let err = get_compact_block(&mut client, &usage, &bounds, 11)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BlockError>(),
+            Some(BlockError::NotFound(11))
+        ));
+        assert!(get_compact_block(&mut client, &usage, &bounds, 13)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream_over_several_chunks() {
+        let mut chain = MockChain::default();
+        let end = 2 * FETCH_CHUNK + 10;
+        for height in 1..=end + 5 {
+            chain.add_block(CompactBlock {
+                height: height as u64,
+                ..CompactBlock::default()
+            });
+        }
+        let url = MockLwd::new(chain).start().await.unwrap();
+        let client = connect_lwd(&url).await.unwrap();
+        let heights = compact_block_stream(&client, 5, end)
+            .map(|b| b.unwrap().height as u32)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(heights, (5..=end).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejected_broadcast() {
+        let chain = MockChain {
+            send_error: Some((-26, "bad-txns-sapling-duplicate-nullifier".to_string())),
+            ..MockChain::default()
+        };
+        let url = MockLwd::new(chain).start().await.unwrap();
+        let mut client = connect_lwd(&url).await.unwrap();
+        let r = broadcast(&mut client, 0, &[1, 2, 3]).await.unwrap();
+        assert!(!r.accepted);
+        assert_eq!(
+            r.error.as_deref(),
+            Some("bad-txns-sapling-duplicate-nullifier")
+        );
+        // the bytes are not a transaction
+        assert!(r.txid.is_none());
+
+        let known = BroadcastResult::from_response(Some([1u8; 32]), -25, "txn-already-known");
+        assert!(known.accepted);
+        assert!(known.error.is_none());
+        assert!(BroadcastResult::from_response(None, 0, "").accepted);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_fetch_matches_serial() {
+        let mut chain = MockChain::default();
+        // three chunks, the last one partial
+        let end = 2 * FETCH_CHUNK + 500;
+        for height in 1..=end {
+            // the order of the outputs gives the position of the notes
+            let outputs = (0..height % 3)
+                .map(|i| CompactSaplingOutput {
+                    cmu: [height.to_le_bytes(), i.to_le_bytes()].concat(),
+                    ..CompactSaplingOutput::default()
+                })
+                .collect();
+            chain.add_block(CompactBlock {
+                height: height as u64,
+                vtx: vec![CompactTx {
+                    outputs,
+                    ..CompactTx::default()
+                }],
+                ..CompactBlock::default()
+            });
+        }
+        let url = MockLwd::new(chain).start().await.unwrap();
+        let client = connect_lwd(&url).await.unwrap();
+        let mut fetched = vec![];
+        for concurrency in [1, 3] {
+            let mut source = fetch_blocks(&client, 1, end, concurrency).await.unwrap();
+            let mut blocks = vec![];
+            while let Some(block) = source.next().await.unwrap() {
+                blocks.push(block);
+            }
+            fetched.push(blocks);
+        }
+        assert_eq!(fetched[0].len(), end as usize);
+        assert_eq!(fetched[0], fetched[1]);
+    }
+}
@@ -0,0 +1,426 @@
+use std::{collections::BTreeMap, net::SocketAddr, pin::Pin, sync::Arc};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use tokio::net::TcpListener;
+use tokio_stream::Stream;
+use tonic::{
+    transport::{server::TcpIncoming, Server},
+    Request, Response, Status,
+};
+
+use super::rpc::{
+    compact_tx_streamer_server::{CompactTxStreamer, CompactTxStreamerServer},
+    BlockId, BlockRange, ChainSpec, CompactBlock, Duration, Empty, GetAddressUtxosArg,
+    GetAddressUtxosReply, GetSubtreeRootsArg, LightdInfo, PingResponse, RawTransaction,
+    SendResponse, SubtreeRoot, TransparentAddressBlockFilter, TreeState, TxFilter,
+};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Scripted chain served by `MockLwd`
+#[derive(Default, Debug)]
+pub struct MockChain {
+    pub blocks: BTreeMap<u32, CompactBlock>,
+    pub tree_states: BTreeMap<u32, TreeState>,
+    /// Subtree roots of the sapling (0) and orchard (1) trees
+    pub subtree_roots: [Vec<SubtreeRoot>; 2],
+    /// Full transactions by txid
+    pub transactions: BTreeMap<Vec<u8>, RawTransaction>,
+    /// Transactions of each transparent address
+    pub taddr_txs: BTreeMap<String, Vec<RawTransaction>>,
+    pub utxos: Vec<GetAddressUtxosReply>,
+    /// Transactions received by `send_transaction`
+    pub sent: Vec<Vec<u8>>,
+    /// Error returned by `send_transaction` when set
+    pub send_error: Option<(i32, String)>,
+    /// Number of calls to `get_tree_state`
+    pub tree_state_requests: u32,
+}
+
+impl MockChain {
+    pub fn add_block(&mut self, block: CompactBlock) {
+        self.blocks.insert(block.height as u32, block);
+    }
+
+    pub fn set_tree_state(&mut self, tree_state: TreeState) {
+        self.tree_states.insert(tree_state.height as u32, tree_state);
+    }
+
+    pub fn add_transaction(&mut self, txid: &[u8], tx: RawTransaction) {
+        self.transactions.insert(txid.to_vec(), tx);
+    }
+
+    /// Drop the blocks above `height`, to script a reorg
+    pub fn truncate(&mut self, height: u32) {
+        self.blocks.retain(|&h, _| h <= height);
+        self.tree_states.retain(|&h, _| h <= height);
+    }
+
+    fn tip(&self) -> u32 {
+        self.blocks.keys().next_back().copied().unwrap_or_default()
+    }
+}
+
+/// In-process lightwalletd for integration tests. It serves an editable
+/// `MockChain` on a local port so that `CoinDef::set_url` (and `set_warp`)
+/// can point the wallet at it and run `warp_sync` or a broadcast
+/// against scripted data
+#[derive(Clone, Default)]
+pub struct MockLwd {
+    pub chain: Arc<Mutex<MockChain>>,
+}
+
+impl MockLwd {
+    pub fn new(chain: MockChain) -> Self {
+        Self {
+            chain: Arc::new(Mutex::new(chain)),
+        }
+    }
+
+    /// Start serving on 127.0.0.1 with a free port and return the url
+    /// to connect to. The server runs until the tokio runtime stops
+    pub async fn start(&self) -> Result<String> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        let incoming =
+            TcpIncoming::from_listener(listener, true, None).map_err(|e| anyhow::anyhow!(e))?;
+        let service = CompactTxStreamerServer::new(self.clone());
+        tokio::spawn(async move {
+            let _ = Server::builder()
+                .add_service(service)
+                .serve_with_incoming(incoming)
+                .await;
+        });
+        Ok(format!("http://{addr}"))
+    }
+}
+
+fn range_bounds(range: &BlockRange) -> Result<(u32, u32), Status> {
+    let start = range
+        .start
+        .as_ref()
+        .ok_or(Status::invalid_argument("Missing start"))?
+        .height as u32;
+    let end = range
+        .end
+        .as_ref()
+        .ok_or(Status::invalid_argument("Missing end"))?
+        .height as u32;
+    Ok((start, end))
+}
+
+fn to_stream<T: Send + 'static>(items: Vec<T>) -> ResponseStream<T> {
+    Box::pin(tokio_stream::iter(items.into_iter().map(Ok)))
+}
+
+#[tonic::async_trait]
+impl CompactTxStreamer for MockLwd {
+    async fn get_latest_block(
+        &self,
+        _request: Request<ChainSpec>,
+    ) -> Result<Response<BlockId>, Status> {
+        let chain = self.chain.lock();
+        let height = chain.tip();
+        let hash = chain
+            .blocks
+            .get(&height)
+            .map(|b| b.hash.clone())
+            .unwrap_or_default();
+        Ok(Response::new(BlockId {
+            height: height as u64,
+            hash,
+        }))
+    }
+
+    type GetBlockRangeStream = ResponseStream<CompactBlock>;
+
+    async fn get_block_range(
+        &self,
+        request: Request<BlockRange>,
+    ) -> Result<Response<Self::GetBlockRangeStream>, Status> {
+        let (start, end) = range_bounds(request.get_ref())?;
+        let chain = self.chain.lock();
+        let blocks = chain
+            .blocks
+            .range(start..=end)
+            .map(|(_, b)| b.clone())
+            .collect::<Vec<_>>();
+        Ok(Response::new(to_stream(blocks)))
+    }
+
+    type GetPrunedBlockRangeStream = ResponseStream<CompactBlock>;
+
+    async fn get_pruned_block_range(
+        &self,
+        request: Request<BlockRange>,
+    ) -> Result<Response<Self::GetPrunedBlockRangeStream>, Status> {
+        let (start, end) = range_bounds(request.get_ref())?;
+        let chain = self.chain.lock();
+        let blocks = chain
+            .blocks
+            .range(start..=end)
+            .map(|(_, b)| b.clone())
+            .collect::<Vec<_>>();
+        Ok(Response::new(to_stream(blocks)))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: Request<TxFilter>,
+    ) -> Result<Response<RawTransaction>, Status> {
+        let chain = self.chain.lock();
+        let tx = chain
+            .transactions
+            .get(&request.get_ref().hash)
+            .cloned()
+            .ok_or(Status::not_found("Unknown transaction"))?;
+        Ok(Response::new(tx))
+    }
+
+    async fn send_transaction(
+        &self,
+        request: Request<RawTransaction>,
+    ) -> Result<Response<SendResponse>, Status> {
+        let mut chain = self.chain.lock();
+        let response = match chain.send_error.clone() {
+            Some((error_code, error_message)) => SendResponse {
+                error_code,
+                error_message,
+            },
+            None => {
+                chain.sent.push(request.into_inner().data);
+                SendResponse {
+                    error_code: 0,
+                    error_message: String::new(),
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    type GetTaddressTxidsStream = ResponseStream<RawTransaction>;
+
+    async fn get_taddress_txids(
+        &self,
+        request: Request<TransparentAddressBlockFilter>,
+    ) -> Result<Response<Self::GetTaddressTxidsStream>, Status> {
+        let filter = request.get_ref();
+        let (start, end) = match filter.range.as_ref() {
+            Some(range) => range_bounds(range)?,
+            None => (0, u32::MAX),
+        };
+        let chain = self.chain.lock();
+        let txs = chain
+            .taddr_txs
+            .get(&filter.address)
+            .map(|txs| {
+                txs.iter()
+                    .filter(|tx| (start..=end).contains(&(tx.height as u32)))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        Ok(Response::new(to_stream(txs)))
+    }
+
+    async fn get_tree_state(
+        &self,
+        request: Request<BlockId>,
+    ) -> Result<Response<TreeState>, Status> {
+        let height = request.get_ref().height as u32;
+        let mut chain = self.chain.lock();
+        chain.tree_state_requests += 1;
+        let tree_state = chain
+            .tree_states
+            .get(&height)
+            .cloned()
+            .ok_or(Status::not_found(format!("No tree state at {height}")))?;
+        Ok(Response::new(tree_state))
+    }
+
+    type GetSubtreeRootsStream = ResponseStream<SubtreeRoot>;
+
+    async fn get_subtree_roots(
+        &self,
+        request: Request<GetSubtreeRootsArg>,
+    ) -> Result<Response<Self::GetSubtreeRootsStream>, Status> {
+        let arg = request.get_ref();
+        let protocol = arg.shielded_protocol as usize;
+        if protocol > 1 {
+            return Err(Status::invalid_argument("Invalid protocol"));
+        }
+        let chain = self.chain.lock();
+        let max = if arg.max_entries == 0 {
+            usize::MAX
+        } else {
+            arg.max_entries as usize
+        };
+        let roots = chain.subtree_roots[protocol]
+            .iter()
+            .skip(arg.start_index as usize)
+            .take(max)
+            .cloned()
+            .collect::<Vec<_>>();
+        Ok(Response::new(to_stream(roots)))
+    }
+
+    type GetAddressUtxosStreamStream = ResponseStream<GetAddressUtxosReply>;
+
+    async fn get_address_utxos_stream(
+        &self,
+        request: Request<GetAddressUtxosArg>,
+    ) -> Result<Response<Self::GetAddressUtxosStreamStream>, Status> {
+        let arg = request.get_ref();
+        let chain = self.chain.lock();
+        let utxos = chain
+            .utxos
+            .iter()
+            .filter(|u| arg.addresses.contains(&u.address) && u.height >= arg.start_height)
+            .cloned()
+            .collect::<Vec<_>>();
+        Ok(Response::new(to_stream(utxos)))
+    }
+
+    async fn get_lightd_info(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<LightdInfo>, Status> {
+        let chain = self.chain.lock();
+        Ok(Response::new(LightdInfo {
+            vendor: "mock".to_string(),
+            block_height: chain.tip() as u64,
+            ..LightdInfo::default()
+        }))
+    }
+
+    async fn ping(&self, _request: Request<Duration>) -> Result<Response<PingResponse>, Status> {
+        Err(Status::unimplemented("ping"))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::{MockChain, MockLwd};
+    use crate::{
+        coin::CoinDef,
+        db::{
+            reset_tables,
+            testing::{test_account, TEST_BIRTH},
+        },
+        lwd::rpc::{CompactBlock, TreeState},
+    };
+    use rusqlite::params;
+    use zcash_primitives::consensus::Network;
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    pub fn block_hash(height: u32, fork: u8) -> Vec<u8> {
+        let mut hash = vec![fork; 32];
+        hash[..4].copy_from_slice(&height.to_le_bytes());
+        hash
+    }
+
+    pub fn block(height: u32, fork: u8) -> CompactBlock {
+        CompactBlock {
+            height: height as u64,
+            hash: block_hash(height, fork),
+            prev_hash: block_hash(height - 1, fork),
+            time: height,
+            ..CompactBlock::default()
+        }
+    }
+
+    /// Empty blocks from `TEST_BIRTH + 1` to `TEST_BIRTH + n`
+    /// with empty trees
+    pub fn chain(n: u32) -> MockChain {
+        let mut chain = MockChain::default();
+        for height in [TEST_BIRTH, TEST_BIRTH + n] {
+            chain.set_tree_state(TreeState {
+                height: height as u64,
+                ..TreeState::default()
+            });
+        }
+        for height in TEST_BIRTH + 1..=TEST_BIRTH + n {
+            chain.add_block(block(height, 0));
+        }
+        chain
+    }
+
+    /// Wallet with the test account synced to `TEST_BIRTH` and
+    /// connected to `lwd`
+    pub async fn wallet(name: &str, lwd: &MockLwd) -> CoinDef {
+        let url = lwd.start().await.unwrap();
+        let path = std::env::temp_dir().join(format!("warp-{name}-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut coin = CoinDef::from_network(NETWORK);
+        coin.set_db_path(&path).unwrap();
+        coin.set_url(&url);
+        coin.set_warp(&url);
+        let connection = coin.connection().unwrap();
+        reset_tables(&connection).unwrap();
+        test_account(&NETWORK, &connection);
+        connection
+            .execute(
+                "INSERT INTO blcks(height, hash, prev_hash, timestamp)
+                VALUES (?1, ?2, ?3, 0)",
+                params![
+                    TEST_BIRTH,
+                    block_hash(TEST_BIRTH, 0),
+                    block_hash(TEST_BIRTH - 1, 0)
+                ],
+            )
+            .unwrap();
+        coin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{block, block_hash, chain, wallet};
+    use crate::{
+        db::{notes::get_block_header, testing::TEST_BIRTH},
+        lwd::rpc::TreeState,
+        types::CheckpointHeight,
+        warp::sync::{warp_sync, SyncError},
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sync_to_the_tip() {
+        let lwd = MockLwd::new(chain(10));
+        let coin = wallet("sync", &lwd).await;
+        let stats = warp_sync(&coin, CheckpointHeight(TEST_BIRTH), TEST_BIRTH + 10)
+            .await
+            .unwrap();
+        assert_eq!(stats.blocks, 10);
+        assert_eq!(stats.notes, 0);
+        assert!(coin.data_usage() > 0);
+        let connection = coin.connection().unwrap();
+        let bh = get_block_header(&connection, TEST_BIRTH + 10).unwrap();
+        assert_eq!(bh.hash.to_vec(), block_hash(TEST_BIRTH + 10, 0));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sync_detects_reorg() {
+        let lwd = MockLwd::new(chain(10));
+        let coin = wallet("reorg", &lwd).await;
+        warp_sync(&coin, CheckpointHeight(TEST_BIRTH), TEST_BIRTH + 10)
+            .await
+            .unwrap();
+        {
+            let mut chain = lwd.chain.lock();
+            chain.truncate(TEST_BIRTH + 5);
+            for height in TEST_BIRTH + 6..=TEST_BIRTH + 20 {
+                chain.add_block(block(height, 1));
+            }
+            for height in [TEST_BIRTH + 10, TEST_BIRTH + 20] {
+                chain.set_tree_state(TreeState {
+                    height: height as u64,
+                    ..TreeState::default()
+                });
+            }
+        }
+        let r = warp_sync(&coin, CheckpointHeight(TEST_BIRTH + 10), TEST_BIRTH + 20).await;
+        assert!(matches!(r, Err(SyncError::Reorg(h)) if h == TEST_BIRTH + 11));
+    }
+}
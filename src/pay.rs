@@ -1,19 +1,34 @@
-use std::str::FromStr;
+use std::{
+    collections::BTreeMap,
+    ops::Deref as _,
+    str::FromStr,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use fee::FeeManager;
-use orchard::circuit::ProvingKey;
-use rand::{CryptoRng, RngCore};
+use orchard::circuit::{ProvingKey, VerifyingKey};
+use rand::{rngs::OsRng, CryptoRng, RngCore};
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
 use thiserror::Error;
 use zcash_keys::address::Address as RecipientAddress;
-use zcash_primitives::{consensus::Network, memo::MemoBytes};
+use zcash_primitives::{
+    consensus::{BlockHeight, BranchId, Network},
+    memo::MemoBytes,
+    transaction::{
+        sighash::{signature_hash, SignableInput},
+        txid::TxIdDigester,
+        Transaction,
+    },
+};
 use zcash_proofs::prover::LocalTxProver;
 use zcash_protocol::memo::Memo;
 
 use self::conv::MemoBytesProxy;
 use crate::{
-    data::fb::{PaymentRequestT, TransactionRecipientT, TransactionSummaryT}, keys::TSKStore, types::{AccountInfo, CheckpointHeight, PoolMask}, warp::{legacy::CommitmentTreeFrontier, AuthPath, Edge, Witness, UTXO}, Hash
+    data::fb::{PaymentRequestT, TransactionChangeT, TransactionRecipientT, TransactionSummaryT}, db::{account::{find_address_account, get_balance}, notes::get_utxo_value, pending::{find_recent_payment, store_recent_payment}}, keys::TSKStore, types::{AccountInfo, CheckpointHeight, PoolMask}, warp::{legacy::CommitmentTreeFrontier, AuthPath, Edge, Witness, UTXO}, Hash, DUPLICATE_PAYMENT_WINDOW, EXPIRATION_HEIGHT_DELTA
 };
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -22,8 +37,11 @@ pub mod builder;
 pub mod conv;
 mod fee;
 pub mod prepare;
+pub mod signer;
 pub mod sweep;
 
+pub use fee::{FeeStrategy, MIN_FEE, MIN_PADDING};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Not Enough Funds, {0} more needed")]
@@ -34,11 +52,33 @@ pub enum Error {
     NoRecipient,
     #[error("Transaction has no change output")]
     NoChangeOutput,
+    #[error("A TEX address must be paid from transparent funds, move funds to the transparent pool first")]
+    NoTransparentFundsForTex,
+    #[error("A memo cannot be sent to the transparent address {0}")]
+    TransparentMemo(String),
+    #[error("Amount overflow")]
+    AmountOverflow,
+    #[error("Note {0} has no witness at the anchor height, wait for more confirmations")]
+    NoteNotWitnessed(u32),
+    #[error("The same payment was built {0} seconds ago, use --force to build it again")]
+    DuplicatePayment(u32),
+    #[error("Fee {0} is above the maximum {1}, select fewer or larger notes")]
+    FeeTooHigh(u64, u64),
+    #[error("Sapling parameters not found, download them to their default location")]
+    NoSaplingParameters,
+    #[error("Account {0} is frozen, unfreeze it to spend")]
+    AccountFrozen(u32),
+    #[error("Transaction of {0} bytes is above the maximum size of {1} bytes")]
+    TxTooLarge(usize, usize),
+    #[error("Recipient {0} is an address of account {1}, use --allow-self to pay it")]
+    SelfPayment(String, u32),
+    #[error("Only one recipient can receive the remainder")]
+    MultipleRemainders,
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct PaymentItem {
     pub address: String,
     pub amount: u64,
@@ -50,13 +90,16 @@ impl TryFrom<&PaymentRequestT> for PaymentItem {
         let memo = p
             .memo_string
             .as_ref()
+            .filter(|s| !s.is_empty())
             .map_or_else(
                 || p.memo_bytes.as_ref().map(|b| Memo::from_bytes(&*b)),
                 |s| Some(Memo::from_str(&s)),
             )
             .transpose()
             .map_err(anyhow::Error::new)?;
-        let memo = memo.map(|memo| MemoBytes::from(&memo));
+        let memo = memo
+            .map(|memo| MemoBytes::from(&memo))
+            .filter(|memo| *memo != MemoBytes::empty());
         Ok(Self {
             address: p.address.clone().unwrap(),
             amount: p.amount,
@@ -67,10 +110,127 @@ impl TryFrom<&PaymentRequestT> for PaymentItem {
     type Error = Error;
 }
 
+impl PaymentItem {
+    /// Memo from raw bytes given in hex, stored as is without
+    /// going through the text encoding. At most 512 bytes, shorter
+    /// memos are padded with zeros
+    pub fn memo_from_hex(memo_hex: &str) -> Result<MemoBytes> {
+        let bytes = hex::decode(memo_hex.trim()).map_err(anyhow::Error::new)?;
+        if bytes.len() > 512 {
+            return Err(anyhow::anyhow!("Memo is {} bytes, the maximum is 512", bytes.len()).into());
+        }
+        let memo = MemoBytes::from_bytes(&bytes).map_err(anyhow::Error::new)?;
+        Ok(memo)
+    }
+}
+
+/// The default maximum fee is a tenth of the amount sent
+/// but never below this value (20 logical actions)
+pub const MIN_MAX_FEE: u64 = 100_000;
+
+/// Maximum number of outputs a payment can be split into
+pub const MAX_SPLIT_OUTPUTS: u32 = 5;
+
+/// Amount of the recipient that gets the funds left after
+/// the other recipients and the fee. At most one per payment
+pub const REMAINDER: u64 = u64::MAX;
+
+impl PaymentItem {
+    /// Split the payment into `n` outputs of random amounts that add up
+    /// to the original amount, to hide it. Every output goes to the same
+    /// address because we cannot derive diversified addresses for
+    /// the recipient. The memo is only kept on the first output.
+    /// Each additional output increases the fee
+    pub fn split<R: RngCore>(&self, n: u32, rng: &mut R) -> Result<Vec<PaymentItem>> {
+        if n == 0 || n > MAX_SPLIT_OUTPUTS {
+            return Err(anyhow::anyhow!("Split must be between 1 and {MAX_SPLIT_OUTPUTS}").into());
+        }
+        if self.amount == REMAINDER {
+            if n > 1 {
+                return Err(anyhow::anyhow!("The remainder cannot be split").into());
+            }
+            return Ok(vec![self.clone()]);
+        }
+        if self.amount < 2 * n as u64 {
+            return Err(anyhow::anyhow!("Amount too small to split").into());
+        }
+        // every output gets at least half of an equal share,
+        // which is never 0
+        let min = self.amount / (2 * n as u64);
+        let free = self.amount - min * n as u64;
+        let mut cuts = (1..n)
+            .map(|_| rng.next_u64() % (free + 1))
+            .collect::<Vec<_>>();
+        cuts.sort();
+        cuts.push(free);
+        let mut prev = 0;
+        let items = cuts
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let amount = min + c - prev;
+                prev = c;
+                PaymentItem {
+                    address: self.address.clone(),
+                    amount,
+                    memo: if i == 0 { self.memo.clone() } else { None },
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(items)
+    }
+
+    /// Memos are only allowed for shielded recipients (ZIP-321)
+    pub fn validate(&self, network: &Network) -> Result<()> {
+        let has_memo = self
+            .memo
+            .as_ref()
+            .map(|m| *m != MemoBytes::empty())
+            .unwrap_or_default();
+        if has_memo {
+            let address = RecipientAddress::decode(network, &self.address)
+                .ok_or(anyhow::anyhow!("Invalid Address"))?;
+            if matches!(
+                address,
+                RecipientAddress::Transparent(_) | RecipientAddress::Tex(_)
+            ) {
+                return Err(Error::TransparentMemo(self.address.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Payment {
     pub recipients: Vec<PaymentItem>,
 }
 
+impl Payment {
+    /// Identifies the payment by the total amount sent to each recipient,
+    /// plus an optional key chosen by the user. Memos and the way
+    /// the amounts are split are not included
+    pub fn fingerprint(&self, account: u32, key: Option<&str>) -> Hash {
+        let mut totals = BTreeMap::<&str, u64>::new();
+        for r in self.recipients.iter() {
+            let total = totals.entry(&r.address).or_default();
+            *total = total.saturating_add(r.amount);
+        }
+        let mut h = Sha256::new();
+        h.update(account.to_le_bytes());
+        for (address, amount) in totals {
+            h.update((address.len() as u32).to_le_bytes());
+            h.update(address.as_bytes());
+            h.update(amount.to_le_bytes());
+        }
+        if let Some(key) = key {
+            h.update([1]);
+            h.update(key.as_bytes());
+        }
+        h.finalize().into()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ExtendedPayment {
     pub payment: PaymentItem,
@@ -78,6 +238,8 @@ pub struct ExtendedPayment {
     pub remaining: u64,
     pub pool: PoolMask,
     pub is_change: bool,
+    /// ZIP-320 address that only accepts transparent inputs
+    pub tex: bool,
 }
 
 impl ExtendedPayment {
@@ -85,9 +247,10 @@ impl ExtendedPayment {
         self.payment
     }
     fn to_extended(network: &Network, payment: PaymentItem) -> Result<Self> {
+        payment.validate(network)?;
         let ua = RecipientAddress::decode(network, &payment.address)
             .ok_or(anyhow::anyhow!("Invalid Address"))?;
-        let pool = match ua {
+        let pool = match &ua {
             RecipientAddress::Sapling(_) => 2,
             RecipientAddress::Tex(_) => 1,
             RecipientAddress::Transparent(_) => 1,
@@ -97,18 +260,22 @@ impl ExtendedPayment {
                 s | o
             }
         };
+        let tex = matches!(ua, RecipientAddress::Tex(_));
         Ok(ExtendedPayment {
             amount: payment.amount,
             remaining: payment.amount,
             payment,
             pool: PoolMask(pool),
             is_change: false,
+            tex,
         })
     }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct TxInput {
+    /// id of the note, 0 for utxos
+    pub id: u32,
     pub amount: u64,
     pub remaining: u64,
     pub pool: u8,
@@ -174,12 +341,22 @@ pub struct PaymentBuilder {
     pub outputs: Vec<ExtendedPayment>,
     pub account_pools: PoolMask,
     pub src_pools: PoolMask,
+    pub source_priority: Option<Vec<u8>>,
+    /// Memo of the shielded outputs that have none
+    pub default_memo: Option<MemoBytes>,
+    /// Receiver and pool of the change when it does not go
+    /// back to the account
+    pub change_address: Option<(String, PoolMask)>,
 
     pub fee_manager: FeeManager,
     pub fee: u64,
 
     pub available: [u64; 3],
     pub use_change: bool,
+    /// Do not shuffle the outputs
+    pub deterministic: bool,
+    /// Highest fee `prepare` accepts, see `MIN_MAX_FEE` for the default
+    pub max_fee: Option<u64>,
 
     pub s_edge: Edge,
     pub o_edge: Edge,
@@ -192,16 +369,32 @@ pub struct AdjustableUnsignedTransaction {
     pub change: i64,
 }
 
+/// Serialized transaction with its id, computed when it is built
+#[derive(Clone, Debug)]
+pub struct SignedTransaction {
+    pub txid: Hash,
+    pub data: Vec<u8>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UnsignedTransaction {
     pub account: u32,
     pub account_name: String,
     pub account_id: Hash,
     pub height: u32,
+    /// Height of the tree state the witnesses and roots are taken at
+    pub checkpoint_height: u32,
     pub tx_notes: Vec<TxInput>,
     pub tx_outputs: Vec<TxOutput>,
     pub roots: [Hash; 2],
     pub edges: [AuthPath; 2],
+    /// Sapling outputs and Orchard actions the bundles are padded to
+    /// with empty outputs, as the fee was computed. 0 leaves the
+    /// padding of the transaction builders
+    #[serde(default)]
+    pub min_sapling_outputs: u32,
+    #[serde(default)]
+    pub min_orchard_actions: u32,
 }
 
 impl UnsignedTransaction {
@@ -232,6 +425,23 @@ impl UnsignedTransaction {
         }).sum::<PoolBalance>();
         let net = ins - outs;
         let fee = (net.0 + net.1 + net.2) as u64;
+        // an exact spend has no change, or a change output of 0
+        let change = self
+            .tx_outputs
+            .iter()
+            .find(|o| o.change && o.amount > 0)
+            .map(|o| {
+                let pool = match o.note {
+                    OutputNote::Transparent { .. } => 0,
+                    OutputNote::Sapling { .. } => 1,
+                    OutputNote::Orchard { .. } => 2,
+                };
+                Box::new(TransactionChangeT {
+                    address: Some(o.address_string.clone()),
+                    amount: o.amount,
+                    pool,
+                })
+            });
         let data = bincode::serialize(&self).unwrap();
         Ok(TransactionSummaryT {
             recipients: Some(recipients),
@@ -240,6 +450,9 @@ impl UnsignedTransaction {
             orchard_net: net.2,
             fee,
             data: Some(data),
+            anchor_height: self.checkpoint_height,
+            change,
+            size: 0,
         })
     }
 }
@@ -251,6 +464,14 @@ impl TransactionSummaryT {
     }
 }
 
+/// Sum of amounts that fails instead of wrapping around
+pub(crate) fn checked_sum<I: IntoIterator<Item = u64>>(amounts: I) -> Result<u64> {
+    amounts
+        .into_iter()
+        .try_fold(0u64, |a, b| a.checked_add(b))
+        .ok_or(Error::AmountOverflow)
+}
+
 #[derive(Clone, Copy, Debug)]
 struct PoolBalance(i64, i64, i64);
 
@@ -278,8 +499,64 @@ impl std::ops::Sub for PoolBalance {
 }
 
 lazy_static::lazy_static! {
-    pub static ref PROVER: LocalTxProver = LocalTxProver::with_default_location().unwrap();
-    pub static ref ORCHARD_PROVER: ProvingKey = ProvingKey::build();
+    pub static ref ORCHARD_VERIFIER: VerifyingKey = VerifyingKey::build();
+}
+
+/// Proving keys, loaded by the first transaction built
+pub struct Provers {
+    pub sapling: LocalTxProver,
+    orchard: OnceLock<ProvingKey>,
+}
+
+impl Provers {
+    /// Built on first use, when an Orchard bundle is proven
+    pub fn orchard(&self) -> &ProvingKey {
+        self.orchard.get_or_init(ProvingKey::build)
+    }
+}
+
+static PROVERS: OnceLock<Provers> = OnceLock::new();
+
+/// The provers, loading the Sapling parameters from their default
+/// location the first time. Commands that build no transaction
+/// never call it and work without the parameters
+pub fn provers() -> Result<&'static Provers> {
+    if let Some(provers) = PROVERS.get() {
+        return Ok(provers);
+    }
+    let sapling = LocalTxProver::with_default_location().ok_or(Error::NoSaplingParameters)?;
+    Ok(PROVERS.get_or_init(|| Provers {
+        sapling,
+        orchard: OnceLock::new(),
+    }))
+}
+
+/// Settings of `make_payment` and `estimate_fee` that most payments
+/// leave to their defaults. See the setters of `PaymentBuilder`
+#[derive(Clone, Default, Debug)]
+pub struct PaymentOptions {
+    /// Order in which the pools (0: T, 1: S, 2: O) pay the outputs.
+    /// Pools not listed are not used
+    pub source_priority: Option<Vec<u8>>,
+    /// ZIP-317 when None
+    pub fee_strategy: Option<FeeStrategy>,
+    /// Address and pool (0: T, 1: S, 2: O) that receive the change
+    /// instead of the account
+    pub change_address: Option<(String, u8)>,
+    /// Minimum Sapling outputs and Orchard actions
+    pub padding: Option<(u32, u32)>,
+    /// Keep the outputs in the order of the payment, for tests
+    /// that compare transactions
+    pub deterministic: bool,
+    /// Tells apart payments that are identical on purpose,
+    /// see `Payment::fingerprint`
+    pub idempotency_key: Option<String>,
+    /// Build the payment even if an identical one was built recently
+    pub force: bool,
+    /// Highest acceptable fee, see `PaymentBuilder::set_max_fee`
+    pub max_fee: Option<u64>,
+    /// Pay an address of one of our accounts
+    pub allow_self: bool,
 }
 
 pub fn make_payment(
@@ -292,19 +569,232 @@ pub fn make_payment(
     fee_paid_by_sender: bool,
     s_tree: &CommitmentTreeFrontier,
     o_tree: &CommitmentTreeFrontier,
+    options: &PaymentOptions,
 ) -> Result<UnsignedTransaction> {
+    for r in p.recipients.iter() {
+        if let Some(owner) = find_address_account(network, connection, &r.address)? {
+            tracing::warn!("Recipient {} belongs to account {owner}", r.address);
+            if !options.allow_self {
+                return Err(Error::SelfPayment(r.address.clone(), owner));
+            }
+        }
+    }
+    let fingerprint = p.fingerprint(account, options.idempotency_key.as_deref());
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(anyhow::Error::new)?
+        .as_secs() as u32;
+    let previous = find_recent_payment(
+        connection,
+        account,
+        &fingerprint,
+        now.saturating_sub(DUPLICATE_PAYMENT_WINDOW),
+    )?;
+    if let Some(previous) = previous {
+        let age = now.saturating_sub(previous);
+        tracing::warn!("An identical payment was built {age} seconds ago");
+        if !options.force {
+            return Err(Error::DuplicatePayment(age));
+        }
+    }
+    let (pb, utx) = prepare_payment(
+        network,
+        connection,
+        account,
+        cp_height,
+        p,
+        src_pools,
+        fee_paid_by_sender,
+        s_tree,
+        o_tree,
+        options,
+    )?;
+    let utx = pb.finalize(utx)?;
+    store_recent_payment(connection, account, &fingerprint, now)?;
+    Ok(utx)
+}
+
+/// Builder setup shared by `make_payment` and `estimate_fee`: select
+/// the source pools when `src_pools` is 0, give the rest of the funds
+/// to a `REMAINDER` recipient and take the fee from the recipients
+/// when the sender does not pay it. Returns the prepared transaction,
+/// ready for `finalize`
+fn prepare_payment(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    cp_height: CheckpointHeight,
+    p: Payment,
+    src_pools: PoolMask,
+    fee_paid_by_sender: bool,
+    s_tree: &CommitmentTreeFrontier,
+    o_tree: &CommitmentTreeFrontier,
+    options: &PaymentOptions,
+) -> Result<(PaymentBuilder, AdjustableUnsignedTransaction)> {
+    let remainders = p.recipients.iter().filter(|r| r.amount == REMAINDER).count();
+    if remainders > 1 {
+        return Err(Error::MultipleRemainders);
+    }
+    let remainder = remainders == 1;
+    let src_pools = match src_pools.0 {
+        // the remainder takes everything
+        0 if remainder => PoolMask(7),
+        0 => auto_select_pools(network, connection, account, cp_height, &p, options)?,
+        _ => src_pools,
+    };
     let mut pb = PaymentBuilder::new(
         network, connection, account, cp_height, p, src_pools, s_tree, o_tree,
     )?;
+    if let Some(priority) = options.source_priority.as_ref() {
+        pb.set_source_priority(priority.clone())?;
+    }
+    if let Some(strategy) = options.fee_strategy {
+        pb.set_fee_strategy(strategy)?;
+    }
+    if let Some((min_sapling_outputs, min_orchard_actions)) = options.padding {
+        pb.set_padding(min_sapling_outputs, min_orchard_actions)?;
+    }
+    if let Some((address, pool)) = options.change_address.as_ref() {
+        pb.set_change_address(address, *pool)?;
+    }
+    pb.set_deterministic(options.deterministic);
     pb.add_account_funds(&connection)?;
-    pb.set_use_change(true)?;
+    pb.set_use_change(!remainder)?;
+    if remainder {
+        pb.assign_remainder()?;
+    }
+    pb.set_max_fee(options.max_fee);
     let mut utx = pb.prepare()?;
-    if !fee_paid_by_sender {
+    if remainder {
+        // every fund is used, the remainder recipient pays the fee
+        let change = utx.change;
+        utx.add_to_change(-change)?;
+    } else if !fee_paid_by_sender {
         let fee = pb.fee_manager.fee();
         utx.add_to_change(fee as i64)?;
     }
-    let utx = pb.finalize(utx)?;
-    Ok(utx)
+    Ok((pb, utx))
+}
+
+/// Fee of the transaction `make_payment` would produce with the same
+/// arguments. Runs the note selection but does not build anything,
+/// the commitment trees are not needed
+pub fn estimate_fee(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    cp_height: CheckpointHeight,
+    p: Payment,
+    src_pools: PoolMask,
+    fee_paid_by_sender: bool,
+    options: &PaymentOptions,
+) -> Result<u64> {
+    let tree = CommitmentTreeFrontier::default();
+    // report the fee however high it is
+    let options = PaymentOptions {
+        max_fee: Some(u64::MAX),
+        ..options.clone()
+    };
+    let (pb, utx) = prepare_payment(
+        network,
+        connection,
+        account,
+        cp_height,
+        p,
+        src_pools,
+        fee_paid_by_sender,
+        &tree,
+        &tree,
+        &options,
+    )?;
+    if utx.change < 0 {
+        return Err(Error::NotEnoughFunds(utx.change.unsigned_abs()));
+    }
+    Ok(pb.fee_manager.fee())
+}
+
+/// Source pools tried by `auto_select_pools`, in order of preference
+const AUTO_POOLS: [u8; 4] = [4, 2, 6, 7];
+
+/// Pick the source pools of a payment when the user does not specify
+/// them (pools = 0). A single shielded pool is preferred, Orchard first,
+/// because it does not reveal any amount crossing pools. Then both
+/// shielded pools, and finally the transparent pool as well.
+/// The first choice that has enough funds for the payment
+/// and its fees wins. A payment with a `REMAINDER` recipient
+/// uses every pool
+pub fn auto_select_pools(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+    cp_height: CheckpointHeight,
+    p: &Payment,
+    options: &PaymentOptions,
+) -> Result<PoolMask> {
+    if p.recipients.iter().any(|r| r.amount == REMAINDER) {
+        // the remainder takes the funds of every pool
+        return Ok(PoolMask(7));
+    }
+    let balance = get_balance(connection, account, cp_height.into())?;
+    let total = checked_sum(p.recipients.iter().map(|r| r.amount))?;
+    let mut missing = total;
+    for mask in AUTO_POOLS {
+        let funds = checked_sum([
+            if mask & 1 != 0 { balance.transparent } else { 0 },
+            if mask & 2 != 0 { balance.sapling } else { 0 },
+            if mask & 4 != 0 { balance.orchard } else { 0 },
+        ])?;
+        if funds < total {
+            missing = missing.min(total - funds);
+            continue;
+        }
+        match estimate_fee(
+            network,
+            connection,
+            account,
+            cp_height,
+            p.clone(),
+            PoolMask(mask),
+            true,
+            options,
+        ) {
+            Ok(_) => {
+                tracing::info!("Pay from pools {mask}");
+                return Ok(PoolMask(mask));
+            }
+            Err(Error::NotEnoughFunds(m)) => missing = missing.min(m),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(Error::NotEnoughFunds(missing))
+}
+
+/// Expiry heights from this value are invalid (ZIP-203)
+pub const TX_EXPIRY_HEIGHT_THRESHOLD: u32 = 500_000_000;
+
+/// Expiry height of a transaction anchored at `cp_height`. By default it
+/// is `EXPIRATION_HEIGHT_DELTA` blocks later. A custom height must be above
+/// the anchor and below `TX_EXPIRY_HEIGHT_THRESHOLD`; with shielded inputs
+/// `check_anchor` warns when it is far from the anchor. 0 means that the
+/// transaction never expires
+pub fn expiry_height(cp_height: u32, expiry: Option<u32>) -> Result<u32> {
+    match expiry {
+        None => Ok(cp_height + EXPIRATION_HEIGHT_DELTA),
+        Some(0) => {
+            tracing::warn!(
+                "The transaction never expires, anyone who has it can broadcast it again at any time until its inputs are spent"
+            );
+            Ok(0)
+        }
+        Some(h) if h <= cp_height => {
+            Err(anyhow::anyhow!("Expiry height {h} is not above the anchor {cp_height}").into())
+        }
+        Some(h) if h >= TX_EXPIRY_HEIGHT_THRESHOLD => Err(anyhow::anyhow!(
+            "Expiry height {h} must be below {TX_EXPIRY_HEIGHT_THRESHOLD}"
+        )
+        .into()),
+        Some(h) => Ok(h),
+    }
 }
 
 pub fn sign_tx<R: RngCore + CryptoRng>(
@@ -314,7 +804,866 @@ pub fn sign_tx<R: RngCore + CryptoRng>(
     utx: UnsignedTransaction,
     tsk_store: &mut TSKStore,
     mut rng: R,
-) -> Result<Vec<u8>> {
-    let txb = utx.build(network, connection, expiration_height, tsk_store, &mut rng)?;
-    Ok(txb)
+) -> Result<SignedTransaction> {
+    let tx = utx.build(network, connection, expiration_height, tsk_store, &mut rng)?;
+    Ok(tx)
+}
+
+/// Local checks of a signed transaction before it is broadcast:
+/// consensus branch of the next block, expiry, inputs and outputs,
+/// value balance, Sapling and Orchard proofs and signatures.
+/// Transparent inputs must be utxos of the wallet so that their value
+/// is known. Their scripts are only checked to be present, the
+/// signatures are not verified.
+/// lightwalletd has no test-accept call, so the node may still
+/// reject it, for instance if a note was spent in the meantime
+pub fn validate_tx(
+    network: &Network,
+    connection: &Connection,
+    data: &[u8],
+    bc_height: u32,
+) -> Result<()> {
+    let next_height = BlockHeight::from_u32(bc_height + 1);
+    let branch_id = BranchId::for_height(network, next_height);
+    let tx = Transaction::read(data, branch_id).map_err(anyhow::Error::new)?;
+    if tx.consensus_branch_id() != branch_id {
+        return Err(anyhow::anyhow!(
+            "Transaction is for branch {:?}, the next block is {:?}",
+            tx.consensus_branch_id(),
+            branch_id
+        )
+        .into());
+    }
+    let expiry_height = u32::from(tx.expiry_height());
+    if expiry_height != 0 && expiry_height <= bc_height {
+        return Err(anyhow::anyhow!("Transaction expired at height {expiry_height}").into());
+    }
+
+    let t_ins = tx.transparent_bundle().map(|b| b.vin.len()).unwrap_or_default();
+    let t_outs = tx.transparent_bundle().map(|b| b.vout.len()).unwrap_or_default();
+    let s_ins = tx.sapling_bundle().map(|b| b.shielded_spends().len()).unwrap_or_default();
+    let s_outs = tx.sapling_bundle().map(|b| b.shielded_outputs().len()).unwrap_or_default();
+    let o_actions = tx.orchard_bundle().map(|b| b.actions().len()).unwrap_or_default();
+    if t_ins + s_ins + o_actions == 0 {
+        return Err(anyhow::anyhow!("Transaction has no input").into());
+    }
+    if t_outs + s_outs + o_actions == 0 {
+        return Err(anyhow::anyhow!("Transaction has no output").into());
+    }
+
+    let mut balance = 0i128;
+    if let Some(b) = tx.transparent_bundle() {
+        if b.vin.iter().any(|i| i.script_sig.0.is_empty()) {
+            return Err(anyhow::anyhow!("Transparent input without signature").into());
+        }
+        for i in b.vin.iter() {
+            let value = get_utxo_value(connection, i.prevout.hash(), i.prevout.n())?.ok_or(
+                anyhow::anyhow!(
+                    "Transparent input {}:{} is not a utxo of the wallet",
+                    hex::encode(i.prevout.hash()),
+                    i.prevout.n()
+                ),
+            )?;
+            balance += value as i128;
+        }
+        for o in b.vout.iter() {
+            balance -= u64::from(o.value) as i128;
+        }
+    }
+    if let Some(b) = tx.sapling_bundle() {
+        balance += i64::from(*b.value_balance()) as i128;
+    }
+    if let Some(b) = tx.orchard_bundle() {
+        balance += i64::from(*b.value_balance()) as i128;
+    }
+    if balance < 0 {
+        return Err(anyhow::anyhow!("Outputs exceed the inputs by {}", -balance).into());
+    }
+
+    let txid_parts = tx.deref().digest(TxIdDigester);
+    let sighash = signature_hash(tx.deref(), &SignableInput::Shielded, &txid_parts);
+    let sighash: [u8; 32] = *sighash.as_ref();
+    if let Some(b) = tx.sapling_bundle() {
+        let (spend_vk, output_vk) = provers()?.sapling.verifying_keys();
+        let mut validator = sapling_crypto::BatchValidator::new();
+        if !validator.check_bundle(b.clone(), sighash)
+            || !validator.validate(&spend_vk.prepare(), &output_vk.prepare(), OsRng)
+        {
+            return Err(anyhow::anyhow!("Invalid Sapling proofs or signatures").into());
+        }
+    }
+    if let Some(b) = tx.orchard_bundle() {
+        let mut validator = orchard::bundle::BatchValidator::new();
+        validator.add_bundle(b, sighash);
+        if !validator.validate(&ORCHARD_VERIFIER, OsRng) {
+            return Err(anyhow::anyhow!("Invalid Orchard proof or signatures").into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use rusqlite::Connection;
+    use zcash_primitives::consensus::Network;
+
+    use super::{make_payment, Payment, PaymentOptions, Result, UnsignedTransaction};
+    use crate::{
+        db::testing::TEST_BIRTH,
+        types::{CheckpointHeight, PoolMask},
+        warp::legacy::CommitmentTreeFrontier,
+    };
+
+    pub const CP_HEIGHT: CheckpointHeight = CheckpointHeight(TEST_BIRTH + 100);
+
+    /// `make_payment` on mainnet at `CP_HEIGHT` without commitment trees,
+    /// with the fee paid by the sender
+    pub fn pay_from(
+        connection: &Connection,
+        account: u32,
+        p: Payment,
+        src_pools: PoolMask,
+        options: &PaymentOptions,
+    ) -> Result<UnsignedTransaction> {
+        let tree = CommitmentTreeFrontier::default();
+        make_payment(
+            &Network::MainNetwork,
+            connection,
+            account,
+            CP_HEIGHT,
+            p,
+            src_pools,
+            true,
+            &tree,
+            &tree,
+            options,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_primitives::consensus::Network;
+
+    use super::{
+        auto_select_pools, checked_sum, estimate_fee, make_payment,
+        testing::{pay_from, CP_HEIGHT},
+        validate_tx, Error, FeeStrategy, InputNote, Memo, OutputNote, Payment, PaymentItem,
+        PaymentOptions, Result, UnsignedTransaction, REMAINDER,
+    };
+    use crate::{
+        data::fb::PaymentRequestT,
+        db::{
+            account::get_account_info,
+            testing::{add_note, add_utxo, external_address, test_account, test_db, TEST_BIRTH},
+        },
+        types::PoolMask,
+        utils::testing::capture_logs,
+        warp::legacy::CommitmentTreeFrontier,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    fn payment(amount: u64) -> Payment {
+        Payment {
+            recipients: vec![PaymentItem {
+                address: external_address(&NETWORK),
+                amount,
+                memo: None,
+            }],
+        }
+    }
+
+    /// `make_payment` from an account that has 0.1 ZEC
+    fn funded_payment(p: Payment, fee_paid_by_sender: bool) -> Result<UnsignedTransaction> {
+        funded_payment_with(p, fee_paid_by_sender, &PaymentOptions::default())
+    }
+
+    fn funded_payment_with(
+        p: Payment,
+        fee_paid_by_sender: bool,
+        options: &PaymentOptions,
+    ) -> Result<UnsignedTransaction> {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        let tree = CommitmentTreeFrontier::default();
+        make_payment(
+            &NETWORK,
+            &connection,
+            account,
+            CP_HEIGHT,
+            p,
+            PoolMask(0),
+            fee_paid_by_sender,
+            &tree,
+            &tree,
+            options,
+        )
+    }
+
+    /// Fee of the transaction built by `make_payment`
+    fn payment_fee(p: Payment, fee_paid_by_sender: bool) -> u64 {
+        let utx = funded_payment(p, fee_paid_by_sender).unwrap();
+        utx.to_summary().unwrap().fee
+    }
+
+    fn estimated_fee(p: Payment, fee_paid_by_sender: bool) -> u64 {
+        estimated_fee_with(p, fee_paid_by_sender, &PaymentOptions::default())
+    }
+
+    fn estimated_fee_with(p: Payment, fee_paid_by_sender: bool, options: &PaymentOptions) -> u64 {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        estimate_fee(
+            &NETWORK,
+            &connection,
+            account,
+            CP_HEIGHT,
+            p,
+            PoolMask(0),
+            fee_paid_by_sender,
+            options,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fixed_fee_strategy() {
+        let options = PaymentOptions {
+            fee_strategy: Some(FeeStrategy::Fixed(50_000)),
+            ..PaymentOptions::default()
+        };
+        assert_eq!(
+            estimated_fee_with(payment(1_000_000), true, &options),
+            50_000
+        );
+        let utx = funded_payment_with(payment(1_000_000), true, &options).unwrap();
+        assert_eq!(utx.to_summary().unwrap().fee, 50_000);
+    }
+
+    #[test]
+    fn change_to_another_address() {
+        let change_address = external_address(&NETWORK);
+        let options = PaymentOptions {
+            change_address: Some((change_address.clone(), 1)),
+            ..PaymentOptions::default()
+        };
+        let utx = funded_payment_with(payment(1_000_000), true, &options).unwrap();
+        let fee = utx.to_summary().unwrap().fee;
+        let change = utx.tx_outputs.iter().find(|o| o.change).unwrap();
+        assert_eq!(change.address_string, change_address);
+        assert_eq!(change.amount, 10_000_000 - 1_000_000 - fee);
+    }
+
+    #[test]
+    fn source_priority_limits_the_pools() {
+        let sapling_only = PaymentOptions {
+            source_priority: Some(vec![1]),
+            ..PaymentOptions::default()
+        };
+        assert!(matches!(
+            funded_payment_with(payment(1_000_000), true, &sapling_only),
+            Err(Error::NotEnoughFunds(_))
+        ));
+        let transparent = PaymentOptions {
+            source_priority: Some(vec![0, 1]),
+            ..PaymentOptions::default()
+        };
+        assert!(funded_payment_with(payment(1_000_000), true, &transparent).is_ok());
+    }
+
+    #[test]
+    fn deterministic_outputs_keep_their_order() {
+        let address = external_address(&NETWORK);
+        let p = Payment {
+            recipients: (1..=4u64)
+                .map(|i| PaymentItem {
+                    address: address.clone(),
+                    amount: i * 100_000,
+                    memo: None,
+                })
+                .collect(),
+        };
+        let options = PaymentOptions {
+            deterministic: true,
+            ..PaymentOptions::default()
+        };
+        let utx = funded_payment_with(p, true, &options).unwrap();
+        assert!(utx.tx_outputs[0].change);
+        let amounts = utx.tx_outputs[1..]
+            .iter()
+            .map(|o| o.amount)
+            .collect::<Vec<_>>();
+        assert_eq!(amounts, vec![100_000, 200_000, 300_000, 400_000]);
+    }
+
+    #[test]
+    fn padding_below_the_minimum() {
+        let options = PaymentOptions {
+            padding: Some((1, 2)),
+            ..PaymentOptions::default()
+        };
+        assert!(funded_payment_with(payment(1_000_000), true, &options).is_err());
+    }
+
+    #[test]
+    fn estimate_matches_payment() {
+        for fee_paid_by_sender in [true, false] {
+            assert_eq!(
+                estimated_fee(payment(1_000_000), fee_paid_by_sender),
+                payment_fee(payment(1_000_000), fee_paid_by_sender)
+            );
+        }
+    }
+
+    #[test]
+    fn estimate_matches_remainder_payment() {
+        assert_eq!(
+            estimated_fee(payment(REMAINDER), true),
+            payment_fee(payment(REMAINDER), true)
+        );
+    }
+
+    #[test]
+    fn remainder_takes_the_rest() {
+        let mut p = payment(1_000_000);
+        p.recipients.push(PaymentItem {
+            amount: 2_000_000,
+            ..p.recipients[0].clone()
+        });
+        p.recipients.push(PaymentItem {
+            amount: REMAINDER,
+            ..p.recipients[0].clone()
+        });
+        let summary = funded_payment(p, true).unwrap().to_summary().unwrap();
+        let amounts = summary
+            .recipients
+            .unwrap()
+            .iter()
+            .map(|r| r.amount)
+            .collect::<Vec<_>>();
+        assert_eq!(amounts.len(), 3);
+        assert!(amounts.contains(&1_000_000));
+        assert!(amounts.contains(&2_000_000));
+        assert_eq!(amounts.iter().sum::<u64>() + summary.fee, 10_000_000);
+    }
+
+    #[test]
+    fn only_one_remainder() {
+        let mut p = payment(REMAINDER);
+        p.recipients.push(p.recipients[0].clone());
+        assert!(matches!(
+            funded_payment(p, true),
+            Err(Error::MultipleRemainders)
+        ));
+    }
+
+    #[test]
+    fn split_outputs_are_not_empty() {
+        let mut rng = rand::rngs::OsRng;
+        let mut item = payment(10).recipients.remove(0);
+        for _ in 0..100 {
+            let items = item.split(5, &mut rng).unwrap();
+            assert_eq!(items.len(), 5);
+            assert!(items.iter().all(|i| i.amount > 0));
+            assert_eq!(items.iter().map(|i| i.amount).sum::<u64>(), 10);
+        }
+        item.amount = 9;
+        assert!(item.split(5, &mut rng).is_err());
+    }
+
+    #[test]
+    fn remainder_is_not_split() {
+        let item = payment(REMAINDER).recipients.remove(0);
+        let mut rng = rand::rngs::OsRng;
+        assert_eq!(item.split(1, &mut rng).unwrap(), vec![item.clone()]);
+        assert!(item.split(2, &mut rng).is_err());
+    }
+
+    /// Serialized NU5 transaction without any bundle
+    fn empty_tx(expiry_height: u32) -> Vec<u8> {
+        use zcash_primitives::{
+            consensus::{BlockHeight, BranchId},
+            transaction::{Authorized, TransactionData, TxVersion},
+        };
+
+        let tx = TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from_u32(expiry_height),
+            None,
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        let mut data = vec![];
+        tx.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn expired_tx_is_invalid() {
+        let connection = test_db();
+        let e = validate_tx(&NETWORK, &connection, &empty_tx(1_999_999), 2_000_000)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("expired"), "{e}");
+    }
+
+    #[test]
+    fn tx_without_inputs_is_invalid() {
+        let connection = test_db();
+        let e = validate_tx(&NETWORK, &connection, &empty_tx(2_000_050), 2_000_000)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("no input"), "{e}");
+    }
+
+    #[test]
+    fn tex_address_is_paid_from_transparent_funds() {
+        let tex = zcash_keys::address::Address::Tex([7u8; 20]).encode(&NETWORK);
+        let p = Payment {
+            recipients: vec![PaymentItem {
+                address: tex,
+                amount: 1_000_000,
+                memo: None,
+            }],
+        };
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_note(&connection, account, CP_HEIGHT.0, 10_000_000, false);
+        let pay = |connection: &rusqlite::Connection| {
+            pay_from(
+                connection,
+                account,
+                p.clone(),
+                PoolMask(0),
+                &PaymentOptions::default(),
+            )
+        };
+        assert!(matches!(
+            pay(&connection),
+            Err(Error::NoTransparentFundsForTex)
+        ));
+
+        add_utxo(&connection, account, TEST_BIRTH + 10, 5_000_000);
+        let utx = pay(&connection).unwrap();
+        assert!(utx
+            .tx_notes
+            .iter()
+            .all(|n| matches!(n.note, InputNote::Transparent { .. })));
+    }
+
+    #[test]
+    fn memo_to_transparent_is_rejected() {
+        use zcash_primitives::legacy::TransparentAddress;
+
+        let address =
+            zcash_keys::address::Address::Transparent(TransparentAddress::PublicKeyHash([7u8; 20]));
+        let request = PaymentRequestT {
+            address: Some(address.encode(&NETWORK)),
+            amount: 100_000,
+            memo_string: Some("hello".to_string()),
+            ..PaymentRequestT::default()
+        };
+        let item = PaymentItem::try_from(&request).unwrap();
+        let p = Payment {
+            recipients: vec![item],
+        };
+        assert!(matches!(
+            funded_payment(p, true),
+            Err(Error::TransparentMemo(_))
+        ));
+    }
+
+    #[test]
+    fn fee_above_the_inputs_is_an_error() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 5_000);
+        let r = pay_from(
+            &connection,
+            account,
+            payment(1),
+            PoolMask(0),
+            &PaymentOptions::default(),
+        );
+        assert!(matches!(r, Err(Error::NotEnoughFunds(_))));
+    }
+
+    #[test]
+    fn amounts_do_not_wrap_around() {
+        assert_eq!(checked_sum([1, 2, 3]).unwrap(), 6);
+        assert!(matches!(
+            checked_sum([u64::MAX, 1]),
+            Err(Error::AmountOverflow)
+        ));
+    }
+
+    #[test]
+    fn auto_pools_prefer_orchard() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 5_000_000);
+        add_note(&connection, account, CP_HEIGHT.0, 5_000_000, false);
+        add_note(&connection, account, CP_HEIGHT.0, 5_000_000, true);
+        let pools = |amount: u64| {
+            auto_select_pools(
+                &NETWORK,
+                &connection,
+                account,
+                CP_HEIGHT,
+                &payment(amount),
+                &PaymentOptions::default(),
+            )
+            .unwrap()
+            .0
+        };
+        assert_eq!(pools(1_000_000), 4);
+        assert_eq!(pools(8_000_000), 6);
+        assert_eq!(pools(12_000_000), 7);
+    }
+
+    #[test]
+    fn note_without_witness_at_the_anchor() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        // the witness of the note is empty, it does not lead to this tree
+        add_note(&connection, account, CP_HEIGHT.0, 10_000_000, false);
+        let s_tree = CommitmentTreeFrontier {
+            left: Some([1u8; 32]),
+            right: None,
+            parents: vec![],
+        };
+        let o_tree = CommitmentTreeFrontier::default();
+        let r = make_payment(
+            &NETWORK,
+            &connection,
+            account,
+            CP_HEIGHT,
+            payment(1_000_000),
+            PoolMask(2),
+            true,
+            &s_tree,
+            &o_tree,
+            &PaymentOptions::default(),
+        );
+        assert!(matches!(r, Err(Error::NoteNotWitnessed(1))));
+    }
+
+    #[test]
+    fn default_memo_on_shielded_outputs() {
+        use crate::db::account_manager::set_default_memo;
+        use zcash_primitives::{legacy::TransparentAddress, memo::MemoBytes};
+
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        set_default_memo(&connection, account, Some("invoice 42")).unwrap();
+        let taddr =
+            zcash_keys::address::Address::Transparent(TransparentAddress::PublicKeyHash([7u8; 20]));
+        let mut p = payment(1_000_000);
+        p.recipients.push(PaymentItem {
+            address: taddr.encode(&NETWORK),
+            amount: 1_000_000,
+            memo: None,
+        });
+        let utx = pay_from(
+            &connection,
+            account,
+            p,
+            PoolMask(0),
+            &PaymentOptions::default(),
+        )
+        .unwrap();
+        let expected = MemoBytes::from(&"invoice 42".parse::<Memo>().unwrap());
+        let mut shielded = 0;
+        for o in utx.tx_outputs.iter() {
+            match &o.note {
+                OutputNote::Sapling { memo, .. } | OutputNote::Orchard { memo, .. } => {
+                    if o.change {
+                        assert_eq!(*memo, MemoBytes::empty());
+                    } else {
+                        assert_eq!(*memo, expected);
+                        shielded += 1;
+                    }
+                }
+                OutputNote::Transparent { .. } => assert!(!o.change),
+            }
+        }
+        assert_eq!(shielded, 1);
+    }
+
+    #[test]
+    fn duplicate_payment_is_flagged() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        let pay = |key: Option<&str>, force: bool| {
+            pay_from(
+                &connection,
+                account,
+                payment(1_000_000),
+                PoolMask(0),
+                &PaymentOptions {
+                    idempotency_key: key.map(String::from),
+                    force,
+                    ..PaymentOptions::default()
+                },
+            )
+        };
+        assert!(pay(None, false).is_ok());
+        assert!(matches!(pay(None, false), Err(Error::DuplicatePayment(_))));
+        assert!(pay(None, true).is_ok());
+        assert!(pay(Some("invoice 2"), false).is_ok());
+    }
+
+    #[test]
+    fn notes_of_diversified_addresses_are_spent_together() {
+        use crate::db::account::get_account_info;
+        use zcash_primitives::zip32::DiversifierIndex;
+
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let vk = get_account_info(&NETWORK, &connection, account)
+            .unwrap()
+            .sapling
+            .vk;
+        let (mut di, a0) = vk.find_address(DiversifierIndex::new()).unwrap();
+        di.increment().unwrap();
+        let (_, a1) = vk.find_address(di).unwrap();
+        assert_ne!(a0, a1);
+        for address in [a0, a1] {
+            add_note(&connection, account, CP_HEIGHT.0, 5_000_000, false);
+            connection
+                .execute(
+                    "UPDATE notes SET address = ?1 WHERE id_note = last_insert_rowid()",
+                    [&address.to_bytes()[..]],
+                )
+                .unwrap();
+        }
+        // a note of another account is never spent
+        add_note(&connection, account + 1, CP_HEIGHT.0, 20_000_000, false);
+        let utx = pay_from(
+            &connection,
+            account,
+            payment(8_000_000),
+            PoolMask(2),
+            &PaymentOptions::default(),
+        )
+        .unwrap();
+        let mut addresses = utx
+            .tx_notes
+            .iter()
+            .map(|n| match &n.note {
+                InputNote::Sapling { address, .. } => *address,
+                _ => panic!("only sapling notes are spent"),
+            })
+            .collect::<Vec<_>>();
+        addresses.sort();
+        let mut expected = vec![a0.to_bytes(), a1.to_bytes()];
+        expected.sort();
+        assert_eq!(addresses, expected);
+    }
+
+    #[test]
+    fn raw_hex_memo_is_kept_as_is() {
+        // not a text memo: the first byte is above 0xF4
+        let bytes = (0..512).map(|i| (0xF5 + i) as u8).collect::<Vec<_>>();
+        let memo = PaymentItem::memo_from_hex(&hex::encode(&bytes)).unwrap();
+        let mut p = payment(1_000_000);
+        p.recipients[0].memo = Some(memo);
+        let utx = funded_payment(p, true).unwrap();
+        let memos = utx
+            .tx_outputs
+            .iter()
+            .filter(|o| !o.change)
+            .filter_map(|o| match &o.note {
+                OutputNote::Sapling { memo, .. } | OutputNote::Orchard { memo, .. } => Some(memo),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(memos.len(), 1);
+        assert_eq!(&memos[0].as_array()[..], &bytes[..]);
+
+        let short = PaymentItem::memo_from_hex("f5aa").unwrap();
+        assert_eq!(&short.as_array()[..2], &[0xF5, 0xAA]);
+        assert!(short.as_array()[2..].iter().all(|&b| b == 0));
+        assert!(PaymentItem::memo_from_hex(&hex::encode([0u8; 513])).is_err());
+        assert!(PaymentItem::memo_from_hex("not hex").is_err());
+    }
+
+    #[test]
+    fn fee_of_a_dusty_wallet_is_capped() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        // every utxo adds 6_000 zats for 5_000 of fee
+        for _ in 0..40 {
+            add_utxo(&connection, account, TEST_BIRTH + 10, 6_000);
+        }
+        let pay = |max_fee: Option<u64>| {
+            pay_from(
+                &connection,
+                account,
+                payment(20_000),
+                PoolMask(0),
+                &PaymentOptions {
+                    force: true,
+                    max_fee,
+                    ..PaymentOptions::default()
+                },
+            )
+        };
+        assert!(matches!(
+            pay(None),
+            Err(Error::FeeTooHigh(_, super::MIN_MAX_FEE))
+        ));
+        assert!(matches!(
+            pay(Some(150_000)),
+            Err(Error::FeeTooHigh(_, 150_000))
+        ));
+        let utx = pay(Some(u64::MAX)).unwrap();
+        assert!(utx.tx_notes.len() >= 30);
+    }
+
+    #[test]
+    fn change_in_the_summary() {
+        let summary = funded_payment(payment(1_000_000), true)
+            .unwrap()
+            .to_summary()
+            .unwrap();
+        let change = summary.change.unwrap();
+        assert_eq!(change.amount, 10_000_000 - 1_000_000 - summary.fee);
+        assert!(change.address.is_some_and(|a| !a.is_empty()));
+
+        // the remainder spends everything, there is no change
+        let summary = funded_payment(payment(REMAINDER), true)
+            .unwrap()
+            .to_summary()
+            .unwrap();
+        assert!(summary.change.is_none());
+    }
+
+    #[test]
+    fn fee_is_logged_once_per_payment() {
+        let logs = capture_logs(|| {
+            funded_payment(payment(1_000_000), true).unwrap();
+        });
+        // the level follows the timestamp
+        let fee_lines = |level: &str| {
+            logs.lines()
+                .filter(|l| l.contains("fee: ") && l.split_whitespace().nth(1) == Some(level))
+                .collect::<Vec<_>>()
+        };
+        // every estimate is at trace, the summary at info
+        assert!(fee_lines("TRACE").len() > 1);
+        let summaries = fee_lines("INFO");
+        assert_eq!(summaries.len(), 1, "{logs}");
+        assert!(summaries[0].contains("inputs and"));
+    }
+
+    #[test]
+    fn paying_our_own_address() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let own = ai.to_address(&NETWORK, PoolMask(2)).unwrap();
+        let pay = |allow_self: bool| {
+            let p = Payment {
+                recipients: vec![PaymentItem {
+                    address: own.clone(),
+                    amount: 1_000_000,
+                    memo: None,
+                }],
+            };
+            pay_from(
+                &connection,
+                account,
+                p,
+                PoolMask(0),
+                &PaymentOptions {
+                    force: true,
+                    allow_self,
+                    ..PaymentOptions::default()
+                },
+            )
+        };
+        assert!(matches!(
+            pay(false),
+            Err(Error::SelfPayment(address, owner)) if address == own && owner == account
+        ));
+
+        let logs = capture_logs(|| {
+            pay(true).unwrap();
+        });
+        assert!(logs
+            .lines()
+            .any(|l| l.contains("WARN") && l.contains(&format!("belongs to account {account}"))));
+    }
+
+    #[test]
+    fn payment_at_an_older_checkpoint() {
+        use crate::{db::notes::checkpoint_anchor, warp::hasher::SaplingHasher};
+        use rusqlite::params;
+
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let (older, latest) = (TEST_BIRTH + 100, TEST_BIRTH + 200);
+        for height in [older, latest] {
+            connection
+                .execute(
+                    "INSERT INTO blcks(height, hash, prev_hash, timestamp)
+                    VALUES (?1, randomblob(32), randomblob(32), 0)",
+                    params![height],
+                )
+                .unwrap();
+        }
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        // the sapling tree has one more commitment at the latest checkpoint
+        let s_older = CommitmentTreeFrontier {
+            left: Some([1u8; 32]),
+            right: None,
+            parents: vec![],
+        };
+        let s_latest = CommitmentTreeFrontier {
+            right: Some([2u8; 32]),
+            ..s_older.clone()
+        };
+        let o_tree = CommitmentTreeFrontier::default();
+
+        let cp_height = checkpoint_anchor(&connection, account, older).unwrap();
+        assert_eq!(cp_height.0, older);
+        let utx = make_payment(
+            &NETWORK,
+            &connection,
+            account,
+            cp_height,
+            payment(1_000_000),
+            PoolMask(1),
+            true,
+            &s_older,
+            &o_tree,
+            &PaymentOptions::default(),
+        )
+        .unwrap();
+        let sh = SaplingHasher::default();
+        let root = |tree: &CommitmentTreeFrontier| tree.to_edge(&sh).root(&sh);
+        assert_eq!(utx.checkpoint_height, older);
+        assert_eq!(utx.roots[0], root(&s_older));
+        assert_ne!(utx.roots[0], root(&s_latest));
+
+        assert!(checkpoint_anchor(&connection, account, older + 1).is_err());
+        // funds spent after the checkpoint make it too old
+        connection
+            .execute("UPDATE utxos SET spent = ?1", [older + 50])
+            .unwrap();
+        assert!(checkpoint_anchor(&connection, account, older).is_err());
+        assert!(checkpoint_anchor(&connection, account, latest).is_ok());
+    }
 }
@@ -5,20 +5,26 @@ use crate::{
         hasher::{empty_roots, OrchardHasher, SaplingHasher},
         MERKLE_DEPTH,
     },
+    Hash, ANCHOR_WARN_DEPTH, MAX_TX_SIZE,
 };
 use anyhow::Result;
-use sapling_crypto::{note_encryption::Zip212Enforcement, PaymentAddress};
+use sapling_crypto::{
+    builder::{OutputInfo as SaplingOutputInfo, SpendInfo as SaplingSpendInfo},
+    note_encryption::Zip212Enforcement,
+    PaymentAddress, ProofGenerationKey,
+};
 use zcash_client_backend::encoding::AddressCodec as _;
 use zcash_protocol::value::Zatoshis;
 
 use super::{
-    InputNote, OutputNote, UnsignedTransaction, ORCHARD_PROVER, PROVER,
+    signer::{LocalSigner, OrchardUnsignedBundle, SaplingUnsignedBundle, TxSigner},
+    provers, Error, InputNote, OutputNote, SignedTransaction, UnsignedTransaction,
 };
 use jubjub::Fr;
 use orchard::{
     builder::{Builder as OrchardBuilder, BundleType},
     bundle::Flags,
-    keys::{Scope, SpendAuthorizingKey},
+    keys::Scope,
     note::Rho,
     tree::MerkleHashOrchard,
     Address,
@@ -29,7 +35,13 @@ use zcash_primitives::{
     consensus::{BlockHeight, BranchId, Network},
     legacy::TransparentAddress,
     transaction::{
-        components::{transparent::builder::TransparentBuilder, OutPoint, TxOut},
+        components::{
+            transparent::{
+                builder::TransparentBuilder, Authorized as TransparentAuthorized,
+                Bundle as TransparentBundle,
+            },
+            OutPoint, TxOut,
+        },
         sighash::{signature_hash, SignableInput},
         txid::TxIdDigester,
         TransactionData, TxVersion,
@@ -44,14 +56,57 @@ impl UnsignedTransaction {
         connection: &Connection,
         expiration_height: u32,
         tsk_store: &mut TSKStore,
+        rng: R,
+    ) -> Result<SignedTransaction> {
+        let ai = get_account_info(network, connection, self.account)?;
+        let sks = ai.to_secret_keys();
+        sks.sapling.ok_or(anyhow::anyhow!("No Secret Keys"))?;
+        let mut signer = LocalSigner::new(&ai);
+        self.build_with_signer(network, connection, expiration_height, tsk_store, &mut signer, rng)
+    }
+
+    /// Build the transaction and let `signer` authorize the shielded
+    /// spends. The Sapling proofs are made with the proof generation
+    /// key of the signer
+    pub fn build_with_signer<R: RngCore + CryptoRng, S: TxSigner>(
+        self,
+        network: &Network,
+        connection: &Connection,
+        expiration_height: u32,
+        tsk_store: &mut TSKStore,
+        signer: &mut S,
+        mut rng: R,
+    ) -> Result<SignedTransaction> {
+        let pgk = signer.sapling_proof_generation_key();
+        let proven = self.prove(
+            network,
+            connection,
+            expiration_height,
+            tsk_store,
+            pgk.as_ref(),
+            &mut rng,
+        )?;
+        proven.authorize(signer, rng)
+    }
+
+    /// First half of `build_with_signer`: make the proofs and
+    /// the transparent signatures. The shielded spends are left
+    /// for `ProvenTransaction::authorize`. Sapling spends only need
+    /// the proof generation key, not the spending key
+    pub fn prove<R: RngCore + CryptoRng>(
+        self,
+        network: &Network,
+        connection: &Connection,
+        expiration_height: u32,
+        tsk_store: &mut TSKStore,
+        proof_generation_key: Option<&ProofGenerationKey>,
         mut rng: R,
-    ) -> Result<Vec<u8>> {
+    ) -> Result<ProvenTransaction> {
         let ai = get_account_info(network, connection, self.account)?;
         if ai.to_account_unique_id() != self.account_id {
             anyhow::bail!("Invalid Account");
         }
-        let sks = ai.to_secret_keys();
-        sks.sapling.ok_or(anyhow::anyhow!("No Secret Keys"))?;
+        self.check_anchor(expiration_height);
 
         if let Some(ti) = ai.transparent.as_ref() {
             tsk_store.0.insert(ti.addr.encode(network), ti.sk.clone());
@@ -63,15 +118,8 @@ impl UnsignedTransaction {
         ];
 
         let mut transparent_builder = TransparentBuilder::empty();
-        let mut sapling_builder = sapling_crypto::builder::Builder::new(
-            Zip212Enforcement::On,
-            sapling_crypto::builder::BundleType::Transactional {
-                bundle_required: false,
-            },
-            sapling_crypto::Anchor::from_bytes(self.roots[0].clone())
-                .unwrap()
-                .into(),
-        );
+        let mut sapling_spends = vec![];
+        let mut sapling_outputs = vec![];
         let mut orchard_builder = OrchardBuilder::new(
             BundleType::Transactional {
                 flags: Flags::from_byte(3).unwrap(),
@@ -108,7 +156,8 @@ impl UnsignedTransaction {
                     rseed,
                     witness,
                 } => {
-                    let extsk = ai.sapling.sk.as_ref().unwrap();
+                    let pgk = proof_generation_key
+                        .ok_or(anyhow::anyhow!("No Sapling Proof Generation Key"))?;
                     let recipient = PaymentAddress::from_bytes(address).unwrap();
                     let note = sapling_crypto::Note::from_parts(
                         recipient,
@@ -125,9 +174,7 @@ impl UnsignedTransaction {
                         (witness.position as u64).into(),
                     )
                     .unwrap();
-                    sapling_builder
-                        .add_spend(extsk, note, merkle_path)
-                        .map_err(anyhow::Error::msg)?;
+                    sapling_spends.push(SaplingSpendInfo::new(pgk.clone(), note, merkle_path));
                 }
 
                 InputNote::Orchard {
@@ -185,14 +232,12 @@ impl UnsignedTransaction {
                     let vk = &ai.sapling.vk;
                     let ovk = vk.fvk.ovk;
                     let recipient = PaymentAddress::from_bytes(address).unwrap();
-                    sapling_builder
-                        .add_output(
-                            Some(ovk),
-                            recipient,
-                            sapling_crypto::value::NoteValue::from_raw(txout.amount),
-                            Some(memo.as_array().clone()),
-                        )
-                        .map_err(anyhow::Error::msg)?;
+                    sapling_outputs.push(SaplingOutputInfo::new(
+                        Some(ovk),
+                        recipient,
+                        sapling_crypto::value::NoteValue::from_raw(txout.amount),
+                        Some(memo.as_array().clone()),
+                    ));
                 }
                 OutputNote::Orchard { address, memo } => {
                     let vk = ai.orchard.as_ref().map(|oi| oi.vk.clone());
@@ -211,14 +256,50 @@ impl UnsignedTransaction {
             }
         }
 
+        let (s_spends, s_outputs, o_spends, o_outputs) = self.shielded_counts();
+        if s_spends > 0 {
+            for _ in s_outputs..self.min_sapling_outputs {
+                sapling_outputs.push(SaplingOutputInfo::new(
+                    None,
+                    dummy_sapling_address(&mut rng),
+                    sapling_crypto::value::NoteValue::from_raw(0),
+                    None,
+                ));
+            }
+        }
+        if o_spends + o_outputs > 0 {
+            for _ in o_spends.max(o_outputs)..self.min_orchard_actions {
+                orchard_builder
+                    .add_output(
+                        None,
+                        dummy_orchard_address(&mut rng),
+                        orchard::value::NoteValue::from_raw(0),
+                        None,
+                    )
+                    .map_err(anyhow::Error::msg)?;
+            }
+        }
+
         let transparent_bundle = transparent_builder.build();
-        let prover: &LocalTxProver = &PROVER;
-        let sapling_bundle = sapling_builder
-            .build::<LocalTxProver, LocalTxProver, _, _>(&mut rng)
-            .unwrap()
-            .map(|pair| pair.0);
-        let sapling_bundle =
-            sapling_bundle.map(|sb| sb.create_proofs(prover, prover, &mut rng, ()));
+        let sapling_bundle = sapling_crypto::builder::bundle::<LocalTxProver, LocalTxProver, _, _>(
+            &mut rng,
+            sapling_crypto::builder::BundleType::Transactional {
+                bundle_required: false,
+            },
+            Zip212Enforcement::On,
+            sapling_crypto::Anchor::from_bytes(self.roots[0].clone()).unwrap(),
+            sapling_spends,
+            sapling_outputs,
+        )
+        .map_err(anyhow::Error::msg)?
+        .map(|pair| pair.0);
+        // the parameters are only loaded when there is something to prove
+        let sapling_bundle = sapling_bundle
+            .map(|sb| -> Result<_> {
+                let prover: &LocalTxProver = &provers()?.sapling;
+                Ok(sb.create_proofs(prover, prover, &mut rng, ()))
+            })
+            .transpose()?;
 
         let has_orchard = self.tx_notes.iter().any(|n| match n.note {
             InputNote::Orchard { .. } => true,
@@ -232,6 +313,10 @@ impl UnsignedTransaction {
         if has_orchard {
             orchard_bundle = orchard_builder.build(&mut rng).unwrap().map(|pair| pair.0);
         }
+        self.check_padding(
+            sapling_bundle.as_ref().map(|b| b.shielded_outputs().len()),
+            orchard_bundle.as_ref().map(|b| b.actions().len()),
+        )?;
 
         let consensus_branch_id = BranchId::for_height(network, BlockHeight::from_u32(self.height));
         let version = TxVersion::suggested_for_branch(consensus_branch_id);
@@ -256,38 +341,678 @@ impl UnsignedTransaction {
             .transparent_bundle()
             .map(|tb| tb.clone().apply_signatures(&unauthed_tx, &txid_parts));
 
-        let ask = &ai.sapling.sk.as_ref().unwrap().expsk.ask;
-        let sapling_bundle = unauthed_tx.sapling_bundle().map(|sb| {
-            sb.clone()
-                .apply_signatures(&mut rng, sig_hash, std::slice::from_ref(ask))
-                .unwrap()
-        });
+        let sapling_bundle = unauthed_tx
+            .sapling_bundle()
+            .map(|sb| sb.clone().prepare(&mut rng, sig_hash));
 
-        let orchard_bundle = unauthed_tx.orchard_bundle().map(|ob| {
-            let sk = ai.orchard.as_ref().and_then(|oi| oi.sk).unwrap();
-            let sak = SpendAuthorizingKey::from(&sk);
-            let proven = ob.clone().create_proof(&ORCHARD_PROVER, &mut rng).unwrap();
-            proven
-                .apply_signatures(&mut rng, sig_hash, std::slice::from_ref(&sak))
-                .unwrap()
-        });
+        let orchard_bundle = unauthed_tx
+            .orchard_bundle()
+            .map(|ob| -> Result<_> {
+                let proven = ob
+                    .clone()
+                    .create_proof(provers()?.orchard(), &mut rng)
+                    .map_err(|e| anyhow::anyhow!("{e:?}"))?;
+                Ok(proven.prepare(&mut rng, sig_hash))
+            })
+            .transpose()?;
+
+        Ok(ProvenTransaction {
+            version,
+            consensus_branch_id,
+            expiration_height,
+            sighash: sig_hash,
+            transparent_bundle,
+            sapling_bundle,
+            orchard_bundle,
+        })
+    }
+
+    /// Number of Sapling spends and outputs, Orchard spends and outputs
+    fn shielded_counts(&self) -> (u32, u32, u32, u32) {
+        let spends = |orchard: bool| {
+            self.tx_notes
+                .iter()
+                .filter(|n| match n.note {
+                    InputNote::Sapling { .. } => !orchard,
+                    InputNote::Orchard { .. } => orchard,
+                    InputNote::Transparent { .. } => false,
+                })
+                .count() as u32
+        };
+        let outputs = |orchard: bool| {
+            self.tx_outputs
+                .iter()
+                .filter(|o| match o.note {
+                    OutputNote::Sapling { .. } => !orchard,
+                    OutputNote::Orchard { .. } => orchard,
+                    OutputNote::Transparent { .. } => false,
+                })
+                .count() as u32
+        };
+        (spends(false), outputs(false), spends(true), outputs(true))
+    }
+
+    /// The bundles must have at least the outputs and actions
+    /// the fee was computed for
+    fn check_padding(&self, s_outputs: Option<usize>, o_actions: Option<usize>) -> Result<()> {
+        let (s_spends, _, _, _) = self.shielded_counts();
+        if s_spends > 0 && s_outputs.unwrap_or_default() < self.min_sapling_outputs as usize {
+            anyhow::bail!(
+                "Sapling bundle has {} outputs, {} expected",
+                s_outputs.unwrap_or_default(),
+                self.min_sapling_outputs
+            );
+        }
+        if let Some(o_actions) = o_actions {
+            if o_actions < self.min_orchard_actions as usize {
+                anyhow::bail!(
+                    "Orchard bundle has {o_actions} actions, {} expected",
+                    self.min_orchard_actions
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Warn when the shielded spends use an anchor far below
+    /// the expiration height
+    pub fn check_anchor(&self, expiration_height: u32) {
+        let has_shielded_inputs = self
+            .tx_notes
+            .iter()
+            .any(|n| !matches!(n.note, InputNote::Transparent { .. }));
+        if !has_shielded_inputs {
+            return;
+        }
+        if let Some(depth) = stale_anchor_depth(self.checkpoint_height, expiration_height) {
+            tracing::warn!(
+                "Anchor at height {} is {} blocks below the expiration height, the wallet may be behind the chain",
+                self.checkpoint_height,
+                depth
+            );
+        }
+    }
+}
+
+/// Receiver of a padding output. It belongs to a throwaway key,
+/// the empty note cannot be found or spent by anyone
+fn dummy_sapling_address<R: RngCore>(rng: &mut R) -> PaymentAddress {
+    let mut seed = [0u8; 32];
+    rng.fill_bytes(&mut seed);
+    sapling_crypto::zip32::ExtendedSpendingKey::master(&seed)
+        .default_address()
+        .1
+}
+
+/// Orchard receiver of a padding output, see `dummy_sapling_address`
+fn dummy_orchard_address<R: RngCore>(rng: &mut R) -> Address {
+    let sk = loop {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        if let Some(sk) = Option::from(orchard::keys::SpendingKey::from_bytes(bytes)) {
+            break sk;
+        }
+    };
+    orchard::keys::FullViewingKey::from(&sk).address_at(0u32, Scope::External)
+}
+
+/// Depth of the anchor below the expiration height when it is more than
+/// `ANCHOR_WARN_DEPTH`. Transactions that never expire have no depth
+pub fn stale_anchor_depth(anchor_height: u32, expiration_height: u32) -> Option<u32> {
+    if expiration_height == 0 {
+        return None;
+    }
+    let depth = expiration_height.saturating_sub(anchor_height);
+    (depth > ANCHOR_WARN_DEPTH).then_some(depth)
+}
+
+/// Consensus rejects transactions above `MAX_TX_SIZE` bytes
+pub fn check_tx_size(size: usize) -> Result<(), Error> {
+    if size > MAX_TX_SIZE {
+        return Err(Error::TxTooLarge(size, MAX_TX_SIZE));
+    }
+    Ok(())
+}
+
+/// A transaction with its proofs, waiting for the signatures
+/// of its shielded spends. It has no encoding: sapling-crypto and
+/// orchard keep the randomizers of the spend signatures private
+/// until the bundles are authorized
+pub struct ProvenTransaction {
+    version: TxVersion,
+    consensus_branch_id: BranchId,
+    expiration_height: u32,
+    sighash: Hash,
+    transparent_bundle: Option<TransparentBundle<TransparentAuthorized>>,
+    sapling_bundle: Option<SaplingUnsignedBundle>,
+    orchard_bundle: Option<OrchardUnsignedBundle>,
+}
+
+impl ProvenTransaction {
+    /// Shielded sighash the spends must sign
+    pub fn sighash(&self) -> &Hash {
+        &self.sighash
+    }
+
+    /// Sign the shielded spends with the randomness of `rng`
+    /// and serialize the transaction
+    pub fn authorize<R: RngCore + CryptoRng, S: TxSigner>(
+        self,
+        signer: &mut S,
+        mut rng: R,
+    ) -> Result<SignedTransaction> {
+        let sighash = self.sighash;
+        let sapling_bundle = self
+            .sapling_bundle
+            .map(|sb| -> Result<_> {
+                let sb = signer.sign_sapling(&sighash, sb, &mut rng)?;
+                sb.finalize().map_err(|e| anyhow::anyhow!("{e:?}"))
+            })
+            .transpose()?;
+        let orchard_bundle = self
+            .orchard_bundle
+            .map(|ob| -> Result<_> {
+                let ob = signer.sign_orchard(&sighash, ob, &mut rng)?;
+                ob.finalize().map_err(|e| anyhow::anyhow!("{e:?}"))
+            })
+            .transpose()?;
 
         let tx_data: TransactionData<zcash_primitives::transaction::Authorized> =
             TransactionData::from_parts(
-                version,
-                consensus_branch_id,
+                self.version,
+                self.consensus_branch_id,
                 0,
-                BlockHeight::from_u32(expiration_height),
-                transparent_bundle,
+                BlockHeight::from_u32(self.expiration_height),
+                self.transparent_bundle,
                 None,
                 sapling_bundle,
                 orchard_bundle,
             );
-        let tx = tx_data.freeze().unwrap();
+        let tx = tx_data.freeze()?;
+        let txid: Hash = *tx.txid().as_ref();
 
         let mut tx_bytes = vec![];
-        tx.write(&mut tx_bytes).unwrap();
+        tx.write(&mut tx_bytes)?;
+        check_tx_size(tx_bytes.len())?;
+
+        Ok(SignedTransaction {
+            txid,
+            data: tx_bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng as _};
+    use rusqlite::{params, Connection};
+    use sapling_crypto::ProofGenerationKey;
+    use zcash_client_backend::encoding::AddressCodec as _;
+    use zcash_primitives::consensus::Network;
+
+    use super::{
+        check_tx_size, dummy_orchard_address, dummy_sapling_address, stale_anchor_depth,
+        ProvenTransaction,
+    };
+    use crate::{
+        db::{
+            account::get_account_info,
+            testing::{add_note, add_utxo, test_account, test_db, TEST_BIRTH},
+        },
+        keys::TSKStore,
+        pay::{
+            expiry_height, make_payment, provers,
+            signer::{LocalSigner, OrchardUnsignedBundle, SaplingUnsignedBundle, TxSigner},
+            testing::{pay_from, CP_HEIGHT},
+            validate_tx, Error, InputNote, Payment, PaymentItem, PaymentOptions, TxInput,
+            UnsignedTransaction,
+        },
+        types::PoolMask,
+        warp::{legacy::CommitmentTreeFrontier, verify::recompute_sapling, AuthPath, Witness},
+        Hash, ANCHOR_WARN_DEPTH, EXPIRATION_HEIGHT_DELTA, MAX_TX_SIZE,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    /// Transaction that spends one sapling note
+    fn sapling_spend(min_sapling_outputs: u32) -> UnsignedTransaction {
+        UnsignedTransaction {
+            account: 1,
+            account_name: "test".to_string(),
+            account_id: [0u8; 32],
+            height: 2_000_000,
+            checkpoint_height: 2_000_000,
+            tx_notes: vec![TxInput {
+                id: 1,
+                amount: 100_000,
+                remaining: 0,
+                pool: 1,
+                note: InputNote::Sapling {
+                    address: [0u8; 43],
+                    rseed: [0u8; 32],
+                    witness: Witness::default(),
+                },
+            }],
+            tx_outputs: vec![],
+            roots: [[0u8; 32]; 2],
+            edges: [AuthPath::default(), AuthPath::default()],
+            min_sapling_outputs,
+            min_orchard_actions: 2,
+        }
+    }
+
+    #[test]
+    fn bundles_must_be_padded() {
+        let utx = sapling_spend(4);
+        assert_eq!(utx.shielded_counts(), (1, 0, 0, 0));
+        assert!(utx.check_padding(Some(2), None).is_err());
+        assert!(utx.check_padding(Some(4), None).is_ok());
+        assert!(utx.check_padding(Some(4), Some(1)).is_err());
+        assert!(utx.check_padding(Some(4), Some(2)).is_ok());
+    }
+
+    #[test]
+    fn padding_receivers_are_valid() {
+        let mut rng = rand::rngs::OsRng;
+        let s = dummy_sapling_address(&mut rng);
+        assert!(sapling_crypto::PaymentAddress::from_bytes(&s.to_bytes()).is_some());
+        let o = dummy_orchard_address(&mut rng);
+        let o2 = orchard::Address::from_raw_address_bytes(&o.to_raw_address_bytes());
+        assert!(bool::from(o2.is_some()));
+        assert_ne!(s, dummy_sapling_address(&mut rng));
+    }
+
+    /// Fails the test if it is asked for a signature
+    struct NoSigner;
+
+    impl TxSigner for NoSigner {
+        fn sapling_proof_generation_key(&self) -> Option<ProofGenerationKey> {
+            None
+        }
+
+        fn sign_sapling<R: rand::RngCore + rand::CryptoRng>(
+            &mut self,
+            _sighash: &Hash,
+            _bundle: SaplingUnsignedBundle,
+            _rng: R,
+        ) -> anyhow::Result<SaplingUnsignedBundle> {
+            anyhow::bail!("unexpected Sapling bundle")
+        }
+
+        fn sign_orchard<R: rand::RngCore + rand::CryptoRng>(
+            &mut self,
+            _sighash: &Hash,
+            _bundle: OrchardUnsignedBundle,
+            _rng: R,
+        ) -> anyhow::Result<OrchardUnsignedBundle> {
+            anyhow::bail!("unexpected Orchard bundle")
+        }
+    }
+
+    #[test]
+    fn authorize_without_shielded_spends() {
+        let proven = || ProvenTransaction {
+            version: zcash_primitives::transaction::TxVersion::Zip225,
+            consensus_branch_id: zcash_primitives::consensus::BranchId::Nu5,
+            expiration_height: 2_000_040,
+            sighash: [0u8; 32],
+            transparent_bundle: None,
+            sapling_bundle: None,
+            orchard_bundle: None,
+        };
+        let tx = proven()
+            .authorize(&mut NoSigner, rand::rngs::OsRng)
+            .unwrap();
+        let tx2 = proven()
+            .authorize(&mut NoSigner, rand::rngs::OsRng)
+            .unwrap();
+        assert!(!tx.data.is_empty());
+        assert_eq!(tx.txid, tx2.txid);
+        assert_eq!(tx.data, tx2.data);
+    }
+
+    #[test]
+    fn recent_anchor_is_not_stale() {
+        let h = 2_000_000;
+        assert_eq!(stale_anchor_depth(h, h + EXPIRATION_HEIGHT_DELTA), None);
+        assert_eq!(stale_anchor_depth(h, h + ANCHOR_WARN_DEPTH), None);
+    }
+
+    #[test]
+    fn old_anchor_is_stale() {
+        let h = 2_000_000;
+        let depth = ANCHOR_WARN_DEPTH + 1;
+        assert_eq!(stale_anchor_depth(h, h + depth), Some(depth));
+    }
+
+    #[test]
+    fn no_expiry_is_not_stale() {
+        assert_eq!(stale_anchor_depth(2_000_000, 0), None);
+    }
+
+    /// Payment from the transparent funds of the account with the
+    /// change back to its transparent address, there is nothing to prove
+    fn transparent_payment(connection: &Connection, account: u32) -> UnsignedTransaction {
+        let ai = get_account_info(&NETWORK, connection, account).unwrap();
+        let taddr = ai.transparent.as_ref().unwrap().addr.encode(&NETWORK);
+        let p = Payment {
+            recipients: vec![PaymentItem {
+                address: taddr.clone(),
+                amount: 1_000_000,
+                memo: None,
+            }],
+        };
+        let options = PaymentOptions {
+            change_address: Some((taddr, 1)),
+            force: true,
+            allow_self: true,
+            ..PaymentOptions::default()
+        };
+        pay_from(connection, account, p, PoolMask(1), &options).unwrap()
+    }
 
-        Ok(tx_bytes)
+    /// The Sapling parameters are not installed everywhere the tests run
+    fn has_sapling_params() -> bool {
+        provers().is_ok()
+    }
+
+    /// Payment to the transparent address of the account from a Sapling
+    /// note of 0.1 ZEC, the only leaf of the tree at the checkpoint
+    fn shielded_payment(connection: &Connection, account: u32) -> UnsignedTransaction {
+        let ai = get_account_info(&NETWORK, connection, account).unwrap();
+        let address = ai.sapling.addr.to_bytes();
+        let rcm = jubjub::Fr::from(1234u64).to_bytes();
+        let value = 10_000_000;
+        let (cmx, nf) =
+            recompute_sapling(Some(&ai.sapling.vk.fvk.vk), &address, value, &rcm, 0).unwrap();
+        let id_tx = add_note(connection, account, CP_HEIGHT.0, value, false);
+        connection
+            .execute(
+                "UPDATE notes SET address = ?1, rcm = ?2, nf = ?3 WHERE tx = ?4",
+                params![&address[..], &rcm[..], &nf.unwrap()[..], id_tx],
+            )
+            .unwrap();
+        let witness = Witness {
+            value: cmx,
+            position: 0,
+            ..Witness::default()
+        };
+        connection
+            .execute(
+                "UPDATE witnesses SET witness = ?1
+                WHERE note = (SELECT id_note FROM notes WHERE tx = ?2)",
+                params![bincode::serialize(&witness).unwrap(), id_tx],
+            )
+            .unwrap();
+        let s_tree = CommitmentTreeFrontier {
+            left: Some(cmx),
+            right: None,
+            parents: vec![],
+        };
+        let taddr = ai.transparent.as_ref().unwrap().addr.encode(&NETWORK);
+        let p = Payment {
+            recipients: vec![PaymentItem {
+                address: taddr,
+                amount: 1_000_000,
+                memo: None,
+            }],
+        };
+        let options = PaymentOptions {
+            force: true,
+            allow_self: true,
+            ..PaymentOptions::default()
+        };
+        make_payment(
+            &NETWORK,
+            connection,
+            account,
+            CP_HEIGHT,
+            p,
+            PoolMask(2),
+            true,
+            &s_tree,
+            &CommitmentTreeFrontier::default(),
+            &options,
+        )
+        .unwrap()
+    }
+
+    /// `UnsignedTransaction` is not `Clone`, copy it through its encoding
+    fn copy(utx: &UnsignedTransaction) -> UnsignedTransaction {
+        bincode::deserialize(&bincode::serialize(utx).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn prove_then_authorize_is_build() {
+        if !has_sapling_params() {
+            return;
+        }
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let pgk = LocalSigner::new(&ai).sapling_proof_generation_key();
+        let expiration_height = TEST_BIRTH + 140;
+        let utx = shielded_payment(&connection, account);
+        assert!(utx
+            .tx_notes
+            .iter()
+            .any(|n| matches!(n.note, InputNote::Sapling { .. })));
+
+        let tx = copy(&utx)
+            .build_with_signer(
+                &NETWORK,
+                &connection,
+                expiration_height,
+                &mut TSKStore::default(),
+                &mut LocalSigner::new(&ai),
+                StdRng::seed_from_u64(7),
+            )
+            .unwrap();
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let proven = utx
+            .prove(
+                &NETWORK,
+                &connection,
+                expiration_height,
+                &mut TSKStore::default(),
+                pgk.as_ref(),
+                &mut rng,
+            )
+            .unwrap();
+        let tx2 = proven.authorize(&mut LocalSigner::new(&ai), rng).unwrap();
+        assert_eq!(tx.txid, tx2.txid);
+        assert_eq!(tx.data, tx2.data);
+    }
+
+    #[test]
+    fn prove_with_the_proof_generation_key_only() {
+        if !has_sapling_params() {
+            return;
+        }
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let mut signer = LocalSigner::new(&ai);
+        let pgk = signer.sapling_proof_generation_key().unwrap();
+        let utx = shielded_payment(&connection, account);
+        // the wallet only has the viewing key of the Sapling pool
+        connection
+            .execute(
+                "UPDATE accounts SET seed = NULL, sk = NULL WHERE id_account = ?1",
+                [account],
+            )
+            .unwrap();
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        assert!(ai.sapling.sk.is_none());
+
+        let prove = |pgk: Option<&ProofGenerationKey>| {
+            copy(&utx).prove(
+                &NETWORK,
+                &connection,
+                TEST_BIRTH + 140,
+                &mut TSKStore::default(),
+                pgk,
+                rand::rngs::OsRng,
+            )
+        };
+        let e = prove(None).err().unwrap();
+        assert!(e.to_string().contains("Proof Generation Key"), "{e}");
+        let tx = prove(Some(&pgk))
+            .unwrap()
+            .authorize(&mut signer, rand::rngs::OsRng)
+            .unwrap();
+        validate_tx(&NETWORK, &connection, &tx.data, CP_HEIGHT.0).unwrap();
+    }
+
+    /// Signs with the keys of the account and keeps the sighashes
+    /// it was given
+    struct RecordingSigner {
+        signer: LocalSigner,
+        sighashes: Vec<Hash>,
+    }
+
+    impl TxSigner for RecordingSigner {
+        fn sapling_proof_generation_key(&self) -> Option<ProofGenerationKey> {
+            self.signer.sapling_proof_generation_key()
+        }
+
+        fn sign_sapling<R: rand::RngCore + rand::CryptoRng>(
+            &mut self,
+            sighash: &Hash,
+            bundle: SaplingUnsignedBundle,
+            rng: R,
+        ) -> anyhow::Result<SaplingUnsignedBundle> {
+            self.sighashes.push(*sighash);
+            self.signer.sign_sapling(sighash, bundle, rng)
+        }
+
+        fn sign_orchard<R: rand::RngCore + rand::CryptoRng>(
+            &mut self,
+            sighash: &Hash,
+            bundle: OrchardUnsignedBundle,
+            rng: R,
+        ) -> anyhow::Result<OrchardUnsignedBundle> {
+            self.sighashes.push(*sighash);
+            self.signer.sign_orchard(sighash, bundle, rng)
+        }
+    }
+
+    #[test]
+    fn signer_is_given_the_sighash_of_the_transaction() {
+        if !has_sapling_params() {
+            return;
+        }
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let mut signer = RecordingSigner {
+            signer: LocalSigner::new(&ai),
+            sighashes: vec![],
+        };
+        let utx = shielded_payment(&connection, account);
+        let proven = utx
+            .prove(
+                &NETWORK,
+                &connection,
+                TEST_BIRTH + 140,
+                &mut TSKStore::default(),
+                signer.sapling_proof_generation_key().as_ref(),
+                rand::rngs::OsRng,
+            )
+            .unwrap();
+        let sighash = *proven.sighash();
+        assert!(proven.sapling_bundle.is_some());
+        let bundles = 1 + proven.orchard_bundle.is_some() as usize;
+        let tx = proven.authorize(&mut signer, rand::rngs::OsRng).unwrap();
+        // every shielded bundle signs the same sighash
+        assert_eq!(signer.sighashes, vec![sighash; bundles]);
+        validate_tx(&NETWORK, &connection, &tx.data, CP_HEIGHT.0).unwrap();
+    }
+
+    #[test]
+    fn built_txid_is_the_txid_of_the_data() {
+        use zcash_primitives::{consensus::BranchId, transaction::Transaction};
+
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        let tx = transparent_payment(&connection, account)
+            .build(
+                &NETWORK,
+                &connection,
+                TEST_BIRTH + 140,
+                &mut TSKStore::default(),
+                rand::rngs::OsRng,
+            )
+            .unwrap();
+        let branch_id = BranchId::for_height(&NETWORK, (TEST_BIRTH + 100).into());
+        let decoded = Transaction::read(&*tx.data, branch_id).unwrap();
+        assert_eq!(&tx.txid, decoded.txid().as_ref());
+    }
+
+    #[test]
+    fn size_of_a_transaction_with_many_inputs() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        for _ in 0..50 {
+            add_utxo(&connection, account, TEST_BIRTH + 10, 100_000);
+        }
+        let utx = transparent_payment(&connection, account);
+        let inputs = utx.tx_notes.len();
+        assert!(inputs > 10);
+        let tx = utx
+            .build(
+                &NETWORK,
+                &connection,
+                TEST_BIRTH + 140,
+                &mut TSKStore::default(),
+                rand::rngs::OsRng,
+            )
+            .unwrap();
+        // a signed P2PKH input takes about 150 bytes
+        let size = tx.data.len();
+        assert!(size > inputs * 140 && size < inputs * 160 + 1_000, "{size}");
+        assert!(check_tx_size(size).is_ok());
+
+        assert!(check_tx_size(MAX_TX_SIZE).is_ok());
+        assert!(matches!(
+            check_tx_size(MAX_TX_SIZE + 1),
+            Err(Error::TxTooLarge(size, MAX_TX_SIZE)) if size == MAX_TX_SIZE + 1
+        ));
+    }
+
+    #[test]
+    fn transaction_without_expiry() {
+        use zcash_primitives::{consensus::BranchId, transaction::Transaction};
+
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        add_utxo(&connection, account, TEST_BIRTH + 10, 10_000_000);
+        let cp_height = TEST_BIRTH + 100;
+        let utx = transparent_payment(&connection, account);
+        let tx = utx
+            .build(
+                &NETWORK,
+                &connection,
+                expiry_height(cp_height, Some(0)).unwrap(),
+                &mut TSKStore::default(),
+                rand::rngs::OsRng,
+            )
+            .unwrap();
+        let branch_id = BranchId::for_height(&NETWORK, cp_height.into());
+        let decoded = Transaction::read(&*tx.data, branch_id).unwrap();
+        assert_eq!(u32::from(decoded.expiry_height()), 0);
+
+        assert_eq!(
+            expiry_height(cp_height, None).unwrap(),
+            cp_height + EXPIRATION_HEIGHT_DELTA
+        );
+        assert_eq!(
+            expiry_height(cp_height, Some(cp_height + 1_000)).unwrap(),
+            cp_height + 1_000
+        );
+        assert!(expiry_height(cp_height, Some(cp_height)).is_err());
+        assert!(expiry_height(cp_height, Some(500_000_000)).is_err());
     }
 }
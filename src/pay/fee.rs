@@ -1,10 +1,82 @@
-#[derive(Debug, Default)]
+/// Sapling outputs and Orchard actions that bundles are padded to
+pub const MIN_PADDING: u32 = 2;
+
+/// Lowest ZIP-317 fee, the two logical actions that any
+/// transaction is charged for
+pub const MIN_FEE: u64 = 10_000;
+
+/// How `FeeManager` computes the fee
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub enum FeeStrategy {
+    /// 5000 zats per logical action
+    #[default]
+    Zip317,
+    /// This fee whatever the inputs and outputs, at least `MIN_FEE`
+    Fixed(u64),
+    /// Always `MIN_FEE`
+    Minimum,
+}
+
+impl std::str::FromStr for FeeStrategy {
+    type Err = anyhow::Error;
+
+    /// `zip317`, `minimum` or a fixed fee in zats
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "zip317" => Ok(FeeStrategy::Zip317),
+            "minimum" => Ok(FeeStrategy::Minimum),
+            _ => s.parse::<u64>().map(FeeStrategy::Fixed).map_err(|_| {
+                anyhow::anyhow!("Invalid fee strategy {s} (zip317, minimum or a fee)")
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct FeeManager {
-    num_inputs: [u8; 3],
-    num_outputs: [u8; 3],
+    num_inputs: [u32; 3],
+    num_outputs: [u32; 3],
+    strategy: FeeStrategy,
+    /// Minimum number of Sapling outputs when there are Sapling inputs
+    pub min_sapling_outputs: u32,
+    /// Minimum number of Orchard actions of a non empty bundle
+    pub min_orchard_actions: u32,
+}
+
+impl Default for FeeManager {
+    fn default() -> Self {
+        Self {
+            num_inputs: [0; 3],
+            num_outputs: [0; 3],
+            strategy: FeeStrategy::default(),
+            min_sapling_outputs: MIN_PADDING,
+            min_orchard_actions: MIN_PADDING,
+        }
+    }
 }
 
 impl FeeManager {
+    /// Change the padding minimums. They cannot be lower than
+    /// what the transaction builders produce
+    pub fn set_padding(&mut self, min_sapling_outputs: u32, min_orchard_actions: u32) -> anyhow::Result<()> {
+        if min_sapling_outputs < MIN_PADDING || min_orchard_actions < MIN_PADDING {
+            anyhow::bail!("Padding must be at least {MIN_PADDING}");
+        }
+        self.min_sapling_outputs = min_sapling_outputs;
+        self.min_orchard_actions = min_orchard_actions;
+        Ok(())
+    }
+
+    pub fn set_strategy(&mut self, strategy: FeeStrategy) -> anyhow::Result<()> {
+        if let FeeStrategy::Fixed(fee) = strategy {
+            if fee < MIN_FEE {
+                anyhow::bail!("Fee {fee} is below the minimum {MIN_FEE}");
+            }
+        }
+        self.strategy = strategy;
+        Ok(())
+    }
+
     pub fn add_input(&mut self, pool: u8) -> u64 {
         let fee = self.fee();
         self.num_inputs[pool as usize] += 1;
@@ -18,29 +90,37 @@ impl FeeManager {
     }
 
     pub fn fee(&self) -> u64 {
+        match self.strategy {
+            FeeStrategy::Zip317 => self.zip317_fee(),
+            FeeStrategy::Fixed(fee) => fee,
+            FeeStrategy::Minimum => MIN_FEE,
+        }
+    }
+
+    fn zip317_fee(&self) -> u64 {
         let t = self.num_inputs[0].max(self.num_outputs[0]);
         let s = {
             let o = if self.num_inputs[1] > 0 {
-                // if any input
-                self.num_outputs[1].max(2) // min 2 outputs
+                // if any input, padded to min_sapling_outputs
+                self.num_outputs[1].max(self.min_sapling_outputs)
             } else {
                 self.num_outputs[1]
             };
             self.num_inputs[1].max(o)
         };
         let o = if self.num_inputs[2] > 0 || self.num_outputs[2] > 0 {
-            // padding min 2 actions
-            self.num_inputs[2].max(self.num_outputs[2]).max(2)
+            // padded to min_orchard_actions
+            self.num_inputs[2].max(self.num_outputs[2]).max(self.min_orchard_actions)
         } else {
             0
         };
         let f = t + s + o;
-        tracing::info!("fee: {t} {s} {o} -> {f}");
+        tracing::trace!("fee: {t} {s} {o} -> {f}");
         f as u64 * 5_000
     }
 
     #[allow(dead_code)]
-    fn min_actions_padding(a: u8) -> u8 {
+    fn min_actions_padding(a: u32) -> u32 {
         if a == 0 {
             0
         } else {
@@ -48,3 +128,25 @@ impl FeeManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FeeStrategy;
+
+    #[test]
+    fn fee_strategies_are_parsed() {
+        assert_eq!(
+            "zip317".parse::<FeeStrategy>().unwrap(),
+            FeeStrategy::Zip317
+        );
+        assert_eq!(
+            "minimum".parse::<FeeStrategy>().unwrap(),
+            FeeStrategy::Minimum
+        );
+        assert_eq!(
+            "20000".parse::<FeeStrategy>().unwrap(),
+            FeeStrategy::Fixed(20_000)
+        );
+        assert!("fast".parse::<FeeStrategy>().is_err());
+    }
+}
@@ -1,13 +1,22 @@
 use super::{
-    fee::FeeManager, AdjustableUnsignedTransaction, Error, ExtendedPayment, OutputNote, Payment,
-    PaymentBuilder, PaymentItem, Result, TxInput, TxOutput, UnsignedTransaction,
+    checked_sum, fee::{FeeManager, FeeStrategy}, AdjustableUnsignedTransaction, Error, ExtendedPayment, InputNote,
+    OutputNote, Payment,
+    PaymentBuilder, PaymentItem, Result, TxInput, TxOutput, UnsignedTransaction, MIN_MAX_FEE,
+    REMAINDER,
 };
+use rand::{rngs::OsRng, seq::SliceRandom as _, CryptoRng, RngCore};
 use rusqlite::Connection;
-use zcash_primitives::{consensus::Network, memo::MemoBytes};
+use std::str::FromStr as _;
+
+use zcash_primitives::{
+    consensus::Network,
+    memo::{Memo, MemoBytes},
+};
 
 use crate::{
     db::{
         account::get_account_info,
+        account_manager::{get_default_memo, is_account_frozen},
         notes::{list_received_notes, list_utxos},
     },
     types::{CheckpointHeight, PoolMask},
@@ -78,12 +87,27 @@ impl PaymentBuilder {
         o_tree: &CommitmentTreeFrontier,
     ) -> Result<Self> {
         let height: u32 = height.into();
+        if is_account_frozen(connection, account)? {
+            return Err(Error::AccountFrozen(account));
+        }
         let ai = get_account_info(network, connection, account)?;
+        let default_memo = get_default_memo(connection, account)?
+            .map(|memo| Memo::from_str(&memo).map(|memo| MemoBytes::from(&memo)))
+            .transpose()
+            .map_err(anyhow::Error::new)?;
         let outputs = payment
             .recipients
             .into_iter()
             .map(|p| ExtendedPayment::to_extended(network, p))
             .collect::<Result<Vec<_>>>()?;
+        let src_pools = if outputs.iter().any(|o| o.tex) {
+            tracing::warn!(
+                "Paying a TEX address: only transparent funds can be used and they are publicly linked to the payment"
+            );
+            PoolMask(src_pools.0 & 1)
+        } else {
+            src_pools
+        };
         Ok(Self {
             network: network.clone(),
             height,
@@ -93,10 +117,15 @@ impl PaymentBuilder {
             outputs,
             account_pools: PoolMask::default(),
             src_pools,
+            source_priority: None,
+            default_memo,
+            change_address: None,
             fee_manager: FeeManager::default(),
             fee: 0,
             available: [0; 3],
             use_change: true,
+            deterministic: false,
+            max_fee: None,
             s_edge: s_tree.to_edge(&SaplingHasher::default()),
             o_edge: o_tree.to_edge(&OrchardHasher::default()),
         })
@@ -128,19 +157,26 @@ impl PaymentBuilder {
             vec![]
         };
 
+        // The notes are listed for every account. They can be
+        // received on any diversified address of the account,
+        // the spend only needs its keys
+        let account = self.account;
         self.inputs[0].extend(
             transparent_inputs
                 .iter()
+                .filter(|utxo| utxo.account == account)
                 .map(|utxo| TxInput::from_utxo(utxo)),
         );
         self.inputs[1].extend(
             sapling_inputs
                 .iter()
+                .filter(|note| note.account == account)
                 .map(|note| TxInput::from_sapling(note)),
         );
         self.inputs[2].extend(
             orchard_inputs
                 .iter()
+                .filter(|note| note.account == account)
                 .map(|note| TxInput::from_orchard(note)),
         );
         tracing::debug!("{:?}", self.inputs);
@@ -153,6 +189,84 @@ impl PaymentBuilder {
         Ok(())
     }
 
+    /// Order in which the pools (0: T, 1: S, 2: O) are used to pay
+    /// the outputs, instead of the default strategy that favors
+    /// spending from the same pool as the recipient.
+    /// Pools not listed are not used
+    pub fn set_source_priority(&mut self, priority: Vec<u8>) -> Result<()> {
+        if priority.iter().any(|&p| p > 2) {
+            return Err(anyhow::anyhow!("Invalid pool in source priority").into());
+        }
+        self.source_priority = Some(priority);
+        Ok(())
+    }
+
+    /// Send the change to `address` in `pool` (0: T, 1: S, 2: O)
+    /// instead of the account. The address must have a receiver
+    /// for this pool
+    pub fn set_change_address(&mut self, address: &str, pool: u8) -> Result<()> {
+        if pool > 2 {
+            return Err(anyhow::anyhow!("Invalid pool {pool}").into());
+        }
+        let pool = PoolMask::from_pool(pool);
+        let address = single_receiver_address(&self.network, address, pool)?
+            .ok_or(anyhow::anyhow!("Address has no receiver for pool {pool:?}"))?;
+        if pool.0 == 1 {
+            tracing::warn!("Change sent to a transparent address is public");
+        }
+        self.change_address = Some((address, pool));
+        Ok(())
+    }
+
+    /// Fail `prepare` when the fee is above `max_fee`. None restores
+    /// the default of a tenth of the amount sent, at least `MIN_MAX_FEE`
+    pub fn set_max_fee(&mut self, max_fee: Option<u64>) {
+        self.max_fee = max_fee;
+    }
+
+    /// Keep the outputs in the order of the payment, change first.
+    /// Only meant for tests that compare transactions
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Compute the fee with `strategy` instead of ZIP-317
+    pub fn set_fee_strategy(&mut self, strategy: FeeStrategy) -> Result<()> {
+        self.fee_manager.set_strategy(strategy)?;
+        Ok(())
+    }
+
+    /// Pad the Sapling outputs and the Orchard actions to these
+    /// minimums. The fees are computed for them and the built bundles
+    /// get empty outputs up to them. The defaults are 2
+    pub fn set_padding(&mut self, min_sapling_outputs: u32, min_orchard_actions: u32) -> Result<()> {
+        self.fee_manager.set_padding(min_sapling_outputs, min_orchard_actions)?;
+        Ok(())
+    }
+
+    /// Give the recipient paid `REMAINDER` the funds added so far
+    /// minus the amounts of the other recipients. It is moved last,
+    /// where `add_to_change` can take the fee from it.
+    /// Call after adding the funds, without a change output
+    pub fn assign_remainder(&mut self) -> Result<()> {
+        let i = self
+            .outputs
+            .iter()
+            .position(|o| o.payment.amount == REMAINDER)
+            .ok_or(anyhow::anyhow!("No remainder recipient"))?;
+        let mut output = self.outputs.remove(i);
+        let funds = checked_sum(self.inputs.iter().flatten().map(|n| n.amount))?;
+        let fixed = checked_sum(self.outputs.iter().map(|o| o.payment.amount))?;
+        let amount = funds
+            .checked_sub(fixed)
+            .ok_or(Error::NotEnoughFunds(fixed - funds))?;
+        output.payment.amount = amount;
+        output.amount = amount;
+        output.remaining = amount;
+        self.outputs.push(output);
+        Ok(())
+    }
+
     pub fn add_utxos(&mut self, utxos: &[UTXO]) -> Result<()> {
         let mut utxos = utxos
             .iter()
@@ -166,25 +280,33 @@ impl PaymentBuilder {
         if self.outputs.is_empty() {
             return Err(Error::NoRecipient);
         }
+        if self.outputs.iter().any(|o| o.tex) && self.inputs[0].is_empty() {
+            return Err(Error::NoTransparentFundsForTex);
+        }
 
         let mut used = [false; 3];
 
         if self.use_change {
             // add a change output in first position
-            // Determine which pool to use for the change output
-            // 1. pick one of the output pools if they are supported by our account
-            let o_pools = self.outputs.iter().map(|o| o.pool.0).fold(0, |a, b| a | b);
-            // 2. Use a pool in common between our account's and the recipients
-            let change_pools = self.account_pools.0 & o_pools & 6; // but not the transparent pool
-            let change_pools = if change_pools != 0 {
-                change_pools
-            } else {
-                // fallback to the account's best pool if there is nothing
-                self.account_pools.0
+            let (change_address, change_pools) = match self.change_address.clone() {
+                Some(change) => change,
+                None => {
+                    // Determine which pool to use for the change output
+                    // 1. pick one of the output pools if they are supported by our account
+                    let o_pools = self.outputs.iter().map(|o| o.pool.0).fold(0, |a, b| a | b);
+                    // 2. Use a pool in common between our account's and the recipients
+                    let change_pools = self.account_pools.0 & o_pools & 6; // but not the transparent pool
+                    let change_pools = if change_pools != 0 {
+                        change_pools
+                    } else {
+                        // fallback to the account's best pool if there is nothing
+                        self.account_pools.0
+                    };
+                    let change_pools = PoolMask(change_pools);
+                    let change_address = self.ai.to_address(&self.network, change_pools).unwrap();
+                    (change_address, change_pools)
+                }
             };
-            let change_pools = PoolMask(change_pools);
-
-            let change_address = self.ai.to_address(&self.network, change_pools).unwrap();
             tracing::info!("Use pool {change_pools:?} for change");
             let change = ExtendedPayment {
                 payment: PaymentItem {
@@ -196,6 +318,7 @@ impl PaymentBuilder {
                 remaining: 0,
                 pool: change_pools,
                 is_change: true,
+                tex: false,
             };
             self.outputs.insert(0, change);
         }
@@ -206,9 +329,19 @@ impl PaymentBuilder {
             }
         }
 
-        for phase in 0..8 {
+        // phases 8, 9, 10 take funds from T, S, O in the user's order
+        let phases = match self.source_priority.as_ref() {
+            Some(priority) => std::iter::once(0)
+                .chain(priority.iter().map(|&p| 8 + p))
+                .collect::<Vec<_>>(),
+            None => (0..8).collect::<Vec<_>>(),
+        };
+
+        // a fixed fee is due before any input or output is counted
+        self.fee = self.fee_manager.fee();
+        for phase in phases {
             for i in 0..3 {
-                self.available[i] = self.inputs[i].iter().map(|n| n.remaining).sum::<u64>();
+                self.available[i] = checked_sum(self.inputs[i].iter().map(|n| n.remaining))?;
             }
 
             for output in self.outputs.iter_mut() {
@@ -300,6 +433,20 @@ impl PaymentBuilder {
                         }
                         src_pool = 0;
                     }
+                    // explicit source priority
+                    8..=10 => {
+                        tracing::debug!("Priority {}", phase - 8);
+                        src_pool = phase - 8;
+                        if out_pool_mask == 6 {
+                            let out_pool = if src_pool != 0 {
+                                src_pool
+                            } else {
+                                Self::select_pool(&used, &self.available)
+                            };
+                            output.pool = PoolMask::from_pool(out_pool);
+                            self.fee += self.fee_manager.add_output(out_pool);
+                        }
+                    }
 
                     _ => unreachable!(),
                 }
@@ -340,6 +487,8 @@ impl PaymentBuilder {
             }
         }
 
+        self.check_witnesses(&tx_notes)?;
+
         for n in self.outputs.iter() {
             let pi = n.clone().to_inner();
             let PaymentItem {
@@ -348,6 +497,12 @@ impl PaymentBuilder {
                 amount,
                 ..
             } = pi;
+            // transparent outputs cannot have a memo
+            let memo = if !n.is_change && n.pool.0 & 6 != 0 {
+                memo.or_else(|| self.default_memo.clone())
+            } else {
+                memo
+            };
             let address = single_receiver_address(&self.network, &address, n.pool)?.unwrap();
             let note = OutputNote::from_address(
                 &self.network,
@@ -365,9 +520,28 @@ impl PaymentBuilder {
             tx_outputs[0].change = true;
         }
 
-        let sum_ins = tx_notes.iter().map(|n| n.amount).sum::<u64>();
-        let sum_outs = tx_outputs.iter().map(|n| n.amount).sum::<u64>() + self.fee_manager.fee();
-        let change = (sum_ins as i64) - (sum_outs as i64); // can be negative at this point
+        let fee = self.fee_manager.fee();
+        let max_fee = match self.max_fee {
+            Some(max_fee) => max_fee,
+            None => {
+                let sent = checked_sum(tx_outputs.iter().filter(|o| !o.change).map(|o| o.amount))?;
+                (sent / 10).max(MIN_MAX_FEE)
+            }
+        };
+        if fee > max_fee {
+            return Err(Error::FeeTooHigh(fee, max_fee));
+        }
+
+        let sum_ins = checked_sum(tx_notes.iter().map(|n| n.amount))?;
+        let sum_outs = checked_sum(
+            tx_outputs
+                .iter()
+                .map(|n| n.amount)
+                .chain(std::iter::once(self.fee_manager.fee())),
+        )?;
+        // can be negative at this point
+        let change = i64::try_from(sum_ins as i128 - sum_outs as i128)
+            .map_err(|_| Error::AmountOverflow)?;
 
         let transaction = AdjustableUnsignedTransaction {
             tx_notes,
@@ -378,11 +552,22 @@ impl PaymentBuilder {
         Ok(transaction)
     }
 
-    pub fn finalize(self, mut utx: AdjustableUnsignedTransaction) -> Result<UnsignedTransaction> {
+    pub fn finalize(self, utx: AdjustableUnsignedTransaction) -> Result<UnsignedTransaction> {
+        self.finalize_with_rng(utx, &mut OsRng)
+    }
+
+    /// `finalize` with the RNG used to shuffle the outputs, so that
+    /// the change is not always the first one. The order is kept
+    /// when the builder is deterministic
+    pub fn finalize_with_rng<R: RngCore + CryptoRng>(
+        self,
+        mut utx: AdjustableUnsignedTransaction,
+        rng: &mut R,
+    ) -> Result<UnsignedTransaction> {
         tracing::debug!("{:?}", utx.tx_notes);
         let change = utx.change;
         if change < 0 {
-            return Err(Error::NotEnoughFunds(-change as u64));
+            return Err(Error::NotEnoughFunds(change.unsigned_abs()));
         }
         if self.use_change {
             let note = OutputNote::from_address(
@@ -395,13 +580,25 @@ impl PaymentBuilder {
         } else if change != 0 {
             return Err(Error::NoChangeOutput);
         }
+        if !self.deterministic {
+            utx.tx_outputs.shuffle(rng);
+        }
         tracing::debug!("{:?}", utx.tx_outputs);
+        // the fee is computed after every input and output,
+        // only the final one is logged at info
+        tracing::info!(
+            "fee: {} for {} inputs and {} outputs",
+            self.fee_manager.fee(),
+            utx.tx_notes.len(),
+            utx.tx_outputs.len()
+        );
 
         let utx = UnsignedTransaction {
             account: self.account,
             account_name: self.ai.name.clone(),
             account_id: self.ai.to_account_unique_id(),
             height: self.height,
+            checkpoint_height: self.height,
             edges: [
                 self.s_edge.to_auth_path(&SaplingHasher::default()),
                 self.o_edge.to_auth_path(&OrchardHasher::default()),
@@ -412,11 +609,40 @@ impl PaymentBuilder {
             ],
             tx_notes: utx.tx_notes,
             tx_outputs: utx.tx_outputs,
+            min_sapling_outputs: self.fee_manager.min_sapling_outputs,
+            min_orchard_actions: self.fee_manager.min_orchard_actions,
         };
 
         Ok(utx)
     }
 
+    /// The witnesses of the selected notes must lead to the roots
+    /// of the commitment trees at the anchor height
+    fn check_witnesses(&self, notes: &[TxInput]) -> Result<()> {
+        let sh = SaplingHasher::default();
+        let oh = OrchardHasher::default();
+        // an empty edge means that no tree state was given (fee estimation)
+        let s_anchor = (!self.s_edge.0.iter().all(|n| n.is_none()))
+            .then(|| (self.s_edge.to_auth_path(&sh), self.s_edge.root(&sh)));
+        let o_anchor = (!self.o_edge.0.iter().all(|n| n.is_none()))
+            .then(|| (self.o_edge.to_auth_path(&oh), self.o_edge.root(&oh)));
+        for n in notes.iter() {
+            let valid = match (&n.note, &s_anchor, &o_anchor) {
+                (InputNote::Sapling { witness, .. }, Some((path, root)), _) => {
+                    witness.root(path, &sh) == *root
+                }
+                (InputNote::Orchard { witness, .. }, _, Some((path, root))) => {
+                    witness.root(path, &oh) == *root
+                }
+                _ => true,
+            };
+            if !valid {
+                return Err(Error::NoteNotWitnessed(n.id));
+            }
+        }
+        Ok(())
+    }
+
     fn select_pool(used: &[bool], available: &[u64]) -> u8 {
         // if we used sapling but not orchard, assign to sapling
         if used[1] && !used[2] && available[1] > 0 {
@@ -446,11 +672,14 @@ impl AdjustableUnsignedTransaction {
                     return Err(Error::FeesTooHighForRecipient(o));
                 }
                 payee.amount -= o;
-                self.change += o as i64;
+                self.change = self.change.checked_add(offset).ok_or(Error::AmountOverflow)?;
             } else {
-                let o = -offset;
-                payee.amount += o as u64;
-                self.change -= o as i64;
+                let o = offset.unsigned_abs();
+                payee.amount = payee.amount.checked_add(o).ok_or(Error::AmountOverflow)?;
+                self.change = self
+                    .change
+                    .checked_sub_unsigned(o)
+                    .ok_or(Error::AmountOverflow)?;
             }
         } else {
             return Err(Error::NoRecipient);
@@ -0,0 +1,101 @@
+use anyhow::Result;
+use orchard::keys::SpendAuthorizingKey as OrchardSpendAuthorizingKey;
+use rand::{CryptoRng, RngCore};
+use sapling_crypto::{keys::SpendAuthorizingKey as SaplingSpendAuthorizingKey, ProofGenerationKey};
+use zcash_protocol::value::ZatBalance;
+
+use crate::{types::AccountInfo, Hash};
+
+pub type SaplingUnsignedBundle = sapling_crypto::Bundle<
+    sapling_crypto::builder::InProgress<
+        sapling_crypto::builder::Proven,
+        sapling_crypto::builder::PartiallyAuthorized,
+    >,
+    ZatBalance,
+>;
+
+pub type OrchardUnsignedBundle = orchard::Bundle<
+    orchard::builder::InProgress<orchard::circuit::Proof, orchard::builder::PartiallyAuthorized>,
+    ZatBalance,
+>;
+
+/// Authorizes the shielded spends of a transaction.
+/// `UnsignedTransaction::build_with_signer` makes the proofs
+/// and hands over the bundles with the sighash. An implementation
+/// returns them with every spend signed, either with `sign` when it has
+/// the keys or with `append_signatures` when they are made elsewhere,
+/// for instance by a hardware wallet. The signatures are randomized
+/// with `rng`, the RNG given to the builder.
+/// Transparent inputs are still signed with the `TSKStore`
+pub trait TxSigner {
+    /// Key of the Sapling spend proofs. A hardware wallet can hand it
+    /// over and keep the spend authorizing key
+    fn sapling_proof_generation_key(&self) -> Option<ProofGenerationKey>;
+
+    fn sign_sapling<R: RngCore + CryptoRng>(
+        &mut self,
+        sighash: &Hash,
+        bundle: SaplingUnsignedBundle,
+        rng: R,
+    ) -> Result<SaplingUnsignedBundle>;
+
+    fn sign_orchard<R: RngCore + CryptoRng>(
+        &mut self,
+        sighash: &Hash,
+        bundle: OrchardUnsignedBundle,
+        rng: R,
+    ) -> Result<OrchardUnsignedBundle>;
+}
+
+/// Signs with the spending keys of the account
+pub struct LocalSigner {
+    sapling: Option<SaplingSpendAuthorizingKey>,
+    sapling_pgk: Option<ProofGenerationKey>,
+    orchard: Option<OrchardSpendAuthorizingKey>,
+}
+
+impl LocalSigner {
+    pub fn new(ai: &AccountInfo) -> Self {
+        let sks = ai.to_secret_keys();
+        Self {
+            sapling: sks.sapling.as_ref().map(|sk| sk.expsk.ask.clone()),
+            sapling_pgk: sks
+                .sapling
+                .as_ref()
+                .map(|sk| sk.expsk.proof_generation_key()),
+            orchard: sks.orchard.map(|sk| OrchardSpendAuthorizingKey::from(&sk)),
+        }
+    }
+}
+
+impl TxSigner for LocalSigner {
+    fn sapling_proof_generation_key(&self) -> Option<ProofGenerationKey> {
+        self.sapling_pgk.clone()
+    }
+
+    fn sign_sapling<R: RngCore + CryptoRng>(
+        &mut self,
+        _sighash: &Hash,
+        bundle: SaplingUnsignedBundle,
+        rng: R,
+    ) -> Result<SaplingUnsignedBundle> {
+        let ask = self
+            .sapling
+            .as_ref()
+            .ok_or(anyhow::anyhow!("No Sapling Secret Key"))?;
+        Ok(bundle.sign(rng, ask))
+    }
+
+    fn sign_orchard<R: RngCore + CryptoRng>(
+        &mut self,
+        _sighash: &Hash,
+        bundle: OrchardUnsignedBundle,
+        rng: R,
+    ) -> Result<OrchardUnsignedBundle> {
+        let sak = self
+            .orchard
+            .as_ref()
+            .ok_or(anyhow::anyhow!("No Orchard Secret Key"))?;
+        Ok(bundle.sign(rng, sak))
+    }
+}
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use anyhow::Result;
 use rusqlite::Connection;
 use tonic::Request;
@@ -6,7 +8,7 @@ use zcash_primitives::consensus::Network;
 
 use super::{Payment, PaymentBuilder, PaymentItem, UnsignedTransaction};
 use crate::{
-    coin::connect_lwd, db::notes::snap_to_checkpoint, keys::{Bip32KeyIterator, TSKStore}, lwd::rpc::{BlockId, BlockRange, GetAddressUtxosArg, TransparentAddressBlockFilter}, types::{AccountInfo, AccountType, PoolMask}, warp::{legacy::CommitmentTreeFrontier, UTXO}
+    coin::connect_lwd, db::{account_manager::parse_seed_phrase, notes::snap_to_checkpoint}, keys::{Bip32KeyIterator, TSKStore}, lwd::rpc::{BlockId, BlockRange, GetAddressUtxosArg, TransparentAddressBlockFilter}, types::{AccountInfo, AccountType, PoolMask}, warp::{legacy::CommitmentTreeFrontier, UTXO}
 };
 
 pub async fn scan_utxo_by_address(
@@ -76,29 +78,68 @@ pub async fn scan_utxo_by_seed(
     let mut tsk_store = TSKStore::default();
     let mut utxos = vec![];
     if let AccountType::Seed(ref seed) = at {
-        let mut tis = Bip32KeyIterator::new(network, seed, ai.aindex, addr_index, compressed);
-        let mut gap = 0;
-        loop {
-            if gap >= gap_limit {
-                break;
-            }
-            let ti = tis.next().unwrap();
-            let address = ti.addr.encode(network);
-            let mut funds =
-                scan_utxo_by_address(url.to_string(), ai.account, height, address).await?;
-            if !funds.is_empty() {
-                tsk_store.0.insert(ti.addr.encode(network), ti.sk.clone());
-                utxos.append(&mut funds);
-            } else {
-                gap += 1;
-            }
-        }
+        let tis = Bip32KeyIterator::new(network, seed, ai.aindex, addr_index, compressed);
+        utxos = scan_utxo_by_keys(network, url, tis, ai.account, height, gap_limit, &mut tsk_store)
+            .await?;
     } else {
         anyhow::bail!("Account has no seed");
     }
     Ok((utxos, tsk_store))
 }
 
+/// Scan the addresses of `tis` until `gap_limit` consecutive addresses
+/// have no funds. The keys of the funded addresses go into `tsk_store`
+async fn scan_utxo_by_keys(
+    network: &Network,
+    url: &str,
+    mut tis: Bip32KeyIterator,
+    account: u32,
+    height: u32,
+    gap_limit: usize,
+    tsk_store: &mut TSKStore,
+) -> Result<Vec<UTXO>> {
+    let mut utxos = vec![];
+    let mut gap = 0;
+    while gap < gap_limit {
+        let ti = tis.next().unwrap();
+        let address = ti.addr.encode(network);
+        let mut funds = scan_utxo_by_address(url.to_string(), account, height, address).await?;
+        if !funds.is_empty() {
+            tsk_store.0.insert(ti.addr.encode(network), ti.sk.clone());
+            utxos.append(&mut funds);
+        } else {
+            gap += 1;
+        }
+    }
+    Ok(utxos)
+}
+
+/// Transparent funds of every account index of `account_range`
+/// derived from the seed, external chain only. The UTXOs are
+/// attributed to the wallet `account` that will sweep them
+pub async fn sweep_seed(
+    network: &Network,
+    url: &str,
+    seed: &str,
+    account: u32,
+    account_range: Range<u32>,
+    height: u32,
+    gap_limit: usize,
+) -> Result<(Vec<UTXO>, TSKStore)> {
+    let seed = parse_seed_phrase(seed)?;
+    let mut tsk_store = TSKStore::default();
+    let mut utxos = vec![];
+    for acc_index in account_range {
+        let tis = Bip32KeyIterator::new(network, &seed, acc_index, 0, true);
+        let mut funds =
+            scan_utxo_by_keys(network, url, tis, account, height, gap_limit, &mut tsk_store)
+                .await?;
+        tracing::info!("Account index {acc_index}: {} UTXOs", funds.len());
+        utxos.append(&mut funds);
+    }
+    Ok((utxos, tsk_store))
+}
+
 pub fn prepare_sweep(
     network: &Network,
     connection: &Connection,
@@ -130,6 +171,76 @@ pub fn prepare_sweep(
     utx.add_to_change(-change)?;
     let utx = builder.finalize(utx)?;
 
-    println!("{:?}", utx);
+    tracing::debug!("{:?}", utx);
     Ok(utx)
 }
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::encoding::AddressCodec as _;
+    use zcash_primitives::consensus::Network;
+
+    use super::sweep_seed;
+    use crate::{
+        db::{
+            account_manager::parse_seed_phrase,
+            testing::{TEST_BIRTH, TEST_SEED},
+        },
+        keys::derive_bip32,
+        lwd::{
+            mock::{MockChain, MockLwd},
+            rpc::{GetAddressUtxosReply, RawTransaction},
+        },
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    /// Fund the address `addr_index` of the account `acc_index` of the test seed
+    fn fund(chain: &mut MockChain, acc_index: u32, addr_index: u32, value: i64) -> String {
+        let seed = parse_seed_phrase(TEST_SEED).unwrap();
+        let address = derive_bip32(&NETWORK, &seed, acc_index, addr_index, true)
+            .addr
+            .encode(&NETWORK);
+        let height = TEST_BIRTH as u64;
+        chain.taddr_txs.insert(
+            address.clone(),
+            vec![RawTransaction {
+                data: vec![],
+                height,
+            }],
+        );
+        chain.utxos.push(GetAddressUtxosReply {
+            address: address.clone(),
+            txid: vec![acc_index as u8; 32],
+            index: 0,
+            script: vec![],
+            value_zat: value,
+            height,
+        });
+        address
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn funds_of_several_account_indices() {
+        let mut chain = MockChain::default();
+        let a0 = fund(&mut chain, 0, 0, 10_000);
+        let a2 = fund(&mut chain, 2, 1, 20_000);
+        // beyond the account range
+        fund(&mut chain, 5, 0, 40_000);
+        let url = MockLwd::new(chain).start().await.unwrap();
+
+        let (utxos, tsk_store) = sweep_seed(&NETWORK, &url, TEST_SEED, 1, 0..3, TEST_BIRTH + 10, 3)
+            .await
+            .unwrap();
+        let mut found = utxos
+            .iter()
+            .map(|u| (u.address.as_str(), u.value, u.account))
+            .collect::<Vec<_>>();
+        found.sort();
+        let mut expected = vec![(a0.as_str(), 10_000, 1), (a2.as_str(), 20_000, 1)];
+        expected.sort();
+        assert_eq!(found, expected);
+        assert_eq!(tsk_store.0.len(), 2);
+        assert!(tsk_store.0.contains_key(&a0) && tsk_store.0.contains_key(&a2));
+    }
+}
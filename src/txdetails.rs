@@ -8,15 +8,19 @@ use rusqlite::Connection;
 use sapling_crypto::{note_encryption::SaplingDomain, PaymentAddress};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use zcash_client_backend::encoding::AddressCodec as _;
+use zcash_keys::address::Address as RecipientAddress;
 use zcash_note_encryption::{try_note_decryption, try_output_recovery_with_ovk};
 use zcash_primitives::{
-    consensus::Network,
+    consensus::{BranchId, Network},
     memo::Memo,
     transaction::{components::sapling::zip212_enforcement, Transaction as ZTransaction},
 };
 
 use crate::{
-    account::contacts::{add_contact, ChunkedContactV1, ChunkedMemoDecoder},
+    account::{
+        contacts::{add_contact, ChunkedContactV1, ChunkedMemoDecoder},
+        txs::{confirmations, tx_status},
+    },
     coin::connect_lwd,
     data::fb::{
         InputShieldedT, InputTransparentT, OutputShieldedT, OutputTransparentT, ShieldedMessageT,
@@ -25,16 +29,20 @@ use crate::{
     db::{
         account::get_account_info,
         notes::{get_note_by_nf, store_tx_details},
-        tx::{get_tx, list_new_txids, store_message, update_tx_primary_address_memo},
+        tx::{
+            get_tx, get_tx_details, list_new_txids, store_message, store_raw_tx, update_tx_primary_address_memo,
+            update_tx_self_transfer,
+        },
     },
-    lwd::{get_transaction, get_txin_coins},
+    lwd::{get_transaction, get_txin_coins, DataUsage},
     types::{Addresses, PoolMask},
-    utils::ua::ua_of_orchard,
+    utils::{from_txid_str, to_txid_str, ua::ua_of_orchard},
     warp::{
         sync::{FullPlainNote, PlainNote, ReceivedTx},
+        verify::{recompute_orchard, recompute_sapling},
         OutPoint, TxOut2,
     },
-    Hash, PooledSQLConnection,
+    Client, Hash, PooledSQLConnection,
 };
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -125,10 +133,19 @@ pub struct TransactionDetails {
     pub oouts: Vec<ShieldedOutput>,
 }
 
+/// Parse a user supplied raw transaction, using the consensus branch of `height`
+pub fn decode_raw_transaction(network: &Network, hex: &str, height: u32) -> Result<ZTransaction> {
+    let data = hex::decode(hex.trim()).map_err(|_| anyhow::anyhow!("Invalid transaction hex"))?;
+    let branch_id = BranchId::for_height(network, height.into());
+    let tx = ZTransaction::read(&*data, branch_id)?;
+    Ok(tx)
+}
+
 pub fn analyze_raw_transaction(
     network: &Network,
     connection: &Connection,
     url: String,
+    usage: &DataUsage,
     height: u32,
     timestamp: u32,
     account: u32,
@@ -254,7 +271,7 @@ pub fn analyze_raw_transaction(
         .iter()
         .map(|tin| tin.out_point.clone())
         .collect::<Vec<_>>();
-    let txouts = get_txin_coins(*network, url.clone(), ops)?;
+    let txouts = get_txin_coins(*network, url.clone(), usage, ops)?;
     for (tin, txout) in tins.iter_mut().zip(txouts.into_iter()) {
         tin.coin = txout;
     }
@@ -273,34 +290,93 @@ pub fn analyze_raw_transaction(
     Ok(tx)
 }
 
+/// Outcome of `retrieve_tx_details`
+#[derive(Clone, Serialize, Default, Debug)]
+pub struct TxDetailsReport {
+    pub succeeded: u32,
+    /// Transactions that still have no details, they are
+    /// tried again by the next call
+    pub failed: Vec<String>,
+}
+
+/// Fetch and decode the transactions that have no details yet.
+/// A transaction that fails is logged and skipped
 pub async fn retrieve_tx_details(
     network: &Network,
     connection: Mutex<PooledSQLConnection>,
     url: String,
-) -> Result<()> {
+    usage: &DataUsage,
+) -> Result<TxDetailsReport> {
     let txids = list_new_txids(&connection.lock())?;
     let mut client = connect_lwd(&url).await?;
+    let mut report = TxDetailsReport::default();
     for (id_tx, account, timestamp, txid) in txids {
-        let ai = get_account_info(network, &connection.lock(), account)?;
-        let account_addrs = ai.to_addresses(network);
-        let rtx = get_tx(&connection.lock(), id_tx)?;
-        let (height, tx) = get_transaction(network, &mut client, &txid).await?;
-        let txd = analyze_raw_transaction(
+        let r = retrieve_one_tx_details(
             network,
-            &connection.lock(),
-            url.clone(),
-            height,
-            timestamp,
+            &connection,
+            &mut client,
+            &url,
+            usage,
+            id_tx,
             account,
-            tx,
-        )?;
-        let tx_bin = bincode::serialize(&txd)?;
-        store_tx_details(&connection.lock(), id_tx, &txid, &tx_bin)?;
-        let (tx_address, tx_memo) =
-            get_tx_primary_address_memo(network, &account_addrs, &rtx, &txd)?;
-        update_tx_primary_address_memo(&connection.lock(), id_tx, tx_address, tx_memo)?;
-        decode_tx_details(network, &connection.lock(), account, id_tx, &txd)?;
+            timestamp,
+            &txid,
+        )
+        .await;
+        match r {
+            Ok(_) => report.succeeded += 1,
+            Err(e) => {
+                let txid = to_txid_str(&txid);
+                tracing::warn!("Cannot retrieve the details of {txid}: {e}");
+                report.failed.push(txid);
+            }
+        }
     }
+    Ok(report)
+}
+
+async fn retrieve_one_tx_details(
+    network: &Network,
+    connection: &Mutex<PooledSQLConnection>,
+    client: &mut Client,
+    url: &str,
+    usage: &DataUsage,
+    id_tx: u32,
+    account: u32,
+    timestamp: u32,
+    txid: &Hash,
+) -> Result<()> {
+    let ai = get_account_info(network, &connection.lock(), account)?;
+    let account_addrs = ai.to_addresses(network);
+    let rtx = get_tx(&connection.lock(), id_tx)?;
+    let (height, tx) = get_transaction(network, client, usage, txid).await?;
+    let mut raw = vec![];
+    tx.write(&mut raw)?;
+    let value_balance = tx
+        .sapling_bundle()
+        .map(|b| i64::from(*b.value_balance()))
+        .unwrap_or_default()
+        + tx.orchard_bundle()
+            .map(|b| i64::from(*b.value_balance()))
+            .unwrap_or_default();
+    let txd = analyze_raw_transaction(
+        network,
+        &connection.lock(),
+        url.to_string(),
+        usage,
+        height,
+        timestamp,
+        account,
+        tx,
+    )?;
+    let tx_bin = bincode::serialize(&txd)?;
+    store_tx_details(&connection.lock(), id_tx, txid, &tx_bin)?;
+    store_raw_tx(&connection.lock(), id_tx, &raw)?;
+    let (tx_address, tx_memo) = get_tx_primary_address_memo(network, &account_addrs, &rtx, &txd)?;
+    update_tx_primary_address_memo(&connection.lock(), id_tx, tx_address, tx_memo)?;
+    let self_transfer = txd.is_self_transfer(&account_addrs, value_balance);
+    update_tx_self_transfer(&connection.lock(), id_tx, self_transfer)?;
+    decode_tx_details(network, &connection.lock(), account, id_tx, &txd)?;
     Ok(())
 }
 
@@ -535,7 +611,55 @@ pub fn get_tx_primary_address_memo(
 }
 
 impl TransactionDetails {
-    pub fn to_transaction_info_ext(self, network: &Network) -> TransactionInfoExtendedT {
+    /// The transaction spends our funds and every output comes back
+    /// to the account. Outputs to other recipients are recovered with
+    /// our ovk. Shielded outputs that we cannot decrypt are padding only
+    /// if `value_balance`, the net value of the shielded bundles, leaves
+    /// nothing for them beyond the inputs we cannot decrypt either
+    pub fn is_self_transfer(&self, addrs: &Addresses, value_balance: i64) -> bool {
+        let tins_ours = self
+            .tins
+            .iter()
+            .all(|t| t.coin.address.is_some() && t.coin.address == addrs.transparent);
+        let spends = (!self.tins.is_empty() && tins_ours)
+            || self.sins.iter().any(|s| s.note.is_some())
+            || self.oins.iter().any(|s| s.note.is_some());
+        let touts_ours = self
+            .touts
+            .iter()
+            .all(|t| t.coin.address.is_some() && t.coin.address == addrs.transparent);
+        let zouts = || self.souts.iter().chain(self.oouts.iter());
+        let zouts_ours = zouts().all(|o| o.note.as_ref().map(|n| n.incoming).unwrap_or(true));
+        let padding = zouts().any(|o| o.note.is_none());
+        let padding_is_empty = !padding || self.undecrypted_value(value_balance) == 0;
+        spends && tins_ours && touts_ours && zouts_ours && padding_is_empty
+    }
+
+    /// Value of the shielded outputs we cannot decrypt, less the value
+    /// of the shielded inputs that are not ours
+    fn undecrypted_value(&self, value_balance: i64) -> i128 {
+        let zins = self
+            .sins
+            .iter()
+            .chain(self.oins.iter())
+            .filter_map(|s| s.note.as_ref())
+            .map(|n| n.value as i128)
+            .sum::<i128>();
+        let zouts = self
+            .souts
+            .iter()
+            .chain(self.oouts.iter())
+            .filter_map(|o| o.note.as_ref())
+            .map(|n| n.note.value as i128)
+            .sum::<i128>();
+        zins - zouts - value_balance as i128
+    }
+
+    pub fn to_transaction_info_ext(
+        self,
+        network: &Network,
+        bc_height: u32,
+    ) -> TransactionInfoExtendedT {
         let tins = self
             .tins
             .into_iter()
@@ -623,6 +747,7 @@ impl TransactionDetails {
             })
             .collect::<Vec<_>>();
 
+        let confirmations = confirmations(bc_height, self.height);
         let etx = TransactionInfoExtendedT {
             height: self.height,
             timestamp: self.timestamp,
@@ -633,7 +758,434 @@ impl TransactionDetails {
             souts: Some(souts),
             oins: Some(oins),
             oouts: Some(oouts),
+            confirmations,
+            status: Some(tx_status(confirmations).to_string()),
         };
         etx
     }
 }
+
+/// Shielded output of a payment, with the note recovered by the
+/// outgoing viewing key of the payer
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PaymentProofOutput {
+    /// 1 for sapling, 2 for orchard
+    pub pool: u8,
+    pub address: String,
+    pub amount: u64,
+    pub memo: String,
+    /// Note commitment, as found in the transaction
+    pub cmx: String,
+    pub rcm: String,
+    pub rho: Option<String>,
+}
+
+/// Evidence that a transaction paid its recipients. Anyone can check
+/// with `check_payment_proof` that the note fields reproduce the note
+/// commitments and that these commitments are outputs of the transaction
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PaymentProof {
+    pub txid: String,
+    pub height: u32,
+    pub outputs: Vec<PaymentProofOutput>,
+}
+
+/// Proof of the payments made by the transaction `id_tx`, from its
+/// stored details. Only the outputs to other recipients are included
+pub fn export_payment_proof(
+    network: &Network,
+    connection: &Connection,
+    id_tx: u32,
+) -> Result<PaymentProof> {
+    let (_, tx) = get_tx_details(connection, id_tx)?;
+    let mut outputs = vec![];
+    for (pool, souts) in [(1u8, &tx.souts), (2u8, &tx.oouts)] {
+        for sout in souts.iter() {
+            let Some(note) = sout.note.as_ref() else {
+                continue;
+            };
+            if note.incoming {
+                continue;
+            }
+            let address = if pool == 1 {
+                PaymentAddress::from_bytes(&note.note.address)
+                    .ok_or(anyhow::anyhow!("Invalid sapling address"))?
+                    .encode(network)
+            } else {
+                ua_of_orchard(&note.note.address).encode(network)
+            };
+            outputs.push(PaymentProofOutput {
+                pool,
+                address,
+                amount: note.note.value,
+                memo: note.memo.to_string(),
+                cmx: hex::encode(sout.cmx),
+                rcm: hex::encode(note.note.rcm),
+                rho: note.note.rho.map(hex::encode),
+            });
+        }
+    }
+    if outputs.is_empty() {
+        anyhow::bail!("Transaction {id_tx} pays no shielded recipient");
+    }
+    Ok(PaymentProof {
+        txid: to_txid_str(&tx.txid),
+        height: tx.height,
+        outputs,
+    })
+}
+
+/// Check the proof against its transaction, fetched from the server
+pub async fn check_payment_proof(
+    network: &Network,
+    client: &mut Client,
+    usage: &DataUsage,
+    proof: &PaymentProof,
+) -> Result<()> {
+    let txid = from_txid_str(&proof.txid)?;
+    let (height, tx) = get_transaction(network, client, usage, &txid).await?;
+    if tx.txid().as_ref() != &txid {
+        anyhow::bail!("The server returned another transaction than {}", proof.txid);
+    }
+    if height != proof.height {
+        anyhow::bail!(
+            "Transaction {} is at height {height}, not {}",
+            proof.txid,
+            proof.height
+        );
+    }
+    check_payment_proof_tx(network, proof, &tx)
+}
+
+/// Check that the note of every output of the proof is for its
+/// address and amount, has its commitment and that this commitment
+/// is an output of `tx`
+pub fn check_payment_proof_tx(
+    network: &Network,
+    proof: &PaymentProof,
+    tx: &ZTransaction,
+) -> Result<()> {
+    let s_cmxs = tx
+        .sapling_bundle()
+        .map(|b| {
+            b.shielded_outputs()
+                .iter()
+                .map(|o| o.cmu().to_bytes())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let o_cmxs = tx
+        .orchard_bundle()
+        .map(|b| {
+            b.actions()
+                .iter()
+                .map(|a| a.cmx().to_bytes())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    for output in proof.outputs.iter() {
+        let address = RecipientAddress::decode(network, &output.address)
+            .ok_or(anyhow::anyhow!("Invalid address {}", output.address))?;
+        let receiver = match (output.pool, address) {
+            (1, RecipientAddress::Sapling(pa)) => Some(pa.to_bytes()),
+            (1, RecipientAddress::Unified(ua)) => ua.sapling().map(|pa| pa.to_bytes()),
+            (2, RecipientAddress::Unified(ua)) => ua.orchard().map(|a| a.to_raw_address_bytes()),
+            _ => None,
+        }
+        .ok_or(anyhow::anyhow!(
+            "Address {} has no receiver in pool {}",
+            output.address,
+            output.pool
+        ))?;
+        let rcm = hex::decode(&output.rcm)?;
+        let recomputed = if output.pool == 1 {
+            recompute_sapling(None, &receiver, output.amount, &rcm, 0)
+        } else {
+            let rho = output.rho.as_deref().map(hex::decode).transpose()?;
+            recompute_orchard(&None, &receiver, output.amount, &rcm, rho.as_deref())
+        };
+        let (cmx, _) = recomputed.ok_or(anyhow::anyhow!("Invalid note of {}", output.address))?;
+        if hex::encode(cmx) != output.cmx {
+            anyhow::bail!("The note of {} does not match its commitment", output.address);
+        }
+        let cmxs = if output.pool == 1 { &s_cmxs } else { &o_cmxs };
+        if !cmxs.contains(&cmx) {
+            anyhow::bail!(
+                "The note of {} is not an output of {}",
+                output.address,
+                proof.txid
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        analyze_raw_transaction, check_payment_proof_tx, decode_raw_transaction,
+        retrieve_tx_details, CompressedMemo, PaymentProof, PaymentProofOutput, ShieldedOutput,
+        TransactionDetails, TransparentInput,
+    };
+    use crate::{
+        db::{
+            testing::{external_address, test_account, test_db, TEST_BIRTH},
+            tx::list_new_txids,
+        },
+        lwd::{
+            mock::{testing::wallet, MockChain, MockLwd},
+            rpc::RawTransaction,
+            DataUsage,
+        },
+        types::Addresses,
+        utils::to_txid_str,
+        warp::{
+            sync::{FullPlainNote, PlainNote},
+            verify::recompute_sapling,
+            OutPoint, TxOut2,
+        },
+    };
+    use parking_lot::Mutex;
+    use sapling_crypto::PaymentAddress;
+    use zcash_client_backend::encoding::AddressCodec as _;
+    use zcash_primitives::{
+        consensus::{BlockHeight, BranchId, Network},
+        legacy::TransparentAddress,
+        transaction::{
+            components::{
+                transparent::{Authorized as TransparentAuthorized, Bundle as TransparentBundle},
+                TxOut,
+            },
+            Authorized, Transaction, TransactionData, TxVersion,
+        },
+    };
+    use zcash_protocol::value::Zatoshis;
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    fn empty_tx() -> Transaction {
+        TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from_u32(2_000_040),
+            None,
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap()
+    }
+
+    fn proof(amount: u64) -> PaymentProof {
+        let address = external_address(&NETWORK);
+        let pa = PaymentAddress::decode(&NETWORK, &address).unwrap();
+        let mut rcm = [0u8; 32];
+        rcm[0] = 1;
+        let (cmx, _) = recompute_sapling(None, &pa.to_bytes(), 100_000, &rcm, 0).unwrap();
+        PaymentProof {
+            txid: "00".repeat(32),
+            height: 2_000_000,
+            outputs: vec![PaymentProofOutput {
+                pool: 1,
+                address,
+                amount,
+                memo: String::new(),
+                cmx: hex::encode(cmx),
+                rcm: hex::encode(rcm),
+                rho: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn note_must_match_commitment() {
+        let err = check_payment_proof_tx(&NETWORK, &proof(200_000), &empty_tx()).unwrap_err();
+        assert!(err.to_string().contains("does not match its commitment"));
+    }
+
+    #[test]
+    fn commitment_must_be_in_tx() {
+        let err = check_payment_proof_tx(&NETWORK, &proof(100_000), &empty_tx()).unwrap_err();
+        assert!(err.to_string().contains("is not an output of"));
+    }
+
+    /// Serialized transaction with transparent outputs of these values
+    fn raw_tx(values: &[u64]) -> Vec<u8> {
+        let vout = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| TxOut {
+                value: Zatoshis::from_u64(*value).unwrap(),
+                script_pubkey: TransparentAddress::PublicKeyHash([i as u8; 20]).script(),
+            })
+            .collect::<Vec<_>>();
+        let tx = TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from_u32(2_000_040),
+            Some(TransparentBundle {
+                vin: vec![],
+                vout,
+                authorization: TransparentAuthorized,
+            }),
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        let mut data = vec![];
+        tx.write(&mut data).unwrap();
+        data
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn raw_tx_outputs_are_decoded() {
+        let data = raw_tx(&[10_000, 20_000]);
+        let url = MockLwd::new(MockChain::default()).start().await.unwrap();
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let tx = decode_raw_transaction(&NETWORK, &hex::encode(&data), 2_000_020).unwrap();
+        let usage = DataUsage::default();
+        let tx = analyze_raw_transaction(
+            &NETWORK,
+            &connection,
+            url,
+            &usage,
+            2_000_020,
+            0,
+            account,
+            tx,
+        )
+        .unwrap();
+        assert_eq!(tx.touts.len(), 2);
+        assert_eq!(tx.touts[1].coin.value, 20_000);
+        assert!(tx.tins.is_empty() && tx.souts.is_empty() && tx.oouts.is_empty());
+
+        assert!(decode_raw_transaction(&NETWORK, "not hex", 2_000_020).is_err());
+        assert!(decode_raw_transaction(&NETWORK, &hex::encode(&data[..10]), 2_000_020).is_err());
+    }
+
+    fn orchard_output(incoming: bool) -> ShieldedOutput {
+        ShieldedOutput {
+            cmx: [0u8; 32],
+            note: Some(FullPlainNote {
+                note: PlainNote {
+                    address: [0u8; 43],
+                    value: 90_000,
+                    rcm: [0u8; 32],
+                    rho: Some([0u8; 32]),
+                },
+                memo: CompressedMemo(vec![]),
+                incoming,
+            }),
+        }
+    }
+
+    /// Details of a transaction made by `transfer_pools`: our transparent
+    /// funds are moved to our orchard address, with a padding action
+    fn transfer_pools_tx(taddr: &str) -> TransactionDetails {
+        TransactionDetails {
+            height: 2_000_000,
+            timestamp: 0,
+            txid: [0u8; 32],
+            tins: vec![TransparentInput {
+                out_point: OutPoint {
+                    txid: [1u8; 32],
+                    vout: 0,
+                },
+                coin: TxOut2 {
+                    address: Some(taddr.to_string()),
+                    value: 100_000,
+                    vout: 0,
+                },
+            }],
+            touts: vec![],
+            sins: vec![],
+            souts: vec![],
+            oins: vec![],
+            oouts: vec![
+                orchard_output(true),
+                ShieldedOutput {
+                    cmx: [0u8; 32],
+                    note: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn transfer_between_pools_is_a_self_transfer() {
+        let taddr = "t1VmmGiyjVNeCjxDZzg7vZmd99WyzVby9yC";
+        let addrs = Addresses {
+            transparent: Some(taddr.to_string()),
+            sapling: None,
+            orchard: None,
+        };
+        // the orchard bundle receives the 90_000 of our output
+        let mut tx = transfer_pools_tx(taddr);
+        assert!(tx.is_self_transfer(&addrs, -90_000));
+        // an output recovered with our ovk went to someone else
+        tx.oouts.push(orchard_output(false));
+        assert!(!tx.is_self_transfer(&addrs, -180_000));
+        // nothing of ours is spent
+        let tx = transfer_pools_tx("t1VmmGiyjVNeCjxDZzg7vZmd99WyzVby9yD");
+        assert!(!tx.is_self_transfer(&addrs, -90_000));
+    }
+
+    #[test]
+    fn undecryptable_output_with_value_is_not_padding() {
+        let taddr = "t1VmmGiyjVNeCjxDZzg7vZmd99WyzVby9yC";
+        let addrs = Addresses {
+            transparent: Some(taddr.to_string()),
+            sapling: None,
+            orchard: None,
+        };
+        let tx = transfer_pools_tx(taddr);
+        // the output we cannot decrypt has 5_000
+        assert!(!tx.is_self_transfer(&addrs, -95_000));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_details_do_not_stop_the_others() {
+        let (found, missing) = ([1u8; 32], [2u8; 32]);
+        let mut chain = MockChain::default();
+        chain.add_transaction(
+            &found,
+            RawTransaction {
+                data: raw_tx(&[10_000]),
+                height: (TEST_BIRTH + 20) as u64,
+            },
+        );
+        let lwd = MockLwd::new(chain);
+        let coin = wallet("details", &lwd).await;
+        let connection = coin.connection().unwrap();
+        // the server does not have the second transaction
+        for txid in [missing, found] {
+            connection
+                .execute(
+                    "INSERT INTO txs(account, txid, height, timestamp, value)
+                    VALUES (1, ?1, ?2, 0, 10000)",
+                    rusqlite::params![txid, TEST_BIRTH + 20],
+                )
+                .unwrap();
+        }
+
+        let report = retrieve_tx_details(
+            &NETWORK,
+            Mutex::new(coin.connection().unwrap()),
+            coin.url.clone(),
+            &coin.data_usage,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.succeeded, 1);
+        assert_eq!(report.failed, [to_txid_str(&missing)]);
+        let left = list_new_txids(&connection).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].3, missing);
+    }
+}
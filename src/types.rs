@@ -21,6 +21,8 @@ use zcash_primitives::{
     legacy::TransparentAddress,
 };
 
+use serde::Serialize;
+
 use crate::{data::fb::{BackupT, ContactCardT}, db::account_manager::parse_seed_phrase, keys::export_sk_bip38};
 
 #[derive(Clone, Copy, Default, Debug)]
@@ -263,6 +265,47 @@ impl AccountInfo {
         addr
     }
 
+    /// Address with a receiver for every pool of the account. Since
+    /// accounts always have a sapling address, it is never empty
+    pub fn default_unified_address(&self, network: &Network) -> String {
+        self.to_address(network, PoolMask(7)).unwrap()
+    }
+
+    /// Whether the account receives on `address`: its transparent
+    /// address or a shielded receiver of any of its diversified addresses.
+    /// A unified address is ours if one of its receivers is
+    pub fn owns_address(&self, address: &RecipientAddress) -> bool {
+        let t = |t: &TransparentAddress| {
+            self.transparent
+                .as_ref()
+                .map(|ti| ti.addr == *t)
+                .unwrap_or_default()
+        };
+        let s = |s: &PaymentAddress| {
+            self.sapling
+                .vk
+                .to_diversifiable_full_viewing_key()
+                .decrypt_diversifier(s)
+                .is_some()
+        };
+        let o = |o: &Address| {
+            self.orchard
+                .as_ref()
+                .map(|oi| oi.vk.scope_for_address(o).is_some())
+                .unwrap_or_default()
+        };
+        match address {
+            RecipientAddress::Transparent(ta) => t(ta),
+            RecipientAddress::Tex(pkh) => t(&TransparentAddress::PublicKeyHash(*pkh)),
+            RecipientAddress::Sapling(sa) => s(sa),
+            RecipientAddress::Unified(ua) => {
+                ua.transparent().map_or(false, t)
+                    || ua.sapling().map_or(false, s)
+                    || ua.orchard().map_or(false, o)
+            }
+        }
+    }
+
     pub fn to_addresses(&self, network: &Network) -> Addresses {
         Addresses {
             transparent: self.to_address(network, PoolMask(1)),
@@ -314,11 +357,15 @@ pub struct Addresses {
     pub orchard: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
 pub struct Balance {
     pub transparent: u64,
     pub sapling: u64,
     pub orchard: u64,
+    /// Number of unspent utxos/notes of each pool
+    pub transparent_notes: u32,
+    pub sapling_notes: u32,
+    pub orchard_notes: u32,
 }
 
 #[derive(Debug)]
@@ -40,3 +40,62 @@ pub fn to_txid_str(txid: &Hash) -> String {
     txid.reverse();
     hex::encode(&txid)
 }
+
+/// Inverse of `to_txid_str`
+pub fn from_txid_str(txid: &str) -> anyhow::Result<Hash> {
+    let mut txid: Hash = hex::decode(txid.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or(anyhow::anyhow!("Invalid txid {txid}"))?;
+    txid.reverse();
+    Ok(txid)
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use parking_lot::Mutex;
+    use std::{io::Write, sync::Arc};
+
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Logs of every level written while `f` runs
+    pub fn capture_logs<F: FnOnce()>(f: F) -> String {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let logs = logs.0.lock().clone();
+        String::from_utf8(logs).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_txid_str, to_txid_str};
+
+    #[test]
+    fn txid_str_round_trip() {
+        let mut txid = [0u8; 32];
+        txid[0] = 1;
+        let s = to_txid_str(&txid);
+        assert!(s.ends_with("01"));
+        assert_eq!(from_txid_str(&s).unwrap(), txid);
+        assert!(from_txid_str("00").is_err());
+    }
+}
@@ -1,19 +1,49 @@
 use anyhow::Result;
 use zcash_address::ZcashAddress;
 use zcash_client_backend::zip321::{Payment, TransactionRequest};
+use zcash_primitives::{consensus::Network, memo::MemoBytes};
 use zcash_protocol::value::Zatoshis;
 
 use crate::pay::PaymentItem;
 
-pub fn make_payment_uri(recipients: &[PaymentItem]) -> Result<String> {
+/// Recipient of a payment URI, with the ZIP-321 label and message
+/// shown to the payer
+#[derive(Clone, PartialEq, Debug)]
+pub struct UriPayment {
+    pub item: PaymentItem,
+    pub label: Option<String>,
+    pub message: Option<String>,
+}
+
+impl From<PaymentItem> for UriPayment {
+    fn from(item: PaymentItem) -> Self {
+        Self {
+            item,
+            label: None,
+            message: None,
+        }
+    }
+}
+
+pub fn make_payment_uri(network: &Network, recipients: &[UriPayment]) -> Result<String> {
     let payments = recipients
         .iter()
         .map(|r| {
-            let recipient_address = ZcashAddress::try_from_encoded(&r.address)?;
-            let amount = Zatoshis::from_u64(r.amount)?;
-            let memo = r.memo.clone();
-            let p = Payment::new(recipient_address, amount, memo, None, None, vec![])
-                .ok_or(anyhow::anyhow!("Incompatible with Payment URI"));
+            r.item.validate(network)?;
+            let recipient_address = ZcashAddress::try_from_encoded(&r.item.address)?;
+            let amount = Zatoshis::from_u64(r.item.amount)?;
+            // an empty memo is the same as no memo
+            let memo = r.item.memo.clone().filter(|m| *m != MemoBytes::empty());
+            // label and message are percent-encoded by to_uri
+            let p = Payment::new(
+                recipient_address,
+                amount,
+                memo,
+                r.label.clone(),
+                r.message.clone(),
+                vec![],
+            )
+            .ok_or(anyhow::anyhow!("Incompatible with Payment URI"));
             p
         })
         .collect::<Result<Vec<_>, _>>()?;
@@ -22,13 +52,129 @@ pub fn make_payment_uri(recipients: &[PaymentItem]) -> Result<String> {
     Ok(uri)
 }
 
-pub fn parse_payment_uri(uri: &str) -> Result<crate::pay::Payment> {
+/// Recipients of a payment URI with their label and message
+pub fn parse_payment_uri_details(network: &Network, uri: &str) -> Result<Vec<UriPayment>> {
     let treq = TransactionRequest::from_uri(uri)?;
-    let recipients = treq.payments().iter().map(|(_, p)| PaymentItem {
-        address: p.recipient_address().encode(),
-        amount: p.amount().into(),
-        memo: p.memo().cloned(),
-    }).collect::<Vec<_>>();
+    let recipients = treq
+        .payments()
+        .iter()
+        .map(|(_, p)| UriPayment {
+            item: PaymentItem {
+                address: p.recipient_address().encode(),
+                amount: p.amount().into(),
+                memo: p.memo().cloned().filter(|m| *m != MemoBytes::empty()),
+            },
+            label: p.label().cloned(),
+            message: p.message().cloned(),
+        })
+        .collect::<Vec<_>>();
+    for r in recipients.iter() {
+        r.item.validate(network)?;
+    }
+    Ok(recipients)
+}
+
+pub fn parse_payment_uri(network: &Network, uri: &str) -> Result<crate::pay::Payment> {
+    let recipients = parse_payment_uri_details(network, uri)?
+        .into_iter()
+        .map(|r| r.item)
+        .collect::<Vec<_>>();
     let p = crate::pay::Payment { recipients };
     Ok(p)
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::OsRng, seq::SliceRandom as _, Rng as _, RngCore as _};
+    use sapling_crypto::zip32::ExtendedSpendingKey;
+    use zcash_keys::address::Address;
+    use zcash_primitives::{
+        consensus::Network,
+        legacy::TransparentAddress,
+        memo::{Memo, MemoBytes},
+    };
+
+    use super::{make_payment_uri, parse_payment_uri, parse_payment_uri_details, UriPayment};
+    use crate::pay::PaymentItem;
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    fn memo(text: &str) -> MemoBytes {
+        MemoBytes::from(&text.parse::<Memo>().unwrap())
+    }
+
+    #[test]
+    fn memo_to_transparent_is_rejected() {
+        let address = Address::Transparent(TransparentAddress::PublicKeyHash([7u8; 20]));
+        let address = address.encode(&NETWORK);
+        let item = PaymentItem {
+            address: address.clone(),
+            amount: 100_000,
+            memo: Some(memo("hello")),
+        };
+        assert!(make_payment_uri(&NETWORK, &[UriPayment::from(item)]).is_err());
+        let uri = format!("zcash:{address}?amount=0.001&memo=aGVsbG8");
+        assert!(parse_payment_uri(&NETWORK, &uri).is_err());
+    }
+
+    /// Text with the characters that must be escaped in a URI
+    fn random_text(rng: &mut OsRng) -> String {
+        const CHARS: &[char] = &['a', 'Z', '0', ' ', '&', '=', '?', '%', '+', '/', '#', 'é'];
+        let len = rng.gen_range(1..20);
+        (0..len).map(|_| *CHARS.choose(rng).unwrap()).collect()
+    }
+
+    fn random_payment(rng: &mut OsRng) -> UriPayment {
+        let mut seed = [0u8; 32];
+        rng.fill_bytes(&mut seed);
+        let (_, address) = ExtendedSpendingKey::master(&seed).default_address();
+        let item = PaymentItem {
+            address: Address::Sapling(address).encode(&NETWORK),
+            amount: rng.gen_range(1..2_100_000_000_000_000),
+            memo: rng.gen_bool(0.5).then(|| memo(&random_text(rng))),
+        };
+        UriPayment {
+            item,
+            label: rng.gen_bool(0.5).then(|| random_text(rng)),
+            message: rng.gen_bool(0.5).then(|| random_text(rng)),
+        }
+    }
+
+    #[test]
+    fn payment_uri_round_trip() {
+        let mut rng = OsRng;
+        for _ in 0..50 {
+            let count = rng.gen_range(1..5);
+            let payments = (0..count)
+                .map(|_| random_payment(&mut rng))
+                .collect::<Vec<_>>();
+            let uri = make_payment_uri(&NETWORK, &payments).unwrap();
+            assert_eq!(
+                parse_payment_uri_details(&NETWORK, &uri).unwrap(),
+                payments,
+                "{uri}"
+            );
+        }
+    }
+
+    #[test]
+    fn label_and_message_are_encoded() {
+        let (_, address) = ExtendedSpendingKey::master(&[1u8; 32]).default_address();
+        let payment = UriPayment {
+            item: PaymentItem {
+                address: Address::Sapling(address).encode(&NETWORK),
+                amount: 150_000_000,
+                memo: None,
+            },
+            label: Some("Coffee & Co".to_string()),
+            message: Some("Order #12".to_string()),
+        };
+        let uri = make_payment_uri(&NETWORK, &[payment.clone()]).unwrap();
+        assert!(uri.contains("label=Coffee%20%26%20Co"), "{uri}");
+        assert!(uri.contains("message=Order%20%2312"), "{uri}");
+        assert_eq!(
+            parse_payment_uri_details(&NETWORK, &uri).unwrap(),
+            [payment]
+        );
+    }
+}
@@ -1,10 +1,13 @@
 mod decrypter;
 pub mod edge;
 pub mod hasher;
+pub mod import;
 pub mod legacy;
 mod orchard;
 mod sapling;
+pub mod snapshot;
 pub mod sync;
+pub mod verify;
 
 use crate::{lwd::rpc::CompactBlock, Hash};
 use serde::{Deserialize, Serialize};
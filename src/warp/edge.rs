@@ -1,6 +1,38 @@
-use super::{AuthPath, Edge, Hash, Hasher};
+use super::{AuthPath, Edge, Hash, Hasher, MERKLE_DEPTH};
+
+/// Depth of the subtrees whose roots are served by lightwalletd
+pub const SUBTREE_DEPTH: usize = 16;
 
 impl Edge {
+    /// Frontier of a tree made of complete subtrees of depth `SUBTREE_DEPTH`,
+    /// i.e. whose size is a multiple of 2^16.
+    /// The levels below the subtrees are empty
+    pub fn from_subtree_roots<H: Hasher>(roots: &[Hash], h: &H) -> Self {
+        let mut edge = Edge::default();
+        let mut layer = roots.to_vec();
+        for depth in SUBTREE_DEPTH..MERKLE_DEPTH as usize {
+            let len = layer.len();
+            if len % 2 == 1 {
+                edge.0[depth] = Some(layer[len - 1]);
+            }
+            layer = h.parallel_combine(depth as u8, &layer, len / 2);
+        }
+        edge
+    }
+
+    /// Frontier of a tree whose size is not a multiple of 2^16: the
+    /// levels of the complete subtrees come from their roots and the
+    /// levels below, those of the incomplete subtree, from `frontier`
+    pub fn from_subtree_roots_and_frontier<H: Hasher>(
+        roots: &[Hash],
+        frontier: &Edge,
+        h: &H,
+    ) -> Self {
+        let mut edge = Edge::from_subtree_roots(roots, h);
+        edge.0[..SUBTREE_DEPTH].copy_from_slice(&frontier.0[..SUBTREE_DEPTH]);
+        edge
+    }
+
     pub fn root<H: Hasher>(&self, h: &H) -> Hash {
         let mut empty = h.empty();
         let mut hash = h.empty();
@@ -37,3 +69,94 @@ impl Edge {
         path
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SUBTREE_DEPTH;
+    use crate::warp::{Edge, Hash, Hasher, MERKLE_DEPTH};
+
+    #[derive(Default, Debug)]
+    struct TestHasher;
+
+    impl Hasher for TestHasher {
+        fn empty(&self) -> Hash {
+            [0u8; 32]
+        }
+
+        fn combine(&self, depth: u8, l: &Hash, r: &Hash) -> Hash {
+            let hash = blake2b_simd::Params::new()
+                .hash_length(32)
+                .to_state()
+                .update(&[depth])
+                .update(l)
+                .update(r)
+                .finalize();
+            hash.as_bytes().try_into().unwrap()
+        }
+
+        fn parallel_combine(&self, depth: u8, layer: &[Hash], pairs: usize) -> Vec<Hash> {
+            (0..pairs)
+                .map(|i| self.combine(depth, &layer[2 * i], &layer[2 * i + 1]))
+                .collect()
+        }
+
+        fn parallel_combine_opt(
+            &self,
+            depth: u8,
+            layer: &[Option<Hash>],
+            pairs: usize,
+        ) -> Vec<Option<Hash>> {
+            (0..pairs)
+                .map(|i| match (&layer[2 * i], &layer[2 * i + 1]) {
+                    (Some(l), Some(r)) => Some(self.combine(depth, l, r)),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    /// Frontier and subtree roots of a tree of `size` leaves, computed
+    /// from all its nodes
+    fn tree(size: usize, h: &TestHasher) -> (Edge, Vec<Hash>) {
+        let mut layer: Vec<Hash> = (0..size as u64)
+            .map(|i| {
+                let mut leaf = [0u8; 32];
+                leaf[..8].copy_from_slice(&i.to_le_bytes());
+                leaf
+            })
+            .collect();
+        let mut edge = Edge::default();
+        let mut roots = vec![];
+        for depth in 0..MERKLE_DEPTH as usize {
+            if depth == SUBTREE_DEPTH {
+                roots = layer.clone();
+            }
+            if layer.len() % 2 == 1 {
+                edge.0[depth] = layer.last().cloned();
+            }
+            layer = h.parallel_combine(depth as u8, &layer, layer.len() / 2);
+        }
+        (edge, roots)
+    }
+
+    #[test]
+    fn aligned_tree() {
+        let h = TestHasher;
+        let (frontier, roots) = tree(2 << SUBTREE_DEPTH, &h);
+        assert_eq!(roots.len(), 2);
+        let edge = Edge::from_subtree_roots(&roots, &h);
+        assert_eq!(edge.0, frontier.0);
+    }
+
+    #[test]
+    fn unaligned_tree() {
+        let h = TestHasher;
+        let (frontier, roots) = tree((3 << SUBTREE_DEPTH) + 5, &h);
+        assert_eq!(roots.len(), 3);
+        let edge = Edge::from_subtree_roots(&roots, &h);
+        assert_ne!(edge.root(&h), frontier.root(&h));
+        let edge = Edge::from_subtree_roots_and_frontier(&roots, &frontier, &h);
+        assert_eq!(edge.0, frontier.0);
+        assert_eq!(edge.root(&h), frontier.root(&h));
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension as _};
+use sapling_crypto::PaymentAddress;
+use serde::{Deserialize, Serialize};
+use zcash_keys::address::Address as RecipientAddress;
+use zcash_primitives::consensus::Network;
+
+use crate::{
+    db::{
+        account::get_account_info,
+        notes::{get_block_header, get_sync_height, store_block, store_received_note},
+    },
+    types::AccountInfo,
+    Hash,
+};
+
+use super::{
+    hasher::{OrchardHasher, SaplingHasher},
+    sync::{ReceivedNote, ReceivedTx},
+    verify::{recompute_orchard, recompute_sapling},
+    BlockHeader, Edge, Witness,
+};
+
+/// Unspent note exported by another wallet, with its witness
+/// at the height of the import checkpoint
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ImportedNote {
+    pub position: u32,
+    pub height: u32,
+    #[serde(with = "hex")]
+    pub txid: Hash,
+    pub timestamp: u32,
+    pub vout: u32,
+    #[serde(with = "serde_bytes")]
+    pub address: [u8; 43],
+    pub value: u64,
+    pub rcm: Hash,
+    pub nf: Hash,
+    /// Only for orchard notes
+    pub rho: Option<Hash>,
+    pub witness: Witness,
+}
+
+/// Block and commitment trees at which the imported witnesses
+/// are taken, usually from the tree state of the server
+#[derive(Clone, Debug)]
+pub struct ImportCheckpoint {
+    pub block: BlockHeader,
+    pub sapling: Edge,
+    pub orchard: Edge,
+}
+
+/// Store notes of another wallet instead of scanning from the birth
+/// height. Every note must be received by the account, reproduce the
+/// commitment of its witness and its nullifier with the account keys,
+/// have a position that the wallet does not have yet, and every witness
+/// must lead to the root of the checkpoint tree.
+/// Nothing is imported if one of them does not.
+/// The checkpoint becomes the sync height, the wallet must not
+/// be synced or be synced exactly at the checkpoint
+pub fn import_notes(
+    network: &Network,
+    connection: &mut Connection,
+    account: u32,
+    checkpoint: &ImportCheckpoint,
+    notes: Vec<ImportedNote>,
+) -> Result<()> {
+    let height = checkpoint.block.height;
+    let sync_height = get_sync_height(connection)?;
+    match sync_height {
+        Some(h) if h != height => {
+            anyhow::bail!("The wallet is synced at {h}, import notes at that height")
+        }
+        Some(_) => {
+            let bh = get_block_header(connection, height)?;
+            if bh.hash != checkpoint.block.hash {
+                anyhow::bail!("Checkpoint {height} is not on the synced chain");
+            }
+        }
+        None => {}
+    }
+
+    let ai = get_account_info(network, connection, account)?;
+    let sh = SaplingHasher::default();
+    let oh = OrchardHasher::default();
+    let s_path = checkpoint.sapling.to_auth_path(&sh);
+    let s_root = checkpoint.sapling.root(&sh);
+    let o_path = checkpoint.orchard.to_auth_path(&oh);
+    let o_root = checkpoint.orchard.root(&oh);
+
+    let mut received = vec![];
+    let mut positions = HashSet::new();
+    for n in notes.iter() {
+        let orchard = n.rho.is_some();
+        if !positions.insert((n.position, orchard)) || has_note_at(connection, n.position, orchard)?
+        {
+            anyhow::bail!("The wallet already has a note at position {}", n.position);
+        }
+    }
+    for n in notes {
+        if n.height > height {
+            anyhow::bail!("Note at position {} is above the checkpoint", n.position);
+        }
+        let orchard = n.rho.is_some();
+        if !owns_note_address(&ai, &n.address, orchard) {
+            anyhow::bail!(
+                "Note at position {} is not received by the account",
+                n.position
+            );
+        }
+        let recomputed = if orchard {
+            recompute_orchard(
+                &ai.orchard.as_ref().map(|oi| &oi.vk),
+                &n.address,
+                n.value,
+                &n.rcm,
+                n.rho.as_ref().map(|r| &r[..]),
+            )
+        } else {
+            recompute_sapling(
+                Some(&ai.sapling.vk.fvk.vk),
+                &n.address,
+                n.value,
+                &n.rcm,
+                n.position,
+            )
+        };
+        let (cmx, nf) = recomputed.ok_or(anyhow::anyhow!(
+            "Note at position {} has invalid fields",
+            n.position
+        ))?;
+        if cmx != n.witness.value {
+            anyhow::bail!("Note at position {} does not match its witness", n.position);
+        }
+        if nf != Some(n.nf) {
+            anyhow::bail!(
+                "Note at position {} does not match its nullifier",
+                n.position
+            );
+        }
+        let root = if orchard {
+            n.witness.root(&o_path, &oh) == o_root
+        } else {
+            n.witness.root(&s_path, &sh) == s_root
+        };
+        if n.witness.position != n.position || !root {
+            anyhow::bail!("Invalid witness for note at position {}", n.position);
+        }
+        received.push(ReceivedNote {
+            is_new: true,
+            id: 0,
+            account,
+            position: n.position,
+            height: n.height,
+            address: n.address,
+            value: n.value,
+            rcm: n.rcm,
+            nf: n.nf,
+            rho: n.rho,
+            vout: n.vout,
+            tx: ReceivedTx {
+                id: 0,
+                account,
+                height: n.height,
+                txid: n.txid,
+                timestamp: n.timestamp,
+                ivtx: 0,
+                value: n.value as i64,
+            },
+            spent: None,
+            witness: n.witness,
+        });
+    }
+
+    let db_tx = connection.transaction()?;
+    if sync_height.is_none() {
+        store_block(&db_tx, &checkpoint.block)?;
+    }
+    store_received_note(&db_tx, height, &received)?;
+    db_tx.commit()?;
+    Ok(())
+}
+
+/// Whether a note at this position is already stored
+fn has_note_at(connection: &Connection, position: u32, orchard: bool) -> Result<bool> {
+    let id = connection
+        .query_row(
+            "SELECT id_note FROM notes WHERE position = ?1 AND orchard = ?2",
+            params![position, orchard],
+            |r| r.get::<_, u32>(0),
+        )
+        .optional()?;
+    Ok(id.is_some())
+}
+
+/// Whether the raw receiver of a note decrypts with the viewing key
+/// of the account
+fn owns_note_address(ai: &AccountInfo, address: &[u8; 43], orchard: bool) -> bool {
+    if orchard {
+        let address =
+            Option::<orchard::Address>::from(orchard::Address::from_raw_address_bytes(address));
+        match (ai.orchard.as_ref(), address) {
+            (Some(oi), Some(address)) => oi.vk.scope_for_address(&address).is_some(),
+            _ => false,
+        }
+    } else {
+        PaymentAddress::from_bytes(address)
+            .map(|a| ai.owns_address(&RecipientAddress::Sapling(a)))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::{params, Connection};
+    use zcash_primitives::consensus::Network;
+
+    use super::{import_notes, ImportCheckpoint, ImportedNote};
+    use crate::{
+        db::{
+            account::{get_account_info, get_balance},
+            notes::get_sync_height,
+            testing::{test_account, test_db, TEST_BIRTH},
+        },
+        warp::{verify::recompute_sapling, BlockHeader, Edge, Witness},
+        Hash,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    fn checkpoint() -> ImportCheckpoint {
+        ImportCheckpoint {
+            block: BlockHeader {
+                height: TEST_BIRTH + 100,
+                ..BlockHeader::default()
+            },
+            sapling: Edge::default(),
+            orchard: Edge::default(),
+        }
+    }
+
+    /// Sapling note of the account at `position`, with a matching
+    /// commitment and nullifier
+    fn sapling_note(connection: &Connection, account: u32, position: u32) -> ImportedNote {
+        let ai = get_account_info(&NETWORK, connection, account).unwrap();
+        let address = ai.sapling.addr.to_bytes();
+        let rcm = jubjub::Fr::from(1234u64).to_bytes();
+        let (cmx, nf) = recompute_sapling(
+            Some(&ai.sapling.vk.fvk.vk),
+            &address,
+            100_000,
+            &rcm,
+            position,
+        )
+        .unwrap();
+        ImportedNote {
+            position,
+            height: TEST_BIRTH + 10,
+            txid: [1u8; 32],
+            timestamp: 0,
+            vout: 0,
+            address,
+            value: 100_000,
+            rcm,
+            nf: nf.unwrap(),
+            rho: None,
+            witness: Witness {
+                value: cmx,
+                position,
+                ..Witness::default()
+            },
+        }
+    }
+
+    fn import_error(connection: &mut Connection, account: u32, note: ImportedNote) -> String {
+        import_notes(&NETWORK, connection, account, &checkpoint(), vec![note])
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn wrong_nullifier_is_rejected() {
+        let mut connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let mut note = sapling_note(&connection, account, 5);
+        note.nf = [0u8; 32];
+        let e = import_error(&mut connection, account, note);
+        assert!(e.contains("nullifier"), "{e}");
+    }
+
+    #[test]
+    fn note_of_another_wallet_is_rejected() {
+        use sapling_crypto::zip32::ExtendedSpendingKey;
+
+        let mut connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let mut note = sapling_note(&connection, account, 5);
+        let (_, other) = ExtendedSpendingKey::master(&[1u8; 32]).default_address();
+        note.address = other.to_bytes();
+        let (cmx, _) = recompute_sapling(None, &note.address, note.value, &note.rcm, 5).unwrap();
+        note.witness.value = cmx;
+        let e = import_error(&mut connection, account, note);
+        assert!(e.contains("not received by the account"), "{e}");
+    }
+
+    #[test]
+    fn existing_position_is_rejected() {
+        let mut connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        connection
+            .execute(
+                "INSERT INTO notes(account, position, height, output_index, address,
+                value, rcm, nf, orchard) VALUES (?1, 5, ?2, 0, x'', 0, x'', x'00', FALSE)",
+                params![account, TEST_BIRTH],
+            )
+            .unwrap();
+        let note = sapling_note(&connection, account, 5);
+        let e = import_error(&mut connection, account, note);
+        assert!(e.contains("already has a note"), "{e}");
+    }
+
+    #[test]
+    fn duplicate_positions_are_rejected() {
+        let mut connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let note = sapling_note(&connection, account, 5);
+        let e = import_notes(
+            &NETWORK,
+            &mut connection,
+            account,
+            &checkpoint(),
+            vec![note.clone(), note],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(e.contains("already has a note"), "{e}");
+    }
+
+    /// Checkpoint of a tree that has only this leaf
+    fn checkpoint_of(cmx: Hash) -> ImportCheckpoint {
+        let mut sapling = Edge::default();
+        sapling.0[0] = Some(cmx);
+        ImportCheckpoint {
+            sapling,
+            ..checkpoint()
+        }
+    }
+
+    #[test]
+    fn valid_note_is_imported() {
+        let mut connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let note = sapling_note(&connection, account, 0);
+        let checkpoint = checkpoint_of(note.witness.value);
+        import_notes(&NETWORK, &mut connection, account, &checkpoint, vec![note]).unwrap();
+
+        let height = checkpoint.block.height;
+        assert_eq!(get_sync_height(&connection).unwrap(), Some(height));
+        let balance = get_balance(&connection, account, height).unwrap();
+        assert_eq!(balance.sapling, 100_000);
+        assert_eq!(balance.sapling_notes, 1);
+        // the note is listed in the history
+        let tx = connection
+            .query_row(
+                "SELECT height, value FROM txs WHERE account = ?1 AND txid = ?2",
+                params![account, [1u8; 32]],
+                |r| Ok((r.get::<_, u32>(0)?, r.get::<_, i64>(1)?)),
+            )
+            .unwrap();
+        assert_eq!(tx, (TEST_BIRTH + 10, 100_000));
+    }
+
+    #[test]
+    fn tampered_witness_is_rejected() {
+        let mut connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let mut note = sapling_note(&connection, account, 0);
+        let checkpoint = checkpoint_of(note.witness.value);
+        note.witness.ommers.0[3] = Some([7u8; 32]);
+        let e = import_notes(&NETWORK, &mut connection, account, &checkpoint, vec![note])
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("Invalid witness"), "{e}");
+        assert_eq!(get_sync_height(&connection).unwrap(), None);
+    }
+}
@@ -4,7 +4,7 @@ use zcash_encoding::{Optional, Vector};
 
 use super::{Edge, Hasher, MERKLE_DEPTH};
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct CommitmentTreeFrontier {
     pub left: Option<Hash>,
     pub right: Option<Hash>,
@@ -0,0 +1,275 @@
+use anyhow::Result;
+use rusqlite::{types::ValueRef, Connection, ToSql};
+use serde::{Deserialize, Serialize};
+
+use crate::db::table_columns;
+
+/// Tables written by the sync. Witnesses carry the tree positions
+/// and the edges of the notes at every checkpoint. Transaction
+/// details and messages refer to the ids of `txs`
+const SYNC_TABLES: &[&str] = &[
+    "blcks",
+    "txs",
+    "notes",
+    "witnesses",
+    "utxos",
+    "txdetails",
+    "msgs",
+];
+
+const SNAPSHOT_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Cell {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl ToSql for Cell {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        let v = match self {
+            Cell::Null => ValueRef::Null,
+            Cell::Integer(v) => ValueRef::Integer(*v),
+            Cell::Real(v) => ValueRef::Real(*v),
+            Cell::Text(v) => ValueRef::Text(v.as_bytes()),
+            Cell::Blob(v) => ValueRef::Blob(v),
+        };
+        Ok(rusqlite::types::ToSqlOutput::Borrowed(v))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TableSnapshot {
+    name: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Snapshot {
+    version: u8,
+    tables: Vec<TableSnapshot>,
+}
+
+/// Serialize the sync state: checkpoints, transactions with their
+/// details and messages, notes, witnesses and utxos. Accounts are not included, the state
+/// must be imported in a wallet with the same accounts
+pub fn export_state(connection: &Connection) -> Result<Vec<u8>> {
+    let mut tables = vec![];
+    for &name in SYNC_TABLES {
+        let mut s = connection.prepare(&format!("SELECT * FROM {name}"))?;
+        let columns = s
+            .column_names()
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        let n = columns.len();
+        let rows = s.query_map([], |r| {
+            let mut row = vec![];
+            for i in 0..n {
+                let cell = match r.get_ref(i)? {
+                    ValueRef::Null => Cell::Null,
+                    ValueRef::Integer(v) => Cell::Integer(v),
+                    ValueRef::Real(v) => Cell::Real(v),
+                    ValueRef::Text(v) => Cell::Text(String::from_utf8_lossy(v).to_string()),
+                    ValueRef::Blob(v) => Cell::Blob(v.to_vec()),
+                };
+                row.push(cell);
+            }
+            Ok(row)
+        })?;
+        let rows = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        tables.push(TableSnapshot {
+            name: name.to_string(),
+            columns,
+            rows,
+        });
+    }
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        tables,
+    };
+    let data = bincode::serialize(&snapshot)?;
+    Ok(data)
+}
+
+/// Replace the sync state with a snapshot made by `export_state`.
+/// Every sync table is cleared, including those missing from the
+/// snapshot, so that no row refers to a transaction that is gone.
+/// Tables and columns must be those of the wallet
+pub fn import_state(connection: &mut Connection, data: &[u8]) -> Result<()> {
+    let snapshot: Snapshot = bincode::deserialize(data)?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        anyhow::bail!("Unsupported snapshot version {}", snapshot.version);
+    }
+    for table in snapshot.tables.iter() {
+        if !SYNC_TABLES.contains(&table.name.as_str()) {
+            anyhow::bail!("Unexpected table {} in snapshot", table.name);
+        }
+        let columns = table_columns(connection, &table.name)?;
+        for (i, c) in table.columns.iter().enumerate() {
+            if !columns.contains(c) || table.columns[..i].contains(c) {
+                anyhow::bail!("Unexpected column {c} of table {} in snapshot", table.name);
+            }
+        }
+    }
+    let db_tx = connection.transaction()?;
+    for &name in SYNC_TABLES {
+        db_tx.execute(&format!("DELETE FROM {name}"), [])?;
+    }
+    for table in snapshot.tables.iter() {
+        let placeholders = (1..=table.columns.len())
+            .map(|i| format!("?{i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut s = db_tx.prepare(&format!(
+            "INSERT INTO {}({}) VALUES ({})",
+            table.name,
+            table.columns.join(", "),
+            placeholders
+        ))?;
+        for row in table.rows.iter() {
+            s.execute(rusqlite::params_from_iter(row.iter()))?;
+        }
+    }
+    db_tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use zcash_primitives::consensus::Network;
+
+    use super::{export_state, import_state, Snapshot, TableSnapshot, SNAPSHOT_VERSION};
+    use crate::{
+        db::{
+            account::get_balance,
+            notes::list_received_notes,
+            testing::{add_note, add_utxo, test_account, test_db, TEST_BIRTH},
+        },
+        types::CheckpointHeight,
+        warp::{
+            hasher::{OrchardHasher, SaplingHasher},
+            Edge, Hasher,
+        },
+        Hash,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+    const CHECKPOINT: u32 = TEST_BIRTH + 10;
+
+    fn txdetails(connection: &Connection) -> Vec<(u32, Vec<u8>)> {
+        let mut s = connection
+            .prepare("SELECT id_tx, txid FROM txdetails ORDER BY id_tx")
+            .unwrap();
+        let rows = s.query_map([], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+        rows.collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    fn balance(connection: &Connection, account: u32) -> (u64, u64, u64) {
+        let b = get_balance(connection, account, CHECKPOINT).unwrap();
+        (b.transparent, b.sapling, b.orchard)
+    }
+
+    /// Positions and roots of the witnesses at the checkpoint, against
+    /// the tree state of the checkpoint
+    fn witness_roots<H: Hasher>(
+        connection: &Connection,
+        orchard: bool,
+        tree: &Edge,
+        h: &H,
+    ) -> Vec<(u32, Hash)> {
+        let path = tree.to_auth_path(h);
+        let notes = list_received_notes(connection, CheckpointHeight(CHECKPOINT), orchard).unwrap();
+        let mut roots = notes
+            .iter()
+            .map(|n| (n.position, n.witness.root(&path, h)))
+            .collect::<Vec<_>>();
+        roots.sort();
+        roots
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let source = test_db();
+        let account = test_account(&NETWORK, &source);
+        source
+            .execute_batch(
+                "INSERT INTO blcks(height, hash, prev_hash, timestamp)
+                VALUES (2000000, x'01', x'00', 1);
+                INSERT INTO blcks(height, hash, prev_hash, timestamp)
+                VALUES (2000010, x'02', x'01', 2);
+                INSERT INTO txs(id_tx, account, txid, height, timestamp, value)
+                VALUES (1, 1, x'aa', 2000000, 1, 1000);
+                INSERT INTO txdetails(id_tx, txid, data) VALUES (1, x'aa', x'');",
+            )
+            .unwrap();
+        add_utxo(&source, account, CHECKPOINT, 1_000);
+        add_note(&source, account, CHECKPOINT, 20_000, false);
+        add_note(&source, account, CHECKPOINT, 30_000, false);
+        add_note(&source, account, CHECKPOINT, 40_000, true);
+        let mut tree = Edge::default();
+        tree.0[0] = Some([3u8; 32]);
+        tree.0[1] = Some([4u8; 32]);
+        let (sh, oh) = (SaplingHasher::default(), OrchardHasher::default());
+        let sapling_roots = witness_roots(&source, false, &tree, &sh);
+        let orchard_roots = witness_roots(&source, true, &tree, &oh);
+        assert_eq!(sapling_roots.len(), 2);
+        assert_eq!(orchard_roots.len(), 1);
+        let data = export_state(&source).unwrap();
+
+        let mut target = test_db();
+        test_account(&NETWORK, &target);
+        target
+            .execute(
+                "INSERT INTO txdetails(id_tx, txid, data) VALUES (5, x'bb', x'')",
+                [],
+            )
+            .unwrap();
+        import_state(&mut target, &data).unwrap();
+        assert_eq!(txdetails(&target), txdetails(&source));
+        let height: u32 = target
+            .query_row("SELECT MAX(height) FROM blcks", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(height, CHECKPOINT);
+        assert_eq!(balance(&target, account), (1_000, 50_000, 40_000));
+        assert_eq!(balance(&target, account), balance(&source, account));
+        assert_eq!(witness_roots(&target, false, &tree, &sh), sapling_roots);
+        assert_eq!(witness_roots(&target, true, &tree, &oh), orchard_roots);
+    }
+
+    #[test]
+    fn unknown_column_is_rejected() {
+        let mut target = test_db();
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            tables: vec![TableSnapshot {
+                name: "blcks".to_string(),
+                columns: vec!["height) SELECT 1; --".to_string()],
+                rows: vec![],
+            }],
+        };
+        let data = bincode::serialize(&snapshot).unwrap();
+        assert!(import_state(&mut target, &data).is_err());
+    }
+
+    #[test]
+    fn unknown_table_is_rejected() {
+        let mut target = test_db();
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            tables: vec![TableSnapshot {
+                name: "accounts".to_string(),
+                columns: vec![],
+                rows: vec![],
+            }],
+        };
+        let data = bincode::serialize(&snapshot).unwrap();
+        assert!(import_state(&mut target, &data).is_err());
+    }
+}
@@ -1,17 +1,22 @@
 use crate::{
+    account::address::rotate_received_addresses,
     cli::CONFIG, coin::{connect_lwd, CoinDef}, db::{
         notes::{
             get_block_header, mark_shielded_spent, mark_transparent_spent, rewind_checkpoint,
             store_block, store_received_note, store_utxo, update_tx_timestamp,
         },
         tx::add_tx_value,
-    }, lwd::{get_compact_block_range, get_transparent, get_tree_state}, txdetails::CompressedMemo, types::CheckpointHeight, warp::{
+    }, lwd::{fetch_blocks, rpc::CompactBlock, get_subtree_roots, get_transparent, get_tree_state, DataUsage}, txdetails::CompressedMemo, types::CheckpointHeight, warp::{
+        edge::SUBTREE_DEPTH,
         hasher::{OrchardHasher, SaplingHasher},
-        BlockHeader,
-    }, Hash
+        BlockHeader, Edge, Hasher,
+    }, utils::to_txid_str, Client, Hash
 };
 use anyhow::Result;
+use rusqlite::DropBehavior;
 use header::BlockHeaderStore;
+use prost::Message as _;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use thiserror::Error;
@@ -51,6 +56,10 @@ pub struct ExtendedReceivedTx {
     pub rtx: ReceivedTx,
     pub address: Option<String>,
     pub memo: Option<String>,
+    pub self_transfer: bool,
+    /// Value the account received in the transaction, notes and UTXOs.
+    /// Change and self-transfers are included
+    pub value_in: u64,
 }
 
 #[derive(Serialize, Debug)]
@@ -101,38 +110,258 @@ pub struct ReceivedNote {
     pub witness: Witness,
 }
 
+/// Blocks and notes processed by a synchronization and the
+/// time spent in each of its phases, accumulated over the batches
+#[derive(Clone, Serialize, Default, Debug)]
+pub struct SyncStats {
+    pub blocks: u32,
+    pub notes: u32,
+    pub decrypt_ms: u64,
+    pub witness_ms: u64,
+    pub spend_ms: u64,
+    /// Note witnesses checked against the tree root,
+    /// only in verify-witness mode
+    pub witnesses_verified: u32,
+}
+
+impl SyncStats {
+    /// Combine the stats of two pools that processed the same blocks
+    pub fn merge(&self, other: &SyncStats) -> SyncStats {
+        SyncStats {
+            blocks: self.blocks.max(other.blocks),
+            notes: self.notes + other.notes,
+            decrypt_ms: self.decrypt_ms + other.decrypt_ms,
+            witness_ms: self.witness_ms + other.witness_ms,
+            spend_ms: self.spend_ms + other.spend_ms,
+            witnesses_verified: self.witnesses_verified + other.witnesses_verified,
+        }
+    }
+
+    pub fn add(&mut self, other: &SyncStats) {
+        self.blocks += other.blocks;
+        self.notes += other.notes;
+        self.decrypt_ms += other.decrypt_ms;
+        self.witness_ms += other.witness_ms;
+        self.spend_ms += other.spend_ms;
+        self.witnesses_verified += other.witnesses_verified;
+    }
+}
+
 pub use orchard::Synchronizer as OrchardSync;
 pub use sapling::Synchronizer as SaplingSync;
 
-pub async fn warp_sync(coin: &CoinDef, start: CheckpointHeight, end: u32) -> Result<(), SyncError> {
+/// Tree state built from the roots of the complete subtrees served
+/// by lightwalletd, with the incomplete subtree taken from the frontier
+/// of the tree state. The frontier is used as is when the tree has no
+/// complete subtree, if the roots do not match it or if the server
+/// does not support the RPC
+async fn initial_edge<H: Hasher>(
+    client: &mut Client,
+    usage: &DataUsage,
+    pool: u8,
+    size: u32,
+    frontier_edge: Edge,
+    h: &H,
+) -> Edge {
+    let n = size >> SUBTREE_DEPTH;
+    if n == 0 {
+        return frontier_edge;
+    }
+    match get_subtree_roots(client, usage, pool, 0, n).await {
+        Ok(roots) if roots.len() == n as usize => {
+            let edge = Edge::from_subtree_roots_and_frontier(&roots, &frontier_edge, h);
+            if edge.root(h) != frontier_edge.root(h) {
+                tracing::warn!("Subtree roots do not match the tree state");
+                return frontier_edge;
+            }
+            edge
+        }
+        Ok(_) => frontier_edge,
+        Err(e) => {
+            tracing::info!("Subtree roots unavailable: {e}");
+            frontier_edge
+        }
+    }
+}
+
+/// Note, UTXO or spend a synchronization found
+#[derive(Clone, Serialize, Debug)]
+pub struct SyncEvent {
+    pub account: u32,
+    pub height: u32,
+    pub txid: String,
+    /// 0: transparent, 1: sapling, 2: orchard
+    pub pool: u8,
+    /// Negative for spends
+    pub value: i64,
+}
+
+/// What a synchronization recorded, see `simulate_sync`
+#[derive(Clone, Serialize, Default, Debug)]
+pub struct SyncReport {
+    pub received: Vec<SyncEvent>,
+    pub spent: Vec<SyncEvent>,
+    pub stats: SyncStats,
+}
+
+/// Position in the commitment tree of the first output of every
+/// transaction of `blocks`, by block and then by transaction, counted
+/// from `position`. A bridge stands for the outputs it replaces and
+/// counts for its length. `pool` is 1 for sapling and 2 for orchard
+pub fn compute_positions(mut position: u32, blocks: &[CompactBlock], pool: u8) -> Vec<Vec<u32>> {
+    blocks
+        .iter()
+        .map(|cb| {
+            cb.vtx
+                .iter()
+                .map(|tx| {
+                    let p = position;
+                    let (outputs, bridge) = if pool == 1 {
+                        (tx.outputs.len(), &tx.sapling_bridge)
+                    } else {
+                        (tx.actions.len(), &tx.orchard_bridge)
+                    };
+                    position += outputs as u32;
+                    position += bridge.as_ref().map(|b| b.len as u32).unwrap_or_default();
+                    p
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Index in `blocks` of the block at `height`, where the batch starts
+/// after `start`. The server decides what the batch contains, a height
+/// outside of it or a gap in the heights is an error, not a panic
+pub(crate) fn block_index(blocks: &[CompactBlock], start: u32, height: u32) -> Result<usize> {
+    height
+        .checked_sub(start + 1)
+        .map(|ib| ib as usize)
+        .filter(|&ib| blocks.get(ib).map(|cb| cb.height as u32) == Some(height))
+        .ok_or(anyhow::anyhow!(
+            "Note at height {height} is outside of the blocks {}-{}",
+            start + 1,
+            start + blocks.len() as u32
+        ))
+}
+
+/// Check that the witness of every note leads to the root of
+/// the tree state, and return the number of notes checked.
+/// The witnesses are independent of each other
+pub(crate) fn verify_witnesses<H: Hasher + Sync>(
+    notes: &[ReceivedNote],
+    tree_state: &Edge,
+    h: &H,
+) -> Result<u32> {
+    let auth_path = tree_state.to_auth_path(h);
+    let tree_root = tree_state.root(h);
+    let invalid = notes.par_iter().find_first(|note| {
+        let root = note.witness.root(&auth_path, h);
+        tracing::debug!("{} {}", note.position, hex::encode(&root));
+        root != tree_root
+    });
+    if let Some(note) = invalid {
+        anyhow::bail!("Invalid witness for note at position {}", note.position);
+    }
+    Ok(notes.len() as u32)
+}
+
+pub async fn warp_sync(
+    coin: &CoinDef,
+    start: CheckpointHeight,
+    end: u32,
+) -> Result<SyncStats, SyncError> {
+    let report = sync_range(coin, start, end, None, false).await?;
+    Ok(report.stats)
+}
+
+/// `warp_sync` that only looks for the funds received by the given
+/// accounts. The sync height moves forward for every account: the
+/// funds that the other accounts received from `start` to `end` are
+/// missed until they are rescanned. Spends of the notes already
+/// known are still detected
+pub async fn warp_sync_accounts(
+    coin: &CoinDef,
+    start: CheckpointHeight,
+    end: u32,
+    account_ids: &[u32],
+) -> Result<SyncStats, SyncError> {
+    let report = sync_range(coin, start, end, Some(account_ids), false).await?;
+    Ok(report.stats)
+}
+
+/// Run the synchronizers from `start` to `end` like `warp_sync`
+/// but roll back the database transaction at the end. The report
+/// lists the notes and spends that would have been recorded
+pub async fn simulate_sync(
+    coin: &CoinDef,
+    start: CheckpointHeight,
+    end: u32,
+) -> Result<SyncReport, SyncError> {
+    sync_range(coin, start, end, None, true).await
+}
+
+async fn sync_range(
+    coin: &CoinDef,
+    start: CheckpointHeight,
+    end: u32,
+    account_ids: Option<&[u32]>,
+    simulate: bool,
+) -> Result<SyncReport, SyncError> {
     tracing::info!("{:?}-{}", start, end);
     let mut connection = coin.connection()?;
     let mut client = coin.connect_lwd().await?;
-    let (sapling_state, orchard_state) = get_tree_state(&mut client, start.into()).await?;
+    let (sapling_state, orchard_state) =
+        get_tree_state(&mut client, &coin.data_usage, start.into()).await?;
 
     let sap_hasher = SaplingHasher::default();
-    let mut sap_dec = SaplingSync::new(
-        &coin.network,
-        &connection,
-        start,
-        sapling_state.size() as u32,
+    let sap_size = sapling_state.size() as u32;
+    let sap_edge = initial_edge(
+        &mut client,
+        &coin.data_usage,
+        1,
+        sap_size,
         sapling_state.to_edge(&sap_hasher),
-    )?;
+        &sap_hasher,
+    )
+    .await;
+    let mut sap_dec = SaplingSync::new(&coin.network, &connection, start, sap_size, sap_edge)?;
+    sap_dec.min_note_value = coin.min_note_value;
 
     let orch_hasher = OrchardHasher::default();
-    let mut orch_dec = OrchardSync::new(
-        &coin.network,
-        &connection,
-        start,
-        orchard_state.size() as u32,
+    let orch_size = orchard_state.size() as u32;
+    let orch_edge = initial_edge(
+        &mut client,
+        &coin.data_usage,
+        2,
+        orch_size,
         orchard_state.to_edge(&orch_hasher),
-    )?;
+        &orch_hasher,
+    )
+    .await;
+    let mut orch_dec = OrchardSync::new(&coin.network, &connection, start, orch_size, orch_edge)?;
+    orch_dec.min_note_value = coin.min_note_value;
 
     let mut trp_dec = TransparentSync::new(&coin.network, &connection, start)?;
 
+    if let Some(account_ids) = account_ids {
+        sap_dec.account_infos.retain(|ai| account_ids.contains(&ai.account));
+        orch_dec.account_infos.retain(|ai| account_ids.contains(&ai.account));
+        trp_dec.addresses.retain(|(account, _)| account_ids.contains(account));
+    }
+
     let addresses = trp_dec.addresses.clone();
     for (account, taddr) in addresses.into_iter() {
-        let txs = get_transparent(&coin.network, &mut client, account, taddr, start.into(), end).await?;
+        let txs = get_transparent(
+            &coin.network,
+            &mut client,
+            &coin.data_usage,
+            account,
+            taddr,
+            start.into(),
+            end,
+        )
+        .await?;
         trp_dec.process_txs(&txs)?;
     }
     let heights = trp_dec
@@ -147,12 +376,14 @@ pub async fn warp_sync(coin: &CoinDef, start: CheckpointHeight, end: u32) -> Res
     let mut prev_hash = bh.hash;
 
     let block_url = if end < CONFIG.warp_end_height { &coin.warp } else { &coin.url };
-    let mut block_client = connect_lwd(block_url).await?;
-    let mut blocks = get_compact_block_range(&mut block_client, u32::from(start) + 1, end).await?;
+    let block_client = connect_lwd(block_url).await?;
+    let concurrency = CONFIG.block_fetch_concurrency.unwrap_or(1);
+    let mut blocks = fetch_blocks(&block_client, u32::from(start) + 1, end, concurrency).await?;
     let mut bs = vec![];
     let mut bh = BlockHeader::default();
     let mut c = 0;
-    while let Some(block) = blocks.message().await.map_err(anyhow::Error::new)? {
+    while let Some(block) = blocks.next().await? {
+        coin.data_usage.record(block.encoded_len());
         bh = BlockHeader {
             height: block.height as u32,
             hash: block.hash.clone().try_into().unwrap(),
@@ -160,7 +391,10 @@ pub async fn warp_sync(coin: &CoinDef, start: CheckpointHeight, end: u32) -> Res
             timestamp: block.time,
         };
         if prev_hash != bh.prev_hash {
-            rewind_checkpoint(&connection)?;
+            if !simulate {
+                rewind_checkpoint(&connection)?;
+            }
+            coin.tree_states.lock().clear();
             return Err(SyncError::Reorg(bh.height));
         }
         prev_hash = bh.hash;
@@ -191,7 +425,12 @@ pub async fn warp_sync(coin: &CoinDef, start: CheckpointHeight, end: u32) -> Res
     orch_dec.add(&bs)?;
 
     // Verification
-    let (s, o) = get_tree_state(&mut client, CheckpointHeight(bh.height as u32)).await?;
+    let (s, o) = get_tree_state(
+        &mut client,
+        &coin.data_usage,
+        CheckpointHeight(bh.height as u32),
+    )
+    .await?;
     let r = s.to_edge(&sap_dec.hasher).root(&sap_dec.hasher);
     let r2 = sap_dec.tree_state.root(&sap_dec.hasher);
     info!("s_root {}", hex::encode(&r));
@@ -201,8 +440,53 @@ pub async fn warp_sync(coin: &CoinDef, start: CheckpointHeight, end: u32) -> Res
     assert_eq!(r, r2);
     info!("o_root {}", hex::encode(&r));
 
+    let mut report = SyncReport::default();
+    for (pool, notes) in [(1u8, &sap_dec.notes), (2, &orch_dec.notes)] {
+        for n in notes.iter().filter(|n| n.is_new) {
+            report.received.push(SyncEvent {
+                account: n.account,
+                height: n.height,
+                txid: to_txid_str(&n.tx.txid),
+                pool,
+                value: n.value as i64,
+            });
+        }
+    }
+    for u in trp_dec.utxos.iter().filter(|u| u.is_new) {
+        report.received.push(SyncEvent {
+            account: u.account,
+            height: u.height,
+            txid: to_txid_str(&u.txid),
+            pool: 0,
+            value: u.value as i64,
+        });
+    }
+    for (pool, spends) in [(1u8, &sap_dec.spends), (2, &orch_dec.spends)] {
+        for s in spends.iter() {
+            report.spent.push(SyncEvent {
+                account: s.account,
+                height: s.height,
+                txid: to_txid_str(&s.txid),
+                pool,
+                value: s.value,
+            });
+        }
+    }
+    for s in trp_dec.tx_updates.iter().filter(|s| s.id_spent.is_some()) {
+        report.spent.push(SyncEvent {
+            account: s.account,
+            height: s.height,
+            txid: to_txid_str(&s.txid),
+            pool: 0,
+            value: s.value,
+        });
+    }
+
     if bh.height != 0 {
-        let db_tx = connection.transaction().map_err(anyhow::Error::new)?;
+        let mut db_tx = connection.transaction().map_err(anyhow::Error::new)?;
+        if simulate {
+            db_tx.set_drop_behavior(DropBehavior::Rollback);
+        }
 
         store_received_note(&db_tx, bh.height, &*sap_dec.notes)?;
         for s in sap_dec.spends.iter() {
@@ -229,8 +513,439 @@ pub async fn warp_sync(coin: &CoinDef, start: CheckpointHeight, end: u32) -> Res
         update_tx_timestamp(&db_tx, header_dec.heights.values())?;
 
         store_block(&db_tx, &bh)?;
-        db_tx.commit().map_err(anyhow::Error::new)?;
+        if !simulate {
+            db_tx.commit().map_err(anyhow::Error::new)?;
+            let received = sap_dec
+                .notes
+                .iter()
+                .chain(orch_dec.notes.iter())
+                .filter(|n| n.is_new)
+                .map(|n| (n.account, n.address))
+                .collect::<Vec<_>>();
+            rotate_received_addresses(&coin.network, &connection, &received)?;
+        }
     }
 
-    Ok(())
+    let stats = sap_dec.stats.merge(&orch_dec.stats);
+    info!("{:?}", stats);
+    report.stats = stats;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::OsRng, Rng as _};
+    use rayon::prelude::*;
+    use sapling_crypto::{
+        note_encryption::sapling_note_encryption, value::NoteValue, PaymentAddress, Rseed,
+    };
+    use zcash_note_encryption::COMPACT_NOTE_SIZE;
+    use zcash_primitives::consensus::Network;
+
+    use super::{
+        block_index, compute_positions, verify_witnesses, ReceivedNote, ReceivedTx, SaplingSync,
+        SyncStats,
+    };
+    use crate::{
+        db::testing::{test_account, test_db, TEST_BIRTH},
+        lwd::rpc::{Bridge, CompactBlock, CompactOrchardAction, CompactSaplingOutput, CompactTx},
+        types::CheckpointHeight,
+        warp::{hasher::SaplingHasher, Edge, Witness},
+        Hash,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    /// Transaction with `outputs` sapling outputs and `actions` orchard
+    /// actions, or bridges of these lengths when `bridged`
+    fn tx(outputs: usize, actions: usize, bridged: bool) -> CompactTx {
+        let bridge = |len: usize| {
+            (bridged && len > 0).then(|| Bridge {
+                len: len as u32,
+                ..Bridge::default()
+            })
+        };
+        CompactTx {
+            outputs: if bridged {
+                vec![]
+            } else {
+                vec![CompactSaplingOutput::default(); outputs]
+            },
+            actions: if bridged {
+                vec![]
+            } else {
+                vec![CompactOrchardAction::default(); actions]
+            },
+            sapling_bridge: bridge(outputs),
+            orchard_bridge: bridge(actions),
+            ..CompactTx::default()
+        }
+    }
+
+    fn block(height: u32, vtx: Vec<CompactTx>) -> CompactBlock {
+        CompactBlock {
+            height: height as u64,
+            vtx,
+            ..CompactBlock::default()
+        }
+    }
+
+    #[test]
+    fn positions_of_outputs() {
+        let blocks = vec![
+            block(101, vec![tx(2, 1, false), tx(0, 3, false)]),
+            block(102, vec![]),
+            block(103, vec![tx(1, 0, false)]),
+        ];
+        assert_eq!(
+            compute_positions(10, &blocks, 1),
+            vec![vec![10, 12], vec![], vec![12]]
+        );
+        assert_eq!(
+            compute_positions(5, &blocks, 2),
+            vec![vec![5, 6], vec![], vec![9]]
+        );
+    }
+
+    #[test]
+    fn bridges_across_blocks() {
+        // the bridges of the last transaction of a block
+        // move the first position of the next block
+        let blocks = vec![
+            block(101, vec![tx(1, 0, false), tx(1000, 40, true)]),
+            block(102, vec![tx(500, 0, true)]),
+            block(103, vec![tx(2, 2, false)]),
+        ];
+        assert_eq!(
+            compute_positions(0, &blocks, 1),
+            vec![vec![0, 1], vec![1001], vec![1501]]
+        );
+        assert_eq!(
+            compute_positions(0, &blocks, 2),
+            vec![vec![0, 0], vec![40], vec![40]]
+        );
+    }
+
+    #[test]
+    fn block_of_a_height() {
+        let blocks = vec![block(101, vec![]), block(102, vec![])];
+        assert_eq!(block_index(&blocks, 100, 101).unwrap(), 0);
+        assert_eq!(block_index(&blocks, 100, 102).unwrap(), 1);
+        assert!(block_index(&blocks, 100, 100).is_err());
+        assert!(block_index(&blocks, 100, 103).is_err());
+        // a gap in the heights of the batch
+        let blocks = vec![block(101, vec![]), block(103, vec![])];
+        assert!(block_index(&blocks, 100, 102).is_err());
+        assert!(block_index(&blocks, 100, 103).is_err());
+    }
+
+    /// Note at `position` whose witness starts from `value`
+    fn note(position: u32, value: Hash) -> ReceivedNote {
+        ReceivedNote {
+            is_new: true,
+            id: 0,
+            account: 1,
+            position,
+            height: 0,
+            address: [0u8; 43],
+            value: 0,
+            rcm: [0u8; 32],
+            nf: [0u8; 32],
+            rho: None,
+            vout: 0,
+            tx: ReceivedTx::default(),
+            spent: None,
+            witness: Witness {
+                value,
+                position,
+                ommers: Edge::default(),
+            },
+        }
+    }
+
+    /// Tree state with the single commitment `cmx`
+    fn tree_state(cmx: Hash) -> Edge {
+        let mut edge = Edge::default();
+        edge.0[0] = Some(cmx);
+        edge
+    }
+
+    #[test]
+    fn witnesses_lead_to_the_root() {
+        let h = SaplingHasher::default();
+        let cmx = [1u8; 32];
+        let tree_state = tree_state(cmx);
+        assert_eq!(
+            verify_witnesses(&[note(0, cmx)], &tree_state, &h).unwrap(),
+            1
+        );
+        assert!(verify_witnesses(&[note(0, [2u8; 32])], &tree_state, &h).is_err());
+    }
+
+    #[test]
+    fn witnesses_are_only_verified_in_verify_mode() {
+        let connection = test_db();
+        test_account(&NETWORK, &connection);
+        let cmx = [1u8; 32];
+        let mut sync = SaplingSync::new(
+            &NETWORK,
+            &connection,
+            CheckpointHeight(TEST_BIRTH),
+            1,
+            tree_state(cmx),
+        )
+        .unwrap();
+        sync.notes.push(note(0, cmx));
+        sync.add(&[]).unwrap();
+        let expected = u32::from(cfg!(feature = "verify-witness"));
+        assert_eq!(sync.stats.witnesses_verified, expected);
+    }
+
+    #[test]
+    fn stats_add_up_over_batches() {
+        let connection = test_db();
+        test_account(&NETWORK, &connection);
+        let mut sync = SaplingSync::new(
+            &NETWORK,
+            &connection,
+            CheckpointHeight(TEST_BIRTH),
+            0,
+            Edge::default(),
+        )
+        .unwrap();
+        let blocks = (1..=5)
+            .map(|i| block(TEST_BIRTH + i, vec![]))
+            .collect::<Vec<_>>();
+        sync.add(&blocks[..2]).unwrap();
+        sync.add(&blocks[2..]).unwrap();
+        assert_eq!(sync.stats.blocks, 5);
+        assert_eq!(sync.stats.notes, 0);
+
+        let orchard = SyncStats {
+            blocks: 5,
+            notes: 2,
+            decrypt_ms: 1,
+            ..SyncStats::default()
+        };
+        let merged = sync.stats.merge(&orchard);
+        assert_eq!(merged.blocks, 5);
+        assert_eq!(merged.notes, 2);
+        assert_eq!(merged.decrypt_ms, sync.stats.decrypt_ms + 1);
+        let mut total = merged.clone();
+        total.add(&merged);
+        assert_eq!(total.blocks, 10);
+        assert_eq!(total.notes, 4);
+        assert_eq!(total.witness_ms, 2 * merged.witness_ms);
+    }
+
+    /// Compact output of a note of `value` zats to `address`
+    fn output(address: &PaymentAddress, value: u64) -> CompactSaplingOutput {
+        let note = address.create_note(NoteValue::from_raw(value), Rseed::AfterZip212(OsRng.gen()));
+        let encryptor = sapling_note_encryption(None, note.clone(), [0u8; 512], &mut OsRng);
+        let ciphertext = encryptor.encrypt_note_plaintext();
+        CompactSaplingOutput {
+            cmu: note.cmu().to_bytes().to_vec(),
+            epk: encryptor.epk().to_bytes().0.to_vec(),
+            ciphertext: ciphertext[..COMPACT_NOTE_SIZE].to_vec(),
+        }
+    }
+
+    #[test]
+    fn notes_below_the_minimum_are_skipped() {
+        let connection = test_db();
+        test_account(&NETWORK, &connection);
+        let mut sync = SaplingSync::new(
+            &NETWORK,
+            &connection,
+            CheckpointHeight(TEST_BIRTH),
+            0,
+            Edge::default(),
+        )
+        .unwrap();
+        sync.min_note_value = 2;
+        let address = sync.account_infos[0].sapling.addr;
+        let outputs = vec![output(&address, 1), output(&address, 10_000)];
+        let cmu: Hash = outputs[1].cmu.clone().try_into().unwrap();
+        let tx = CompactTx {
+            hash: vec![0u8; 32],
+            outputs,
+            ..CompactTx::default()
+        };
+        sync.add(&[block(TEST_BIRTH + 1, vec![tx])]).unwrap();
+
+        assert_eq!(sync.notes.len(), 1);
+        assert_eq!(sync.stats.notes, 1);
+        let note = &sync.notes[0];
+        assert_eq!(note.value, 10_000);
+        // the skipped note still takes its place in the tree
+        assert_eq!(note.position, 1);
+        assert_eq!(note.witness.value, cmu);
+        assert_eq!(sync.position, 2);
+    }
+
+    #[test]
+    fn parallel_and_serial_roots_agree() {
+        let connection = test_db();
+        test_account(&NETWORK, &connection);
+        let mut sync = SaplingSync::new(
+            &NETWORK,
+            &connection,
+            CheckpointHeight(TEST_BIRTH),
+            0,
+            Edge::default(),
+        )
+        .unwrap();
+        let address = sync.account_infos[0].sapling.addr;
+        let tx = CompactTx {
+            hash: vec![0u8; 32],
+            outputs: (1..=5).map(|i| output(&address, i * 1000)).collect(),
+            ..CompactTx::default()
+        };
+        sync.add(&[block(TEST_BIRTH + 1, vec![tx])]).unwrap();
+        assert_eq!(sync.notes.len(), 5);
+
+        let h = &sync.hasher;
+        let auth_path = sync.tree_state.to_auth_path(h);
+        let serial = sync
+            .notes
+            .iter()
+            .map(|n| n.witness.root(&auth_path, h))
+            .collect::<Vec<_>>();
+        let parallel = sync
+            .notes
+            .par_iter()
+            .map(|n| n.witness.root(&auth_path, h))
+            .collect::<Vec<_>>();
+        assert_eq!(serial, parallel);
+        assert!(serial.iter().all(|&r| r == sync.tree_state.root(h)));
+        assert_eq!(
+            verify_witnesses(&sync.notes, &sync.tree_state, h).unwrap(),
+            5
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn simulated_sync_leaves_the_wallet_unchanged() {
+        use super::simulate_sync;
+        use crate::{
+            db::{account::get_account_info, notes::get_sync_height},
+            lwd::{
+                mock::{
+                    testing::{block, chain, wallet},
+                    MockLwd,
+                },
+                rpc::TreeState,
+            },
+        };
+
+        let lwd = MockLwd::new(chain(2));
+        let coin = wallet("simulate", &lwd).await;
+        let connection = coin.connection().unwrap();
+        let ai = get_account_info(&NETWORK, &connection, 1).unwrap();
+        let o = output(&ai.sapling.addr, 50_000);
+        {
+            let mut chain = lwd.chain.lock();
+            let mut b = block(TEST_BIRTH + 1, 0);
+            b.vtx.push(CompactTx {
+                hash: vec![0u8; 32],
+                outputs: vec![o.clone()],
+                ..CompactTx::default()
+            });
+            chain.add_block(b);
+            // the sapling tree with the single commitment of the note
+            for height in [TEST_BIRTH + 1, TEST_BIRTH + 2] {
+                chain.set_tree_state(TreeState {
+                    height: height as u64,
+                    sapling_tree: format!("01{}0000", hex::encode(&o.cmu)),
+                    ..TreeState::default()
+                });
+            }
+        }
+
+        let report = simulate_sync(&coin, CheckpointHeight(TEST_BIRTH), TEST_BIRTH + 2)
+            .await
+            .unwrap();
+        assert_eq!(report.received.len(), 1);
+        let event = &report.received[0];
+        assert_eq!((event.pool, event.value), (1, 50_000));
+        assert_eq!(event.height, TEST_BIRTH + 1);
+
+        assert_eq!(get_sync_height(&connection).unwrap(), Some(TEST_BIRTH));
+        let notes = connection
+            .query_row("SELECT COUNT(*) FROM notes", [], |r| r.get::<_, u32>(0))
+            .unwrap();
+        assert_eq!(notes, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sync_restricted_to_one_account() {
+        use super::warp_sync_accounts;
+        use crate::{
+            db::{
+                account::{get_account_info, get_balance},
+                account_manager::{create_new_account, detect_key},
+                notes::get_sync_height,
+                testing::TEST_SEED,
+            },
+            lwd::{
+                mock::{
+                    testing::{block, chain, wallet},
+                    MockLwd,
+                },
+                rpc::TreeState,
+            },
+        };
+
+        let lwd = MockLwd::new(chain(2));
+        let coin = wallet("restricted", &lwd).await;
+        let connection = coin.connection().unwrap();
+        let key = detect_key(&NETWORK, TEST_SEED, 1, 0).unwrap();
+        let other =
+            create_new_account(&NETWORK, &connection, "other", key, TEST_BIRTH, false).unwrap();
+        let outputs = [1, other]
+            .map(|account| {
+                let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+                output(&ai.sapling.addr, 50_000)
+            })
+            .to_vec();
+        {
+            let mut chain = lwd.chain.lock();
+            let sapling_tree = format!(
+                "01{}01{}00",
+                hex::encode(&outputs[0].cmu),
+                hex::encode(&outputs[1].cmu)
+            );
+            let mut b = block(TEST_BIRTH + 1, 0);
+            b.vtx.push(CompactTx {
+                hash: vec![0u8; 32],
+                outputs,
+                ..CompactTx::default()
+            });
+            chain.add_block(b);
+            for height in [TEST_BIRTH + 1, TEST_BIRTH + 2] {
+                chain.set_tree_state(TreeState {
+                    height: height as u64,
+                    sapling_tree: sapling_tree.clone(),
+                    ..TreeState::default()
+                });
+            }
+        }
+
+        warp_sync_accounts(
+            &coin,
+            CheckpointHeight(TEST_BIRTH),
+            TEST_BIRTH + 2,
+            &[other],
+        )
+        .await
+        .unwrap();
+        assert_eq!(get_sync_height(&connection).unwrap(), Some(TEST_BIRTH + 2));
+        let sapling = |account: u32| {
+            get_balance(&connection, account, TEST_BIRTH + 2)
+                .unwrap()
+                .sapling
+        };
+        assert_eq!(sapling(other), 50_000);
+        assert_eq!(sapling(1), 0);
+    }
 }
@@ -1,6 +1,6 @@
 use jubjub::Fr;
 use rusqlite::Connection;
-use std::{collections::HashMap, mem::swap, sync::mpsc::channel};
+use std::{collections::HashMap, mem::swap, sync::mpsc::channel, time::Instant};
 
 use crate::{
     db::{
@@ -14,13 +14,15 @@ use crate::{
 };
 use anyhow::Result;
 use rayon::prelude::*;
-use tracing::info;
+use tracing::debug;
 use zcash_primitives::consensus::Network;
 use sapling_crypto::{value::NoteValue, Note, PaymentAddress, Rseed};
 
 use crate::warp::{hasher::SaplingHasher, Edge, Hasher, MERKLE_DEPTH};
 
-use super::{ReceivedNote, TxValueUpdate};
+use super::{
+    block_index, compute_positions, verify_witnesses, ReceivedNote, SyncStats, TxValueUpdate,
+};
 
 #[derive(Debug)]
 pub struct Synchronizer {
@@ -32,6 +34,10 @@ pub struct Synchronizer {
     pub spends: Vec<TxValueUpdate<Hash>>,
     pub position: u32,
     pub tree_state: Edge,
+    pub stats: SyncStats,
+    /// Received notes below this value are dropped. Their
+    /// commitments are still added to the tree
+    pub min_note_value: u64,
 }
 
 #[derive(Debug)]
@@ -66,10 +72,13 @@ impl Synchronizer {
             spends: vec![],
             position,
             tree_state,
+            stats: SyncStats::default(),
+            min_note_value: 0,
         })
     }
 
     pub fn add(&mut self, blocks: &[CompactBlock]) -> Result<()> {
+        let decrypt_start = Instant::now();
         let ivks = self
             .account_infos
             .iter()
@@ -102,36 +111,12 @@ impl Synchronizer {
                 .unwrap();
             });
 
+        let positions = compute_positions(self.position, blocks, 1);
         let mut notes = vec![];
         while let Ok(mut note) = receiver.recv() {
-            let mut position = self.position;
-            for cb in blocks.iter() {
-                if cb.height as u32 == note.height {
-                    break;
-                }
-                for tx in cb.vtx.iter() {
-                    position += tx.outputs.len() as u32;
-                    position += tx
-                        .sapling_bridge
-                        .as_ref()
-                        .map(|b| b.len as u32)
-                        .unwrap_or_default();
-                }
-            }
-            let cb = &blocks[(note.height - self.start - 1) as usize];
-            for (ivtx, tx) in cb.vtx.iter().enumerate() {
-                if ivtx as u32 == note.tx.ivtx {
-                    break;
-                }
-                position += tx.outputs.len() as u32;
-                position += tx
-                    .sapling_bridge
-                    .as_ref()
-                    .map(|b| b.len as u32)
-                    .unwrap_or_default();
-            }
-            position += note.vout;
-            note.position = position;
+            let ib = block_index(blocks, self.start, note.height)?;
+            let cb = &blocks[ib];
+            note.position = positions[ib][note.tx.ivtx as usize] + note.vout;
 
             let ai = self
                 .account_infos
@@ -152,9 +137,17 @@ impl Synchronizer {
                 .clone()
                 .try_into()
                 .unwrap();
+            if note.value < self.min_note_value {
+                debug!("Skipping note of {} zats at position {}", note.value, note.position);
+                continue;
+            }
             notes.push(note);
         }
 
+        self.stats.decrypt_ms += decrypt_start.elapsed().as_millis() as u64;
+        self.stats.notes += notes.len() as u32;
+        let witness_start = Instant::now();
+
         let mut bridges = vec![];
         let mut p = self.position;
         for cb in blocks.iter() {
@@ -270,6 +263,7 @@ impl Synchronizer {
             swap(&mut cmxs, &mut cmxs2);
         }
 
+        self.stats.witness_ms += witness_start.elapsed().as_millis() as u64;
         tracing::info!("Old notes #{}", self.notes.len());
         tracing::info!("New notes #{}", notes.len());
         self.notes.append(&mut notes);
@@ -277,6 +271,7 @@ impl Synchronizer {
         self.start += blocks.len() as u32;
 
         // detect spends
+        let spend_start = Instant::now();
 
         let mut nfs = self
             .notes
@@ -303,12 +298,15 @@ impl Synchronizer {
             }
         }
 
-        info!("# {}", self.notes.len());
-        // let auth_path = self.tree_state.to_auth_path(&self.hasher);
-        // for note in self.notes.iter() {
-        //     let root = note.witness.root(&auth_path, &self.hasher);
-        //     info!("{}", hex::encode(&root));
-        // }
+        self.stats.spend_ms += spend_start.elapsed().as_millis() as u64;
+        self.stats.blocks += blocks.len() as u32;
+
+        debug!("# {}", self.notes.len());
+        // Witness roots are only computed in verification mode
+        if cfg!(feature = "verify-witness") {
+            self.stats.witnesses_verified +=
+                verify_witnesses(&self.notes, &self.tree_state, &self.hasher)?;
+        }
 
         Ok(())
     }
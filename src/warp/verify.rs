@@ -0,0 +1,277 @@
+use anyhow::Result;
+use jubjub::Fr;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use zcash_primitives::consensus::Network;
+
+use crate::{db::account::get_account_info, warp::Witness, Hash};
+
+/// A stored note whose fields do not reproduce its commitment
+/// or its nullifier
+#[derive(Clone, Serialize, Debug)]
+pub struct NoteAudit {
+    pub id_note: u32,
+    pub position: u32,
+    pub height: u32,
+    pub value: u64,
+    pub orchard: bool,
+    pub commitment_ok: bool,
+    pub nullifier_ok: bool,
+    pub reason: String,
+}
+
+/// Recompute the commitment of every note of the account from its
+/// stored address, value and randomness, and compare it with the leaf
+/// of its latest witness. The nullifier is checked too.
+/// Returns the notes that fail
+pub fn audit_notes(
+    network: &Network,
+    connection: &Connection,
+    account: u32,
+) -> Result<Vec<NoteAudit>> {
+    let ai = get_account_info(network, connection, account)?;
+    let mut s = connection.prepare(
+        "SELECT n.id_note, n.position, n.height, n.address, n.value, n.rcm, n.nf, n.rho,
+        n.orchard, w.witness FROM notes n LEFT JOIN witnesses w ON w.note = n.id_note
+        AND w.height = (SELECT MAX(height) FROM witnesses WHERE note = n.id_note)
+        WHERE n.account = ?1 ORDER BY n.id_note",
+    )?;
+    let rows = s.query_map(params![account], |r| {
+        Ok((
+            r.get::<_, u32>(0)?,
+            r.get::<_, u32>(1)?,
+            r.get::<_, u32>(2)?,
+            r.get::<_, Vec<u8>>(3)?,
+            r.get::<_, u64>(4)?,
+            r.get::<_, Vec<u8>>(5)?,
+            r.get::<_, Vec<u8>>(6)?,
+            r.get::<_, Option<Vec<u8>>>(7)?,
+            r.get::<_, bool>(8)?,
+            r.get::<_, Option<Vec<u8>>>(9)?,
+        ))
+    })?;
+
+    let mut audits = vec![];
+    for r in rows {
+        let (id_note, position, height, address, value, rcm, nf, rho, orchard, witness) = r?;
+        let cmx = witness_cmx(witness.as_deref());
+        let recomputed = if orchard {
+            recompute_orchard(
+                &ai.orchard.as_ref().map(|oi| &oi.vk),
+                &address,
+                value,
+                &rcm,
+                rho.as_deref(),
+            )
+        } else {
+            recompute_sapling(Some(&ai.sapling.vk.fvk.vk), &address, value, &rcm, position)
+        };
+        let (commitment_ok, nullifier_ok, reason) = match recomputed {
+            Some((c, n)) => {
+                let commitment_ok = match &cmx {
+                    Ok(Some(cmx)) => *cmx == c,
+                    Ok(None) => true,
+                    Err(_) => false,
+                };
+                let nullifier_ok = n.map(|n| &n[..] == &nf[..]).unwrap_or(true);
+                let reason = match (cmx.is_err(), commitment_ok, nullifier_ok) {
+                    (true, _, false) => "Unreadable witness and nullifier mismatch",
+                    (true, _, true) => "Unreadable witness",
+                    (false, false, false) => "Commitment and nullifier mismatch",
+                    (false, false, true) => "Commitment mismatch",
+                    (false, true, false) => "Nullifier mismatch",
+                    (false, true, true) => "",
+                };
+                (commitment_ok, nullifier_ok, reason.to_string())
+            }
+            None => (false, false, "Invalid note fields".to_string()),
+        };
+        if !commitment_ok || !nullifier_ok {
+            audits.push(NoteAudit {
+                id_note,
+                position,
+                height,
+                value,
+                orchard,
+                commitment_ok,
+                nullifier_ok,
+                reason,
+            });
+        }
+    }
+    Ok(audits)
+}
+
+/// Commitment stored in the latest witness of a note, `None` when the
+/// note has no witness. A witness that cannot be read is an error
+fn witness_cmx(witness: Option<&[u8]>) -> Result<Option<Hash>> {
+    witness
+        .map(|w| Ok(bincode::deserialize_from::<_, Witness>(w)?.value))
+        .transpose()
+}
+
+/// Commitment of a sapling note and, with the viewing key, its nullifier
+pub(crate) fn recompute_sapling(
+    vk: Option<&sapling_crypto::keys::ViewingKey>,
+    address: &[u8],
+    value: u64,
+    rcm: &[u8],
+    position: u32,
+) -> Option<(Hash, Option<Hash>)> {
+    use sapling_crypto::{value::NoteValue, Note, PaymentAddress, Rseed};
+
+    let address: [u8; 43] = address.try_into().ok()?;
+    let rcm: Hash = rcm.try_into().ok()?;
+    let recipient = PaymentAddress::from_bytes(&address)?;
+    let rcm = Option::<Fr>::from(Fr::from_bytes(&rcm))?;
+    let note = Note::from_parts(
+        recipient,
+        NoteValue::from_raw(value),
+        Rseed::BeforeZip212(rcm),
+    );
+    let cmu = note.cmu().to_bytes();
+    let nf = vk.map(|vk| note.nf(&vk.nk, position as u64).0);
+    Some((cmu, nf))
+}
+
+/// Commitment of an orchard note and, with the viewing key, its nullifier
+pub(crate) fn recompute_orchard(
+    vk: &Option<&orchard::keys::FullViewingKey>,
+    address: &[u8],
+    value: u64,
+    rcm: &[u8],
+    rho: Option<&[u8]>,
+) -> Option<(Hash, Option<Hash>)> {
+    use orchard::{
+        note::{ExtractedNoteCommitment, RandomSeed, Rho},
+        value::NoteValue,
+        Address, Note,
+    };
+
+    let address: [u8; 43] = address.try_into().ok()?;
+    let rcm: Hash = rcm.try_into().ok()?;
+    let rho: Hash = rho?.try_into().ok()?;
+    let recipient = Option::<Address>::from(Address::from_raw_address_bytes(&address))?;
+    let rho = Option::<Rho>::from(Rho::from_bytes(&rho))?;
+    let rseed = Option::<RandomSeed>::from(RandomSeed::from_bytes(rcm, &rho))?;
+    let note = Option::<Note>::from(Note::from_parts(
+        recipient,
+        NoteValue::from_raw(value),
+        rho,
+        rseed,
+    ))?;
+    let cmx = ExtractedNoteCommitment::from(note.commitment()).to_bytes();
+    let nf = vk.map(|vk| note.nullifier(vk).to_bytes());
+    Some((cmx, nf))
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::params;
+    use zcash_primitives::consensus::Network;
+
+    use super::{audit_notes, recompute_sapling, witness_cmx};
+    use crate::{
+        db::{
+            account::get_account_info,
+            testing::{test_account, test_db, TEST_BIRTH},
+        },
+        warp::Witness,
+    };
+
+    const NETWORK: Network = Network::MainNetwork;
+
+    #[test]
+    fn missing_witness_has_no_commitment() {
+        assert!(witness_cmx(None).unwrap().is_none());
+    }
+
+    #[test]
+    fn witness_commitment_is_read() {
+        let w = Witness {
+            value: [7u8; 32],
+            position: 42,
+            ..Witness::default()
+        };
+        let data = bincode::serialize(&w).unwrap();
+        assert_eq!(witness_cmx(Some(&data)).unwrap(), Some([7u8; 32]));
+    }
+
+    #[test]
+    fn unreadable_witness_is_an_error() {
+        assert!(witness_cmx(Some(&[1, 2, 3])).is_err());
+    }
+
+    #[test]
+    fn sapling_commitment_is_recomputed() {
+        use sapling_crypto::{value::NoteValue, zip32::ExtendedSpendingKey, Note, Rseed};
+
+        let (_, address) = ExtendedSpendingKey::master(&[0u8; 32]).default_address();
+        let rcm = jubjub::Fr::from(12345u64);
+        let note = Note::from_parts(
+            address,
+            NoteValue::from_raw(100_000),
+            Rseed::BeforeZip212(rcm),
+        );
+        let (cmu, nf) =
+            recompute_sapling(None, &address.to_bytes(), 100_000, &rcm.to_bytes(), 0).unwrap();
+        assert_eq!(cmu, note.cmu().to_bytes());
+        assert!(nf.is_none());
+    }
+
+    #[test]
+    fn invalid_sapling_address_is_rejected() {
+        assert!(recompute_sapling(None, &[0u8; 43], 100_000, &[0u8; 32], 0).is_none());
+    }
+
+    #[test]
+    fn corrupted_value_is_flagged() {
+        let connection = test_db();
+        let account = test_account(&NETWORK, &connection);
+        let ai = get_account_info(&NETWORK, &connection, account).unwrap();
+        let address = ai.sapling.addr.to_bytes();
+        let rcm = jubjub::Fr::from(12345u64).to_bytes();
+        let vk = &ai.sapling.vk.fvk.vk;
+        // the second note is stored with another value than the one committed to
+        for (position, stored_value) in [(0u32, 100_000u64), (1, 200_000)] {
+            let (cmu, nf) = recompute_sapling(Some(vk), &address, 100_000, &rcm, position).unwrap();
+            let witness = Witness {
+                value: cmu,
+                position,
+                ..Witness::default()
+            };
+            connection
+                .execute(
+                    "INSERT INTO notes(account, position, height, output_index, address,
+                    value, rcm, nf, orchard) VALUES (?1, ?2, ?3, 0, ?4, ?5, ?6, ?7, FALSE)",
+                    params![
+                        account,
+                        position,
+                        TEST_BIRTH,
+                        &address[..],
+                        stored_value,
+                        &rcm[..],
+                        &nf.unwrap()[..]
+                    ],
+                )
+                .unwrap();
+            connection
+                .execute(
+                    "INSERT INTO witnesses(account, note, height, witness)
+                    VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        account,
+                        connection.last_insert_rowid(),
+                        TEST_BIRTH,
+                        bincode::serialize(&witness).unwrap()
+                    ],
+                )
+                .unwrap();
+        }
+        let audits = audit_notes(&NETWORK, &connection, account).unwrap();
+        assert_eq!(audits.len(), 1);
+        assert_eq!(audits[0].position, 1);
+        assert_eq!(audits[0].value, 200_000);
+        assert!(!audits[0].commitment_ok);
+    }
+}